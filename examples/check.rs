@@ -0,0 +1,51 @@
+//! Checks paths against an existing baseline and prints the findings.
+//!
+//! cargo run --example check -- files_data.redb /etc /usr/bin
+
+use integrity_checker::baseline::Baseline;
+use integrity_checker::exclude::ExcludeSet;
+use integrity_checker::fileops::CheckOptions;
+use integrity_checker::scan::ScanOptions;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio_stream::StreamExt;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(db) = args.next() else {
+        eprintln!("usage: check <baseline.redb> <path>...");
+        return ExitCode::FAILURE;
+    };
+    let paths: Vec<PathBuf> = args.map(PathBuf::from).collect();
+
+    let baseline = match Baseline::open(&db){
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut findings = match baseline.check(paths, ExcludeSet::default(), ScanOptions::default(), CheckOptions::default()){
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut count = 0;
+    while let Some(finding) = findings.next().await{
+        match finding{
+            Ok(f) => {
+                count += 1;
+                println!("{:?} {}: {}", f.kind, f.path, f.message);
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    println!("{count} findings");
+    if count > 0 { ExitCode::from(2) } else { ExitCode::SUCCESS }
+}
@@ -0,0 +1,201 @@
+//! Acknowledged findings, kept in a `--ack-file` DB: known changes that can't
+//! go into the baseline yet are reported at info level and don't fail the
+//! check, until the entry changes again.
+
+use super::dbfile;
+use super::error::IntegrityWatcherError;
+use super::fileops::{Finding, FindingKind};
+use super::types::{EscapedPath, FileMetadataExt, Owner, Security, Timestamp};
+use serde::{Deserialize, Serialize};
+use redb::{ReadableDatabase, ReadableTable, TableDefinition, Value};
+use postcard::{from_bytes, to_allocvec};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Acknowledgments of findings in entries table `table`.
+pub fn acks_table_name(table: &str) -> String {
+    format!("{table}_acks")
+}
+
+fn acks_table(name: &str) -> TableDefinition<'_, String, Ack> {
+    TableDefinition::new(name)
+}
+
+/// What an entry is now, the part of a finding an acknowledgment is bound
+/// to besides path and kind: everything a check compares but the times, so
+/// acknowledging a content change doesn't hide a later permission, owner or
+/// flags change.
+pub fn entry_state(entry: Option<&FileMetadataExt>) -> String {
+    match entry{
+        Some(FileMetadataExt::File(f)) => {
+            let mut state = format!("file {} perms {:o} size {}", f.strong.as_ref().unwrap_or(&f.hash), f.permissions, u64::from(f.size));
+            if let Some(ino) = f.ino{
+                state += &format!(" ino {ino}");
+            }
+            if let Some(content_type) = &f.content_type{
+                state += &format!(" type {content_type}");
+            }
+            for stream in f.streams.iter().flatten(){
+                state += &format!(" stream {stream}");
+            }
+            with_owner_flags(state, f.security.as_ref(), f.flags, f.owner.as_ref())
+        }
+        Some(FileMetadataExt::Symlink(s)) => {
+            let mut state = format!("symlink {} perms {:o}", s.data, s.permissions);
+            if let Some(content) = &s.content{
+                state += &format!(" content {content}");
+            }
+            state
+        }
+        Some(FileMetadataExt::Dir(d)) => {
+            let mut state = format!("dir perms {:o} size {}", d.permissions, d.size);
+            if let Some(entries) = d.entries{
+                state += &format!(" entries {entries}");
+            }
+            with_owner_flags(state, d.security.as_ref(), d.flags, d.owner.as_ref())
+        }
+        Some(FileMetadataExt::Whiteout) => "whiteout".to_owned(),
+        Some(FileMetadataExt::Opaque) => "opaque".to_owned(),
+        None => "gone".to_owned(),
+    }
+}
+
+fn with_owner_flags(mut state: String, security: Option<&Security>, flags: Option<u32>, owner: Option<&Owner>) -> String {
+    if let Some(security) = security{
+        state += &format!(" {security}");
+    }
+    if let Some(owner) = owner{
+        state += &format!(" owner {}:{}", owner.uid, owner.gid);
+    }
+    if let Some(flags) = flags{
+        state += &format!(" flags {flags:x}");
+    }
+    state
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ack{
+    pub kind: FindingKind,
+    /// `entry_state` when acknowledged
+    pub state: String,
+    /// seconds since the epoch
+    pub acked: u64,
+    /// the finding as it was printed
+    pub message: String,
+}
+
+impl Ack {
+    /// Covers a finding of `kind` whose entry is now `state`.
+    pub fn covers(&self, kind: FindingKind, state: &str) -> bool {
+        self.kind == kind && self.state == state
+    }
+}
+
+impl std::fmt::Display for Ack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} acknowledged {}: {}", self.kind, EscapedPath(&self.state), Timestamp::new(self.acked), self.message)
+    }
+}
+
+impl Value for Ack {
+    type SelfType<'a> = Self;
+    type AsBytes<'a> = Vec<u8>;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+        where Self: 'a{
+        from_bytes(data).unwrap()
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a> {
+        to_allocvec(value).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new("Ack")
+    }
+}
+
+/// All acknowledgments by path, empty when the file or table doesn't exist yet.
+pub fn load(path: &str, table: &str) -> Result<BTreeMap<String, Ack>, IntegrityWatcherError> {
+    if !Path::new(path).exists(){
+        return Ok(BTreeMap::new());
+    }
+    let db = dbfile::open(path)?;
+    let name = acks_table_name(table);
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let table = match read_txn.open_table(acks_table(&name)){
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut all = BTreeMap::new();
+    for k in table.iter()?{
+        let k = k?;
+        all.insert(k.0.value(), k.1.value());
+    }
+    Ok(all)
+}
+
+/// Acknowledges `findings`, replacing earlier acknowledgments of their paths.
+pub fn record(path: &str, table: &str, findings: &[Finding], now: u64) -> Result<(), IntegrityWatcherError> {
+    let db = dbfile::create(path)?;
+    let name = acks_table_name(table);
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    {
+        let mut table = write_txn.open_table(acks_table(&name))?;
+        for f in findings{
+            let ack = Ack{ kind: f.kind, state: f.state.clone(), acked: now, message: f.message.clone() };
+            table.insert(f.path.clone(), &ack)?;
+        }
+    }
+    write_txn.commit()?;
+    db.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FileMetadata;
+    use log::Level;
+
+    #[test]
+    fn test_record_and_load() {
+        let path = std::env::current_dir().unwrap().join("test_db_acks.redb");
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+        assert!(load(&path, "files").unwrap().is_empty());
+
+        let finding = |p: &str, kind, state: &str| Finding{ kind, path: p.to_owned(), state: state.to_owned(), level: Level::Error, message: format!("File {p} changed"), old: None, new: None, entropy: None, changes: Default::default() };
+        record(&path, "files", &[finding("/etc/motd", FindingKind::Changed, "file 00"), finding("/tmp/x", FindingKind::Removed, "gone")], 1000).unwrap();
+        record(&path, "files", &[finding("/etc/motd", FindingKind::Changed, "file 11")], 2000).unwrap();
+        let acks = load(&path, "files").unwrap();
+        assert_eq!(acks.len(), 2);
+        assert!(acks["/etc/motd"].covers(FindingKind::Changed, "file 11"));
+        assert!(!acks["/etc/motd"].covers(FindingKind::Changed, "file 00"));
+        assert_eq!(acks["/etc/motd"].acked, 2000);
+        assert!(acks["/tmp/x"].covers(FindingKind::Removed, "gone"));
+        // kept per entries table
+        assert!(load(&path, "other").unwrap().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_entry_state() {
+        let file = FileMetadata{ hash: [7u8; 32].into(), permissions: 0o100644, modified: 1000, size: 10.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None };
+        let state = |f: &FileMetadata| entry_state(Some(&FileMetadataExt::File(f.clone())));
+        let acked = state(&file);
+        assert_eq!(acked, format!("file {} perms 100644 size 10", file.hash));
+        // touched only
+        assert_eq!(state(&FileMetadata{ modified: 2000, ..file.clone() }), acked);
+        // changed again after the acknowledged change
+        assert_ne!(state(&FileMetadata{ permissions: 0o104755, ..file.clone() }), acked);
+        assert_ne!(state(&FileMetadata{ flags: Some(crate::inodeflags::IMMUTABLE), ..file.clone() }), acked);
+        assert_ne!(state(&FileMetadata{ security: Some(Security{ owner: "S-1-5-18".to_owned(), descriptor: [3u8; 32].into() }), ..file.clone() }), acked);
+        assert_eq!(entry_state(None), "gone");
+    }
+}
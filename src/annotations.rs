@@ -0,0 +1,307 @@
+use super::error::IntegrityWatcherError;
+use super::types::EscapedPath;
+use serde::{Deserialize, Serialize};
+use redb::{Database, MultimapTableDefinition, TableDefinition, Value, ReadableDatabase, ReadableMultimapTable, ReadableTable, WriteTransaction};
+use postcard::{from_bytes, to_allocvec};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Notes and tags attached to the entries of `table`, keyed by the same path.
+pub fn annotations_table_name(table: &str) -> String {
+    format!("{table}_annotations")
+}
+
+/// `--tag-prefix` rules of `table`, every entry under the prefix carries the tags.
+pub fn tag_prefixes_table_name(table: &str) -> String {
+    format!("{table}_tag_prefixes")
+}
+
+/// `--mark-volatile` entries of `table`, whose content is expected to change.
+pub fn volatile_table_name(table: &str) -> String {
+    format!("{table}_volatile")
+}
+
+fn annotations_table(name: &str) -> TableDefinition<'_, String, Annotations> {
+    TableDefinition::new(name)
+}
+
+fn tag_prefixes_table(name: &str) -> MultimapTableDefinition<'_, String, String> {
+    MultimapTableDefinition::new(name)
+}
+
+fn volatile_table(name: &str) -> TableDefinition<'_, String, ()> {
+    TableDefinition::new(name)
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotations{
+    pub notes: Vec<String>,
+    pub tags: BTreeSet<String>,
+}
+
+impl std::fmt::Display for Annotations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = "";
+        for note in &self.notes{
+            write!(f, "{sep}note: {}", EscapedPath(note))?;
+            sep = " ";
+        }
+        if !self.tags.is_empty(){
+            let tags = self.tags.iter().map(|t| EscapedPath(t).to_string()).collect::<Vec<_>>();
+            write!(f, "{sep}tags: {}", tags.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl Value for Annotations {
+    type SelfType<'a> = Self;
+    type AsBytes<'a> = Vec<u8>;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+        where Self: 'a{
+        from_bytes(data).unwrap()
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a> {
+        to_allocvec(value).unwrap()
+    }
+
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new("Annotations")
+    }
+}
+
+/// Adds a note and/or tag to the annotations of `path` in entries table `table`.
+pub fn annotate(db: &Database, table: &str, path: &str, note: Option<&str>, tag: Option<&str>) -> Result<Annotations, IntegrityWatcherError> {
+    let name = annotations_table_name(table);
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    let annotations = {
+        let mut table = write_txn.open_table(annotations_table(&name))?;
+        let mut annotations = table.get(path.to_owned())?.map(|v| v.value()).unwrap_or_default();
+        if let Some(note) = note{
+            annotations.notes.push(note.to_owned());
+        }
+        if let Some(tag) = tag{
+            annotations.tags.insert(tag.to_owned());
+        }
+        table.insert(path.to_owned(), &annotations)?;
+        annotations
+    };
+    write_txn.commit()?;
+    Ok(annotations)
+}
+
+/// All annotations, empty for databases that never had any.
+pub fn load_all(db: &Database, table: &str) -> Result<BTreeMap<String, Annotations>, IntegrityWatcherError> {
+    let name = annotations_table_name(table);
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let table = match read_txn.open_table(annotations_table(&name)){
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut all = BTreeMap::new();
+    for k in table.iter()?{
+        let k = k?;
+        all.insert(k.0.value(), k.1.value());
+    }
+    Ok(all)
+}
+
+/// Drops the annotations and volatile mark of an entry removed from the DB as
+/// part of `write_txn`, returns the annotations.
+pub fn remove_in(write_txn: &WriteTransaction, table: &str, path: &str) -> Result<Option<Annotations>, IntegrityWatcherError> {
+    write_txn.open_table(volatile_table(&volatile_table_name(table)))?.remove(path.to_owned())?;
+    let name = annotations_table_name(table);
+    let mut table = write_txn.open_table(annotations_table(&name))?;
+    Ok(table.remove(path.to_owned())?.map(|v| v.value()))
+}
+
+/// Marks `path` volatile, or clears the mark. Returns false when it already was
+/// or wasn't marked.
+pub fn mark_volatile(db: &Database, table: &str, path: &str, volatile: bool) -> Result<bool, IntegrityWatcherError> {
+    let name = volatile_table_name(table);
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    let changed = {
+        let mut table = write_txn.open_table(volatile_table(&name))?;
+        match volatile{
+            true => table.insert(path.to_owned(), ())?.is_none(),
+            false => table.remove(path.to_owned())?.is_some(),
+        }
+    };
+    write_txn.commit()?;
+    Ok(changed)
+}
+
+/// Entries marked volatile, empty for databases that never had any.
+pub fn load_volatile(db: &Database, table: &str) -> Result<BTreeSet<String>, IntegrityWatcherError> {
+    let name = volatile_table_name(table);
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let table = match read_txn.open_table(volatile_table(&name)){
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(BTreeSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    table.iter()?.map(|k| Ok(k?.0.value())).collect()
+}
+
+/// Parses a `--tag-prefix` rule `/etc=config`.
+pub fn parse_tag_prefix(rule: &str) -> Result<(String, String), IntegrityWatcherError> {
+    match rule.rsplit_once('='){
+        Some((prefix, tag)) if !prefix.is_empty() && !tag.trim().is_empty() => Ok((prefix.to_owned(), tag.trim().to_owned())),
+        _ => Err(IntegrityWatcherError::InvalidArgument(format!("--tag-prefix {rule}, expected PREFIX=TAG"))),
+    }
+}
+
+pub fn add_tag_prefixes(db: &Database, table: &str, rules: &[(String, String)]) -> Result<(), IntegrityWatcherError> {
+    let name = tag_prefixes_table_name(table);
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    {
+        let mut table = write_txn.open_multimap_table(tag_prefixes_table(&name))?;
+        for (prefix, tag) in rules{
+            table.insert(prefix, tag)?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+/// Effective tags of DB paths: those of `--tag-prefix` rules covering the path
+/// plus the entry's own `--label` tags.
+#[derive(Debug, Default)]
+pub struct TagIndex{
+    prefixes: Vec<(String, String)>,
+    annotations: BTreeMap<String, Annotations>,
+}
+
+impl TagIndex {
+    pub fn load(db: &Database, table: &str) -> Result<Self, IntegrityWatcherError> {
+        let name = tag_prefixes_table_name(table);
+        let mut prefixes = Vec::new();
+        {
+            let read_txn = db.begin_read().map_err(Box::new)?;
+            match read_txn.open_multimap_table(tag_prefixes_table(&name)){
+                Ok(table) => {
+                    for k in table.iter()?{
+                        let (prefix, tags) = k?;
+                        for tag in tags{
+                            prefixes.push((prefix.value(), tag?.value()));
+                        }
+                    }
+                }
+                Err(redb::TableError::TableDoesNotExist(_)) => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(TagIndex { prefixes, annotations: load_all(db, table)? })
+    }
+
+    pub fn tags(&self, path: &str) -> BTreeSet<&str> {
+        let mut tags: BTreeSet<&str> = self.prefixes.iter()
+            .filter(|(prefix, _)| Path::new(path).starts_with(prefix))
+            .map(|(_, tag)| tag.as_str())
+            .collect();
+        if let Some(a) = self.annotations.get(path){
+            tags.extend(a.tags.iter().map(String::as_str));
+        }
+        tags
+    }
+
+    pub fn has(&self, path: &str, tag: &str) -> bool {
+        self.tags(path).contains(tag)
+    }
+
+    /// Where entries tagged `tag` can be found: its prefixes and the paths
+    /// labeled with it that aren't under one of them.
+    pub fn scope(&self, tag: &str) -> Vec<String> {
+        let prefixes: Vec<&String> = self.prefixes.iter().filter(|(_, t)| t == tag).map(|(p, _)| p).collect();
+        let labeled = self.annotations.iter()
+            .filter(|(path, a)| a.tags.contains(tag) && !prefixes.iter().any(|p| Path::new(path).starts_with(p)))
+            .map(|(path, _)| path);
+        let mut scope: Vec<String> = prefixes.iter().copied().chain(labeled).cloned().collect();
+        scope.sort();
+        scope.dedup();
+        scope
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::DEFAULT_TABLE;
+    use std::fs;
+
+    #[test]
+    fn test_annotations(){
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_annotations");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        let db = Database::create(path.join("database.redb")).unwrap();
+        assert!(load_all(&db, DEFAULT_TABLE).unwrap().is_empty());
+
+        annotate(&db, DEFAULT_TABLE, "/etc/passwd", Some("approved change TICKET-123 2024-05-01"), None).unwrap();
+        annotate(&db, DEFAULT_TABLE, "/etc/passwd", None, Some("vendor")).unwrap();
+        let a = annotate(&db, DEFAULT_TABLE, "/etc/passwd", Some("known flaky mtime"), Some("vendor")).unwrap();
+        assert_eq!(a.notes.len(), 2);
+        assert_eq!(a.to_string(), "note: approved change TICKET-123 2024-05-01 note: known flaky mtime tags: vendor");
+        assert_eq!(load_all(&db, DEFAULT_TABLE).unwrap().get("/etc/passwd"), Some(&a));
+
+        assert!(load_volatile(&db, DEFAULT_TABLE).unwrap().is_empty());
+        assert!(mark_volatile(&db, DEFAULT_TABLE, "/etc/adjtime", true).unwrap());
+        assert!(mark_volatile(&db, DEFAULT_TABLE, "/etc/passwd", true).unwrap());
+        assert!(!mark_volatile(&db, DEFAULT_TABLE, "/etc/passwd", true).unwrap());
+        assert!(!mark_volatile(&db, DEFAULT_TABLE, "/etc/shadow", false).unwrap());
+        assert_eq!(load_volatile(&db, DEFAULT_TABLE).unwrap(), BTreeSet::from(["/etc/adjtime".to_owned(), "/etc/passwd".to_owned()]));
+        assert!(mark_volatile(&db, DEFAULT_TABLE, "/etc/adjtime", false).unwrap());
+
+        let write_txn = db.begin_write().unwrap();
+        assert_eq!(remove_in(&write_txn, DEFAULT_TABLE, "/etc/passwd").unwrap(), Some(a));
+        assert_eq!(remove_in(&write_txn, DEFAULT_TABLE, "/etc/shadow").unwrap(), None);
+        write_txn.commit().unwrap();
+        assert!(load_all(&db, DEFAULT_TABLE).unwrap().is_empty());
+        assert!(load_volatile(&db, DEFAULT_TABLE).unwrap().is_empty());
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_tag_index(){
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_tag_index");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        let db = Database::create(path.join("database.redb")).unwrap();
+        assert!(TagIndex::load(&db, DEFAULT_TABLE).unwrap().tags("/etc/passwd").is_empty());
+
+        let rules: Vec<_> = ["/etc=config", "/boot=boot", "/etc/ssh=boot", "/etc=config"].iter().map(|r| parse_tag_prefix(r).unwrap()).collect();
+        add_tag_prefixes(&db, DEFAULT_TABLE, &rules).unwrap();
+        annotate(&db, DEFAULT_TABLE, "/usr/lib/modules/vmlinuz", None, Some("boot")).unwrap();
+        annotate(&db, DEFAULT_TABLE, "/boot/grub/grub.cfg", None, Some("vendor")).unwrap();
+
+        let tags = TagIndex::load(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(tags.tags("/etc/ssh/sshd_config"), BTreeSet::from(["boot", "config"]));
+        assert_eq!(tags.tags("/boot/grub/grub.cfg"), BTreeSet::from(["boot", "vendor"]));
+        assert!(tags.tags("/etcetera/x").is_empty());
+        assert!(tags.has("/usr/lib/modules/vmlinuz", "boot"));
+        assert_eq!(tags.scope("boot"), ["/boot", "/etc/ssh", "/usr/lib/modules/vmlinuz"]);
+        assert_eq!(tags.scope("config"), ["/etc"]);
+
+        for bad in ["/etc", "=config", "/etc="]{
+            assert!(parse_tag_prefix(bad).is_err(), "Parsed invalid rule {:?}", bad);
+        }
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,631 @@
+//! `--check-archive`: checks the members of a tar archive, plain, gzip or zstd
+//! compressed, against the baseline as it's read, nothing is extracted.
+//!
+//! Member paths become baseline paths by `--map-prefix FROM=TO`, without it
+//! they're taken relative to `/`. What a tar header doesn't carry, the size of
+//! directories, Windows ACLs and streams and whether symlink targets exist, is
+//! taken from the DB entry and not compared. Symlinks the DB stores as the file
+//! they resolve to are resolved within the archive, regular files are kept in
+//! memory for that and for hard links.
+
+use super::error::IntegrityWatcherError;
+use super::exclude::ExcludeSet;
+use super::fileops::{AddFileInfo, CheckDB};
+use super::magic;
+use super::policy::{self, HashMode};
+use super::scan::ScanOptions;
+use super::treehash::TreeHasher;
+use super::types::{self, DirMetadata, EscapedPath, FileMetadata, FileMetadataExt, SymlinkMetadata, MODE_PERMS_MASK};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use tar::EntryType;
+use tracing::{debug, warn};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Members checked at once.
+const BATCH: usize = 1024;
+
+/// Symlinks followed resolving one, like `MAXSYMLINKS` of Linux.
+const MAX_LINK_HOPS: usize = 40;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// `--map-prefix FROM=TO`: members under `FROM` are checked as the entries
+/// under `TO`, the others are left out.
+#[derive(Debug, Clone)]
+pub struct PrefixMap{
+    from: PathBuf,
+    to: PathBuf,
+}
+
+impl Default for PrefixMap {
+    fn default() -> Self {
+        PrefixMap { from: PathBuf::new(), to: PathBuf::from("/") }
+    }
+}
+
+impl FromStr for PrefixMap {
+    type Err = IntegrityWatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || IntegrityWatcherError::InvalidArgument(format!("--map-prefix {s:?}, expected FROM=TO with an absolute TO"));
+        let (from, to) = s.split_once('=').ok_or_else(invalid)?;
+        let to = Path::new(to);
+        if !to.is_absolute(){
+            return Err(invalid());
+        }
+        Ok(PrefixMap{ from: relative(Path::new(from)).ok_or_else(invalid)?, to: to.components().collect() })
+    }
+}
+
+impl PrefixMap {
+    /// Baseline path of the member `name`, `None` outside `FROM`.
+    pub fn map(&self, name: &Path) -> Option<String> {
+        let rest = relative(name)?;
+        let rest = rest.strip_prefix(&self.from).ok()?;
+        let path = match rest.as_os_str().is_empty(){
+            true => self.to.clone(),
+            false => self.to.join(rest),
+        };
+        Some(path.to_string_lossy().into_owned())
+    }
+
+    /// `TO`, the removed pass reports only entries under it.
+    pub fn root(&self) -> &Path {
+        &self.to
+    }
+}
+
+/// `path` without `.`, root and trailing `/`, `None` when it climbs out with `..`.
+fn relative(path: &Path) -> Option<PathBuf> {
+    let mut rel = PathBuf::new();
+    for part in path.components(){
+        match part{
+            Component::Normal(part) => rel.push(part),
+            Component::ParentDir => return None,
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {},
+        }
+    }
+    Some(rel)
+}
+
+/// Reads the tar archive at `path`, decompressed when its first bytes tell
+/// gzip or zstd.
+pub fn open(path: &str) -> Result<Box<dyn Read + Send>, IntegrityWatcherError> {
+    let failed = |e| IntegrityWatcherError::IOError { source: e, path: path.to_owned() };
+    let mut reader = BufReader::new(std::fs::File::open(path).map_err(failed)?);
+    Ok(match magic::detect(reader.fill_buf().map_err(failed)?){
+        "application/gzip" => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        "application/zstd" => Box::new(zstd::Decoder::with_buffer(reader).map_err(failed)?),
+        _ => Box::new(reader),
+    })
+}
+
+/// Hashes of a member's content the way `scan::hash_file` hashes a file, fed
+/// as the archive is read.
+struct ContentHash{
+    sha256: Option<Sha256>,
+    tree: Option<TreeHasher>,
+    xxh3: Option<Xxh3>,
+    /// bytes to hash, `policy::HEAD_HASH_LEN` for `head-hash`
+    limit: u64,
+    hashed: u64,
+    /// first bytes for the content type
+    head: Option<Vec<u8>>,
+}
+
+impl ContentHash {
+    fn new(mode: HashMode, opts: &ScanOptions) -> Self {
+        let (sha256, xxh3, limit) = match mode{
+            HashMode::StatOnly | HashMode::Skip => (false, false, 0),
+            HashMode::HeadHash => (true, false, policy::HEAD_HASH_LEN),
+            HashMode::Full => (!opts.prefilter || opts.verify_matches, opts.prefilter, u64::MAX),
+        };
+        let tree = opts.stripe_size.filter(|_| mode == HashMode::Full).map(TreeHasher::new);
+        ContentHash{ sha256: (sha256 && tree.is_none()).then(Sha256::new), tree, xxh3: xxh3.then(Xxh3::new), limit, hashed: 0, head: opts.detect_type.then(Vec::new) }
+    }
+
+    /// Whether the rest of the content changes nothing.
+    fn is_done(&self) -> bool {
+        self.hashed >= self.limit && self.head.as_ref().is_none_or(|h| h.len() >= magic::HEAD_LEN)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        if let Some(head) = &mut self.head && head.len() < magic::HEAD_LEN{
+            head.extend_from_slice(&data[..data.len().min(magic::HEAD_LEN - head.len())]);
+        }
+        let data = &data[..data.len().min(usize::try_from(self.limit - self.hashed).unwrap_or(usize::MAX))];
+        if let Some(hasher) = &mut self.sha256{
+            hasher.update(data);
+        }
+        if let Some(hasher) = &mut self.xxh3{
+            hasher.update(data);
+        }
+        if let Some(hasher) = &mut self.tree{
+            hasher.update(data);
+        }
+        self.hashed += data.len() as u64;
+    }
+
+    fn read_from(&mut self, mut content: impl Read) -> io::Result<()> {
+        let mut buffer = [0u8; 65536];
+        while !self.is_done(){
+            let n = content.read(&mut buffer)?;
+            if n == 0 { break; }
+            self.update(&buffer[..n]);
+        }
+        Ok(())
+    }
+
+    /// The content hash, the SHA-256 next to an XXH3 one and the content type.
+    fn finish(self) -> (types::Hash, Option<types::Hash>, Option<String>) {
+        let content_type = self.head.map(|head| magic::detect(&head).to_owned());
+        if let Some(tree) = self.tree{
+            return (tree.finish(), None, content_type);
+        }
+        let sha256 = self.sha256.map(|hasher| -> types::Hash {
+            let result: [u8; 32] = hasher.finalize().into();
+            result.into()
+        });
+        let (hash, strong) = match (self.xxh3, sha256){
+            (Some(fast), strong) => (fast.digest().to_be_bytes().into(), strong),
+            (None, Some(hash)) => (hash, None),
+            (None, None) => (types::Hash::none(), None),
+        };
+        (hash, strong, content_type)
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_owned())
+}
+
+/// Content of a PAX 1.0 sparse member (`tar --sparse --format=posix`): a map
+/// of the data chunks in front of them, the holes between them read as zeros.
+struct PaxSparse<R>{
+    data: R,
+    /// offset and length of the chunks left
+    chunks: VecDeque<(u64, u64)>,
+    pos: u64,
+    size: u64,
+}
+
+impl<R: Read> PaxSparse<R> {
+    fn new(mut data: R, size: u64) -> io::Result<Self> {
+        let mut read = 0;
+        let count = sparse_number(&mut data, &mut read)?;
+        let mut chunks = VecDeque::new();
+        let mut end = 0;
+        for _ in 0..count{
+            let offset = sparse_number(&mut data, &mut read)?;
+            let len = sparse_number(&mut data, &mut read)?;
+            if offset < end || offset.checked_add(len).is_none_or(|e| e > size){
+                return Err(invalid("sparse map chunks overlap or end past the file"));
+            }
+            end = offset + len;
+            chunks.push_back((offset, len));
+        }
+        // the map is padded to a whole block
+        io::copy(&mut (&mut data).take((512 - read % 512) % 512), &mut io::sink())?;
+        Ok(PaxSparse{ data, chunks, pos: 0, size })
+    }
+}
+
+/// A newline terminated decimal of a PAX sparse map.
+fn sparse_number(data: &mut impl Read, read: &mut u64) -> io::Result<u64> {
+    let mut digits = String::new();
+    loop{
+        let mut byte = [0u8];
+        data.read_exact(&mut byte)?;
+        *read += 1;
+        match byte[0]{
+            b'\n' => break,
+            b @ b'0'..=b'9' if digits.len() < 20 => digits.push(b.into()),
+            _ => return Err(invalid("sparse map is no list of numbers")),
+        }
+    }
+    digits.parse().map_err(|_| invalid("sparse map is no list of numbers"))
+}
+
+impl<R: Read> Read for PaxSparse<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(&(offset, len)) = self.chunks.front() && self.pos >= offset + len{
+            self.chunks.pop_front();
+        }
+        if self.pos >= self.size || buf.is_empty(){
+            return Ok(0);
+        }
+        let n = match self.chunks.front(){
+            Some(&(offset, len)) if self.pos >= offset => {
+                let left = usize::try_from(offset + len - self.pos).unwrap_or(usize::MAX);
+                let len = buf.len().min(left);
+                let n = self.data.read(&mut buf[..len])?;
+                if n == 0{
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                n
+            }
+            next => {
+                let hole = usize::try_from(next.map_or(self.size, |c| c.0) - self.pos).unwrap_or(usize::MAX);
+                let n = buf.len().min(hole);
+                buf[..n].fill(0);
+                n
+            }
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// State of one archive check.
+struct ArchiveCheck<'a>{
+    map: &'a PrefixMap,
+    exclude: &'a ExcludeSet,
+    opts: &'a ScanOptions,
+    /// regular files by baseline path, for hard links and symlinks to them
+    files: HashMap<String, FileMetadata>,
+    /// symlink targets by baseline path
+    links: HashMap<String, String>,
+    batch: Vec<(String, FileMetadataExt)>,
+    /// symlinks the DB stores as the file they resolve to, with that entry
+    deferred: Vec<(String, FileMetadataExt)>,
+}
+
+impl ArchiveCheck<'_> {
+    fn member<R: Read>(&mut self, mut member: tar::Entry<'_, R>) -> io::Result<()> {
+        let header = member.header();
+        let entry_type = header.entry_type();
+        let mode = header.mode()? & MODE_PERMS_MASK;
+        let modified = header.mtime()?;
+        let mut name = member.path()?.into_owned();
+        let mut sparse_size = None;
+        if let Some(pax) = member.pax_extensions()?{
+            let pax: HashMap<_, _> = pax.filter_map(|e| e.ok())
+                .filter_map(|e| Some((e.key().ok()?.to_owned(), e.value().ok()?.to_owned())))
+                .collect();
+            if ["GNU.sparse.map", "GNU.sparse.offset", "GNU.sparse.size"].iter().any(|k| pax.contains_key(*k)){
+                return Err(invalid(&format!("{} is in a PAX sparse format before 1.0, which isn't supported", name.display())));
+            }
+            if let (Some(real), Some(size)) = (pax.get("GNU.sparse.name"), pax.get("GNU.sparse.realsize")){
+                name = real.into();
+                sparse_size = Some(size.parse().map_err(|_| invalid("GNU.sparse.realsize is no number"))?);
+            }
+        }
+        let Some(path) = self.map.map(&name) else {
+            debug!("Member {} outside of --map-prefix", EscapedPath(&name.to_string_lossy()));
+            return Ok(());
+        };
+        // like roots, the mapped prefix isn't an entry itself
+        if Path::new(&path) == self.map.root() || self.exclude.is_excluded(&path){
+            return Ok(());
+        }
+        let hash_mode = match &self.opts.policy{
+            Some(policy) if policy.mode(&path) == HashMode::Skip => {
+                debug!("Skipping {} by policy", EscapedPath(&path));
+                return Ok(());
+            }
+            _ if self.opts.stat_only => HashMode::StatOnly,
+            Some(policy) => policy.mode(&path),
+            None => HashMode::Full,
+        };
+
+        let entry = match entry_type{
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                let mut content = ContentHash::new(hash_mode, self.opts);
+                // GNU sparse members are read with their holes by tar already
+                let size = match sparse_size{
+                    Some(size) => {
+                        content.read_from(PaxSparse::new(&mut member, size)?)?;
+                        size
+                    }
+                    None => {
+                        content.read_from(&mut member)?;
+                        member.size()
+                    }
+                };
+                let (hash, strong, content_type) = content.finish();
+                let file = FileMetadata{ hash, permissions: S_IFREG | mode, modified, size: size.into(), ino: None, strong, security: None, streams: None, content_type, created: None, flags: None, owner: None };
+                self.files.insert(path.clone(), file.clone());
+                FileMetadataExt::File(file)
+            }
+            EntryType::Link => {
+                let target = member.link_name()?.and_then(|t| self.map.map(&t));
+                let Some(file) = target.as_ref().and_then(|t| self.files.get(t)) else {
+                    warn!("Hard link {} to a file not in the archive", EscapedPath(&path));
+                    return Ok(());
+                };
+                let file = FileMetadata{ permissions: S_IFREG | mode, modified, ..file.clone() };
+                self.files.insert(path.clone(), file.clone());
+                FileMetadataExt::File(file)
+            }
+            EntryType::Symlink => {
+                let data = member.link_name()?.map(|t| t.to_string_lossy().into_owned()).unwrap_or_default();
+                self.links.insert(path.clone(), data.clone());
+                // whether the target exists isn't known within an archive
+                FileMetadataExt::Symlink(SymlinkMetadata{ size: (data.len() as u64).into(), data, permissions: S_IFLNK | mode, modified, content: None, target_exists: true, target_type: None })
+            }
+            EntryType::Directory if !self.opts.no_dirs => {
+                FileMetadataExt::Dir(DirMetadata{ permissions: S_IFDIR | mode, modified, size: 0, security: None, entries: None, flags: None, owner: None })
+            }
+            EntryType::Directory | EntryType::XGlobalHeader => return Ok(()),
+            _ => {
+                warn!("Path {} unsuported type", EscapedPath(&path));
+                return Ok(());
+            }
+        };
+        self.batch.push((path, entry));
+        Ok(())
+    }
+
+    /// Checks the members read so far.
+    fn flush(&mut self, finfo: &mut CheckDB<'_>) -> Result<(), IntegrityWatcherError> {
+        let batch = std::mem::take(&mut self.batch);
+        let stored = finfo.stored(batch.iter().map(|(path, _)| path.as_str()))?;
+        let mut checked = Vec::with_capacity(batch.len());
+        for ((path, entry), stored) in batch.into_iter().zip(stored){
+            match (entry, stored){
+                // the file it resolves to may come later in the archive
+                (FileMetadataExt::Symlink(_), Some(stored @ FileMetadataExt::File(_))) if !self.opts.hash_link_content => {
+                    self.deferred.push((path, stored));
+                }
+                (entry, stored) => checked.push((path, complete(entry, stored.as_ref()))),
+            }
+        }
+        finfo.add_file_info(&checked)
+    }
+
+    /// Checks the rest, returns the number of symlinks not resolved within the
+    /// archive, which are left as they are in the DB.
+    fn finish(mut self, finfo: &mut CheckDB<'_>) -> Result<u64, IntegrityWatcherError> {
+        self.flush(finfo)?;
+        let mut unresolved = 0;
+        for links in std::mem::take(&mut self.deferred).chunks(BATCH){
+            let checked: Vec<_> = links.iter().map(|(path, stored)| {
+                let entry = match self.resolve(path){
+                    Some(file) => complete(FileMetadataExt::File(file.clone()), Some(stored)),
+                    None => {
+                        debug!("Symlink {} doesn't resolve within the archive", EscapedPath(path));
+                        unresolved += 1;
+                        stored.clone()
+                    }
+                };
+                (path.clone(), entry)
+            }).collect();
+            finfo.add_file_info(&checked)?;
+        }
+        Ok(unresolved)
+    }
+
+    /// The file the symlink at `path` resolves to, resolved lexically.
+    fn resolve(&self, path: &str) -> Option<&FileMetadata> {
+        let mut path = PathBuf::from(path);
+        for _ in 0..MAX_LINK_HOPS{
+            let target = self.links.get(path.to_string_lossy().as_ref())?;
+            let mut resolved = PathBuf::new();
+            for part in path.parent()?.join(target).components(){
+                match part{
+                    Component::CurDir => {},
+                    Component::ParentDir => { resolved.pop(); },
+                    part => resolved.push(part),
+                }
+            }
+            if let Some(file) = self.files.get(resolved.to_string_lossy().as_ref()){
+                return Some(file);
+            }
+            path = resolved;
+        }
+        None
+    }
+}
+
+/// `entry` with what an archive doesn't tell taken from the DB entry `stored`.
+fn complete(entry: FileMetadataExt, stored: Option<&FileMetadataExt>) -> FileMetadataExt {
+    match (entry, stored){
+        (FileMetadataExt::File(file), Some(FileMetadataExt::File(old))) => {
+            FileMetadataExt::File(FileMetadata{ security: old.security.clone(), streams: old.streams.clone(), ..file })
+        }
+        (FileMetadataExt::Dir(dir), Some(FileMetadataExt::Dir(old))) => {
+            FileMetadataExt::Dir(DirMetadata{ size: old.size, security: old.security.clone(), ..dir })
+        }
+        (FileMetadataExt::Symlink(link), Some(FileMetadataExt::Symlink(old))) => {
+            FileMetadataExt::Symlink(SymlinkMetadata{ content: old.content.clone(), target_exists: old.target_exists, target_type: old.target_type, ..link })
+        }
+        (entry, _) => entry,
+    }
+}
+
+/// Checks the members of `archive` under the `map` prefix with `finfo`, the
+/// caller runs the removed pass. `name` is the archive as errors mention it.
+/// Returns the number of symlinks whose target isn't in the archive, those
+/// weren't compared.
+pub fn check_archive(archive: impl Read, name: &str, map: &PrefixMap, exclude: &ExcludeSet, opts: &ScanOptions, finfo: &mut CheckDB<'_>) -> Result<u64, IntegrityWatcherError> {
+    if opts.hash_command.is_some(){
+        return Err(IntegrityWatcherError::InvalidArgument("--check-archive can't hash with the --hash-command of the DB".to_owned()));
+    }
+    let failed = |e| IntegrityWatcherError::IOError { source: e, path: name.to_owned() };
+    let mut archive = tar::Archive::new(archive);
+    let mut check = ArchiveCheck{ map, exclude, opts, files: HashMap::new(), links: HashMap::new(), batch: Vec::new(), deferred: Vec::new() };
+    for member in archive.entries().map_err(failed)?{
+        check.member(member.map_err(failed)?).map_err(failed)?;
+        if check.batch.len() >= BATCH{
+            check.flush(finfo)?;
+        }
+        if finfo.should_stop(){
+            return Ok(0);
+        }
+    }
+    check.finish(finfo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::{CheckOptions, FindingKind, WriteToDB};
+    use redb::Database;
+    use std::io::Write;
+    use tar::{Builder, Header};
+
+    const SPARSE_SIZE: u64 = 1 << 20;
+
+    fn sha256(data: &[u8]) -> types::Hash {
+        let hash: [u8; 32] = Sha256::digest(data).into();
+        hash.into()
+    }
+
+    fn file(data: &[u8]) -> FileMetadataExt {
+        FileMetadataExt::File(FileMetadata{ hash: sha256(data), permissions: 0o100644, modified: 1000, size: (data.len() as u64).into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None })
+    }
+
+    fn header(entry_type: EntryType, path: &str, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_path(path).unwrap();
+        header.set_mode(if entry_type == EntryType::Directory { 0o755 } else { 0o644 });
+        header.set_mtime(1000);
+        header.set_size(size);
+        header
+    }
+
+    fn append(tar: &mut Builder<Vec<u8>>, mut header: Header, data: &[u8]) {
+        header.set_cksum();
+        tar.append(&header, data).unwrap();
+    }
+
+    fn link(tar: &mut Builder<Vec<u8>>, entry_type: EntryType, path: &str, target: &str) {
+        let mut header = header(entry_type, path, 0);
+        header.set_link_name(target).unwrap();
+        append(tar, header, &[]);
+    }
+
+    /// 512 bytes of `a` at 4 KiB in a file of `SPARSE_SIZE`.
+    fn sparse_content() -> Vec<u8> {
+        let mut content = vec![0; SPARSE_SIZE as usize];
+        content[4096..4608].fill(b'a');
+        content
+    }
+
+    fn gnu_sparse(tar: &mut Builder<Vec<u8>>, path: &str) {
+        let mut header = header(EntryType::GNUSparse, path, 512);
+        let gnu = header.as_gnu_mut().unwrap();
+        gnu.sparse[0].set_offset(4096);
+        gnu.sparse[0].set_length(512);
+        gnu.sparse[1].set_offset(SPARSE_SIZE);
+        gnu.sparse[1].set_length(0);
+        gnu.set_real_size(SPARSE_SIZE);
+        append(tar, header, &[b'a'; 512]);
+    }
+
+    fn pax_sparse(tar: &mut Builder<Vec<u8>>, path: &str) {
+        let mut records = Vec::new();
+        for (key, value) in [("GNU.sparse.major", "1".to_owned()), ("GNU.sparse.minor", "0".to_owned()), ("GNU.sparse.name", path.to_owned()), ("GNU.sparse.realsize", SPARSE_SIZE.to_string())]{
+            let record = format!(" {key}={value}\n");
+            // the length counts itself
+            let len = record.len() + (record.len() + 2).to_string().len();
+            records.extend(format!("{len}{record}").bytes());
+        }
+        append(tar, header(EntryType::XHeader, "PaxHeaders/sparse", records.len() as u64), &records);
+        let mut data = format!("2\n4096\n512\n{SPARSE_SIZE}\n0\n").into_bytes();
+        data.resize(512, 0);
+        data.extend([b'a'; 512]);
+        append(tar, header(EntryType::Regular, "app/GNUSparseFile.0/sparse", data.len() as u64), &data);
+    }
+
+    fn archive(motd: &[u8], with_sparse: bool) -> Vec<u8> {
+        let mut tar = Builder::new(Vec::new());
+        append(&mut tar, header(EntryType::Directory, "app/", 0), &[]);
+        append(&mut tar, header(EntryType::Directory, "app/sub/", 0), &[]);
+        append(&mut tar, header(EntryType::Regular, "app/motd", motd.len() as u64), motd);
+        link(&mut tar, EntryType::Link, "app/sub/hard", "app/motd");
+        // resolved from before its target and through another link
+        link(&mut tar, EntryType::Symlink, "app/current", "sub/issue");
+        link(&mut tar, EntryType::Symlink, "app/sub/issue", "../motd");
+        link(&mut tar, EntryType::Symlink, "app/true", "/usr/bin/true");
+        link(&mut tar, EntryType::Symlink, "app/dangling", "gone");
+        if with_sparse{
+            gnu_sparse(&mut tar, "app/gnu-sparse");
+            pax_sparse(&mut tar, "app/pax-sparse");
+        }
+        else{
+            append(&mut tar, header(EntryType::Regular, "app/new", 1), b"x");
+        }
+        append(&mut tar, header(EntryType::Regular, "other/outside", 1), b"x");
+        tar.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_check_archive() {
+        let path = std::env::current_dir().unwrap().join("test_db_archive.redb");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::create(&path).unwrap();
+        let motd = b"welcome\n";
+        WriteToDB::new(&db).add_file_info(&[
+            ("/srv/app/sub".to_owned(), FileMetadataExt::Dir(DirMetadata{ permissions: 0o40755, modified: 1000, size: 4096, entries: Some(1), security: None, flags: None, owner: None })),
+            ("/srv/app/motd".to_owned(), file(motd)),
+            ("/srv/app/sub/hard".to_owned(), file(motd)),
+            ("/srv/app/current".to_owned(), file(motd)),
+            ("/srv/app/sub/issue".to_owned(), file(motd)),
+            ("/srv/app/true".to_owned(), file(b"\x7fELF")),
+            ("/srv/app/dangling".to_owned(), FileMetadataExt::Symlink(SymlinkMetadata{ data: "gone".to_owned(), permissions: 0o120644, modified: 1000, size: 4.into(), content: None, target_exists: false, target_type: None })),
+            ("/srv/app/gnu-sparse".to_owned(), file(&sparse_content())),
+            ("/srv/app/pax-sparse".to_owned(), file(&sparse_content())),
+            ("/etc/passwd".to_owned(), file(b"root")),
+        ]).unwrap();
+        let map: PrefixMap = "./app=/srv/app/".parse().unwrap();
+        let check = |archive: &[u8]| {
+            let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+            let unresolved = check_archive(archive, "test.tar", &map, &ExcludeSet::default(), &ScanOptions::default(), &mut checker).unwrap();
+            checker.report_removed(|path| !Path::new(path).starts_with(map.root())).unwrap();
+            let mut found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.kind)).collect();
+            found.sort();
+            (checker.get_counter(), unresolved, found)
+        };
+
+        // sparse members hash as their full content, the dir size isn't in the archive
+        assert_eq!(check(&archive(motd, true)), (9, 1, vec![]));
+
+        let (_, _, found) = check(&archive(b"hacked\n", false));
+        assert_eq!(found, [
+            ("/srv/app/current".to_owned(), FindingKind::Changed),
+            ("/srv/app/gnu-sparse".to_owned(), FindingKind::Removed),
+            ("/srv/app/motd".to_owned(), FindingKind::Changed),
+            ("/srv/app/new".to_owned(), FindingKind::New),
+            ("/srv/app/pax-sparse".to_owned(), FindingKind::Removed),
+            ("/srv/app/sub/hard".to_owned(), FindingKind::Changed),
+            ("/srv/app/sub/issue".to_owned(), FindingKind::Changed),
+        ]);
+
+        // compressed archives are told by their first bytes
+        for (name, compressed) in [
+            ("test_tree_archive.tar.gz", { let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()); gz.write_all(&archive(motd, true)).unwrap(); gz.finish().unwrap() }),
+            ("test_tree_archive.tar.zst", zstd::encode_all(archive(motd, true).as_slice(), 3).unwrap()),
+        ]{
+            std::fs::write(name, compressed).unwrap();
+            let mut tar = Vec::new();
+            open(name).unwrap().read_to_end(&mut tar).unwrap();
+            assert_eq!(check(&tar).2, vec![]);
+            std::fs::remove_file(name).unwrap();
+        }
+
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_prefix_map() {
+        let map = PrefixMap::default();
+        assert_eq!(map.map(Path::new("./etc/passwd")).as_deref(), Some("/etc/passwd"));
+        assert_eq!(map.map(Path::new("etc/")).as_deref(), Some("/etc"));
+        assert_eq!(map.map(Path::new("../etc/passwd")), None);
+        let map: PrefixMap = "backup/=/srv".parse().unwrap();
+        assert_eq!(map.map(Path::new("./backup/app/x")).as_deref(), Some("/srv/app/x"));
+        assert_eq!(map.map(Path::new("backup")).as_deref(), Some("/srv"));
+        assert_eq!(map.map(Path::new("other/x")), None);
+        assert!("backup".parse::<PrefixMap>().is_err());
+        assert!("backup=srv".parse::<PrefixMap>().is_err());
+    }
+}
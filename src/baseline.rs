@@ -0,0 +1,1159 @@
+use super::annotations;
+use super::dbfile::{self, DbFile};
+use super::dbmeta::{self, DBSettings};
+use super::error::IntegrityWatcherError;
+use super::exclude::ExcludeSet;
+use super::fileops::{self, files_table, AddFileInfo, CheckDB, CheckOptions, Finding, WriteToDB, DEFAULT_TABLE};
+use super::pkgverify;
+use super::policy::{HashMode, Policy};
+use super::scan::{get_file_hash, resolve_key, visit_dirs, with_scan_timeout, ScanOptions};
+use super::types::{self, EscapedPath, FileMetadataExt};
+use super::runs;
+use redb::{Database, Key, MultimapTableDefinition, ReadTransaction, ReadableDatabase, ReadableMultimapTable, ReadableTable, TableDefinition, TableHandle, Value, WriteTransaction};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{error, info, warn, Instrument};
+
+/// `--rehash`: recomputes the digest of every file in the DB with the algorithm
+/// of `opts`. Only files whose size, mtime and inode still match their entry can
+/// be trusted to hold the baseline content; the others are returned separately.
+pub async fn rehash_entries(db: &Database, table: &str, opts: &ScanOptions) -> Result<(Vec<(String, types::Hash)>, Vec<String>), IntegrityWatcherError> {
+    const CHUNK: usize = 1024;
+    let mut entries = Vec::new();
+    {
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(files_table(table))?;
+        for k in table.iter()?{
+            let k = k?;
+            if let FileMetadataExt::File(file) = k.1.value(){
+                entries.push((k.0.value(), file));
+            }
+        }
+    }
+
+    let mut migrated = Vec::with_capacity(entries.len());
+    let mut failed = Vec::new();
+    for chunk in entries.chunks(CHUNK){
+        let mut files = JoinSet::new();
+        for (path_str, old) in chunk{
+            let (path_str, old, opts) = (path_str.to_owned(), old.clone(), opts.clone());
+            files.spawn(async move {
+                // keys folded by --path-fold are looked up on disk
+                let new = match resolve_key(opts.path_fold, &path_str).await{
+                    Ok(Some(path)) => get_file_hash(path, &opts).await,
+                    Ok(None) => Err(IntegrityWatcherError::IOError{ source: io::ErrorKind::NotFound.into(), path: path_str.clone() }),
+                    Err(source) => Err(IntegrityWatcherError::IOError{ source, path: path_str.clone() }),
+                };
+                (path_str, old, new)
+            }.in_current_span());
+        }
+        for (path_str, old, new) in files.join_all().await{
+            match new{
+                Ok(new) if new.size == old.size && new.modified == old.modified && (old.ino.is_none() || new.ino == old.ino) => {
+                    migrated.push((path_str, new.hash));
+                }
+                Ok(_) => {
+                    warn!("Cannot migrate {}, content differs from the baseline", EscapedPath(&path_str));
+                    failed.push(path_str);
+                }
+                Err(e) => {
+                    error!("Cannot migrate {}: {e}", EscapedPath(&path_str));
+                    failed.push(path_str);
+                }
+            }
+        }
+    }
+    migrated.sort_by(|a, b| a.0.cmp(&b.0));
+    failed.sort();
+    Ok((migrated, failed))
+}
+
+/// `--verify-against-package-manager` on dpkg systems: compares the MD5 of every
+/// DB file owned by a package with the one the package shipped. Returns how many
+/// files were verified and the ones that differ with a message.
+pub async fn verify_dpkg(db: &Database, table: &str, packages: &HashMap<String, pkgverify::PackageFile>, opts: &ScanOptions) -> Result<(u64, Vec<(String, String)>), IntegrityWatcherError> {
+    const CHUNK: usize = 1024;
+    // package paths are real ones, keys may be folded by --path-fold
+    let by_key: HashMap<_, _> = packages.iter().map(|(path, pkg)| (opts.path_fold.fold(path), (path, pkg))).collect();
+    let mut owned = Vec::new();
+    {
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(files_table(table))?;
+        for k in table.iter()?{
+            let k = k?;
+            if let FileMetadataExt::File(file) = k.1.value()
+            && let Some((path, pkg)) = by_key.get(k.0.value().as_str()){
+                owned.push((path.to_string(), file, (*pkg).clone()));
+            }
+        }
+    }
+
+    let mut verified = 0;
+    let mut differing = Vec::new();
+    for chunk in owned.chunks(CHUNK){
+        let mut files = JoinSet::new();
+        for (path_str, old, pkg) in chunk{
+            let (path_str, old, pkg, opts) = (path_str.to_owned(), old.clone(), pkg.clone(), opts.clone());
+            files.spawn(async move {
+                let current = get_file_hash(PathBuf::from(&path_str), &opts).await;
+                (path_str, old, pkg, current)
+            }.in_current_span());
+        }
+        for (path_str, old, pkg, current) in files.join_all().await{
+            let current = match current{
+                Ok(c) => c,
+                Err(e) => {
+                    error!("{e}");
+                    continue;
+                }
+            };
+            verified += 1;
+            if current.size != old.size || current.modified != old.modified{
+                warn!("File {} changed since the baseline, verifying its current content", EscapedPath(&path_str));
+            }
+            if current.hash != pkg.md5{
+                differing.push((path_str, format!("differs from package {}: md5 {} expected {}", pkg.package, current.hash, pkg.md5)));
+            }
+        }
+    }
+    differing.sort();
+    Ok((verified, differing))
+}
+
+/// `--db-verify`: checks that every file entry carries a hash of the length the
+/// algorithm of the DB gives. A digest cut short by corruption or a bug would
+/// have the file reported changed by every check. Files a `--policy`
+/// stat-tracks carry none, those it head-hashes a SHA-256, and `--hash-command`
+/// digests, which can have any length, are expected to be as long as most of
+/// them are. Returns how many hashes were checked and the entries with a wrong
+/// one with a message.
+pub fn verify_hash_lengths(db: &Database, table: &str, settings: &DBSettings) -> Result<(u64, Vec<(String, String)>), IntegrityWatcherError> {
+    const SHA256_LEN: usize = 32;
+    let policy = settings.policy.as_deref().map(str::parse::<Policy>).transpose()?;
+    let algorithm = settings.algorithm();
+    // path, what was hashed with which algorithm, its length and the expected one
+    let mut hashes = Vec::new();
+    {
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(files_table(table))?;
+        for k in table.iter()?{
+            let k = k?;
+            match k.1.value(){
+                FileMetadataExt::File(file) => {
+                    let expected = match policy.as_ref().map(|p| p.mode(&k.0.value())){
+                        _ if settings.stat_only => Some(0),
+                        Some(HashMode::StatOnly) => Some(0),
+                        Some(HashMode::HeadHash) => Some(SHA256_LEN),
+                        _ => settings.hash_len(),
+                    };
+                    let what = if expected == Some(SHA256_LEN) && settings.hash_len() != expected { "SHA-256" } else { &algorithm };
+                    hashes.push((k.0.value(), format!("hash ({what})"), file.hash.byte_len(), expected));
+                    if let Some(strong) = file.strong{
+                        hashes.push((k.0.value(), "--verify-matches SHA-256".to_owned(), strong.byte_len(), Some(SHA256_LEN)));
+                    }
+                }
+                FileMetadataExt::Symlink(types::SymlinkMetadata{ content: Some(content), .. }) => {
+                    hashes.push((k.0.value(), "target SHA-256".to_owned(), content.byte_len(), Some(SHA256_LEN)));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // the length most --hash-command digests have
+    let mut counts: HashMap<usize, u64> = HashMap::new();
+    for (_, _, len, expected) in &hashes{
+        if expected.is_none() && *len > 0{
+            *counts.entry(*len).or_default() += 1;
+        }
+    }
+    let common = counts.into_iter().max_by_key(|&(len, n)| (n, std::cmp::Reverse(len))).map(|(len, _)| len);
+
+    let mut mismatched = Vec::new();
+    for (path, what, len, expected) in &hashes{
+        let Some(expected) = expected.or(common) else {
+            continue;
+        };
+        if *len != expected{
+            mismatched.push((path.clone(), format!("{what} is {len} bytes, expected {expected}")));
+        }
+    }
+    mismatched.sort();
+    Ok((hashes.len() as u64, mismatched))
+}
+
+/// Stores the rehashed digests and the new algorithm in one transaction.
+pub fn apply_rehash(db: &Database, table: &str, migrated: &[(String, types::Hash)], settings: &DBSettings) -> Result<(), IntegrityWatcherError> {
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    {
+        let mut table = write_txn.open_table(files_table(table))?;
+        for (path, hash) in migrated{
+            let entry = table.get(path)?.map(|v| v.value());
+            if let Some(FileMetadataExt::File(mut file)) = entry{
+                file.hash = hash.clone();
+                file.strong = None;
+                table.insert(path, FileMetadataExt::File(file))?;
+            }
+        }
+    }
+    settings.store_in(&write_txn, table)?;
+    write_txn.commit()?;
+    Ok(())
+}
+
+/// `--prune`: removes the entries matching `pattern`, whatever is on disk, with
+/// their annotations in one transaction. With `dry_run` nothing is removed.
+/// Returns the matching paths.
+pub fn prune_entries(db: &Database, table: &str, pattern: &ExcludeSet, dry_run: bool) -> Result<Vec<String>, IntegrityWatcherError> {
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    let mut pruned = Vec::new();
+    {
+        let mut entries = write_txn.open_table(files_table(table))?;
+        for k in entries.iter()?{
+            let path = k?.0.value();
+            if pattern.is_excluded(&path){
+                pruned.push(path);
+            }
+        }
+        if dry_run{
+            return Ok(pruned);
+        }
+        for path in &pruned{
+            entries.remove(path)?;
+            if let Some(a) = annotations::remove_in(&write_txn, table, path)?{
+                warn!("Removed annotations of {} with the entry: {a}", EscapedPath(path));
+            }
+        }
+    }
+    write_txn.commit()?;
+    Ok(pruned)
+}
+
+/// Builds the DB in `tmp_path` and renames it over `db_path` only after `build`
+/// succeeded, so `db_path` is always either the previous or a complete baseline.
+pub async fn create_db_atomic<T, F>(db_path: &str, tmp_path: &str, overwrite: bool, build: F) -> Result<T, IntegrityWatcherError>
+    where F: AsyncFnOnce(&Database) -> Result<T, IntegrityWatcherError> {
+    if !overwrite && fs::try_exists(db_path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: db_path.to_owned() })?{
+        error!("database {} already exists", EscapedPath(db_path));
+        return Err(IntegrityWatcherError::IOError { source: io::Error::new(io::ErrorKind::AlreadyExists, "Already exists".to_owned()), path: db_path.to_owned()});
+    }
+    // leftover of an interrupted run
+    if let Err(e) = fs::remove_file(tmp_path).await
+    && e.kind() != io::ErrorKind::NotFound{
+        return Err(IntegrityWatcherError::IOError { source: e, path: tmp_path.to_owned() });
+    }
+
+    let db = dbfile::create(tmp_path)?;
+    finish_atomic(db, db_path, tmp_path, build).await
+}
+
+/// `--create --table` and `--merge` on an existing DB: builds the new
+/// baselines `tables` in a copy of `db_path` that replaces it only after `build`
+/// succeeded. The baselines already in the DB are kept, existing ones of
+/// `tables` are only replaced with `overwrite`.
+pub async fn add_table_atomic<T, F>(db_path: &str, tmp_path: &str, tables: &[&str], overwrite: bool, build: F) -> Result<T, IntegrityWatcherError>
+    where F: AsyncFnOnce(&Database) -> Result<T, IntegrityWatcherError> {
+    fs::copy(db_path, tmp_path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: tmp_path.to_owned() })?;
+    let db = dbfile::open(tmp_path)?;
+    let mut existing = Vec::new();
+    {
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        for table in tables{
+            match read_txn.open_table(files_table(table)){
+                Ok(_) => existing.push(*table),
+                Err(redb::TableError::TableDoesNotExist(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    if let (Some(table), false) = (existing.first(), overwrite){
+        drop(db);
+        if let Err(e) = fs::remove_file(tmp_path).await{
+            warn!("Can't remove {}: {e}", EscapedPath(tmp_path));
+        }
+        error!("table {table} already exists in database {}", EscapedPath(db_path));
+        return Err(IntegrityWatcherError::IOError { source: io::Error::new(io::ErrorKind::AlreadyExists, format!("Table {table} already exists")), path: db_path.to_owned()});
+    }
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    for table in tables{
+        if existing.contains(table){
+            write_txn.delete_table(files_table(table))?;
+        }
+        // also the leftovers of a baseline without entries, e.g. its volatile
+        // marks, which would apply to the new one
+        delete_siblings(&write_txn, table)?;
+    }
+    write_txn.commit()?;
+    finish_atomic(db, db_path, tmp_path, build).await
+}
+
+/// Baselines of `src` that `--merge` copies into a shared DB, as
+/// `(source table, host)`: every `--host-prefix` namespace of `table` and
+/// `table` itself when it records its host. `host` restricts the choice to
+/// that host, taking `table` as its entries when there is no namespace of it.
+pub fn merge_sources(src: &Database, table: &str, host: Option<&str>) -> Result<Vec<(String, String)>, IntegrityWatcherError> {
+    let read_txn = src.begin_read().map_err(Box::new)?;
+    let prefix = fileops::host_table_name(table, "");
+    let mut sources = Vec::new();
+    for t in read_txn.list_tables()?{
+        let name = t.name();
+        match name.strip_prefix(&prefix){
+            // `{table}@web01_runs` is the run history of web01, not host web01_runs
+            Some(h) if !fileops::is_sibling_table(h) && fileops::validate_host_prefix(h).is_ok() => sources.push((name.to_owned(), h.to_owned())),
+            _ => {}
+        }
+    }
+    sources.retain(|(_, h)| host.is_none_or(|host| host == h));
+    drop(read_txn);
+    if sources.is_empty(){
+        match (host, dbmeta::load_host(src, table)?){
+            (Some(host), _) => sources.push((table.to_owned(), host.to_owned())),
+            (None, Some(stored)) => sources.push((table.to_owned(), stored)),
+            (None, None) => {}
+        }
+    }
+    Ok(sources)
+}
+
+/// `--merge`: copies the baseline `src_table` of `src`, with every table kept
+/// next to it, into `dst_table` of `dst` and records `host` as its owner.
+/// Returns the number of entries copied.
+pub fn merge_table(src: &Database, src_table: &str, dst: &Database, dst_table: &str, host: &str) -> Result<u64, IntegrityWatcherError> {
+    let read_txn = src.begin_read().map_err(Box::new)?;
+    let write_txn = dst.begin_write().map_err(Box::new)?;
+    let mut copied = 0;
+    {
+        let from = read_txn.open_table(files_table(src_table))?;
+        let mut to = write_txn.open_table(files_table(dst_table))?;
+        for k in from.iter()?{
+            let k = k?;
+            to.insert(k.0.value(), k.1.value())?;
+            copied += 1;
+        }
+    }
+    for suffix in fileops::SIBLING_SUFFIXES{
+        let (from, to) = (format!("{src_table}{suffix}"), format!("{dst_table}{suffix}"));
+        match suffix{
+            "_meta" => copy_table::<&str, &str>(&read_txn, &write_txn, &from, &to)?,
+            "_annotations" => copy_table::<String, annotations::Annotations>(&read_txn, &write_txn, &from, &to)?,
+            "_tag_prefixes" => copy_multimap_table(&read_txn, &write_txn, &from, &to)?,
+            "_runs" => copy_table::<u64, runs::Run>(&read_txn, &write_txn, &from, &to)?,
+            "_volatile" => copy_table::<String, ()>(&read_txn, &write_txn, &from, &to)?,
+            _ => unreachable!("sibling table {suffix} not copied by --merge"),
+        }
+    }
+    dbmeta::store_host_in(&write_txn, dst_table, host)?;
+    write_txn.commit()?;
+    Ok(copied)
+}
+
+/// Deletes the tables kept next to the entries of `table`, so a baseline
+/// created over it doesn't inherit its settings, run history or marks.
+fn delete_siblings(write_txn: &WriteTransaction, table: &str) -> Result<(), IntegrityWatcherError> {
+    for suffix in fileops::SIBLING_SUFFIXES{
+        let name = format!("{table}{suffix}");
+        // deleting goes by name, the key and value types don't matter
+        match suffix{
+            "_tag_prefixes" => write_txn.delete_multimap_table(MultimapTableDefinition::<String, String>::new(&name))?,
+            _ => write_txn.delete_table(TableDefinition::<&str, &str>::new(&name))?,
+        };
+    }
+    Ok(())
+}
+
+/// Copies table `from` of `read_txn` into `to`, nothing when it doesn't exist.
+fn copy_table<K: Key + 'static, V: Value + 'static>(read_txn: &ReadTransaction, write_txn: &WriteTransaction, from: &str, to: &str) -> Result<(), IntegrityWatcherError> {
+    match read_txn.open_table(TableDefinition::<K, V>::new(from)){
+        Ok(from) => {
+            let mut to = write_txn.open_table(TableDefinition::<K, V>::new(to))?;
+            for k in from.iter()?{
+                let k = k?;
+                to.insert(k.0.value(), k.1.value())?;
+            }
+            Ok(())
+        }
+        Err(redb::TableError::TableDoesNotExist(_)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// [`copy_table`] for the `--tag-prefix` multimap.
+fn copy_multimap_table(read_txn: &ReadTransaction, write_txn: &WriteTransaction, from: &str, to: &str) -> Result<(), IntegrityWatcherError> {
+    match read_txn.open_multimap_table(MultimapTableDefinition::<String, String>::new(from)){
+        Ok(from) => {
+            let mut to = write_txn.open_multimap_table(MultimapTableDefinition::<String, String>::new(to))?;
+            for k in from.iter()?{
+                let (prefix, tags) = k?;
+                for tag in tags{
+                    to.insert(prefix.value(), tag?.value())?;
+                }
+            }
+            Ok(())
+        }
+        Err(redb::TableError::TableDoesNotExist(_)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Compares the baseline `table_b` against `table_a` like `--compare` and
+/// returns the findings: `New` entries exist only in `table_b`, `Removed` ones
+/// only in `table_a`. Used to tell how the same paths differ between two hosts.
+pub fn diff_tables(db_a: &Database, table_a: &str, db_b: &Database, table_b: &str, opts: CheckOptions) -> Result<Vec<Finding>, IntegrityWatcherError> {
+    let mut entries = Vec::new();
+    {
+        let read_txn = db_b.begin_read().map_err(Box::new)?;
+        for k in read_txn.open_table(files_table(table_b))?.iter()?{
+            let k = k?;
+            entries.push((k.0.value(), k.1.value()));
+        }
+    }
+    let mut writer = CheckDB::new(db_a, opts).with_table(table_a).recording().with_finding_sink(|_| {});
+    writer.add_file_info(&entries)?;
+    writer.report_removed(|_| false)?;
+    Ok(writer.take_recorded())
+}
+
+/// Runs `build` on the DB at `tmp_path` and renames it over `db_path`, or
+/// removes it when `build` failed.
+async fn finish_atomic<T, F>(db: DbFile, db_path: &str, tmp_path: &str, build: F) -> Result<T, IntegrityWatcherError>
+    where F: AsyncFnOnce(&Database) -> Result<T, IntegrityWatcherError> {
+    let result = build(&db).await;
+    let result = result.and_then(|r| db.close().map(|_| r));
+    match result{
+        Ok(r) => {
+            fs::rename(tmp_path, db_path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: db_path.to_owned() })?;
+            Ok(r)
+        }
+        Err(e) => {
+            if let Err(e) = fs::remove_file(tmp_path).await{
+                warn!("Can't remove {}: {e}", EscapedPath(tmp_path));
+            }
+            // e.g. a --timeout: the entries scanned so far are gone with the temp DB
+            error!("Discarded the unfinished baseline, nothing was stored in {}", EscapedPath(db_path));
+            Err(e)
+        }
+    }
+}
+
+/// Fails with `NoBaseline` when `db` has no entries table `table`, e.g. an
+/// empty or unrelated redb file, instead of redb's bare table error.
+pub fn ensure_table(db: &Database, db_name: &str, table: &str) -> Result<(), IntegrityWatcherError> {
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    match read_txn.open_table(files_table(table)){
+        Ok(_) => Ok(()),
+        Err(redb::TableError::TableDoesNotExist(_)) => Err(IntegrityWatcherError::NoBaseline { db: db_name.to_owned(), table: table.to_owned() }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Opens the DB at `path` for a command working on baseline `table`.
+pub fn open_baseline(path: &str, table: &str) -> Result<DbFile, IntegrityWatcherError> {
+    let db = dbfile::open(path)?;
+    ensure_table(&db, path, table)?;
+    Ok(db)
+}
+
+/// Fails unless baseline `b` was scanned like `a`, `db_name` being the DB of
+/// `b`. Baselines hashed with different algorithms would differ in every file.
+pub fn ensure_comparable(a: &DBSettings, b: &DBSettings, db_name: &str) -> Result<(), IntegrityWatcherError> {
+    if a.algorithm() != b.algorithm(){
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: format!("hashed with {}, the other baseline with {}, every file would differ", b.algorithm(), a.algorithm()),
+        });
+    }
+    if a != b{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created with different --no-dirs, --track-inode, --path-fold or other scan settings".to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Scan settings stored in the DB win over the command line; asking for
+/// `--no-dirs` on a DB that has directory entries would report all of them removed,
+/// `--track-inode` on a DB without inodes would report every file changed and
+/// a different `--hash-command` would report every file changed and a
+/// different `--max-depth` the entries between both depths new or removed.
+pub fn apply_db_settings(db: &Database, table: &str, db_name: &str, opts: &mut ScanOptions) -> Result<(), IntegrityWatcherError> {
+    let settings = DBSettings::load(db, table)?;
+    if opts.no_dirs && !settings.no_dirs{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created with directory entries, --no-dirs not allowed".to_owned()
+        });
+    }
+    if settings.no_dirs && !opts.no_dirs{
+        info!("Database {} created with --no-dirs, skipping directories", EscapedPath(db_name));
+    }
+    if opts.max_depth.is_some() && opts.max_depth != settings.max_depth{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: match settings.max_depth{
+                Some(depth) => format!("created with --max-depth {depth}, not {}", opts.max_depth.unwrap_or_default()),
+                None => "created without --max-depth, not allowed".to_owned(),
+            },
+        });
+    }
+    if let (Some(depth), None) = (settings.max_depth, opts.max_depth){
+        info!("Database {} created with --max-depth {depth}, not going deeper", EscapedPath(db_name));
+    }
+    if opts.track_inode && !settings.track_inode{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created without inode numbers, --track-inode not allowed".to_owned()
+        });
+    }
+    match (opts.hash_command.as_deref(), settings.hash_command.as_deref()){
+        (Some(asked), stored) if Some(asked) != stored => {
+            return Err(IntegrityWatcherError::IncompatibleDB {
+                db: db_name.to_owned(),
+                reason: format!("created with {}, not --hash-command {asked:?}, every file would be reported changed", settings.algorithm()),
+            });
+        }
+        (None, Some(stored)) => info!("Database {} created with --hash-command {stored:?}", EscapedPath(db_name)),
+        _ => {}
+    }
+    if opts.prefilter && !settings.prefilter{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: format!("created with {}, --prefilter not allowed", settings.algorithm()),
+        });
+    }
+    if opts.verify_matches && !settings.prefilter{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created without --prefilter, --verify-matches not allowed".to_owned()
+        });
+    }
+    if settings.prefilter && !opts.prefilter{
+        info!("Database {} created with --prefilter, comparing XXH3-64 hashes", EscapedPath(db_name));
+    }
+    if opts.hash_link_content && !settings.hash_link_content{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created with symlinks to files stored as files, --hash-link-content not allowed".to_owned()
+        });
+    }
+    if settings.hash_link_content && !opts.hash_link_content{
+        info!("Database {} created with --hash-link-content, comparing symlink target content", EscapedPath(db_name));
+    }
+    if !opts.path_fold.is_exact() && opts.path_fold != settings.path_fold{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: format!("created with --path-fold {}, not {}", settings.path_fold, opts.path_fold),
+        });
+    }
+    if !settings.path_fold.is_exact(){
+        info!("Database {} created with --path-fold {}", EscapedPath(db_name), settings.path_fold);
+    }
+    if opts.detect_type && !settings.detect_type{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created without content types, --detect-type not allowed".to_owned()
+        });
+    }
+    if settings.detect_type && !opts.detect_type{
+        info!("Database {} created with --detect-type, comparing content types", EscapedPath(db_name));
+    }
+    if opts.stat_only && !settings.stat_only{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: format!("created with {}, --stat-only not allowed", settings.algorithm()),
+        });
+    }
+    if opts.stripe_size.is_some() && opts.stripe_size != settings.stripe_size{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: format!("created with {}, not --parallel-file-hash with {} MiB stripes, every file would be reported changed", settings.algorithm(), opts.stripe_size.unwrap_or_default() >> 20),
+        });
+    }
+    if settings.stripe_size.is_some() && opts.stripe_size.is_none(){
+        info!("Database {} created with --parallel-file-hash, comparing {}", EscapedPath(db_name), settings.algorithm());
+    }
+    if opts.inode_flags && !settings.flags{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created without inode flags, --flags not allowed".to_owned()
+        });
+    }
+    if settings.flags && !opts.inode_flags{
+        info!("Database {} created with --flags, comparing immutable and append-only flags", EscapedPath(db_name));
+    }
+    if opts.owner && !settings.owner{
+        return Err(IntegrityWatcherError::IncompatibleDB {
+            db: db_name.to_owned(),
+            reason: "created without owners, --owner not allowed".to_owned()
+        });
+    }
+    if settings.owner && !opts.owner{
+        info!("Database {} created with --owner, comparing owning users and groups", EscapedPath(db_name));
+    }
+    if settings.stat_only && !opts.stat_only{
+        info!("Database {} created with --stat-only, comparing metadata without content", EscapedPath(db_name));
+    }
+    match (opts.policy.as_ref().map(|p| p.to_string()), &settings.policy){
+        (Some(asked), stored) if Some(&asked) != stored.as_ref() => {
+            return Err(IntegrityWatcherError::IncompatibleDB {
+                db: db_name.to_owned(),
+                reason: "created with another --policy, entries would be hashed differently".to_owned(),
+            });
+        }
+        (None, Some(_)) => info!("Database {} created with a --policy, hashing entries by it", EscapedPath(db_name)),
+        _ => {}
+    }
+    opts.no_dirs = settings.no_dirs;
+    opts.max_depth = settings.max_depth;
+    opts.track_inode = settings.track_inode;
+    opts.hash_command = settings.hash_command.map(Into::into);
+    opts.prefilter = settings.prefilter;
+    opts.hash_link_content = settings.hash_link_content;
+    opts.path_fold = settings.path_fold;
+    opts.detect_type = settings.detect_type;
+    opts.stat_only = settings.stat_only;
+    opts.stripe_size = settings.stripe_size;
+    opts.inode_flags = settings.flags;
+    opts.owner = settings.owner;
+    opts.policy = settings.policy.as_deref().map(str::parse::<Policy>).transpose()?.map(Arc::new);
+    Ok(())
+}
+
+/// Finding of `Baseline::check`, or the error that ended the check.
+pub type CheckItem = Result<Finding, IntegrityWatcherError>;
+
+/// A baseline DB used from code instead of through the CLI.
+///
+/// ```
+/// use integrity_checker::baseline::Baseline;
+/// use integrity_checker::fileops::{CheckOptions, FindingKind};
+/// use integrity_checker::scan::ScanOptions;
+/// use integrity_checker::exclude::ExcludeSet;
+/// use tokio_stream::StreamExt;
+///
+/// # #[tokio::main] async fn main() {
+/// let dir = std::env::temp_dir().join("integrity_checker_doc_baseline");
+/// let db_path = dir.with_extension("redb");
+/// # let _ = std::fs::remove_file(&db_path);
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("a.txt"), b"abc").unwrap();
+///
+/// let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+/// let baseline = Baseline::create(&db_path, &[dir.clone()], &ExcludeSet::default(), &opts).await.unwrap();
+/// std::fs::write(dir.join("a.txt"), b"abd").unwrap();
+///
+/// let findings: Vec<_> = baseline.check(vec![dir.clone()], ExcludeSet::default(), opts, CheckOptions::default()).unwrap()
+///     .map(Result::unwrap)
+///     .collect().await;
+/// assert_eq!(findings.len(), 1);
+/// assert_eq!(findings[0].kind, FindingKind::Changed);
+/// assert!(findings[0].path.ends_with("a.txt"));
+/// # std::fs::remove_dir_all(dir).unwrap();
+/// # std::fs::remove_file(db_path).unwrap();
+/// # }
+/// ```
+pub struct Baseline{
+    db: Arc<DbFile>,
+    name: String,
+    table: String,
+}
+
+impl Baseline {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, IntegrityWatcherError> {
+        let name = path.as_ref().to_string_lossy().to_string();
+        Ok(Baseline { db: Arc::new(dbfile::open(path)?), name, table: DEFAULT_TABLE.to_owned() })
+    }
+
+    /// Uses the baseline in entries table `table` of the DB.
+    pub fn with_table(mut self, table: &str) -> Result<Self, IntegrityWatcherError> {
+        crate::fileops::validate_table_name(table)?;
+        self.table = table.to_owned();
+        Ok(self)
+    }
+
+    /// Scans `paths` into a new baseline at `path`, which must not exist yet.
+    /// `path` is only created once the scan succeeded; it has to be outside of
+    /// `paths` or listed in `exclude`, like its `.tmp` sibling.
+    pub async fn create(path: impl AsRef<Path>, paths: &[PathBuf], exclude: &ExcludeSet, opts: &ScanOptions) -> Result<Self, IntegrityWatcherError> {
+        let name = path.as_ref().to_string_lossy().to_string();
+        create_db_atomic(&name, &format!("{name}.tmp"), false, async |db: &Database| {
+            DBSettings{
+                no_dirs: opts.no_dirs,
+                track_inode: opts.track_inode,
+                hash_command: opts.hash_command.as_deref().map(str::to_owned),
+                prefilter: opts.prefilter,
+                hash_link_content: opts.hash_link_content,
+                path_fold: opts.path_fold,
+                detect_type: opts.detect_type,
+                stat_only: opts.stat_only,
+                policy: opts.policy.as_ref().map(|p| p.to_string()),
+                stripe_size: opts.stripe_size,
+                flags: opts.inode_flags,
+                max_depth: opts.max_depth,
+                owner: opts.owner,
+            }.store(db, DEFAULT_TABLE)?;
+            let mut writer = WriteToDB::new(db).with_path_fold(opts.path_fold);
+            with_scan_timeout(opts.timeout, "create", async {
+                for root in paths{
+                    visit_dirs(root.clone(), exclude, opts, &mut writer).await?;
+                }
+                Ok(())
+            }).await?;
+            writer.finish()
+        }).await?;
+        Self::open(path)
+    }
+
+    /// The underlying DB, e.g. for `annotations`.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Checks `paths` against the baseline in the background, streaming changed
+    /// and new entries as the walk finds them, then every DB entry it didn't
+    /// come across as removed. Settings the baseline was created with override
+    /// `opts`, conflicting ones are an error. Dropping the stream stops the check.
+    pub fn check(&self, paths: Vec<PathBuf>, exclude: ExcludeSet, mut opts: ScanOptions, check: CheckOptions) -> Result<impl Stream<Item = CheckItem> + use<>, IntegrityWatcherError> {
+        ensure_table(&self.db, &self.name, &self.table)?;
+        apply_db_settings(&self.db, &self.table, &self.name, &mut opts)?;
+        let annotated = annotations::load_all(&self.db, &self.table)?;
+        let volatile = annotations::load_volatile(&self.db, &self.table)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (db, table) = (self.db.clone(), self.table.clone());
+        tokio::spawn(async move {
+            let findings = tx.clone();
+            let mut checker = CheckDB::new(&db, CheckOptions{ track_inode: opts.track_inode, ..check })
+                .with_table(&table)
+                .with_path_fold(opts.path_fold)
+                .with_annotations(annotated)
+                .with_volatile(volatile)
+                .with_finding_sink(move |f| {
+                    let _ = findings.send(Ok(f));
+                });
+            let walk = with_scan_timeout(opts.timeout, "check", async {
+                for root in paths{
+                    visit_dirs(root, &exclude, &opts, &mut checker).await?;
+                    if checker.should_stop() || tx.is_closed(){
+                        return Ok(());
+                    }
+                }
+                checker.report_removed(|_| false).map(|_| ())
+            }).await;
+            checker.flush_findings();
+            if let Err(e) = walk{
+                let _ = tx.send(Err(e));
+            }
+        }.in_current_span());
+        Ok(UnboundedReceiverStream::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::{FindingKind, TABLE};
+    use crate::types::FileMetadata;
+    use std::collections::BTreeSet;
+    use std::fs;
+    use tokio_stream::StreamExt;
+
+    fn setup_test_tree(name: &str) -> PathBuf {
+        let mut path = std::env::current_dir().unwrap();
+        path.push(format!("test_tree_{}", name));
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(path.join("sub").join("deeper")).unwrap();
+        fs::write(path.join("top.txt"), b"top").unwrap();
+        fs::write(path.join("sub").join("mid.txt"), b"mid").unwrap();
+        fs::write(path.join("sub").join("deeper").join("low.txt"), b"low").unwrap();
+        path
+    }
+
+    fn test_entry(name: &str) -> (String, FileMetadataExt) {
+        (name.to_owned(), FileMetadataExt::File(FileMetadata {
+            hash: [0u8; 32].into(),
+            permissions: 0o644,
+            modified: 1000,
+            size: 10.into(),
+            ino: None,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: None,
+            flags: None,
+            owner: None,
+        }))
+    }
+
+    fn db_entries(db_path: &Path) -> Vec<String> {
+        let db = Database::open(db_path).unwrap();
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(TABLE).unwrap();
+        table.iter().unwrap().map(|k| k.unwrap().0.value()).collect()
+    }
+
+    fn set_mtime(path: &Path, secs: u64) {
+        let file = fs::File::options().append(true).open(path).unwrap();
+        file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_db_atomic() {
+        let dir = setup_test_tree("create_atomic");
+        let db_path = dir.join("baseline.redb");
+        let tmp_path = dir.join("baseline.redb.tmp");
+        let db_str = db_path.to_string_lossy().to_string();
+        let tmp_str = tmp_path.to_string_lossy().to_string();
+
+        create_db_atomic(&db_str, &tmp_str, false, async |db: &Database| {
+            WriteToDB::new(db).add_file_info(&[test_entry("old")])
+        }).await.unwrap();
+        assert!(!tmp_path.exists());
+        assert_eq!(db_entries(&db_path), ["old"]);
+
+        // refuses to replace without overwrite, target untouched
+        let r = create_db_atomic(&db_str, &tmp_str, false, async |_db: &Database| Ok(())).await;
+        assert!(r.is_err());
+        assert_eq!(db_entries(&db_path), ["old"]);
+
+        // interrupted halfway, some entries already committed to the temp DB
+        let r: Result<(), _> = create_db_atomic(&db_str, &tmp_str, true, async |db: &Database| {
+            WriteToDB::new(db).add_file_info(&[test_entry("partial")])?;
+            Err(IntegrityWatcherError::InvalidArgument("interrupted".to_owned()))
+        }).await;
+        assert!(r.is_err());
+        assert!(!tmp_path.exists());
+        assert_eq!(db_entries(&db_path), ["old"]);
+
+        // a --timeout keeps none of the entries scanned before it
+        let r: Result<(), _> = create_db_atomic(&db_str, &tmp_str, true, async |db: &Database| {
+            let mut writer = WriteToDB::new(db);
+            with_scan_timeout(Some(std::time::Duration::from_millis(50)), "create", async {
+                writer.add_file_info(&[test_entry("scanned")])?;
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                Ok(())
+            }).await
+        }).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::Timeout { .. })), "{r:?}");
+        assert!(!tmp_path.exists());
+        assert_eq!(db_entries(&db_path), ["old"]);
+
+        // stale temp file from a killed run is replaced
+        fs::write(&tmp_path, b"garbage").unwrap();
+        create_db_atomic(&db_str, &tmp_str, true, async |db: &Database| {
+            WriteToDB::new(db).add_file_info(&[test_entry("new")])
+        }).await.unwrap();
+        assert!(!tmp_path.exists());
+        assert_eq!(db_entries(&db_path), ["new"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_add_table_overwrite() {
+        let dir = setup_test_tree("add_table_overwrite");
+        let db_path = dir.join("baseline.redb").to_string_lossy().to_string();
+        let tmp_path = dir.join("baseline.redb.tmp").to_string_lossy().to_string();
+        let table = "etc";
+        create_db_atomic(&db_path, &tmp_path, false, async |db: &Database| {
+            WriteToDB::new(db).add_file_info(&[test_entry("/etc/motd")])
+        }).await.unwrap();
+        add_table_atomic(&db_path, &tmp_path, &[table], false, async |db: &Database| {
+            WriteToDB::new(db).with_table(table).add_file_info(&[test_entry("/etc/motd")])
+        }).await.unwrap();
+        let db = Database::open(&db_path).unwrap();
+        annotations::mark_volatile(&db, table, "/etc/motd", true).unwrap();
+        let run = runs::Run{ started: 1000, command: "check".to_owned(), duration_ms: 10, files: 1, bytes: 10, findings: 0, version: "0.1.0".to_owned(), args: Vec::new(), run_id: String::new() };
+        runs::record(&db, table, &run, runs::RUNS_KEPT).unwrap();
+        drop(db);
+
+        // the new baseline starts without the marks and runs of the old one
+        add_table_atomic(&db_path, &tmp_path, &[table], true, async |db: &Database| {
+            WriteToDB::new(db).with_table(table).add_file_info(&[test_entry("/etc/hosts")])
+        }).await.unwrap();
+        let db = Database::open(&db_path).unwrap();
+        assert!(annotations::load_volatile(&db, table).unwrap().is_empty());
+        assert!(runs::load(&db, table, 10).unwrap().is_empty());
+        drop(db);
+        assert_eq!(db_entries(Path::new(&db_path)), ["/etc/motd"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_empty_db() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_db_open_empty");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("empty.redb").to_string_lossy().to_string();
+        drop(Database::create(&db_path).unwrap());
+
+        let err = open_baseline(&db_path, DEFAULT_TABLE).err().unwrap();
+        assert!(matches!(&err, IntegrityWatcherError::NoBaseline { db, table } if *db == db_path && table == DEFAULT_TABLE));
+        assert!(err.to_string().ends_with("contains no integrity table files_database, did you run --create?"));
+        assert!(matches!(Baseline::open(&db_path).unwrap().check(Vec::new(), ExcludeSet::default(), ScanOptions::default(), CheckOptions::default()),
+            Err(IntegrityWatcherError::NoBaseline { .. })));
+
+        WriteToDB::new(&Database::open(&db_path).unwrap()).add_file_info(&[test_entry("a")]).unwrap();
+        assert!(open_baseline(&db_path, DEFAULT_TABLE).is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_merge_hosts() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_db_merge_hosts");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let changed = |(path, entry): (String, FileMetadataExt)| match entry{
+            FileMetadataExt::File(f) => (path, FileMetadataExt::File(FileMetadata{ hash: [1u8; 32].into(), ..f })),
+            e => (path, e),
+        };
+        let (t1, t2) = (fileops::host_table_name(DEFAULT_TABLE, "web01"), fileops::host_table_name(DEFAULT_TABLE, "web02"));
+        // created with --host-prefix web01
+        let web01 = Database::create(dir.join("web01.redb")).unwrap();
+        WriteToDB::new(&web01).with_table(&t1).add_file_info(&[test_entry("/etc/sshd_config"), test_entry("/etc/motd")]).unwrap();
+        dbmeta::store_host(&web01, &t1, "web01").unwrap();
+        annotations::annotate(&web01, &t1, "/etc/motd", Some("banner"), None).unwrap();
+        let run = runs::Run{ started: 1000, command: "create".to_owned(), duration_ms: 10, files: 2, bytes: 20, findings: 0, version: "0.1.0".to_owned(), args: Vec::new(), run_id: String::new() };
+        runs::record(&web01, &t1, &run, runs::RUNS_KEPT).unwrap();
+        annotations::mark_volatile(&web01, &t1, "/etc/motd", true).unwrap();
+        // created without, the host has to be named
+        let web02 = Database::create(dir.join("web02.redb")).unwrap();
+        WriteToDB::new(&web02).add_file_info(&[changed(test_entry("/etc/sshd_config")), test_entry("/etc/hosts")]).unwrap();
+
+        assert_eq!(merge_sources(&web01, DEFAULT_TABLE, None).unwrap(), [(t1.clone(), "web01".to_owned())]);
+        assert!(merge_sources(&web02, DEFAULT_TABLE, None).unwrap().is_empty());
+        assert_eq!(merge_sources(&web02, DEFAULT_TABLE, Some("web02")).unwrap(), [(DEFAULT_TABLE.to_owned(), "web02".to_owned())]);
+
+        // same paths, no collision
+        let merged = Database::create(dir.join("merged.redb")).unwrap();
+        assert_eq!(merge_table(&web01, &t1, &merged, &t1, "web01").unwrap(), 2);
+        assert_eq!(merge_table(&web02, DEFAULT_TABLE, &merged, &t2, "web02").unwrap(), 2);
+        let mut hosts = merge_sources(&merged, DEFAULT_TABLE, None).unwrap();
+        hosts.sort();
+        assert_eq!(hosts, [(t1.clone(), "web01".to_owned()), (t2.clone(), "web02".to_owned())]);
+        assert_eq!(dbmeta::load_host(&merged, &t2).unwrap().as_deref(), Some("web02"));
+        assert_eq!(annotations::load_all(&merged, &t1).unwrap()["/etc/motd"].notes, ["banner"]);
+        assert_eq!(runs::load(&merged, &t1, 10).unwrap(), [run]);
+        assert_eq!(annotations::load_volatile(&merged, &t1).unwrap(), BTreeSet::from(["/etc/motd".to_owned()]));
+
+        let mut found: Vec<_> = diff_tables(&merged, &t1, &merged, &t2, CheckOptions::default()).unwrap()
+            .into_iter().map(|f| (f.kind, f.path)).collect();
+        found.sort();
+        assert_eq!(found, [
+            (FindingKind::Changed, "/etc/sshd_config".to_owned()),
+            (FindingKind::New, "/etc/hosts".to_owned()),
+            (FindingKind::Removed, "/etc/motd".to_owned()),
+        ]);
+
+        drop((web01, web02, merged));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_entries() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_db_prune");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("prune.redb");
+        let db = Database::create(&db_path).unwrap();
+        let paths = ["/srv/old", "/srv/old/a", "/srv/old/b/c", "/srv/older", "/srv/keep.bak", "/srv/keep"];
+        WriteToDB::new(&db).add_file_info(&paths.map(test_entry)).unwrap();
+        annotations::annotate(&db, DEFAULT_TABLE, "/srv/old/a", Some("legacy"), None).unwrap();
+        let pattern: ExcludeSet = ["/srv/old", "*.bak"].into_iter().collect();
+
+        let mut pruned = prune_entries(&db, DEFAULT_TABLE, &pattern, true).unwrap();
+        pruned.sort();
+        assert_eq!(pruned, ["/srv/keep.bak", "/srv/old", "/srv/old/a", "/srv/old/b/c"]);
+        drop(db);
+        assert_eq!(db_entries(&db_path).len(), paths.len());
+
+        let db = Database::open(&db_path).unwrap();
+        assert_eq!(prune_entries(&db, DEFAULT_TABLE, &pattern, false).unwrap().len(), 4);
+        assert!(annotations::load_all(&db, DEFAULT_TABLE).unwrap().is_empty());
+        drop(db);
+        let mut left = db_entries(&db_path);
+        left.sort();
+        assert_eq!(left, ["/srv/keep", "/srv/older"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_rehash() {
+        fn stored(db: &Database, path: &String) -> FileMetadata {
+            let read_txn = db.begin_read().unwrap();
+            match read_txn.open_table(TABLE).unwrap().get(path).unwrap().unwrap().value(){
+                FileMetadataExt::File(f) => f,
+                e => panic!("unexpected entry {e:?}"),
+            }
+        }
+        let tree = setup_test_tree("rehash");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let db = Database::create(&db_path).unwrap();
+        let opts = ScanOptions{ no_dirs: true, track_inode: true, ..Default::default() };
+        let settings = DBSettings{ no_dirs: true, track_inode: true, hash_command: None, prefilter: false, hash_link_content: false, path_fold: Default::default(), detect_type: false, stat_only: false, policy: None, stripe_size: None, flags: false, max_depth: None, owner: false };
+        settings.store(&db, DEFAULT_TABLE).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+
+        let md5 = ScanOptions{ hash_command: Some("md5sum".into()), ..opts.clone() };
+        let (migrated, failed) = rehash_entries(&db, DEFAULT_TABLE, &md5).await.unwrap();
+        assert!(failed.is_empty());
+        assert_eq!(migrated.len(), 3);
+        let top = tree.join("top.txt").to_string_lossy().to_string();
+        assert!(migrated.contains(&(top.clone(), types::Hash::from_hex("b28354b543375bfa94dabaeda722927f").unwrap())));
+
+        // content changed after the baseline can't be vouched for
+        fs::write(&top, b"changed").unwrap();
+        let (migrated, failed) = rehash_entries(&db, DEFAULT_TABLE, &md5).await.unwrap();
+        assert_eq!((migrated.len(), failed), (2, vec![top.clone()]));
+        fs::write(&top, b"top").unwrap();
+        set_mtime(Path::new(&top), stored(&db, &top).modified);
+
+        let (migrated, failed) = rehash_entries(&db, DEFAULT_TABLE, &md5).await.unwrap();
+        assert!(failed.is_empty());
+        let target = DBSettings{ hash_command: Some("md5sum".to_owned()), ..settings };
+        apply_rehash(&db, DEFAULT_TABLE, &migrated, &target).unwrap();
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), target);
+        let entry = stored(&db, &top);
+        assert!(entry.ino.is_some());
+        assert_eq!(entry.hash.to_string(), "b28354b543375bfa94dabaeda722927f");
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ track_inode: true, ..Default::default() });
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &md5, &mut checker).await.unwrap();
+        assert_eq!((checker.get_counter(), checker.get_changes_count()), (3, 0));
+
+        drop(db);
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_verify_dpkg() {
+        let tree = setup_test_tree("verify_dpkg");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let info = tree.with_extension("dpkg");
+        let _ = fs::remove_dir_all(&info);
+        fs::create_dir_all(&info).unwrap();
+        // md5 of "top" matches, sub/mid.txt was replaced after install
+        fs::write(info.join("fake:amd64.md5sums"), "b28354b543375bfa94dabaeda722927f  top.txt\n\
+            d41d8cd98f00b204e9800998ecf8427e  sub/mid.txt\n\
+            d41d8cd98f00b204e9800998ecf8427e  not/in/db\n").unwrap();
+        fs::write(info.join("fake:amd64.list"), "/top.txt\n").unwrap();
+
+        let db = Database::create(&db_path).unwrap();
+        let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+
+        let packages = pkgverify::load_dpkg(&info, &tree).unwrap();
+        assert_eq!(packages.len(), 3);
+        let md5 = ScanOptions{ hash_command: Some("md5sum".into()), ..Default::default() };
+        let (verified, differing) = verify_dpkg(&db, DEFAULT_TABLE, &packages, &md5).await.unwrap();
+        assert_eq!(verified, 2);
+        assert_eq!(differing.len(), 1);
+        assert_eq!(differing[0].0, tree.join("sub").join("mid.txt").to_string_lossy());
+        assert!(differing[0].1.starts_with("differs from package fake:amd64"), "{}", differing[0].1);
+
+        drop(db);
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_dir_all(info).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_verify_hash_lengths() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_db_hash_lengths");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let db = Database::create(dir.join("lengths.redb")).unwrap();
+        let with_hash = |name: &str, hash: types::Hash| match test_entry(name){
+            (path, FileMetadataExt::File(f)) => (path, FileMetadataExt::File(FileMetadata{ hash, ..f })),
+            _ => unreachable!(),
+        };
+        WriteToDB::new(&db).add_file_info(&[
+            test_entry("/srv/a"),
+            test_entry("/srv/b"),
+            with_hash("/srv/cut", types::Hash::from_hex("00112233").unwrap()),
+            with_hash("/srv/big/stat", types::Hash::none()),
+            with_hash("/srv/big/none", types::Hash::none()),
+        ]).unwrap();
+
+        let settings = DBSettings::default();
+        let (checked, mismatched) = verify_hash_lengths(&db, DEFAULT_TABLE, &settings).unwrap();
+        assert_eq!(checked, 5);
+        assert_eq!(mismatched, [
+            ("/srv/big/none".to_owned(), "hash (SHA-256) is 0 bytes, expected 32".to_owned()),
+            ("/srv/big/stat".to_owned(), "hash (SHA-256) is 0 bytes, expected 32".to_owned()),
+            ("/srv/cut".to_owned(), "hash (SHA-256) is 4 bytes, expected 32".to_owned()),
+        ]);
+
+        // a policy stat-tracking the files without hash
+        let settings = DBSettings{ policy: Some("/srv/big stat-only".to_owned()), ..Default::default() };
+        let (_, mismatched) = verify_hash_lengths(&db, DEFAULT_TABLE, &settings).unwrap();
+        assert_eq!(mismatched.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>(), ["/srv/cut"]);
+
+        // --hash-command digests are expected as long as most are
+        let settings = DBSettings{ hash_command: Some("sha256sum".to_owned()), policy: Some("/srv/big stat-only".to_owned()), ..Default::default() };
+        let (_, mismatched) = verify_hash_lengths(&db, DEFAULT_TABLE, &settings).unwrap();
+        assert_eq!(mismatched, [("/srv/cut".to_owned(), "hash (sha256sum) is 4 bytes, expected 32".to_owned())]);
+
+        drop(db);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_baseline_check() {
+        let tree = setup_test_tree("baseline_check");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+        let baseline = Baseline::create(&db_path, std::slice::from_ref(&tree), &ExcludeSet::default(), &opts).await.unwrap();
+        assert!(Baseline::create(&db_path, std::slice::from_ref(&tree), &ExcludeSet::default(), &opts).await.is_err());
+
+        fs::remove_file(tree.join("sub").join("mid.txt")).unwrap();
+        fs::write(tree.join("new.txt"), b"new").unwrap();
+        let mut findings: Vec<_> = baseline.check(vec![tree.clone()], ExcludeSet::default(), ScanOptions::default(), CheckOptions::default()).unwrap()
+            .map(|f| f.unwrap())
+            .map(|f| (f.kind, f.path))
+            .collect().await;
+        findings.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(findings, [
+            (FindingKind::New, tree.join("new.txt").to_string_lossy().to_string()),
+            (FindingKind::Removed, tree.join("sub").join("mid.txt").to_string_lossy().to_string()),
+        ]);
+
+        // stored settings win, asking for inodes on a DB without them fails
+        let inodes = ScanOptions{ track_inode: true, ..Default::default() };
+        assert!(baseline.check(vec![tree.clone()], ExcludeSet::default(), inodes, CheckOptions::default()).is_err());
+
+        drop(baseline);
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use integrity_checker::error::IntegrityWatcherError;
+use integrity_checker::fileops::AddFileInfo;
+use integrity_checker::scan::HashStats;
+use integrity_checker::types::{ByteSize, FileMetadataExt};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Upper bounds (exclusive) of the file size buckets in the report.
+const SIZE_BUCKETS: [(u64, &str); 6] = [
+    (4 << 10, "<4KiB"),
+    (64 << 10, "<64KiB"),
+    (1 << 20, "<1MiB"),
+    (16 << 20, "<16MiB"),
+    (256 << 20, "<256MiB"),
+    (u64::MAX, ">=256MiB"),
+];
+
+/// Discards scanned entries, only counting them. Asks the walk to stop once
+/// `deadline` passed.
+#[derive(Debug)]
+pub struct BenchSink{
+    deadline: Option<Instant>,
+    files: u64,
+    bytes: u64,
+    buckets: [(u64, u64); SIZE_BUCKETS.len()],
+}
+
+impl BenchSink {
+    pub fn new(duration: Option<Duration>) -> Self{
+        BenchSink { deadline: duration.map(|d| Instant::now() + d), files: 0, bytes: 0, buckets: Default::default() }
+    }
+}
+
+impl AddFileInfo for BenchSink {
+    fn add_file_info(&mut self, files: &[(String, FileMetadataExt)]) -> Result<(), IntegrityWatcherError> {
+        for (_, meta) in files{
+            if let FileMetadataExt::File(file) = meta{
+                let size: u64 = file.size.into();
+                let bucket = SIZE_BUCKETS.iter().position(|(limit, _)| size < *limit).unwrap_or(SIZE_BUCKETS.len() - 1);
+                self.files += 1;
+                self.bytes += size;
+                self.buckets[bucket].0 += 1;
+                self.buckets[bucket].1 += size;
+            }
+        }
+        Ok(())
+    }
+
+    fn should_stop(&self) -> bool{
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketResult{
+    pub bucket: &'static str,
+    pub files: u64,
+    pub bytes: u64,
+    pub files_per_sec: f64,
+}
+
+/// Outcome of one `--bench` pass, serialized as is for the JSON output.
+#[derive(Debug, Serialize)]
+pub struct BenchResult{
+    pub algorithm: String,
+    pub elapsed_secs: f64,
+    pub files: u64,
+    pub bytes: u64,
+    pub bytes_per_sec: f64,
+    pub files_per_sec: f64,
+    /// busy fraction of the wall time for each hashing thread, busiest first
+    pub thread_utilization: Vec<f64>,
+    pub buckets: Vec<BucketResult>,
+}
+
+impl BenchResult {
+    pub fn new(algorithm: String, sink: &BenchSink, stats: &HashStats, elapsed: Duration) -> Self{
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        BenchResult {
+            algorithm,
+            elapsed_secs: elapsed.as_secs_f64(),
+            files: sink.files,
+            bytes: sink.bytes,
+            bytes_per_sec: sink.bytes as f64 / secs,
+            files_per_sec: sink.files as f64 / secs,
+            thread_utilization: stats.per_thread().iter().map(|busy| busy.as_secs_f64() / secs).collect(),
+            buckets: SIZE_BUCKETS.iter().zip(sink.buckets).filter(|(_, (files, _))| *files > 0).map(|((_, name), (files, bytes))| BucketResult {
+                bucket: name,
+                files,
+                bytes,
+                files_per_sec: files as f64 / secs,
+            }).collect(),
+        }
+    }
+
+    pub fn report(&self) -> Vec<String>{
+        let elapsed = Duration::from_secs_f64(self.elapsed_secs);
+        let mut lines = vec![format!("Bench {}: {} files total {} in {:.3}s {} {:.1} files/s",
+            self.algorithm,
+            self.files,
+            ByteSize::new(self.bytes),
+            self.elapsed_secs,
+            ByteSize::new(self.bytes).bandwidth(elapsed),
+            self.files_per_sec,
+        )];
+        let threads = self.thread_utilization.iter().map(|u| format!("{:.0}%", u * 100.0)).collect::<Vec<_>>();
+        lines.push(format!("  {} hashing threads, utilization {}", threads.len(), threads.join(" ")));
+        for b in &self.buckets{
+            lines.push(format!("  {:>9} {} files {} {:.1} files/s", b.bucket, b.files, ByteSize::new(b.bytes), b.files_per_sec));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_checker::types::{DirMetadata, FileMetadata};
+
+    fn file(size: u64) -> FileMetadataExt{
+        FileMetadataExt::File(FileMetadata{ hash: [0u8; 32].into(), permissions: 0o100644, modified: 0, size: size.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None })
+    }
+
+    #[test]
+    fn test_bench_sink_buckets(){
+        let mut sink = BenchSink::new(None);
+        let entries = vec![
+            ("a".to_owned(), file(0)),
+            ("b".to_owned(), file(4095)),
+            ("c".to_owned(), file(4096)),
+            ("d".to_owned(), file(1 << 30)),
+            ("e".to_owned(), FileMetadataExt::Dir(DirMetadata{ permissions: 0o40755, modified: 0, size: 4096, security: None, entries: None, flags: None, owner: None })),
+        ];
+        sink.add_file_info(&entries).unwrap();
+        assert!(!sink.should_stop());
+
+        let stats = HashStats::default();
+        stats.record(Duration::from_millis(500));
+        let result = BenchResult::new("sha256".to_owned(), &sink, &stats, Duration::from_secs(1));
+        assert_eq!(result.files, 4);
+        assert_eq!(result.bytes, 4095 + 4096 + (1 << 30));
+        let buckets: Vec<_> = result.buckets.iter().map(|b| (b.bucket, b.files)).collect();
+        assert_eq!(buckets, vec![("<4KiB", 2), ("<64KiB", 1), (">=256MiB", 1)]);
+        assert_eq!(result.thread_utilization, vec![0.5]);
+        assert_eq!(serde_json::to_value(&result).unwrap()["buckets"][0]["files"], 2);
+
+        assert!(BenchSink::new(Some(Duration::ZERO)).should_stop());
+    }
+}
@@ -1,39 +1,64 @@
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use tokio::sync::Semaphore;
 use serde::{Deserialize, Serialize};
-use redb::{Database, TableDefinition, Value, ReadableDatabase};
+use redb::{Key, ReadableDatabase, ReadableTable, TableDefinition, Value, WriteTransaction};
 use postcard::{from_bytes, to_allocvec};
-use log::{error, trace};
+use tracing::{error, trace};
 use reqwest::{Client, StatusCode};
+use super::dbfile::{self, DbFile};
 use super::types::Hash;
 use super::error::IntegrityWatcherError;
 
 const TABLE_HASH: TableDefinition<Hash, CacheEntry> = TableDefinition::new("circl_cache");
 
+/// The same table as written before `--hash-command`, see `LegacyHash`.
+const LEGACY_TABLE_HASH: TableDefinition<LegacyHash, CacheEntry> = TableDefinition::new("circl_cache");
+
+/// Answers written to the cache in one transaction at most.
+const COMMIT_ENTRIES: usize = 256;
+
+/// Answers are written at least this often while queries go on.
+const COMMIT_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug,Serialize,Deserialize)]
 struct CacheEntry{
     score: Option<u8>,
     entry_time: i64,
 }
 
+/// How long CIRCL answers are cached. Not found answers get a shorter default,
+/// CIRCL keeps ingesting new data so they may turn into found ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheTtl{
+    pub found: Duration,
+    pub not_found: Duration,
+}
+
+impl Default for CacheTtl {
+    fn default() -> Self {
+        CacheTtl { found: Duration::from_secs(30 * 24 * 3600), not_found: Duration::from_secs(7 * 24 * 3600) }
+    }
+}
+
 impl CacheEntry{
     fn new(score: Option<u8>) -> Self{
         let t = chrono::Utc::now().timestamp();
         CacheEntry { score, entry_time: t }
     }
 
-    fn is_valid(&self) -> bool{
+    fn is_valid(&self, ttl: &CacheTtl, now: i64) -> bool{
         let duration = if self.score.is_some(){
-            chrono::Duration::days(30)
+            ttl.found
         } else{
-            chrono::Duration::days(7)
+            ttl.not_found
         };
-        chrono::Utc::now().timestamp() - self.entry_time < duration.num_seconds()
+        now - self.entry_time < i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
     }
 
     fn get_score(&self) -> Option<u8>{
-        //assume cache is valid because it was cleared on start
         self.score
     }
 }
@@ -61,63 +86,184 @@ impl Value for CacheEntry{
 
 }
 
+/// Keys of caches written before `--hash-command`: SHA-256 as fixed width 32
+/// bytes, which redb won't open as the variable width `Hash`.
+#[derive(Debug)]
+struct LegacyHash;
+
+impl Value for LegacyHash{
+    type SelfType<'a> = [u8; 32];
+    type AsBytes<'a> = &'a [u8; 32];
+
+    fn fixed_width() -> Option<usize> {
+        Some(32)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+        where Self: 'a{
+        data.try_into().unwrap()
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a> {
+        value
+    }
+
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new("Hash")
+    }
+}
+
+impl Key for LegacyHash{
+    fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
+        data1.cmp(data2)
+    }
+}
+
+/// Rewrites a cache of fixed width keys with variable width ones, keeping its
+/// answers.
+fn migrate_legacy(write_txn: &WriteTransaction) -> Result<(), IntegrityWatcherError> {
+    let entries = match write_txn.open_table(LEGACY_TABLE_HASH){
+        Ok(table) => table.iter()?
+            .map(|e| e.map(|(hash, entry)| (Hash::from(hash.value()), entry.value())))
+            .collect::<Result<Vec<_>, _>>()?,
+        // written with variable width keys already
+        Err(redb::TableError::TypeDefinitionChanged{ .. }) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    write_txn.delete_table(LEGACY_TABLE_HASH)?;
+    let mut table = write_txn.open_table(TABLE_HASH)?;
+    for (hash, entry) in &entries{
+        table.insert(hash, entry)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
 struct CirclCache{
-    db: Database,
+    db: Arc<DbFile>,
+    ttl: CacheTtl,
 }
 
 impl CirclCache {
-    fn new(path: &str) -> Result<Self, IntegrityWatcherError> {
-        let db = Database::create(path)?;
+    fn new(path: &str, ttl: CacheTtl) -> Result<Self, IntegrityWatcherError> {
+        let db = dbfile::create(path)?;
         let write_txn = db.begin_write().map_err(Box::new)?;
-        {
-            let _table = write_txn.open_table(TABLE_HASH)?;
-        }
+        migrate_legacy(&write_txn)?;
         write_txn.commit()?;
 
-        Ok(CirclCache { db })
+        Ok(CirclCache { db: Arc::new(db), ttl })
     }
 
     fn clear_old(&self) -> Result<(), IntegrityWatcherError>{
         let write_txn = self.db.begin_write().map_err(Box::new)?;
         {
             let mut table = write_txn.open_table(TABLE_HASH)?;
-            table.retain(|_h,v| v.is_valid())?;
+            let now = chrono::Utc::now().timestamp();
+            table.retain(|_h,v| v.is_valid(&self.ttl, now))?;
         }
         write_txn.commit()?;
         Ok(())
     }
 
-    fn insert(&self, hash: &Hash, entry: CacheEntry) -> Result<(), IntegrityWatcherError>{
+    fn insert(&self, entries: &[(Hash, CacheEntry)]) -> Result<(), IntegrityWatcherError>{
         let write_txn = self.db.begin_write().map_err(Box::new)?;
         {
             let mut table = write_txn.open_table(TABLE_HASH)?;
-            table.insert(hash, entry)?;
+            for (hash, entry) in entries{
+                table.insert(hash, entry)?;
+            }
         }
         write_txn.commit()?;
         Ok(())
     }
 
+    /// Writes the answers sent to `rx` until all senders are gone, up to
+    /// `COMMIT_ENTRIES` in a transaction and at least every `COMMIT_INTERVAL`.
+    /// Returns how many were written.
+    fn write_answers(&self, rx: Receiver<(Hash, CacheEntry)>) -> Result<u64, IntegrityWatcherError>{
+        let mut pending = Vec::with_capacity(COMMIT_ENTRIES);
+        let mut since = Instant::now();
+        let mut written = 0;
+        loop{
+            let open = match rx.recv_timeout(COMMIT_INTERVAL){
+                Ok(answer) => {
+                    if pending.is_empty(){
+                        since = Instant::now();
+                    }
+                    pending.push(answer);
+                    true
+                }
+                Err(RecvTimeoutError::Timeout) => true,
+                Err(RecvTimeoutError::Disconnected) => false,
+            };
+            if !pending.is_empty() && (!open || pending.len() >= COMMIT_ENTRIES || since.elapsed() >= COMMIT_INTERVAL){
+                self.insert(&pending)?;
+                trace!("Cached {} answers", pending.len());
+                written += pending.len() as u64;
+                pending.clear();
+            }
+            if !open{
+                return Ok(written);
+            }
+        }
+    }
+
+    /// Cached answer for `hash`, expired ones are ignored even before `clear_old`
+    /// removes them.
     fn contains(&self, hash: &Hash) -> Result<Option<CacheEntry>, IntegrityWatcherError> {
         let read_txn = self.db.begin_read().map_err(Box::new)?;
         let table = read_txn.open_table(TABLE_HASH)?;
         let r = table.get(hash)?;
-        Ok(r.map(|v| v.value()))
+        let now = chrono::Utc::now().timestamp();
+        Ok(r.map(|v| v.value()).filter(|e| e.is_valid(&self.ttl, now)))
     }
 }
 
+/// Queries CIRCL hashlookup, answering from the cache where it can. New
+/// answers are sent to a writer thread that writes them in batches, so queries
+/// don't wait on a commit each; an answer not written yet isn't found in the
+/// cache by another query of the same hash. [`CirclQuery::finish`] writes the
+/// rest.
 pub struct CirclQuery{
     client: Arc<Client>,
     limit: Arc<Semaphore>,
     cache: CirclCache,
+    answers: Option<Sender<(Hash, CacheEntry)>>,
+    writer: Option<JoinHandle<Result<u64, IntegrityWatcherError>>>,
 }
 
 impl CirclQuery {
-    pub fn new(path: &str) -> Result<Self, IntegrityWatcherError>{
+    pub fn new(path: &str, ttl: CacheTtl) -> Result<Self, IntegrityWatcherError>{
         let client = Arc::new(Client::builder().timeout(Duration::from_secs(3)).build()?);
         let limit = Arc::new(Semaphore::new(8));
-        let cache = CirclCache::new(path)?;
+        let cache = CirclCache::new(path, ttl)?;
         cache.clear_old()?;
-        Ok(CirclQuery{ client, limit, cache })
+        let (tx, rx) = mpsc::channel();
+        let writing = cache.clone();
+        let writer = std::thread::spawn(move || writing.write_answers(rx));
+        Ok(CirclQuery{ client, limit, cache, answers: Some(tx), writer: Some(writer) })
+    }
+
+    /// Writes the answers not cached yet and closes the cache. Returns how
+    /// many answers were cached.
+    pub fn finish(mut self) -> Result<u64, IntegrityWatcherError>{
+        self.close()
+    }
+
+    fn close(&mut self) -> Result<u64, IntegrityWatcherError>{
+        drop(self.answers.take());
+        match self.writer.take(){
+            Some(writer) => writer.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => Ok(0),
+        }
+    }
+
+    /// Queues `score` of `hash` for the cache. Answers of a writer that
+    /// failed are dropped, `finish` reports its error.
+    fn remember(&self, hash: &Hash, score: Option<u8>){
+        if let Some(answers) = &self.answers{
+            let _ = answers.send((hash.clone(), CacheEntry::new(score)));
+        }
     }
 
     pub async fn query(&self, hash: &Hash) -> Result<Option<u8>, IntegrityWatcherError>{
@@ -157,11 +303,11 @@ impl CirclQuery {
             match status {
                 StatusCode::OK => {
                     let r =  response.json::<HashLookupResponse>().await?;
-                    self.cache.insert(hash, CacheEntry::new(Some(r.trust_score)))?;
+                    self.remember(hash, Some(r.trust_score));
                     return Ok(Some(r.trust_score))
                 }
                 StatusCode::NOT_FOUND =>{
-                    self.cache.insert(hash, CacheEntry::new(None))?;
+                    self.remember(hash, None);
                     return Ok(None)
                 }
                 _ => {
@@ -176,4 +322,158 @@ impl CirclQuery {
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl Drop for CirclQuery {
+    fn drop(&mut self) {
+        if let Err(e) = self.close(){
+            error!("Can't write CIRCL cache: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::ReadableTableMetadata;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_query_served_from_cache() {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_circl_cache.redb");
+        if path.exists() {
+            fs::remove_file(&path).unwrap();
+        }
+        let path_str = path.to_string_lossy().to_string();
+
+        let known: Hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".parse().unwrap();
+        let unknown: Hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".parse().unwrap();
+        {
+            let cache = CirclCache::new(&path_str, CacheTtl::default()).unwrap();
+            cache.insert(&[(known.clone(), CacheEntry::new(Some(100))), (unknown.clone(), CacheEntry::new(None))]).unwrap();
+        }
+
+        let circl = CirclQuery::new(&path_str, CacheTtl::default()).unwrap();
+        assert_eq!(circl.query(&known).await.unwrap(), Some(100));
+        assert_eq!(circl.query(&unknown).await.unwrap(), None);
+
+        drop(circl);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_answers_cached_in_batches() {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_circl_batches.redb");
+        if path.exists() {
+            fs::remove_file(&path).unwrap();
+        }
+        let path_str = path.to_string_lossy().to_string();
+        let hashes: Vec<Hash> = (0..COMMIT_ENTRIES as u32 * 3 + 7).map(|i| {
+            let mut bytes = [0u8; 32];
+            bytes[..4].copy_from_slice(&i.to_be_bytes());
+            Hash::from(bytes)
+        }).collect();
+
+        // answers of concurrent queries
+        let circl = Arc::new(CirclQuery::new(&path_str, CacheTtl::default()).unwrap());
+        let mut queries = tokio::task::JoinSet::new();
+        for (i, hash) in hashes.iter().cloned().enumerate(){
+            let circl = circl.clone();
+            queries.spawn(async move { circl.remember(&hash, (i % 2 == 0).then_some(100)) });
+        }
+        queries.join_all().await;
+        let circl = Arc::into_inner(circl).unwrap();
+        assert_eq!(circl.finish().unwrap(), hashes.len() as u64);
+
+        let cache = CirclCache::new(&path_str, CacheTtl::default()).unwrap();
+        let read_txn = cache.db.begin_read().unwrap();
+        assert_eq!(read_txn.open_table(TABLE_HASH).unwrap().len().unwrap(), hashes.len() as u64);
+        drop(read_txn);
+        assert_eq!(cache.contains(&hashes[2]).unwrap().unwrap().get_score(), Some(100));
+        assert_eq!(cache.contains(&hashes[3]).unwrap().unwrap().get_score(), None);
+
+        drop(cache);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_cache_migrated() {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_circl_legacy.redb");
+        if path.exists() {
+            fs::remove_file(&path).unwrap();
+        }
+        let path_str = path.to_string_lossy().to_string();
+        let known = [7u8; 32];
+        {
+            let db = dbfile::create(&path_str).unwrap();
+            let write_txn = db.begin_write().unwrap();
+            write_txn.open_table(LEGACY_TABLE_HASH).unwrap().insert(&known, &CacheEntry::new(Some(100))).unwrap();
+            write_txn.commit().unwrap();
+        }
+
+        // opened twice: migrated once, then left as is
+        for _ in 0..2{
+            let cache = CirclCache::new(&path_str, CacheTtl::default()).unwrap();
+            assert_eq!(cache.contains(&Hash::from(known)).unwrap().unwrap().get_score(), Some(100));
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    fn aged(score: Option<u8>, days: i64) -> CacheEntry {
+        CacheEntry { score, entry_time: chrono::Utc::now().timestamp() - days * 24 * 3600 }
+    }
+
+    #[test]
+    fn test_found_ttl() {
+        let now = chrono::Utc::now().timestamp();
+        let ttl = CacheTtl{ found: Duration::from_secs(3 * 24 * 3600), ..Default::default() };
+        assert!(aged(Some(100), 2).is_valid(&ttl, now));
+        assert!(!aged(Some(100), 4).is_valid(&ttl, now));
+        // not found entries keep their own 7 days
+        assert!(aged(None, 4).is_valid(&ttl, now));
+        assert!(!aged(None, 8).is_valid(&ttl, now));
+    }
+
+    #[test]
+    fn test_notfound_ttl() {
+        let now = chrono::Utc::now().timestamp();
+        let ttl = CacheTtl{ not_found: Duration::from_secs(24 * 3600), ..Default::default() };
+        assert!(!aged(None, 2).is_valid(&ttl, now));
+        assert!(aged(Some(100), 2).is_valid(&ttl, now));
+        assert!(aged(Some(100), 29).is_valid(&ttl, now));
+        assert!(!aged(Some(100), 31).is_valid(&ttl, now));
+    }
+
+    #[test]
+    fn test_expired_entries_not_served() {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_circl_ttl.redb");
+        if path.exists() {
+            fs::remove_file(&path).unwrap();
+        }
+        let path_str = path.to_string_lossy().to_string();
+        let found: Hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".parse().unwrap();
+        let not_found: Hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".parse().unwrap();
+        let ttl = CacheTtl{ found: Duration::from_secs(10 * 24 * 3600), not_found: Duration::from_secs(24 * 3600) };
+
+        let cache = CirclCache::new(&path_str, ttl).unwrap();
+        cache.insert(&[(found.clone(), aged(Some(100), 5)), (not_found.clone(), aged(None, 5))]).unwrap();
+        assert!(cache.contains(&found).unwrap().is_some());
+        assert!(cache.contains(&not_found).unwrap().is_none());
+        drop(cache);
+
+        // a shorter found TTL on the next run clears the other entry as well
+        let ttl = CacheTtl{ found: Duration::from_secs(24 * 3600), ..ttl };
+        let cache = CirclCache::new(&path_str, ttl).unwrap();
+        cache.clear_old().unwrap();
+        let read_txn = cache.db.begin_read().unwrap();
+        assert_eq!(read_txn.open_table(TABLE_HASH).unwrap().len().unwrap(), 0);
+        drop(read_txn);
+
+        drop(cache);
+        fs::remove_file(path).unwrap();
+    }
+}
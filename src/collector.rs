@@ -0,0 +1,514 @@
+use super::dbfile::DbFile;
+use super::error::IntegrityWatcherError;
+use super::fileops::{Finding, FindingKind, UpdateDelta};
+use super::types::{EscapedPath, Timestamp};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::AUTHORIZATION;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use redb::{Database, ReadableDatabase, ReadableTable, ReadableTableMetadata, TableDefinition, TableHandle};
+use reqwest::{Certificate, Client, Identity};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::pki_types::pem::{self, PemObject};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tracing::{debug, info, warn, Instrument};
+
+/// Version of the `Report` JSON, collectors reject reports of other versions.
+pub const REPORT_VERSION: u32 = 1;
+
+pub const REPORTS_PATH: &str = "/v1/reports";
+
+/// Reports of host `web01` are kept in table `collector_reports_web01`, keyed
+/// by the time the collector received them in µs.
+const REPORTS_PREFIX: &str = "collector_reports_";
+
+/// Reports carry a bounded sample of findings, far less than this.
+const MAX_REPORT_BYTES: usize = 8 << 20;
+/// Connections `--serve` handles at once, more wait to be accepted.
+const MAX_CONNECTIONS: usize = 64;
+const PUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What `--push` sends after a check or update, also how the collector stores it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Report{
+    pub version: u32,
+    pub host_id: String,
+    /// `check` or `update`
+    pub command: String,
+    /// agent clock, unix seconds
+    pub generated: u64,
+    pub files: u64,
+    pub changed: u64,
+    pub new: u64,
+    pub removed: u64,
+    pub findings: Vec<ReportFinding>,
+    /// id of the agent run, logged in its first line; empty from older agents
+    #[serde(default)]
+    pub run_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportFinding{
+    pub kind: FindingKind,
+    pub path: String,
+    pub level: String,
+    pub message: String,
+}
+
+impl From<Finding> for ReportFinding {
+    fn from(f: Finding) -> Self {
+        ReportFinding { kind: f.kind, path: f.path, level: f.level.as_str().to_owned(), message: f.message }
+    }
+}
+
+impl Report {
+    pub fn new(host_id: &str, command: &str) -> Self {
+        Report {
+            version: REPORT_VERSION,
+            host_id: host_id.to_owned(),
+            command: command.to_owned(),
+            generated: chrono::Utc::now().timestamp().try_into().unwrap_or_default(),
+            files: 0,
+            changed: 0,
+            new: 0,
+            removed: 0,
+            findings: Vec::new(),
+            run_id: String::new(),
+        }
+    }
+
+    /// Report of an `--update`, every delta entry it lists is an INFO finding.
+    pub fn from_delta(host_id: &str, files: u64, delta: &UpdateDelta) -> Self {
+        let finding = |kind, path: &str, message: String| ReportFinding { kind, path: path.to_owned(), level: "INFO".to_owned(), message };
+        let findings = delta.added.entries().into_iter().map(|(k, v)| finding(FindingKind::New, k, format!("+ {} {v}", EscapedPath(k))))
+            .chain(delta.updated.entries().into_iter().map(|(k, (old, new))| finding(FindingKind::Changed, k, format!("~ {} {old} -> {new}", EscapedPath(k)))))
+            .chain(delta.removed.entries().into_iter().map(|(k, v)| finding(FindingKind::Removed, k, format!("- {} {v}", EscapedPath(k)))))
+            .collect();
+        Report {
+            files,
+            changed: delta.updated.count,
+            new: delta.added.count,
+            removed: delta.removed.count,
+            findings,
+            ..Report::new(host_id, "update")
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{} {} files {} changed {} new {} removed", self.command, self.files, self.changed, self.new, self.removed)
+    }
+}
+
+/// Host ids become table names, keep them to a safe alphabet.
+pub fn validate_host_id(host_id: &str) -> Result<(), IntegrityWatcherError> {
+    if host_id.is_empty() || host_id.len() > 64 || !host_id.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)){
+        return Err(IntegrityWatcherError::InvalidArgument(format!("host id {}, expected up to 64 of A-Z a-z 0-9 . _ -", EscapedPath(host_id))));
+    }
+    Ok(())
+}
+
+fn reports_table(name: &str) -> TableDefinition<'_, u64, String> {
+    TableDefinition::new(name)
+}
+
+/// Appends `report` to the reports of its host, returns the key it got.
+pub fn store_report(db: &Database, report: &Report) -> Result<u64, IntegrityWatcherError> {
+    validate_host_id(&report.host_id)?;
+    let name = format!("{REPORTS_PREFIX}{}", report.host_id);
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    let key = {
+        let mut table = write_txn.open_table(reports_table(&name))?;
+        let now: u64 = chrono::Utc::now().timestamp_micros().try_into().unwrap_or_default();
+        // two reports within a µs, or a clock stepped back, still get increasing keys
+        let key = match table.last()?.map(|(k, _)| k.value()){
+            Some(last) => now.max(last + 1),
+            None => now,
+        };
+        table.insert(key, serde_json::to_string(report)?)?;
+        key
+    };
+    write_txn.commit()?;
+    Ok(key)
+}
+
+#[derive(Debug)]
+pub struct HostSummary{
+    pub host_id: String,
+    pub reports: u64,
+    /// collector clock, unix µs
+    pub received: u64,
+    pub latest: Report,
+}
+
+/// Every host with reports in the collector DB, by host id.
+pub fn list_hosts(db: &Database) -> Result<Vec<HostSummary>, IntegrityWatcherError> {
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let mut hosts = Vec::new();
+    for handle in read_txn.list_tables()?{
+        let Some(host_id) = handle.name().strip_prefix(REPORTS_PREFIX) else {
+            continue;
+        };
+        let table = read_txn.open_table(reports_table(handle.name()))?;
+        if let Some((received, latest)) = table.last()?{
+            hosts.push(HostSummary {
+                host_id: host_id.to_owned(),
+                reports: table.len()?,
+                received: received.value(),
+                latest: serde_json::from_str(&latest.value())?,
+            });
+        }
+    }
+    hosts.sort_by(|a, b| a.host_id.cmp(&b.host_id));
+    Ok(hosts)
+}
+
+pub fn latest_report(db: &Database, host_id: &str) -> Result<Report, IntegrityWatcherError> {
+    validate_host_id(host_id)?;
+    let no_reports = || IntegrityWatcherError::InvalidArgument(format!("no reports from host {host_id}"));
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let table = match read_txn.open_table(reports_table(&format!("{REPORTS_PREFIX}{host_id}"))){
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Err(no_reports()),
+        Err(e) => return Err(e.into()),
+    };
+    let latest = table.last()?.ok_or_else(no_reports)?.1.value();
+    Ok(serde_json::from_str(&latest)?)
+}
+
+/// `--diff-host`: the summaries of both reports, then the findings, by path and
+/// kind, that only one of the hosts has.
+pub fn diff_reports(a: &Report, b: &Report) -> Vec<String> {
+    let keys = |r: &Report| r.findings.iter().map(|f| (f.path.clone(), f.kind)).collect::<BTreeSet<_>>();
+    let (keys_a, keys_b) = (keys(a), keys(b));
+    let mut lines = vec![format!("{}: {}", a.host_id, a.summary()), format!("{}: {}", b.host_id, b.summary())];
+    let mut only: Vec<_> = keys_a.difference(&keys_b).map(|k| (k, &a.host_id))
+        .chain(keys_b.difference(&keys_a).map(|k| (k, &b.host_id)))
+        .collect();
+    only.sort();
+    for ((path, kind), host) in only{
+        lines.push(format!("{} {kind} on {host} only", EscapedPath(path)));
+    }
+    lines
+}
+
+/// `--serve`: stores the reports agents POST to `REPORTS_PATH`. With `tokens`
+/// agents have to send the one of their host as bearer token. Client
+/// certificates are checked by the TLS acceptor, a report is only taken from
+/// a certificate naming its host.
+pub struct Collector{
+    db: Arc<DbFile>,
+    /// host id to its token
+    tokens: Option<HashMap<String, String>>,
+}
+
+impl Collector {
+    pub fn new(db: DbFile, tokens: Option<HashMap<String, String>>) -> Self {
+        Collector { db: Arc::new(db), tokens }
+    }
+
+    async fn accept(&self, req: Request<Incoming>, client_cert: Option<&CertificateDer<'_>>) -> Result<String, (StatusCode, String)> {
+        if req.method() != Method::POST || req.uri().path() != REPORTS_PATH{
+            return Err((StatusCode::NOT_FOUND, format!("only POST {REPORTS_PATH}")));
+        }
+        // the host of the token, checked against the report once it's read
+        let token_host = match &self.tokens{
+            Some(tokens) => {
+                let given = req.headers().get(AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+                let host = given.and_then(|given| tokens.iter().find(|(_, token)| token_eq(given, token)).map(|(host, _)| host));
+                match host{
+                    Some(host) => Some(host),
+                    None => return Err((StatusCode::UNAUTHORIZED, "missing or wrong bearer token".to_owned())),
+                }
+            }
+            None => None,
+        };
+        let body = Limited::new(req.into_body(), MAX_REPORT_BYTES).collect().await
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("reading report: {e}")))?
+            .to_bytes();
+        let report: Report = serde_json::from_slice(&body)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid report: {e}")))?;
+        if report.version != REPORT_VERSION{
+            return Err((StatusCode::BAD_REQUEST, format!("report version {}, expected {REPORT_VERSION}", report.version)));
+        }
+        if let Some(host) = token_host && *host != report.host_id{
+            return Err((StatusCode::FORBIDDEN, format!("token of host {host} can't report for {}", EscapedPath(&report.host_id))));
+        }
+        if let Some(cert) = client_cert && !cert_names_host(cert, &report.host_id){
+            return Err((StatusCode::FORBIDDEN, format!("client certificate doesn't name host {}", EscapedPath(&report.host_id))));
+        }
+        let db = self.db.clone();
+        let stored = tokio::task::spawn_blocking(move || store_report(&db, &report).map(|_| report)).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        match stored{
+            Ok(report) => {
+                match report.run_id.is_empty(){
+                    true => info!("Report from {}: {}", report.host_id, report.summary()),
+                    false => info!("Report from {} run {}: {}", report.host_id, report.run_id, report.summary()),
+                }
+                Ok(format!("stored report of {}", report.host_id))
+            }
+            Err(e @ IntegrityWatcherError::InvalidArgument(_)) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+            Err(e) => {
+                warn!("Storing report failed: {e}");
+                Err((StatusCode::INTERNAL_SERVER_ERROR, "storing report failed".to_owned()))
+            }
+        }
+    }
+}
+
+/// Whether `host_id` is a DNS name in the subject alternative names of `cert`.
+fn cert_names_host(cert: &CertificateDer<'_>, host_id: &str) -> bool {
+    let Ok(name) = ServerName::try_from(host_id) else {
+        return false;
+    };
+    webpki::EndEntityCert::try_from(cert).is_ok_and(|cert| cert.verify_is_valid_for_subject_name(&name).is_ok())
+}
+
+/// Compares without returning at the first differing byte.
+fn token_eq(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn handle(collector: Arc<Collector>, client_cert: Option<Arc<CertificateDer<'static>>>, req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let (status, text) = match collector.accept(req, client_cert.as_deref()).await{
+        Ok(text) => (StatusCode::CREATED, text),
+        Err(e) => e,
+    };
+    Ok(Response::builder().status(status).body(Full::new(Bytes::from(text))).expect("status and body are valid"))
+}
+
+/// Accepts connections until an accept fails, up to `MAX_CONNECTIONS` at once.
+/// Without `tls` reports come in over plain HTTP, which only tests use.
+pub async fn serve(listener: TcpListener, tls: Option<TlsAcceptor>, collector: Arc<Collector>) -> Result<(), IntegrityWatcherError> {
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let permit = connections.clone().acquire_owned().await.expect("semaphore is never closed");
+        let (stream, peer) = listener.accept().await
+            .map_err(|e| IntegrityWatcherError::IOError { source: e, path: "listener".to_owned() })?;
+        let (tls, collector) = (tls.clone(), collector.clone());
+        tokio::spawn(async move {
+            let _permit = permit;
+            let served = match tls{
+                Some(tls) => match tls.accept(stream).await{
+                    Ok(stream) => {
+                        let client_cert = stream.get_ref().1.peer_certificates().and_then(<[_]>::first).map(|c| Arc::new(c.clone().into_owned()));
+                        let service = service_fn(move |req| handle(collector.clone(), client_cert.clone(), req));
+                        http1::Builder::new().serve_connection(TokioIo::new(stream), service).await
+                    }
+                    Err(e) => {
+                        warn!("TLS handshake with {peer} failed: {e}");
+                        return;
+                    }
+                },
+                None => http1::Builder::new().serve_connection(TokioIo::new(stream), service_fn(move |req| handle(collector.clone(), None, req))).await,
+            };
+            if let Err(e) = served{
+                debug!("Connection from {peer}: {e}");
+            }
+        }.in_current_span());
+    }
+}
+
+/// TLS for `--serve` from PEM files, requiring client certificates signed by
+/// `client_ca` when given.
+pub fn tls_acceptor(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<TlsAcceptor, IntegrityWatcherError> {
+    let pem_error = |path: &Path, e: pem::Error| IntegrityWatcherError::Tls(format!("{}: {e}", EscapedPath(&path.to_string_lossy())));
+    let load_certs = |path: &Path| -> Result<Vec<CertificateDer<'static>>, IntegrityWatcherError> {
+        CertificateDer::pem_file_iter(path).map_err(|e| pem_error(path, e))?
+            .collect::<Result<_, _>>().map_err(|e| pem_error(path, e))
+    };
+    let certs = load_certs(cert)?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+    let builder = ServerConfig::builder();
+    let builder = match client_ca{
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca)?{
+                roots.add(cert).map_err(|e| IntegrityWatcherError::Tls(e.to_string()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()
+                .map_err(|e| IntegrityWatcherError::Tls(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(certs, key).map_err(|e| IntegrityWatcherError::Tls(e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// `--token-file` of `--serve`: a `HOST-ID TOKEN` line per agent, blank lines
+/// and `#` comments skipped. A token only takes reports of its host.
+pub fn read_host_tokens(path: &str) -> Result<HashMap<String, String>, IntegrityWatcherError> {
+    let text = std::fs::read_to_string(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_owned() })?;
+    let mut tokens = HashMap::new();
+    for (n, line) in text.lines().enumerate().map(|(n, line)| (n + 1, line.trim())){
+        if line.is_empty() || line.starts_with('#'){
+            continue;
+        }
+        let Some((host_id, token)) = line.split_once(char::is_whitespace).map(|(h, t)| (h, t.trim())).filter(|(_, t)| !t.is_empty()) else {
+            return Err(IntegrityWatcherError::InvalidArgument(format!("--token-file {} line {n}: expected HOST-ID TOKEN", EscapedPath(path))));
+        };
+        validate_host_id(host_id)?;
+        if tokens.values().any(|t| t == token){
+            return Err(IntegrityWatcherError::InvalidArgument(format!("--token-file {} line {n}: token of another host", EscapedPath(path))));
+        }
+        tokens.insert(host_id.to_owned(), token.to_owned());
+    }
+    if tokens.is_empty(){
+        return Err(IntegrityWatcherError::InvalidArgument(format!("--token-file {} is empty", EscapedPath(path))));
+    }
+    Ok(tokens)
+}
+
+/// First line of `--token-file`.
+pub fn read_token(path: &str) -> Result<String, IntegrityWatcherError> {
+    let text = std::fs::read_to_string(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_owned() })?;
+    match text.lines().next().map(str::trim){
+        Some(token) if !token.is_empty() => Ok(token.to_owned()),
+        _ => Err(IntegrityWatcherError::InvalidArgument(format!("--token-file {} is empty", EscapedPath(path)))),
+    }
+}
+
+/// How `--push` authenticates to the collector and which CA it trusts.
+#[derive(Debug, Default, Clone)]
+pub struct PushOptions{
+    pub token: Option<String>,
+    /// trust only this CA bundle instead of the system roots
+    pub ca: Option<PathBuf>,
+    /// client certificate and key for mTLS
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, IntegrityWatcherError> {
+    std::fs::read(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })
+}
+
+/// Sends `report` to the collector at `url`.
+pub async fn push(url: &str, report: &Report, opts: &PushOptions) -> Result<(), IntegrityWatcherError> {
+    let mut builder = Client::builder().timeout(PUSH_TIMEOUT);
+    if let Some(ca) = &opts.ca{
+        builder = builder.tls_certs_only(Certificate::from_pem_bundle(&read_file(ca)?)?);
+    }
+    if let Some((cert, key)) = &opts.identity{
+        let mut pem = read_file(cert)?;
+        pem.extend(read_file(key)?);
+        builder = builder.identity(Identity::from_pem(&pem)?);
+    }
+    if opts.token.is_some() && url.starts_with("http://"){
+        warn!("Sending the bearer token to {url} unencrypted");
+    }
+    let url = format!("{}{REPORTS_PATH}", url.trim_end_matches('/'));
+    let mut request = builder.build()?.post(&url).json(report);
+    if let Some(token) = &opts.token{
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success(){
+        return Err(IntegrityWatcherError::PushRejected { url, status: status.as_u16(), reason: response.text().await.unwrap_or_default() });
+    }
+    Ok(())
+}
+
+/// Log lines of `--list-hosts`.
+pub fn host_lines(hosts: &[HostSummary]) -> Vec<String> {
+    hosts.iter().map(|h| format!("Host {}: {} reports, last received {} generated {}: {}",
+        h.host_id, h.reports, Timestamp::new(h.received / 1_000_000), Timestamp::new(h.latest.generated), h.latest.summary()
+    )).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::severity::Changes;
+    use log::Level;
+    use std::fs;
+
+    fn report(host_id: &str, paths: &[(&str, FindingKind)]) -> Report {
+        Report {
+            files: 10,
+            changed: paths.len() as u64,
+            findings: paths.iter().map(|(p, kind)| ReportFinding::from(Finding { kind: *kind, path: p.to_string(), state: String::new(), level: Level::Error, message: format!("File {p} changed"), old: None, new: None, entropy: None, changes: Changes::default() })).collect(),
+            ..Report::new(host_id, "check")
+        }
+    }
+
+    #[test]
+    fn test_store_and_diff_hosts() {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_collector");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        let db = Database::create(path.join("database.redb")).unwrap();
+
+        store_report(&db, &report("web01", &[])).unwrap();
+        let first = store_report(&db, &report("web01", &[("/etc/ssh/sshd_config", FindingKind::Changed), ("/etc/hosts", FindingKind::Changed)])).unwrap();
+        let second = store_report(&db, &report("web02", &[("/etc/hosts", FindingKind::Changed)])).unwrap();
+        assert!(second > first);
+        assert!(store_report(&db, &report("../etc", &[])).is_err());
+
+        let hosts = list_hosts(&db).unwrap();
+        assert_eq!(hosts.iter().map(|h| (h.host_id.as_str(), h.reports)).collect::<Vec<_>>(), [("web01", 2), ("web02", 1)]);
+        assert_eq!(hosts[0].latest.changed, 2);
+
+        let diff = diff_reports(&latest_report(&db, "web01").unwrap(), &latest_report(&db, "web02").unwrap());
+        assert_eq!(diff[2..], ["/etc/ssh/sshd_config changed on web01 only"]);
+        assert!(matches!(latest_report(&db, "web03"), Err(IntegrityWatcherError::InvalidArgument(_))));
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_push_to_collector() {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_collector_push");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        let db = crate::dbfile::create(path.join("database.redb")).unwrap();
+        let tokens = HashMap::from([("web01".to_owned(), "s3cret".to_owned()), ("web02".to_owned(), "other".to_owned())]);
+        let collector = Arc::new(Collector::new(db, Some(tokens)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(serve(listener, None, collector.clone()));
+
+        let sent = report("web01", &[("/etc/passwd", FindingKind::Changed)]);
+        let wrong = PushOptions { token: Some("guess".to_owned()), ..Default::default() };
+        let r = push(&url, &sent, &wrong).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::PushRejected { status: 401, .. })), "{r:?}");
+        let old = Report { version: REPORT_VERSION + 1, ..sent.clone() };
+        let opts = PushOptions { token: Some("s3cret".to_owned()), ..Default::default() };
+        let r = push(&url, &old, &opts).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::PushRejected { status: 400, .. })), "{r:?}");
+        assert!(list_hosts(&collector.db).unwrap().is_empty());
+
+        // a token only reports for its own host
+        let forged = PushOptions { token: Some("other".to_owned()), ..Default::default() };
+        let r = push(&url, &sent, &forged).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::PushRejected { status: 403, .. })), "{r:?}");
+
+        push(&url, &sent, &opts).await.unwrap();
+        assert_eq!(latest_report(&collector.db, "web01").unwrap(), sent);
+
+        server.abort();
+        drop(collector);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Text log lines colored by how serious a finding is: type changes and new
+//! setuid/setgid bits red, content changes yellow, new entries dim, told by
+//! [`fileops::logged_finding`] rather than the message text. env_logger
+//! drops the colors when stdout isn't a terminal or `NO_COLOR` is set.
+
+use integrity_checker::fileops::{self, FindingKind, LoggedFinding};
+use integrity_checker::severity::Change;
+use env_logger::fmt::Formatter;
+use env_logger::fmt::style::{AnsiColor, Effects, Style};
+use log::{Level, Record};
+use std::io::{self, Write};
+
+/// Style of the message text of a log line, plain for lines that aren't
+/// findings.
+pub fn message_style(level: Level, finding: Option<LoggedFinding>) -> Style {
+    let Some(finding) = finding.filter(|_| level <= Level::Warn) else {
+        return Style::new();
+    };
+    if finding.changes.contains(Change::Type) || finding.setid_added{
+        AnsiColor::Red.on_default().effects(Effects::BOLD)
+    }
+    else if finding.changes.contains(Change::Hash){
+        AnsiColor::Yellow.on_default()
+    }
+    else if finding.kind == FindingKind::New{
+        Style::new().effects(Effects::DIMMED)
+    }
+    else{
+        Style::new()
+    }
+}
+
+/// env_logger format: the default layout with microsecond timestamps, the
+/// message styled by `message_style`.
+pub fn format(buf: &mut Formatter, record: &Record<'_>) -> io::Result<()> {
+    let level = buf.default_level_style(record.level());
+    let style = message_style(record.level(), fileops::logged_finding());
+    writeln!(buf, "[{} {level}{:<5}{level:#} {}] {style}{}{style:#}", buf.timestamp_micros(), record.level(), record.target(), record.args())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integrity_checker::severity::Changes;
+    use env_logger::{Builder, Target, WriteStyle};
+    use log::Log;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn logged(write_style: WriteStyle, message: &str, finding: LoggedFinding) -> String {
+        let out = Shared::default();
+        let logger = Builder::new().format(format).target(Target::Pipe(Box::new(out.clone()))).write_style(write_style).build();
+        fileops::logging_finding(finding, || {
+            logger.log(&Record::builder().level(Level::Error).target("integrity_checker::fileops").args(format_args!("{message}")).build());
+        });
+        String::from_utf8(out.0.lock().unwrap().clone()).unwrap()
+    }
+
+    fn changed(changes: &[Change], setid_added: bool) -> LoggedFinding {
+        let mut set = Changes::default();
+        for change in changes{
+            set.insert(*change);
+        }
+        LoggedFinding{ kind: FindingKind::Changed, changes: set, setid_added }
+    }
+
+    #[test]
+    fn test_message_style() {
+        let red = AnsiColor::Red.on_default().effects(Effects::BOLD);
+        assert_eq!(message_style(Level::Error, Some(changed(&[Change::Type], false))), red);
+        assert_eq!(message_style(Level::Error, Some(changed(&[Change::Permissions], true))), red);
+        assert_eq!(message_style(Level::Error, Some(changed(&[Change::Permissions], false))), Style::new());
+        assert_eq!(message_style(Level::Error, Some(changed(&[Change::Hash, Change::Mtime], false))), AnsiColor::Yellow.on_default());
+        let new = LoggedFinding{ kind: FindingKind::New, changes: Changes::default(), setid_added: false };
+        assert_eq!(message_style(Level::Warn, Some(new)), Style::new().effects(Effects::DIMMED));
+        assert_eq!(message_style(Level::Info, Some(changed(&[Change::Type], false))), Style::new());
+        // a line only reading like a finding
+        assert_eq!(message_style(Level::Error, None), Style::new());
+    }
+
+    #[test]
+    fn test_no_color_when_piped() {
+        let message = "/bin/su File perm: 100755 changed to symlink -> /tmp/x";
+        let finding = changed(&[Change::Type], false);
+        // a pipe is no terminal, env_logger decides like it does for stdout
+        let piped = logged(WriteStyle::Auto, message, finding);
+        assert!(piped.contains(message) && piped.contains("ERROR integrity_checker::fileops]"));
+        assert!(!piped.contains('\x1b'), "{piped:?}");
+        assert!(!logged(WriteStyle::Never, message, finding).contains('\x1b'));
+        assert!(logged(WriteStyle::Always, message, finding).contains("\x1b[1m\x1b[31m"));
+    }
+}
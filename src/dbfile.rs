@@ -0,0 +1,617 @@
+//! Opening DB files. redb lets one process at a time have a file open, others
+//! get `DatabaseAlreadyOpen`; that's reported as `DatabaseLocked` naming the
+//! file, after waiting up to `set_lock_wait` for the other process to finish.
+//!
+//! A DB can be encrypted at rest with a key from `--key-file` or a passphrase in
+//! `INTEGRITY_CHECKER_PASSPHRASE`. redb needs a plain file, so an encrypted DB
+//! is decrypted into a copy only the user can read in the temp dir, worked on
+//! there and encrypted back when the [`DbFile`] is closed. While a command runs
+//! the DB is readable by whoever can read that copy or the process memory. A
+//! copy left behind by a killed run is removed by the next one.
+//!
+//! The encrypted file is a header, `MAGIC`, the Argon2id parameters, salt and
+//! a nonce prefix, followed by XChaCha20-Poly1305 chunks of `CHUNK` bytes of
+//! the DB. The nonce counts the chunks and marks the last one, which is always
+//! shorter than `CHUNK`, so reordered, cut or appended chunks don't decrypt.
+
+use super::error::IntegrityWatcherError;
+use super::types::EscapedPath;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use redb::{Database, DatabaseError};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+use xxhash_rust::xxh3::Xxh3;
+use zeroize::Zeroizing;
+
+/// How often a locked DB is tried again while waiting.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `--wait-for-lock`, for every DB the process opens.
+static LOCK_WAIT_MS: AtomicU64 = AtomicU64::new(0);
+
+/// `--key-file` or the passphrase, for every DB the process opens.
+static SECRET: RwLock<Option<Secret>> = RwLock::new(None);
+
+/// Holds the passphrase when no `--key-file` is given.
+pub const PASSPHRASE_ENV: &str = "INTEGRITY_CHECKER_PASSPHRASE";
+
+/// Start of every encrypted DB, redb files start with their own magic.
+const MAGIC: &[u8; 8] = b"ICDBENC1";
+
+/// Bytes of the DB encrypted together.
+const CHUNK: usize = 64 * 1024;
+
+/// Poly1305 tag following every chunk.
+const TAG: usize = 16;
+
+const SALT_LEN: usize = 16;
+
+/// Random part of the nonces, the rest is the chunk counter.
+const PREFIX_LEN: usize = 16;
+
+const HEADER_LEN: usize = MAGIC.len() + 3 * 4 + SALT_LEN + PREFIX_LEN;
+
+/// Marks the nonce of the last chunk.
+const LAST_CHUNK: u64 = 1 << 63;
+
+/// Argon2 memory in KiB a header may ask for, 4 GiB.
+const MAX_M_COST: u32 = 4 * 1024 * 1024;
+
+/// Retries opening a DB another process holds for up to `wait`, by default
+/// it fails right away.
+pub fn set_lock_wait(wait: Duration) {
+    LOCK_WAIT_MS.store(wait.as_millis().try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+}
+
+/// Key of encrypted DBs: opens them and encrypts DBs created from now on.
+pub fn set_secret(secret: Secret) {
+    *SECRET.write().unwrap_or_else(|e| e.into_inner()) = Some(secret);
+}
+
+fn secret() -> Option<Secret> {
+    SECRET.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Whether DBs are encrypted with a key given by `set_secret`.
+pub fn has_secret() -> bool {
+    secret().is_some()
+}
+
+/// Key material of encrypted DBs, the actual key is derived from it with
+/// Argon2id and the salt of each file.
+#[derive(Clone)]
+pub struct Secret(Zeroizing<Vec<u8>>);
+
+impl Secret {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Secret(Zeroizing::new(bytes))
+    }
+
+    /// The whole content of `path`, e.g. 32 random bytes.
+    pub fn from_key_file(path: &str) -> Result<Self, IntegrityWatcherError> {
+        let bytes = Zeroizing::new(fs::read(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_owned() })?);
+        if bytes.is_empty(){
+            return Err(IntegrityWatcherError::InvalidArgument(format!("key file {} is empty", EscapedPath(path))));
+        }
+        Ok(Secret::new(bytes.to_vec()))
+    }
+
+    /// The passphrase in `PASSPHRASE_ENV`, if set. Children the process starts
+    /// have it removed from their environment.
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(PASSPHRASE_ENV).filter(|p| !p.is_empty()).map(|p| Secret::new(p.into_encoded_bytes()))
+    }
+}
+
+/// Whether `path` is an encrypted DB, false when it can't be read.
+pub fn is_encrypted(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && &magic == MAGIC
+}
+
+/// `Database::open`, failing with `DatabaseLocked` when another process has it.
+pub fn open(path: impl AsRef<Path>) -> Result<DbFile, IntegrityWatcherError> {
+    open_with(path.as_ref(), secret().as_ref())
+}
+
+/// `Database::create`, failing with `DatabaseLocked` when another process has it.
+/// A new DB is encrypted when `set_secret` was given a key.
+pub fn create(path: impl AsRef<Path>) -> Result<DbFile, IntegrityWatcherError> {
+    create_with(path.as_ref(), secret().as_ref())
+}
+
+fn open_with(path: &Path, secret: Option<&Secret>) -> Result<DbFile, IntegrityWatcherError> {
+    if is_encrypted(path){
+        return Sealed::unseal(path, secret, |p| Database::open(p));
+    }
+    Ok(DbFile { db: Some(retry_locked(path, |p| Database::open(p))?), sealed: None })
+}
+
+fn create_with(path: &Path, secret: Option<&Secret>) -> Result<DbFile, IntegrityWatcherError> {
+    if is_encrypted(path){
+        return Sealed::unseal(path, secret, |p| Database::create(p));
+    }
+    match secret{
+        Some(secret) if !path.exists() => Sealed::create(path, secret),
+        _ => Ok(DbFile { db: Some(retry_locked(path, |p| Database::create(p))?), sealed: None }),
+    }
+}
+
+/// `--encrypt`: encrypts the plain DB at `path` in place.
+pub fn encrypt(path: impl AsRef<Path>, secret: &Secret) -> Result<(), IntegrityWatcherError> {
+    let path = path.as_ref();
+    if is_encrypted(path){
+        return Err(IntegrityWatcherError::InvalidArgument(format!("{} is encrypted already", EscapedPath(&path.to_string_lossy()))));
+    }
+    // a redb file nobody else has open, kept that way while it's encrypted
+    drop(retry_locked(path, |p| Database::open(p))?);
+    let _lock = lock(path)?;
+    let header = Header::new()?;
+    let key = header.key(path, secret)?;
+    header.seal(path, path, &key)
+}
+
+/// An open DB, derefs to the redb `Database`. An encrypted one is encrypted
+/// back by `close`, or when dropped, logging errors.
+pub struct DbFile{
+    db: Option<Database>,
+    sealed: Option<Sealed>,
+}
+
+impl Deref for DbFile {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.db.as_ref().expect("DB is open until closed")
+    }
+}
+
+impl DbFile {
+    /// Closes the DB, encrypting it back when it is encrypted and changed.
+    pub fn close(mut self) -> Result<(), IntegrityWatcherError> {
+        self.seal()
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.sealed.is_some()
+    }
+
+    fn seal(&mut self) -> Result<(), IntegrityWatcherError> {
+        drop(self.db.take());
+        match self.sealed.take(){
+            Some(sealed) => sealed.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DbFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.seal(){
+            error!("Can't encrypt database: {e}");
+        }
+    }
+}
+
+/// The plain copy of an encrypted DB, removed when dropped.
+struct Sealed{
+    path: PathBuf,
+    plain: PathBuf,
+    header: Header,
+    key: Zeroizing<[u8; 32]>,
+    /// of the plain copy when decrypted, none for a new DB
+    digest: Option<u128>,
+    /// held while the DB is open, like redb locks plain files
+    _lock: Option<File>,
+}
+
+impl Sealed {
+    /// A new encrypted DB at `path`, written when closed.
+    fn create(path: &Path, secret: &Secret) -> Result<DbFile, IntegrityWatcherError> {
+        let header = Header::new()?;
+        let key = header.key(path, secret)?;
+        let (plain, _) = plain_copy()?;
+        let sealed = Sealed { path: path.to_owned(), plain, header, key, digest: None, _lock: None };
+        let db = Database::create(&sealed.plain)?;
+        Ok(DbFile { db: Some(db), sealed: Some(sealed) })
+    }
+
+    /// Decrypts the DB at `path` and opens the plain copy with `open`.
+    fn unseal(path: &Path, secret: Option<&Secret>, open: impl Fn(&Path) -> Result<Database, DatabaseError>) -> Result<DbFile, IntegrityWatcherError> {
+        let name = || path.to_string_lossy().to_string();
+        let Some(secret) = secret else {
+            return Err(IntegrityWatcherError::Encrypted { path: name() });
+        };
+        let lock = lock(path)?;
+        let mut input = io::BufReader::new(&lock);
+        let mut raw = [0; HEADER_LEN];
+        input.read_exact(&mut raw).map_err(|e| IntegrityWatcherError::IOError { source: e, path: name() })?;
+        let header = Header::parse(&raw);
+        let key = header.key(path, secret)?;
+        let (plain, mut out) = plain_copy()?;
+        let mut sealed = Sealed { path: path.to_owned(), plain, header, key, digest: None, _lock: None };
+        let cipher = XChaCha20Poly1305::new((&*sealed.key).into());
+        let mut digest = Xxh3::new();
+        let mut buf = vec![0; CHUNK + TAG];
+        for counter in 0..{
+            let n = read_full(&mut input, &mut buf).map_err(|e| IntegrityWatcherError::IOError { source: e, path: name() })?;
+            let last = n < buf.len();
+            let chunk = cipher.decrypt(&nonce(&raw, counter, last), Payload{ msg: &buf[..n], aad: &raw })
+                .map_err(|_| IntegrityWatcherError::Decrypt { path: name(), reason: "wrong key or damaged file".to_owned() })?;
+            digest.update(&chunk);
+            out.write_all(&chunk).map_err(|e| IntegrityWatcherError::IOError { source: e, path: sealed.plain.to_string_lossy().to_string() })?;
+            if last{
+                break;
+            }
+        }
+        drop(out);
+        drop(input);
+        sealed.digest = Some(digest.digest128());
+        sealed._lock = Some(lock);
+        let db = open(&sealed.plain)?;
+        debug!("Decrypted {} into {}", EscapedPath(&name()), sealed.plain.display());
+        Ok(DbFile { db: Some(db), sealed: Some(sealed) })
+    }
+
+    /// Encrypts the plain copy back unless it is unchanged.
+    fn finish(&self) -> Result<(), IntegrityWatcherError> {
+        if self.digest.is_some() && self.digest == Some(digest_of(&self.plain)?){
+            return Ok(());
+        }
+        Header{ prefix: random()?, ..self.header }.seal(&self.plain, &self.path, &self.key)
+    }
+}
+
+impl Drop for Sealed {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.plain){
+            error!("Can't remove decrypted database {}: {e}", self.plain.display());
+        }
+    }
+}
+
+/// Key derivation parameters and nonce prefix of an encrypted DB.
+#[derive(Clone, Copy)]
+struct Header{
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: [u8; SALT_LEN],
+    prefix: [u8; PREFIX_LEN],
+}
+
+impl Header {
+    /// Argon2's recommended parameters, a new salt and prefix.
+    fn new() -> Result<Self, IntegrityWatcherError> {
+        let params = Params::default();
+        Ok(Header { m_cost: params.m_cost(), t_cost: params.t_cost(), p_cost: params.p_cost(), salt: random()?, prefix: random()? })
+    }
+
+    fn parse(raw: &[u8; HEADER_LEN]) -> Self {
+        let u32_at = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().expect("4 bytes"));
+        let at = MAGIC.len() + 12;
+        Header {
+            m_cost: u32_at(MAGIC.len()),
+            t_cost: u32_at(MAGIC.len() + 4),
+            p_cost: u32_at(MAGIC.len() + 8),
+            salt: raw[at..at + SALT_LEN].try_into().expect("salt"),
+            prefix: raw[at + SALT_LEN..].try_into().expect("prefix"),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut raw = [0; HEADER_LEN];
+        let fields = [MAGIC.as_slice(), &self.m_cost.to_le_bytes(), &self.t_cost.to_le_bytes(), &self.p_cost.to_le_bytes(), &self.salt, &self.prefix];
+        let mut at = 0;
+        for field in fields{
+            raw[at..at + field.len()].copy_from_slice(field);
+            at += field.len();
+        }
+        raw
+    }
+
+    fn key(&self, path: &Path, secret: &Secret) -> Result<Zeroizing<[u8; 32]>, IntegrityWatcherError> {
+        let invalid = |e: argon2::Error| IntegrityWatcherError::Decrypt { path: path.to_string_lossy().to_string(), reason: format!("key derivation {e}") };
+        if self.m_cost > MAX_M_COST{
+            return Err(IntegrityWatcherError::Decrypt { path: path.to_string_lossy().to_string(), reason: format!("key derivation asks for {} KiB", self.m_cost) });
+        }
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32)).map_err(invalid)?;
+        let mut key = Zeroizing::new([0; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(&secret.0, &self.salt, &mut *key).map_err(invalid)?;
+        Ok(key)
+    }
+
+    /// Encrypts the plain DB `plain` to `path`, through a temp file renamed
+    /// over it once complete.
+    fn seal(self, plain: &Path, path: &Path, key: &[u8; 32]) -> Result<(), IntegrityWatcherError> {
+        let tmp = PathBuf::from(format!("{}.enc.tmp", path.display()));
+        let io_err = |p: &Path| { let p = p.to_string_lossy().to_string(); move |e| IntegrityWatcherError::IOError { source: e, path: p } };
+        let mut input = File::open(plain).map_err(io_err(plain))?;
+        let mut out = io::BufWriter::new(File::create(&tmp).map_err(io_err(&tmp))?);
+        let raw = self.to_bytes();
+        out.write_all(&raw).map_err(io_err(&tmp))?;
+        let cipher = XChaCha20Poly1305::new(key.into());
+        let mut buf = vec![0; CHUNK];
+        for counter in 0..{
+            let n = read_full(&mut input, &mut buf).map_err(io_err(plain))?;
+            let last = n < CHUNK;
+            let chunk = cipher.encrypt(&nonce(&raw, counter, last), Payload{ msg: &buf[..n], aad: &raw })
+                .map_err(|_| IntegrityWatcherError::IOError { source: io::Error::other("encryption failed"), path: tmp.to_string_lossy().to_string() })?;
+            out.write_all(&chunk).map_err(io_err(&tmp))?;
+            if last{
+                break;
+            }
+        }
+        out.into_inner().map_err(|e| e.into_error()).and_then(|f| f.sync_all()).map_err(io_err(&tmp))?;
+        fs::rename(&tmp, path).map_err(io_err(path))?;
+        debug!("Encrypted {}", path.display());
+        Ok(())
+    }
+}
+
+/// Nonce of chunk `counter` of the DB with header `raw`.
+fn nonce(raw: &[u8; HEADER_LEN], counter: u64, last: bool) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[..PREFIX_LEN].copy_from_slice(&raw[HEADER_LEN - PREFIX_LEN..]);
+    nonce[PREFIX_LEN..].copy_from_slice(&(counter | if last { LAST_CHUNK } else { 0 }).to_be_bytes());
+    nonce
+}
+
+fn random<const N: usize>() -> Result<[u8; N], IntegrityWatcherError> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|e| IntegrityWatcherError::IOError { source: io::Error::other(e.to_string()), path: "getrandom".to_owned() })?;
+    Ok(bytes)
+}
+
+/// A new file for the plain copy of an encrypted DB, only the user can read.
+fn plain_copy() -> Result<(PathBuf, io::BufWriter<File>), IntegrityWatcherError> {
+    let [a, b, c, d, e, f, g, h] = random()?;
+    let path = std::env::temp_dir().join(format!("integrity-checker-{}-{:016x}.redb", std::process::id(), u64::from_le_bytes([a, b, c, d, e, f, g, h])));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options.open(&path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
+    Ok((path, io::BufWriter::new(file)))
+}
+
+/// Removes the plain copies left in the temp dir by runs that were killed or
+/// crashed, which can't remove them themselves. Returns how many.
+pub fn remove_stale_plain_copies() -> usize {
+    let Ok(dir) = fs::read_dir(std::env::temp_dir()) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in dir.flatten(){
+        let Some(pid) = entry.file_name().to_str().and_then(plain_copy_pid) else {
+            continue;
+        };
+        if pid == std::process::id() || !is_stale(pid, &entry){
+            continue;
+        }
+        match fs::remove_file(entry.path()){
+            Ok(()) => removed += 1,
+            // e.g. the copy of another user in a shared temp dir
+            Err(e) => debug!("Can't remove stale decrypted database {}: {e}", entry.path().display()),
+        }
+    }
+    removed
+}
+
+/// Process id in the name of a plain copy made by [`plain_copy`].
+fn plain_copy_pid(name: &str) -> Option<u32> {
+    let (pid, random) = name.strip_prefix("integrity-checker-")?.strip_suffix(".redb")?.split_once('-')?;
+    if random.len() != 16 || !random.bytes().all(|b| b.is_ascii_hexdigit()){
+        return None;
+    }
+    pid.parse().ok()
+}
+
+/// Whether the process `pid` that made a plain copy is gone.
+#[cfg(target_os = "linux")]
+fn is_stale(pid: u32, _entry: &fs::DirEntry) -> bool {
+    !Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Without a portable way to tell whether the process `pid` still runs, a copy
+/// untouched for a day is taken as left behind.
+#[cfg(not(target_os = "linux"))]
+fn is_stale(_pid: u32, entry: &fs::DirEntry) -> bool {
+    entry.metadata().and_then(|m| m.modified()).is_ok_and(|t| t.elapsed().is_ok_and(|age| age > Duration::from_secs(24 * 3600)))
+}
+
+/// Pattern of the plain copies, for scans to skip them.
+pub fn plain_copies() -> String {
+    format!("{}/integrity-checker-*.redb", std::env::temp_dir().display())
+}
+
+fn digest_of(path: &Path) -> Result<u128, IntegrityWatcherError> {
+    let mut input = File::open(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
+    let mut digest = Xxh3::new();
+    let mut buf = vec![0; CHUNK];
+    loop{
+        match input.read(&mut buf){
+            Ok(0) => return Ok(digest.digest128()),
+            Ok(n) => digest.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() }),
+        }
+    }
+}
+
+/// Reads until `buf` is full or the input ends.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len(){
+        match input.read(&mut buf[n..]){
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// Locks the encrypted DB at `path` against other processes, like redb locks
+/// plain ones. Encrypting replaces the file, so the lock has to be on the file
+/// still at `path`.
+fn lock(path: &Path) -> Result<File, IntegrityWatcherError> {
+    let name = || path.to_string_lossy().to_string();
+    let wait = Duration::from_millis(LOCK_WAIT_MS.load(Ordering::Relaxed));
+    let started = Instant::now();
+    loop{
+        let file = File::open(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: name() })?;
+        match file.try_lock(){
+            Ok(()) if same_file(&file, path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: name() })? => return Ok(file),
+            Ok(()) => {}
+            Err(fs::TryLockError::WouldBlock) if started.elapsed() < wait => {
+                debug!("Database {} is locked, waiting", path.display());
+                std::thread::sleep(RETRY_INTERVAL.min(wait - started.elapsed()));
+            }
+            Err(fs::TryLockError::WouldBlock) => return Err(IntegrityWatcherError::DatabaseLocked { path: name() }),
+            Err(fs::TryLockError::Error(e)) => return Err(IntegrityWatcherError::IOError { source: e, path: name() }),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (file.metadata()?, fs::metadata(path)?);
+    Ok((a.dev(), a.ino()) == (b.dev(), b.ino()))
+}
+
+#[cfg(not(unix))]
+fn same_file(_file: &File, _path: &Path) -> io::Result<bool> {
+    // the file can't be replaced while open
+    Ok(true)
+}
+
+fn retry_locked(path: &Path, open: impl Fn(&Path) -> Result<Database, DatabaseError>) -> Result<Database, IntegrityWatcherError> {
+    let wait = Duration::from_millis(LOCK_WAIT_MS.load(Ordering::Relaxed));
+    let started = Instant::now();
+    loop{
+        match open(path){
+            Err(DatabaseError::DatabaseAlreadyOpen) if started.elapsed() < wait => {
+                debug!("Database {} is locked, waiting", path.display());
+                std::thread::sleep(RETRY_INTERVAL.min(wait - started.elapsed()));
+            }
+            Err(DatabaseError::DatabaseAlreadyOpen) => {
+                return Err(IntegrityWatcherError::DatabaseLocked { path: path.to_string_lossy().to_string() });
+            }
+            r => return Ok(r?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{ReadableDatabase, TableDefinition};
+
+    #[test]
+    fn test_database_locked() {
+        let path = std::env::current_dir().unwrap().join("test_db_locked.redb");
+        let _ = std::fs::remove_file(&path);
+        let db = create(&path).unwrap();
+        let locked = open(&path).err().unwrap();
+        assert!(matches!(&locked, IntegrityWatcherError::DatabaseLocked { path: p } if p.ends_with("test_db_locked.redb")), "{locked}");
+        assert!(locked.to_string().contains("another process has it open"), "{locked}");
+        assert!(matches!(create(&path), Err(IntegrityWatcherError::DatabaseLocked { .. })));
+
+        // released while waiting
+        set_lock_wait(Duration::from_secs(5));
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            drop(db);
+        });
+        let started = Instant::now();
+        assert!(open(&path).is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        set_lock_wait(Duration::ZERO);
+        release.join().unwrap();
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted() {
+        const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("t");
+        let path = std::env::current_dir().unwrap().join("test_db_encrypted.redb");
+        let _ = std::fs::remove_file(&path);
+        let secret = Secret::new(b"correct horse".to_vec());
+        let put = |db: &Database, key: &str, value: &[u8]| {
+            let write_txn = db.begin_write().unwrap();
+            write_txn.open_table(TABLE).unwrap().insert(key, value).unwrap();
+            write_txn.commit().unwrap();
+        };
+        let get = |db: &Database, key: &str| db.begin_read().unwrap().open_table(TABLE).unwrap().get(key).unwrap().map(|v| v.value().to_vec());
+
+        // a plain DB converted in place, spanning several chunks
+        let big = vec![7; 3 * CHUNK];
+        put(&create_with(&path, None).unwrap(), "big", &big);
+        encrypt(&path, &secret).unwrap();
+        assert!(is_encrypted(&path));
+        assert!(!fs::read(&path).unwrap().windows(64).any(|w| w == &big[..64]));
+        assert!(matches!(encrypt(&path, &secret), Err(IntegrityWatcherError::InvalidArgument(_))));
+
+        let no_key = open_with(&path, None).err().unwrap();
+        assert!(matches!(&no_key, IntegrityWatcherError::Encrypted { .. }), "{no_key}");
+        assert!(no_key.to_string().contains(PASSPHRASE_ENV), "{no_key}");
+        let wrong = open_with(&path, Some(&Secret::new(b"battery staple".to_vec()))).err().unwrap();
+        assert!(matches!(&wrong, IntegrityWatcherError::Decrypt { .. }), "{wrong}");
+
+        let db = open_with(&path, Some(&secret)).unwrap();
+        assert!(db.is_encrypted());
+        assert_eq!(get(&db, "big"), Some(big.clone()));
+        // held like a plain DB
+        assert!(matches!(open_with(&path, Some(&secret)), Err(IntegrityWatcherError::DatabaseLocked { .. })));
+        put(&db, "small", b"x");
+        db.close().unwrap();
+        assert!(is_encrypted(&path));
+        let db = open_with(&path, Some(&secret)).unwrap();
+        assert_eq!((get(&db, "big"), get(&db, "small")), (Some(big), Some(b"x".to_vec())));
+        drop(db);
+
+        // cut after a chunk
+        let sealed = fs::read(&path).unwrap();
+        fs::write(&path, &sealed[..HEADER_LEN + CHUNK + TAG]).unwrap();
+        assert!(matches!(open_with(&path, Some(&secret)), Err(IntegrityWatcherError::Decrypt { .. })));
+        fs::remove_file(&path).unwrap();
+
+        // new DBs are encrypted with a key
+        let db = create_with(&path, Some(&secret)).unwrap();
+        put(&db, "new", b"y");
+        db.close().unwrap();
+        assert!(is_encrypted(&path));
+        assert_eq!(get(&open_with(&path, Some(&secret)).unwrap(), "new"), Some(b"y".to_vec()));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_remove_stale_plain_copies() {
+        // above the largest pid_max, never a running process
+        let stale = std::env::temp_dir().join("integrity-checker-4194305-00000000deadbeef.redb");
+        let running = std::env::temp_dir().join(format!("integrity-checker-{}-00000000deadbeef.redb", std::process::id()));
+        let other = std::env::temp_dir().join("integrity-checker-4194305-notours.redb");
+        for path in [&stale, &running, &other]{
+            fs::write(path, b"plain").unwrap();
+        }
+        assert!(remove_stale_plain_copies() >= 1);
+        assert!(!stale.exists());
+        assert!(running.exists() && other.exists());
+        fs::remove_file(running).unwrap();
+        fs::remove_file(other).unwrap();
+    }
+}
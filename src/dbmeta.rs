@@ -0,0 +1,354 @@
+use super::error::IntegrityWatcherError;
+use super::pathfold::PathFold;
+use redb::{Database, TableDefinition, ReadableDatabase, ReadableTable, WriteTransaction};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Settings of entries table `table`, each baseline in a DB has its own.
+pub fn meta_table_name(table: &str) -> String {
+    format!("{table}_meta")
+}
+
+fn meta_table(name: &str) -> TableDefinition<'_, &'static str, &'static str> {
+    TableDefinition::new(name)
+}
+
+const KEY_NO_DIRS: &str = "no_dirs";
+const KEY_TRACK_INODE: &str = "track_inode";
+const KEY_HASH_COMMAND: &str = "hash_command";
+const KEY_PREFILTER: &str = "prefilter";
+const KEY_HASH_LINK_CONTENT: &str = "hash_link_content";
+const KEY_PATH_FOLD: &str = "path_fold";
+const KEY_DETECT_TYPE: &str = "detect_type";
+const KEY_STAT_ONLY: &str = "stat_only";
+const KEY_POLICY: &str = "policy";
+const KEY_STRIPE_SIZE: &str = "stripe_size";
+const KEY_FLAGS: &str = "flags";
+const KEY_MAX_DEPTH: &str = "max_depth";
+const KEY_OWNER: &str = "owner";
+const KEY_HOST: &str = "host";
+const KEY_VERSION: &str = "version";
+const KEY_MAINTENANCE_UNTIL: &str = "maintenance_until";
+const KEY_MAINTENANCE_PATHS: &str = "maintenance_paths";
+const KEY_MAINTENANCE_SUPPRESSED: &str = "maintenance_suppressed";
+
+/// Host the entries of `table` belong to, set by `--host-prefix`.
+pub fn load_host(db: &Database, table: &str) -> Result<Option<String>, IntegrityWatcherError> {
+    let name = meta_table_name(table);
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let table = match read_txn.open_table(meta_table(&name)){
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(table.get(KEY_HOST)?.map(|v| v.value().to_owned()))
+}
+
+pub fn store_host(db: &Database, table: &str, host: &str) -> Result<(), IntegrityWatcherError> {
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    store_host_in(&write_txn, table, host)?;
+    write_txn.commit()?;
+    Ok(())
+}
+
+pub fn store_host_in(write_txn: &WriteTransaction, table: &str, host: &str) -> Result<(), IntegrityWatcherError> {
+    let name = meta_table_name(table);
+    let mut table = write_txn.open_table(meta_table(&name))?;
+    table.insert(KEY_HOST, host)?;
+    Ok(())
+}
+
+/// Version of the tool that last created or updated the baseline of `table`,
+/// see `versions`.
+pub fn load_version(db: &Database, table: &str) -> Result<Option<String>, IntegrityWatcherError> {
+    let name = meta_table_name(table);
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let table = match read_txn.open_table(meta_table(&name)){
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(table.get(KEY_VERSION)?.map(|v| v.value().to_owned()))
+}
+
+pub fn store_version(db: &Database, table: &str, version: &str) -> Result<(), IntegrityWatcherError> {
+    let write_txn = db.begin_write().map_err(Box::new)?;
+    {
+        let mut table = write_txn.open_table(meta_table(&meta_table_name(table)))?;
+        table.insert(KEY_VERSION, version)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+/// Everything stored for `table`, settings, host and version, for `export`.
+pub fn load_all(db: &Database, table: &str) -> Result<BTreeMap<String, String>, IntegrityWatcherError> {
+    let name = meta_table_name(table);
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let table = match read_txn.open_table(meta_table(&name)){
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    table.iter()?.map(|k| k.map(|k| (k.0.value().to_owned(), k.1.value().to_owned())).map_err(Into::into)).collect()
+}
+
+/// Stores what `load_all` returned, e.g. for `export::import`.
+pub fn store_all_in(write_txn: &WriteTransaction, table: &str, meta: &BTreeMap<String, String>) -> Result<(), IntegrityWatcherError> {
+    let name = meta_table_name(table);
+    let mut table = write_txn.open_table(meta_table(&name))?;
+    for (key, value) in meta{
+        table.insert(key.as_str(), value.as_str())?;
+    }
+    Ok(())
+}
+
+/// `--maintenance-until` window: until the deadline findings under the paths
+/// are expected and reported at info level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Maintenance{
+    /// seconds since the epoch
+    pub until: u64,
+    pub paths: Vec<String>,
+    /// findings downgraded so far, for the `--update` reminder once it's over
+    pub suppressed: u64,
+}
+
+impl Maintenance {
+    pub fn load(db: &Database, table: &str) -> Result<Option<Self>, IntegrityWatcherError> {
+        let name = meta_table_name(table);
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = match read_txn.open_table(meta_table(&name)){
+            Ok(t) => t,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let Some(until) = table.get(KEY_MAINTENANCE_UNTIL)?.and_then(|v| v.value().parse().ok()) else {
+            return Ok(None);
+        };
+        Ok(Some(Maintenance{
+            until,
+            paths: table.get(KEY_MAINTENANCE_PATHS)?.map(|v| v.value().lines().map(str::to_owned).collect()).unwrap_or_default(),
+            suppressed: table.get(KEY_MAINTENANCE_SUPPRESSED)?.and_then(|v| v.value().parse().ok()).unwrap_or_default(),
+        }))
+    }
+
+    pub fn store(&self, db: &Database, table: &str) -> Result<(), IntegrityWatcherError> {
+        let name = meta_table_name(table);
+        let write_txn = db.begin_write().map_err(Box::new)?;
+        {
+            let mut table = write_txn.open_table(meta_table(&name))?;
+            table.insert(KEY_MAINTENANCE_UNTIL, self.until.to_string().as_str())?;
+            table.insert(KEY_MAINTENANCE_PATHS, self.paths.join("\n").as_str())?;
+            table.insert(KEY_MAINTENANCE_SUPPRESSED, self.suppressed.to_string().as_str())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Ends the window, e.g. once `--update` took its changes.
+    pub fn clear(db: &Database, table: &str) -> Result<(), IntegrityWatcherError> {
+        let name = meta_table_name(table);
+        let write_txn = db.begin_write().map_err(Box::new)?;
+        {
+            let mut table = write_txn.open_table(meta_table(&name))?;
+            for key in [KEY_MAINTENANCE_UNTIL, KEY_MAINTENANCE_PATHS, KEY_MAINTENANCE_SUPPRESSED]{
+                table.remove(key)?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn is_active(&self, now: u64) -> bool {
+        now < self.until
+    }
+}
+
+/// Scan settings a database was created with. Databases created before the
+/// metadata table existed load as the defaults.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DBSettings{
+    pub no_dirs: bool,
+    pub track_inode: bool,
+    /// `--hash-command` template, SHA-256 when not set
+    pub hash_command: Option<String>,
+    /// `--prefilter`: files are compared by XXH3-64, which detects changes but
+    /// not deliberate tampering
+    pub prefilter: bool,
+    /// `--hash-link-content`: symlinks to files carry the hash of their target
+    pub hash_link_content: bool,
+    /// `--path-fold`: how paths are folded into entry keys
+    pub path_fold: PathFold,
+    /// `--detect-type`: files carry their content type
+    pub detect_type: bool,
+    /// `--stat-only`: files carry no content hash, only their metadata is
+    /// compared
+    pub stat_only: bool,
+    /// `--policy` rules, one per line, see `policy::Policy`
+    pub policy: Option<String>,
+    /// `--parallel-file-hash`: files carry the tree hash of stripes this long,
+    /// see `treehash`
+    pub stripe_size: Option<u64>,
+    /// `--flags`: files and directories carry their immutable and append-only
+    /// inode flags
+    pub flags: bool,
+    /// `--max-depth`: levels below the roots the baseline goes down
+    pub max_depth: Option<usize>,
+    /// `--owner`: files and directories carry their owning user and group
+    pub owner: bool,
+}
+
+impl DBSettings {
+    pub fn load(db: &Database, table: &str) -> Result<Self, IntegrityWatcherError> {
+        let name = meta_table_name(table);
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = match read_txn.open_table(meta_table(&name)){
+            Ok(t) => t,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let flag = |key| -> Result<bool, IntegrityWatcherError> {
+            Ok(table.get(key)?.is_some_and(|v| v.value() == "true"))
+        };
+        Ok(DBSettings{
+            no_dirs: flag(KEY_NO_DIRS)?,
+            track_inode: flag(KEY_TRACK_INODE)?,
+            hash_command: table.get(KEY_HASH_COMMAND)?.map(|v| v.value().to_owned()),
+            prefilter: flag(KEY_PREFILTER)?,
+            hash_link_content: flag(KEY_HASH_LINK_CONTENT)?,
+            path_fold: table.get(KEY_PATH_FOLD)?.map(|v| v.value().parse()).transpose()?.unwrap_or_default(),
+            detect_type: flag(KEY_DETECT_TYPE)?,
+            stat_only: flag(KEY_STAT_ONLY)?,
+            policy: table.get(KEY_POLICY)?.map(|v| v.value().to_owned()),
+            stripe_size: table.get(KEY_STRIPE_SIZE)?.map(|v| v.value().parse())
+                .transpose().map_err(|e| IntegrityWatcherError::InvalidArgument(format!("stored stripe size: {e}")))?,
+            flags: flag(KEY_FLAGS)?,
+            max_depth: table.get(KEY_MAX_DEPTH)?.map(|v| v.value().parse())
+                .transpose().map_err(|e| IntegrityWatcherError::InvalidArgument(format!("stored max depth: {e}")))?,
+            owner: flag(KEY_OWNER)?,
+        })
+    }
+
+    pub fn store(&self, db: &Database, table: &str) -> Result<(), IntegrityWatcherError> {
+        let write_txn = db.begin_write().map_err(Box::new)?;
+        self.store_in(&write_txn, table)?;
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Stores the settings as part of a bigger change, e.g. `--rehash`.
+    pub fn store_in(&self, write_txn: &WriteTransaction, table: &str) -> Result<(), IntegrityWatcherError> {
+        let name = meta_table_name(table);
+        let mut table = write_txn.open_table(meta_table(&name))?;
+        for (key, value) in [(KEY_NO_DIRS, self.no_dirs), (KEY_TRACK_INODE, self.track_inode), (KEY_PREFILTER, self.prefilter), (KEY_HASH_LINK_CONTENT, self.hash_link_content), (KEY_DETECT_TYPE, self.detect_type), (KEY_STAT_ONLY, self.stat_only), (KEY_FLAGS, self.flags), (KEY_OWNER, self.owner)]{
+            table.insert(key, if value { "true" } else { "false" })?;
+        }
+        match &self.hash_command{
+            Some(cmd) => { table.insert(KEY_HASH_COMMAND, cmd.as_str())?; },
+            None => { table.remove(KEY_HASH_COMMAND)?; },
+        }
+        match &self.policy{
+            Some(policy) => { table.insert(KEY_POLICY, policy.as_str())?; },
+            None => { table.remove(KEY_POLICY)?; },
+        }
+        match self.stripe_size{
+            Some(size) => { table.insert(KEY_STRIPE_SIZE, size.to_string().as_str())?; },
+            None => { table.remove(KEY_STRIPE_SIZE)?; },
+        }
+        match self.max_depth{
+            Some(depth) => { table.insert(KEY_MAX_DEPTH, depth.to_string().as_str())?; },
+            None => { table.remove(KEY_MAX_DEPTH)?; },
+        }
+        table.insert(KEY_PATH_FOLD, self.path_fold.to_string().as_str())?;
+        Ok(())
+    }
+
+    /// Bytes of the content hash of fully hashed files, `None` for
+    /// `--hash-command` output, which can have any length.
+    pub fn hash_len(&self) -> Option<usize> {
+        if self.stat_only{
+            return Some(0);
+        }
+        match (&self.hash_command, self.prefilter){
+            (Some(_), _) => None,
+            (None, true) => Some(8),
+            (None, false) => Some(32),
+        }
+    }
+
+    /// Name of the hash algorithm for messages.
+    pub fn algorithm(&self) -> Cow<'_, str> {
+        if self.stat_only{
+            return "no content hash (--stat-only)".into();
+        }
+        match (&self.hash_command, self.prefilter, self.stripe_size){
+            (Some(cmd), _, _) => cmd.into(),
+            (None, true, _) => "XXH3-64 prefilter".into(),
+            (None, false, Some(size)) => format!("SHA-256 tree of {} MiB stripes", size >> 20).into(),
+            (None, false, None) => "SHA-256".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::DEFAULT_TABLE;
+    use std::fs;
+
+    #[test]
+    fn test_settings_roundtrip() {
+        let mut path = std::env::current_dir().unwrap();
+        path.push("test_db_settings_roundtrip");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        let db = Database::create(path.join("database.redb")).unwrap();
+
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), DBSettings::default());
+
+        let settings = DBSettings{ no_dirs: true, track_inode: false, hash_command: Some("b3sum --no-names {}".to_owned()), prefilter: false, hash_link_content: true, path_fold: "icase,nfd".parse().unwrap(), detect_type: true, stat_only: false, policy: Some("/var/cache stat-only\n".to_owned()), stripe_size: None, flags: true, max_depth: None, owner: true };
+        settings.store(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), settings);
+
+        let settings = DBSettings{ hash_command: None, ..settings };
+        settings.store(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), settings);
+
+        let settings = DBSettings{ stripe_size: Some(16 << 20), max_depth: Some(2), ..settings };
+        settings.store(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), settings);
+        assert_eq!(settings.algorithm(), "SHA-256 tree of 16 MiB stripes");
+
+        let settings = DBSettings{ prefilter: true, stripe_size: None, ..settings };
+        settings.store(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), settings);
+        assert_eq!(settings.algorithm(), "XXH3-64 prefilter");
+
+        let settings = DBSettings{ prefilter: false, stat_only: true, ..settings };
+        settings.store(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), settings);
+        assert_eq!(settings.algorithm(), "no content hash (--stat-only)");
+
+        assert_eq!(load_host(&db, DEFAULT_TABLE).unwrap(), None);
+        let write_txn = db.begin_write().unwrap();
+        store_host_in(&write_txn, DEFAULT_TABLE, "web01").unwrap();
+        write_txn.commit().unwrap();
+        assert_eq!(load_host(&db, DEFAULT_TABLE).unwrap().as_deref(), Some("web01"));
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), settings);
+
+        assert_eq!(Maintenance::load(&db, DEFAULT_TABLE).unwrap(), None);
+        let maintenance = Maintenance{ until: 1717221600, paths: vec!["/usr".to_owned(), "/lib".to_owned()], suppressed: 3 };
+        maintenance.store(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(Maintenance::load(&db, DEFAULT_TABLE).unwrap(), Some(maintenance.clone()));
+        assert!(maintenance.is_active(1717221599) && !maintenance.is_active(1717221600));
+        Maintenance::clear(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(Maintenance::load(&db, DEFAULT_TABLE).unwrap(), None);
+        assert_eq!(DBSettings::load(&db, DEFAULT_TABLE).unwrap(), settings);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
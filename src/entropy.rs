@@ -0,0 +1,98 @@
+//! `--entropy`: Shannon entropy of the content of files, counted over the
+//! bytes a check reads for hashing anyway. Text and code stay well below
+//! [`PACKED`] bits per byte, compressed, packed or encrypted content comes
+//! close to 8, so a changed binary or config turning high-entropy stands out.
+//!
+//! Only the built in SHA-256 and XXH3 hashing counts, files hashed by
+//! `--hash-command`, as stripes or by their head aren't measured. The entropy
+//! of the baseline content isn't known, the DB doesn't keep it.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Bits per byte from which content is reported as likely packed or encrypted.
+pub const PACKED: f64 = 7.5;
+
+/// Byte histogram of the content read so far.
+#[derive(Debug, Clone)]
+pub struct Counter{
+    counts: [u64; 256],
+    total: u64,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Counter{ counts: [0; 256], total: 0 }
+    }
+}
+
+impl Counter {
+    pub fn update(&mut self, data: &[u8]) {
+        for b in data{
+            self.counts[*b as usize] += 1;
+        }
+        self.total += data.len() as u64;
+    }
+
+    /// Shannon entropy in bits per byte, none for empty content.
+    pub fn bits_per_byte(&self) -> Option<f64> {
+        if self.total == 0{
+            return None;
+        }
+        let total = self.total as f64;
+        Some(self.counts.iter().filter(|&&n| n > 0).map(|&n| {
+            let p = n as f64 / total;
+            -p * p.log2()
+        }).sum())
+    }
+}
+
+/// Finding text for content of `bits` per byte, e.g. ` new content entropy
+/// 7.98 bits/byte, likely packed or encrypted`.
+pub fn describe(bits: f64) -> String {
+    match bits >= PACKED{
+        true => format!(" new content entropy {bits:.2} bits/byte, likely packed or encrypted"),
+        false => format!(" new content entropy {bits:.2} bits/byte"),
+    }
+}
+
+/// Entropy of the files hashed by a check, by path as walked, until the check
+/// takes it when comparing the file.
+#[derive(Debug, Default)]
+pub struct Measured(Mutex<HashMap<String, f64>>);
+
+impl Measured {
+    pub fn record(&self, path: &str, bits: f64) {
+        self.0.lock().expect("entropy lock").insert(path.to_owned(), bits);
+    }
+
+    pub fn take(&self, path: &str) -> Option<f64> {
+        self.0.lock().expect("entropy lock").remove(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy() {
+        let bits = |data: &[u8]| {
+            let mut counter = Counter::default();
+            for chunk in data.chunks(7){
+                counter.update(chunk);
+            }
+            counter.bits_per_byte()
+        };
+        assert_eq!(bits(b""), None);
+        assert_eq!(bits(b"aaaa"), Some(0.0));
+        assert_eq!(bits(b"abab"), Some(1.0));
+        let all: Vec<u8> = (0..=255).cycle().take(4096).collect();
+        assert_eq!(bits(&all), Some(8.0));
+        let text = bits(b"# /etc/ssh/sshd_config\nPermitRootLogin no\nPasswordAuthentication no\n").unwrap();
+        assert!(text > 3.0 && text < 5.0, "{text}");
+
+        assert_eq!(describe(4.5), " new content entropy 4.50 bits/byte");
+        assert_eq!(describe(7.981), " new content entropy 7.98 bits/byte, likely packed or encrypted");
+    }
+}
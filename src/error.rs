@@ -2,7 +2,7 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum IntegrityWatcherError {
-    #[error("IO error {source} file {path}")]
+    #[error("IO error {source} file {}", super::types::EscapedPath(.path))]
     IOError{
         #[source]
         source: std::io::Error,
@@ -36,9 +36,142 @@ pub enum IntegrityWatcherError {
     #[error("Reqwest error {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    #[error("Database {} contains no integrity table {table}, did you run --create?", super::types::EscapedPath(.db))]
+    NoBaseline{
+        db: String,
+        table: String,
+    },
+
+    #[error("Database {} is locked, another process has it open", super::types::EscapedPath(.path))]
+    DatabaseLocked{
+        path: String,
+    },
+
+    #[error("Database {} is encrypted, give its key with --key-file or {}", super::types::EscapedPath(.path), super::dbfile::PASSPHRASE_ENV)]
+    Encrypted{
+        path: String,
+    },
+
+    #[error("Can't decrypt database {}: {reason}", super::types::EscapedPath(.path))]
+    Decrypt{
+        path: String,
+        reason: String,
+    },
+
+    #[error("Incompatible database {db}: {reason}")]
+    IncompatibleDB{
+        db: String,
+        reason: String,
+    },
+
+    #[error("Timeout after {:.1}s {}", .timeout.as_secs_f32(), super::types::EscapedPath(.path))]
+    Timeout{
+        timeout: std::time::Duration,
+        path: String,
+    },
+
+    #[error("Update would remove {removed} of {entries} entries of {}, most under {prefixes}; none were removed, run it with --force-removals if they are gone", super::types::EscapedPath(.db))]
+    MassRemoval{
+        db: String,
+        removed: u64,
+        entries: u64,
+        prefixes: String,
+    },
+
+    #[error("Invalid argument {0}")]
+    InvalidArgument(String),
+
+    #[error("Hash command {reason} file {}", super::types::EscapedPath(.path))]
+    HashCommand{
+        reason: String,
+        path: String,
+    },
+
+    #[error("Invalid hash {}", super::types::EscapedPath(.0))]
+    InvalidHash(String),
+
+    #[error("TLS error {0}")]
+    Tls(String),
+
+    #[error("Invalid report {0}")]
+    Report(#[from] serde_json::Error),
+
+    #[error("Push to {url} rejected with {status}: {reason}")]
+    PushRejected{
+        url: String,
+        status: u16,
+        reason: String,
+    },
+
     #[error("Invalid response {status} in hash {hash}")]
     InvalidResponse{
         status: u16,
         hash: super::types::Hash
     }
-}
\ No newline at end of file
+}
+/// An error as `--json-errors` writes it to stderr:
+/// `{"kind":"io","message":"IO error ...","path":"/etc/missing"}`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ErrorReport{
+    /// the variant, e.g. `io`, `no_baseline` or `invalid_argument`
+    pub kind: String,
+    /// the text the error is logged with
+    pub message: String,
+    /// the file or database the error is about, if any
+    pub path: Option<String>,
+}
+
+impl IntegrityWatcherError {
+    /// Name of the variant for `ErrorReport::kind`.
+    pub fn kind(&self) -> &'static str {
+        match self{
+            IntegrityWatcherError::IOError{ .. } => "io",
+            IntegrityWatcherError::Join(_) => "join",
+            IntegrityWatcherError::Aquire(_) => "acquire",
+            IntegrityWatcherError::DB(_) => "db",
+            IntegrityWatcherError::DBStorage(_) => "db_storage",
+            IntegrityWatcherError::DBTransaction(_) => "db_transaction",
+            IntegrityWatcherError::DBTable(_) => "db_table",
+            IntegrityWatcherError::DBCommit(_) => "db_commit",
+            IntegrityWatcherError::SystemTime(_) => "system_time",
+            IntegrityWatcherError::Reqwest(_) => "http",
+            IntegrityWatcherError::NoBaseline{ .. } => "no_baseline",
+            IntegrityWatcherError::DatabaseLocked{ .. } => "database_locked",
+            IntegrityWatcherError::Encrypted{ .. } => "encrypted",
+            IntegrityWatcherError::Decrypt{ .. } => "decrypt",
+            IntegrityWatcherError::IncompatibleDB{ .. } => "incompatible_db",
+            IntegrityWatcherError::Timeout{ .. } => "timeout",
+            IntegrityWatcherError::MassRemoval{ .. } => "mass_removal",
+            IntegrityWatcherError::InvalidArgument(_) => "invalid_argument",
+            IntegrityWatcherError::HashCommand{ .. } => "hash_command",
+            IntegrityWatcherError::InvalidHash(_) => "invalid_hash",
+            IntegrityWatcherError::Tls(_) => "tls",
+            IntegrityWatcherError::Report(_) => "report",
+            IntegrityWatcherError::PushRejected{ .. } => "push_rejected",
+            IntegrityWatcherError::InvalidResponse{ .. } => "invalid_response",
+        }
+    }
+
+    /// The file, database or URL the error is about.
+    pub fn path(&self) -> Option<&str> {
+        match self{
+            IntegrityWatcherError::IOError{ path, .. }
+            | IntegrityWatcherError::DatabaseLocked{ path }
+            | IntegrityWatcherError::Encrypted{ path }
+            | IntegrityWatcherError::Decrypt{ path, .. }
+            | IntegrityWatcherError::Timeout{ path, .. }
+            | IntegrityWatcherError::HashCommand{ path, .. } => Some(path),
+            IntegrityWatcherError::NoBaseline{ db, .. }
+            | IntegrityWatcherError::IncompatibleDB{ db, .. }
+            | IntegrityWatcherError::MassRemoval{ db, .. } => Some(db),
+            IntegrityWatcherError::PushRejected{ url, .. } => Some(url),
+            _ => None,
+        }
+    }
+}
+
+impl From<&IntegrityWatcherError> for ErrorReport {
+    fn from(e: &IntegrityWatcherError) -> Self {
+        ErrorReport{ kind: e.kind().to_owned(), message: e.to_string(), path: e.path().map(str::to_owned) }
+    }
+}
@@ -0,0 +1,169 @@
+//! `--check --format ndjson`: every finding written as one JSON line the moment
+//! the check comes across it, for a supervising process to react on:
+//! `{"ts":1718000000,"path":"/etc/passwd","change_kind":"changed","level":"ERROR","old":"..","new":"..","run_id":".."}`
+//!
+//! Findings within a batch keep their order, those of different directories
+//! come in the order the walk finishes them.
+//!
+//! `--print-schema` prints the JSON Schema of these lines, see [`schema`].
+
+use super::fileops::{Finding, FindingKind};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// A finding of `--check`, one line of `--format ndjson`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Event{
+    /// unix seconds
+    pub ts: u64,
+    /// the path as stored in the DB
+    pub path: String,
+    pub change_kind: FindingKind,
+    /// INFO for acknowledged findings and those in a maintenance window
+    pub level: String,
+    /// the DB entry as `--list` shows it, none for new entries
+    pub old: Option<String>,
+    /// the entry found, none for removed ones
+    pub new: Option<String>,
+    /// bits per byte of the new content with `--entropy`, for changed files
+    pub entropy: Option<f64>,
+    /// of the check that found it, as logged first and stored with `--stats`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+}
+
+impl From<&Finding> for Event {
+    fn from(f: &Finding) -> Self {
+        Event {
+            ts: chrono::Utc::now().timestamp().try_into().unwrap_or_default(),
+            path: f.path.clone(),
+            change_kind: f.kind,
+            level: f.level.as_str().to_owned(),
+            old: f.old.as_ref().map(ToString::to_string),
+            new: f.new.as_ref().map(ToString::to_string),
+            entropy: f.entropy,
+            run_id: None,
+        }
+    }
+}
+
+/// JSON Schema of [`Event`], for consumers validating the stream.
+pub fn schema() -> serde_json::Value {
+    schemars::schema_for!(Event).to_value()
+}
+
+/// Writes events to `out`, flushed line by line so nothing waits in a buffer.
+pub struct EventWriter<W: Write>{
+    out: W,
+    run_id: Option<String>,
+}
+
+impl<W: Write> EventWriter<W> {
+    pub fn new(out: W) -> Self {
+        EventWriter { out, run_id: None }
+    }
+
+    /// Tags every event with `run_id`.
+    pub fn with_run_id(self, run_id: &str) -> Self {
+        EventWriter { run_id: Some(run_id.to_owned()), ..self }
+    }
+
+    pub fn write(&mut self, finding: &Finding) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &Event{ run_id: self.run_id.clone(), ..Event::from(finding) })?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::{AddFileInfo, CheckDB, CheckOptions, WriteToDB};
+    use crate::severity::Changes;
+    use crate::types::{FileMetadata, FileMetadataExt};
+    use redb::Database;
+    use std::io::BufRead;
+    use std::sync::{Arc, Mutex};
+
+    /// Lines written so far, shared with the check writing them.
+    #[derive(Clone, Default)]
+    struct Pipe(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Pipe {
+        fn events(&self) -> Vec<Event> {
+            self.0.lock().unwrap().lines().map(|l| serde_json::from_str(&l.unwrap()).unwrap()).collect()
+        }
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let path = std::env::current_dir().unwrap().join("test_db_events.redb");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::create(&path).unwrap();
+        let file = |hash: u8| FileMetadataExt::File(FileMetadata{ hash: [hash; 32].into(), permissions: 0o100644, modified: 1000, size: 10.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None });
+        WriteToDB::new(&db).add_file_info(&[("/etc/motd".to_owned(), file(1)), ("/etc/gone".to_owned(), file(1))]).unwrap();
+
+        let pipe = Pipe::default();
+        let mut events = EventWriter::new(pipe.clone()).with_run_id("0f8c0b7e-3d1a-4c2b-9e4f-5a6b7c8d9e0f");
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).with_finding_sink(move |f| events.write(&f).unwrap());
+        checker.add_file_info(&[("/etc/motd".to_owned(), file(2))]).unwrap();
+        // written as found, not once the check is done
+        let found = pipe.events();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].path.as_str(), found[0].change_kind, found[0].level.as_str()), ("/etc/motd", FindingKind::Changed, "ERROR"));
+        assert_eq!((found[0].old.clone(), found[0].new.clone()), (Some(file(1).to_string()), Some(file(2).to_string())));
+        assert!(found[0].ts > 0);
+        assert_eq!(found[0].run_id.as_deref(), Some("0f8c0b7e-3d1a-4c2b-9e4f-5a6b7c8d9e0f"));
+
+        checker.add_file_info(&[("/etc/new".to_owned(), file(3))]).unwrap();
+        checker.report_removed(|_| false).unwrap();
+        let found = pipe.events();
+        assert_eq!(found.iter().map(|e| (e.path.as_str(), e.change_kind)).collect::<Vec<_>>(),
+            [("/etc/motd", FindingKind::Changed), ("/etc/new", FindingKind::New), ("/etc/gone", FindingKind::Removed)]);
+        assert!(found[1].old.is_none() && found[2].new.is_none());
+
+        drop(checker);
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = schema();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        assert_eq!(schema["properties"]["change_kind"]["$ref"], "#/$defs/FindingKind");
+
+        let pipe = Pipe::default();
+        let mut events = EventWriter::new(pipe.clone());
+        let file = FileMetadataExt::File(FileMetadata{ hash: [1; 32].into(), permissions: 0o100644, modified: 1000, size: 10.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None });
+        for (kind, old, new) in [(FindingKind::Changed, Some(&file), Some(&file)), (FindingKind::New, None, Some(&file)), (FindingKind::Removed, Some(&file), None)]{
+            events.write(&Finding{ kind, path: "/etc/motd".to_owned(), state: String::new(), level: log::Level::Error, message: String::new(), old: old.cloned(), new: new.cloned(), entropy: None, changes: Changes::default() }).unwrap();
+        }
+        for line in pipe.0.lock().unwrap().lines(){
+            let event: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+            assert!(validator.is_valid(&event), "{event}");
+        }
+
+        let event = serde_json::json!({"ts": 1718000000, "path": "/etc/motd", "change_kind": "changed", "level": "ERROR", "old": null, "new": "x"});
+        assert!(validator.is_valid(&event));
+        for (field, value) in [("change_kind", serde_json::json!("moved")), ("ts", serde_json::json!(-1)), ("path", serde_json::json!(null))]{
+            let mut bad = event.clone();
+            bad[field] = value;
+            assert!(!validator.is_valid(&bad), "{bad}");
+        }
+        let mut missing = event.clone();
+        missing.as_object_mut().unwrap().remove("level");
+        assert!(!validator.is_valid(&missing));
+    }
+}
@@ -0,0 +1,314 @@
+//! `--event-socket`: the `--format ndjson` lines of a check written to a Unix
+//! socket as findings are found, for a local agent reacting to them. The check
+//! connects to a listening agent, or with `--event-socket-mode listen` binds the
+//! socket and writes to every agent connected to it.
+//!
+//! Lines are queued for a writer thread. When the queue is full because the
+//! agent reads slowly, `--event-backpressure block` waits for it and `drop`
+//! counts the line as dropped; lines nobody receives, e.g. when the agent went
+//! away or none is connected to a listening socket, are counted the same. An
+//! agent accepting no data for [`STALL_TIMEOUT`] is disconnected, so a stalled
+//! one can't hang the check. [`EventSocket::finish`] writes what's queued and
+//! closes the socket, so the agent reads end of file after the last finding.
+//! A listening socket is only accessible to the user running the check.
+
+use super::events::Event;
+use super::fileops::Finding;
+use std::io::{self, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Lines queued before backpressure applies.
+pub const QUEUE_LINES: usize = 1024;
+
+/// How long a write to an agent may block before it is disconnected.
+#[cfg(not(test))]
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(test)]
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the writer thread looks for new agents and for the end.
+const POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketMode{
+    /// connect to a socket the agent listens on
+    Connect,
+    /// bind the socket, agents connect to it
+    Listen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure{
+    /// wait for a slow agent, slowing the check down
+    Block,
+    /// count lines the queue has no room for as dropped
+    Drop,
+}
+
+enum Message{
+    Line(Vec<u8>),
+    End,
+}
+
+/// The writer thread of an event socket, see [`EventSocket::sender`].
+pub struct EventSocket{
+    tx: SyncSender<Message>,
+    backpressure: Backpressure,
+    dropped: Arc<AtomicU64>,
+    /// set by [`EventSocket::finish`], ends the writer once the queue is empty
+    closing: Arc<AtomicBool>,
+    writer: Option<JoinHandle<()>>,
+    /// the socket bound in listen mode, removed when done
+    bound: Option<PathBuf>,
+}
+
+impl EventSocket {
+    /// Connects to or binds `path`. A stale socket file left by an earlier
+    /// listening check is replaced, any other file isn't. The socket bound is
+    /// made 0600, agents of other users that connected before are turned away.
+    pub fn open(path: &Path, mode: SocketMode, backpressure: Backpressure) -> io::Result<Self> {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LINES);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let closing = Arc::new(AtomicBool::new(false));
+        let (counter, done) = (dropped.clone(), closing.clone());
+        let (writer, bound) = match mode{
+            SocketMode::Connect => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_write_timeout(Some(STALL_TIMEOUT))?;
+                (std::thread::spawn(move || write_connected(stream, rx, &counter, &done)), None)
+            }
+            SocketMode::Listen => {
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) && UnixStream::connect(path).is_err(){
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)){
+                    let _ = std::fs::remove_file(path);
+                    return Err(e);
+                }
+                // connected while the socket still had the permissions of the umask
+                while listener.accept().is_ok(){}
+                (std::thread::spawn(move || write_listening(listener, rx, &counter, &done)), Some(path.to_owned()))
+            }
+        };
+        Ok(EventSocket { tx, backpressure, dropped, closing, writer: Some(writer), bound })
+    }
+
+    /// A sink for the findings of a check, e.g. for `CheckDB::with_finding_sink`.
+    pub fn sender(&self) -> EventSender {
+        EventSender { tx: self.tx.clone(), backpressure: self.backpressure, dropped: self.dropped.clone(), run_id: None }
+    }
+
+    /// Lines dropped since the last call, for a check of `--interval` while
+    /// the socket stays open.
+    pub fn take_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+
+    /// Writes the queued lines and closes the socket. Returns how many lines
+    /// were dropped; findings sent afterwards are dropped too.
+    pub fn finish(mut self) -> u64 {
+        self.close();
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn close(&mut self) {
+        if let Some(writer) = self.writer.take(){
+            // with a full queue the writer ends on the flag once it drained it,
+            // the end message only spares it waiting for the next poll
+            self.closing.store(true, Ordering::Release);
+            let _ = self.tx.try_send(Message::End);
+            let _ = writer.join();
+        }
+        if let Some(path) = self.bound.take(){
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for EventSocket {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Queues findings for an [`EventSocket`].
+#[derive(Clone)]
+pub struct EventSender{
+    tx: SyncSender<Message>,
+    backpressure: Backpressure,
+    dropped: Arc<AtomicU64>,
+    run_id: Option<String>,
+}
+
+impl EventSender {
+    /// Tags every line with `run_id`, as `EventWriter::with_run_id` does.
+    pub fn with_run_id(self, run_id: &str) -> Self {
+        EventSender { run_id: Some(run_id.to_owned()), ..self }
+    }
+
+    pub fn send(&self, finding: &Finding) {
+        let event = Event{ run_id: self.run_id.clone(), ..Event::from(finding) };
+        let mut line = serde_json::to_vec(&event).expect("events serialize");
+        line.push(b'\n');
+        let sent = match self.backpressure{
+            Backpressure::Block => self.tx.send(Message::Line(line)).is_ok(),
+            Backpressure::Drop => self.tx.try_send(Message::Line(line)).is_ok(),
+        };
+        if !sent{
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The next line to write, none once the socket is closing and every line
+/// queued before was taken. `idle` runs each time no line came for a while.
+fn next_line(rx: &Receiver<Message>, closing: &AtomicBool, mut idle: impl FnMut()) -> Option<Vec<u8>> {
+    loop{
+        match rx.recv_timeout(POLL){
+            Ok(Message::Line(line)) => return Some(line),
+            Ok(Message::End) | Err(RecvTimeoutError::Disconnected) => return None,
+            Err(RecvTimeoutError::Timeout) if closing.load(Ordering::Acquire) => return None,
+            Err(RecvTimeoutError::Timeout) => idle(),
+        }
+    }
+}
+
+fn write_connected(mut stream: UnixStream, rx: Receiver<Message>, dropped: &AtomicU64, closing: &AtomicBool) {
+    let mut connected = true;
+    while let Some(line) = next_line(&rx, closing, || {}){
+        if !connected{
+            dropped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        // times out after STALL_TIMEOUT
+        if let Err(e) = stream.write_all(&line){
+            tracing::warn!("Event socket agent went away or stalled: {e}");
+            connected = false;
+            dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+fn write_listening(listener: UnixListener, rx: Receiver<Message>, dropped: &AtomicU64, closing: &AtomicBool) {
+    let mut agents: Vec<UnixStream> = Vec::new();
+    let accept = |agents: &mut Vec<UnixStream>| {
+        while let Ok((agent, _)) = listener.accept(){
+            if agent.set_nonblocking(false).is_ok() && agent.set_write_timeout(Some(STALL_TIMEOUT)).is_ok(){
+                agents.push(agent);
+            }
+        }
+    };
+    while let Some(line) = next_line(&rx, closing, || accept(&mut agents)){
+        accept(&mut agents);
+        // a stalled agent times out after STALL_TIMEOUT and is dropped
+        agents.retain_mut(|agent| agent.write_all(&line).is_ok());
+        if agents.is_empty(){
+            dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    for agent in agents{
+        let _ = agent.shutdown(Shutdown::Write);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::FindingKind;
+    use crate::severity::Changes;
+    use std::io::{BufRead, BufReader};
+
+    fn finding(path: &str) -> Finding {
+        Finding{ kind: FindingKind::New, path: path.to_owned(), state: String::new(), level: log::Level::Warn, message: String::new(), old: None, new: None, entropy: None, changes: Changes::default() }
+    }
+
+    fn read_events(stream: UnixStream) -> Vec<Event> {
+        BufReader::new(stream).lines().map(|l| serde_json::from_str(&l.unwrap()).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_event_socket() {
+        let path = std::env::current_dir().unwrap().join("test_tree_event_socket.sock");
+        let _ = std::fs::remove_file(&path);
+
+        // connecting to a listening agent, which reads until the check is done
+        let agent = UnixListener::bind(&path).unwrap();
+        let socket = EventSocket::open(&path, SocketMode::Connect, Backpressure::Block).unwrap();
+        let (stream, _) = agent.accept().unwrap();
+        let reader = std::thread::spawn(move || read_events(stream));
+        let sender = socket.sender();
+        for i in 0..QUEUE_LINES * 3{
+            sender.send(&finding(&format!("/etc/{i}")));
+        }
+        assert_eq!(socket.finish(), 0);
+        let events = reader.join().unwrap();
+        assert_eq!(events.len(), QUEUE_LINES * 3);
+        assert_eq!((events[5].path.as_str(), events[5].change_kind, events[5].level.as_str()), ("/etc/5", FindingKind::New, "WARN"));
+        // sent after the socket was closed
+        sender.send(&finding("/etc/late"));
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+
+        // an agent not reading: lines the queue and socket buffers have no room for are dropped
+        let agent = UnixListener::bind(&path).unwrap();
+        let socket = EventSocket::open(&path, SocketMode::Connect, Backpressure::Drop).unwrap();
+        let (stream, _) = agent.accept().unwrap();
+        let sender = socket.sender();
+        let sent = QUEUE_LINES * 200;
+        for i in 0..sent{
+            sender.send(&finding(&format!("/etc/{i}")));
+        }
+        let reader = std::thread::spawn(move || read_events(stream));
+        let dropped = socket.finish();
+        let events = reader.join().unwrap();
+        assert!(dropped > 0);
+        assert_eq!(events.len() as u64 + dropped, sent as u64);
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+
+        // listening, a stale socket is replaced and removed when done
+        drop(UnixListener::bind(&path).unwrap());
+        let socket = EventSocket::open(&path, SocketMode::Listen, Backpressure::Block).unwrap();
+        let stream = UnixStream::connect(&path).unwrap();
+        let reader = std::thread::spawn(move || read_events(stream));
+        socket.sender().with_run_id("0f8c0b7e-3d1a-4c2b-9e4f-5a6b7c8d9e0f").send(&finding("/etc/seen"));
+        assert_eq!(socket.finish(), 0);
+        let events = reader.join().unwrap();
+        assert_eq!(events.iter().map(|e| (e.path.as_str(), e.run_id.as_deref())).collect::<Vec<_>>(), [("/etc/seen", Some("0f8c0b7e-3d1a-4c2b-9e4f-5a6b7c8d9e0f"))]);
+        assert!(!path.exists());
+        // nobody connected
+        let socket = EventSocket::open(&path, SocketMode::Listen, Backpressure::Block).unwrap();
+        socket.sender().send(&finding("/etc/unseen"));
+        assert_eq!(socket.finish(), 1);
+
+        // only the user running the check may connect
+        let socket = EventSocket::open(&path, SocketMode::Listen, Backpressure::Block).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        // an agent that stopped reading is disconnected instead of hanging the check
+        let stalled = UnixStream::connect(&path).unwrap();
+        let sender = socket.sender();
+        let sent = QUEUE_LINES * 200;
+        for i in 0..sent{
+            sender.send(&finding(&format!("/etc/{i}")));
+        }
+        let dropped = socket.finish();
+        assert!(dropped > 0 && dropped < sent as u64, "{dropped}");
+        drop(stalled);
+
+        // not replacing other files
+        std::fs::write(&path, b"").unwrap();
+        assert!(EventSocket::open(&path, SocketMode::Listen, Backpressure::Block).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+//! Paths left out of a scan: `--exclude` rules, `--preset` profiles of volatile
+//! paths and the DB itself.
+//!
+//! A rule is a path, which excludes it and everything under it, or a glob with
+//! `*` (not crossing `/`), `**` and `?`. Globs without a `/` match entry names,
+//! like `*.swp`, others the whole path, like `**/.cache`. A leading `!`
+//! re-includes what earlier rules excluded, the last matching rule wins. Paths
+//! under an excluded directory are only walked for a re-included path, not a
+//! re-included glob.
+//!
+//! Rules remember whether they decided the verdict of a walked path, so
+//! `--warn-unmatched-excludes` can point out rules that never did, like a
+//! mistyped path, and count the paths they pruned with a few examples for
+//! `--show-excluded`.
+
+use super::error::IntegrityWatcherError;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Pruned paths a rule keeps as examples.
+pub const EXAMPLES: usize = 3;
+
+/// Curated exclude lists for scanning whole systems, shown by `--print-preset`.
+pub struct Preset{
+    pub name: &'static str,
+    pub description: &'static str,
+    /// preset whose rules come first
+    pub base: Option<&'static str>,
+    pub rules: &'static [&'static str],
+}
+
+pub const PRESETS: [Preset; 3] = [
+    Preset{
+        name: "linux-system",
+        description: "pseudo file systems, runtime state, temporary files, caches and logs of a Linux host",
+        base: None,
+        rules: &[
+            // kernel and device pseudo file systems
+            "/proc", "/sys", "/dev",
+            // runtime state, recreated on every boot
+            "/run", "/var/run", "/var/lock",
+            "/tmp", "/var/tmp",
+            "/var/cache",
+            // rotated and appended all the time
+            "/var/log",
+            "/swapfile",
+            "**/lost+found",
+        ],
+    },
+    Preset{
+        name: "linux-desktop",
+        description: "linux-system plus user caches, trash and browser profiles",
+        base: Some("linux-system"),
+        rules: &[
+            "**/.cache",
+            "/home/*/.local/share/Trash",
+            "/home/*/.thumbnails",
+            "/home/*/.mozilla",
+            "/home/*/.config/google-chrome",
+            "/home/*/.config/chromium",
+            "/home/*/.config/BraveSoftware",
+        ],
+    },
+    Preset{
+        name: "container",
+        description: "pseudo file systems, runtime state and the files a container runtime bind mounts per container",
+        base: None,
+        rules: &[
+            "/proc", "/sys", "/dev",
+            "/run", "/var/run", "/var/lock",
+            "/tmp", "/var/tmp",
+            "/var/cache", "/var/log",
+            // written by the runtime when the container starts
+            "/etc/hostname", "/etc/hosts", "/etc/resolv.conf",
+            "/.dockerenv",
+        ],
+    },
+];
+
+/// Rules of preset `name`, including those of its base.
+pub fn preset_rules(name: &str) -> Result<Vec<&'static str>, IntegrityWatcherError> {
+    let preset = PRESETS.iter().find(|p| p.name == name).ok_or_else(|| {
+        let names: Vec<_> = PRESETS.iter().map(|p| p.name).collect();
+        IntegrityWatcherError::InvalidArgument(format!("Unknown preset {name:?}, available: {}", names.join(", ")))
+    })?;
+    let mut rules = match preset.base{
+        Some(base) => preset_rules(base)?,
+        None => Vec::new(),
+    };
+    rules.extend(preset.rules);
+    Ok(rules)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern{
+    /// the path and everything under it
+    Path(String),
+    /// glob over entry names
+    Name(String),
+    /// glob over whole paths
+    Glob(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule{
+    /// as given, for `unmatched`
+    text: String,
+    pattern: Pattern,
+    negated: bool,
+    usage: Usage,
+}
+
+/// What the rule did so far, shared by the walkers of a scan.
+#[derive(Debug, Default)]
+struct Usage{
+    /// set once the rule decided a verdict
+    matched: AtomicBool,
+    /// walked paths it excluded, a directory counting once
+    pruned: AtomicU64,
+    /// the first `EXAMPLES` of them
+    examples: Mutex<Vec<String>>,
+}
+
+impl Usage {
+    fn set_matched(&self) {
+        self.matched.store(true, Ordering::Relaxed);
+    }
+
+    fn matched(&self) -> bool {
+        self.matched.load(Ordering::Relaxed)
+    }
+
+    fn prune(&self, path: &str) {
+        if self.pruned.fetch_add(1, Ordering::Relaxed) < EXAMPLES as u64{
+            self.examples.lock().expect("examples lock").push(path.to_owned());
+        }
+    }
+
+    fn pruned(&self) -> u64 {
+        self.pruned.load(Ordering::Relaxed)
+    }
+
+    fn examples(&self) -> Vec<String> {
+        self.examples.lock().expect("examples lock").clone()
+    }
+
+    fn reset(&self) {
+        self.matched.store(false, Ordering::Relaxed);
+        self.pruned.store(0, Ordering::Relaxed);
+        self.examples.lock().expect("examples lock").clear();
+    }
+}
+
+impl Clone for Usage {
+    fn clone(&self) -> Self {
+        Usage{ matched: AtomicBool::new(self.matched()), pruned: AtomicU64::new(self.pruned()), examples: Mutex::new(self.examples()) }
+    }
+}
+
+impl PartialEq for Usage {
+    fn eq(&self, other: &Self) -> bool {
+        self.matched() == other.matched() && self.pruned() == other.pruned()
+    }
+}
+
+impl Eq for Usage {}
+
+/// What a rule did in a scan, for `--show-excluded`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleUsage<'a>{
+    /// as given
+    pub rule: &'a str,
+    /// whether it decided the verdict of any path
+    pub matched: bool,
+    /// walked paths it excluded, a directory counting once
+    pub pruned: u64,
+    /// the first `EXAMPLES` of them
+    pub examples: Vec<String>,
+}
+
+/// What the walker does with an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict{
+    Include,
+    Exclude,
+    /// excluded, but a re-included path lies under it: walk into it without
+    /// storing the entry itself
+    Traverse,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExcludeSet{
+    rules: Vec<Rule>,
+}
+
+impl ExcludeSet {
+    /// Adds `rule`, see the module docs for the syntax.
+    pub fn add(&mut self, rule: &str) {
+        let text = rule.to_owned();
+        let (negated, rule) = match rule.strip_prefix('!'){
+            Some(rule) => (true, rule),
+            None => (false, rule),
+        };
+        let trimmed = match rule.len(){
+            0 | 1 => rule,
+            _ => rule.trim_end_matches('/'),
+        };
+        let pattern = match (rule.contains(['*', '?']), rule.contains('/')){
+            (false, _) => Pattern::Path(trimmed.to_owned()),
+            (true, false) => Pattern::Name(rule.to_owned()),
+            (true, true) => Pattern::Glob(trimmed.to_owned()),
+        };
+        self.rules.push(Rule{ text, pattern, negated, usage: Usage::default() });
+    }
+
+    /// Excludes `path` as is, e.g. the DB, whatever characters it contains.
+    pub fn add_path(&mut self, path: &str) {
+        self.rules.push(Rule{ text: path.to_owned(), pattern: Pattern::Path(path.to_owned()), negated: false, usage: Usage::default() });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Excluded by the last rule matching `path` or one of its parents.
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.decide(path, false).is_some_and(|r| !r.negated)
+    }
+
+    /// The rule deciding the verdict of `path`, none when no rule matches it
+    /// or its parents.
+    fn decide(&self, path: &str, mark: bool) -> Option<&Rule> {
+        if self.rules.is_empty(){
+            return None;
+        }
+        let p = Path::new(path);
+        let mut decided = None;
+        for ancestor in p.ancestors().collect::<Vec<_>>().into_iter().rev(){
+            if ancestor.as_os_str().is_empty(){
+                continue;
+            }
+            let ancestor = ancestor.to_string_lossy();
+            if let Some(rule) = self.rules.iter().rev().find(|r| r.matches(&ancestor)){
+                decided = Some(rule);
+                if mark{
+                    rule.usage.set_matched();
+                }
+            }
+        }
+        decided
+    }
+
+    /// Verdict for `path`, whose parents were included or traversed. Marks
+    /// the rules deciding it as matched and counts pruned paths to the rule
+    /// excluding them.
+    pub fn verdict(&self, path: &str) -> Verdict {
+        let rule = match self.decide(path, true){
+            Some(rule) if !rule.negated => rule,
+            _ => return Verdict::Include,
+        };
+        let p = Path::new(path);
+        let reincluded_below = self.rules.iter().any(|r| r.negated && matches!(&r.pattern, Pattern::Path(n) if Path::new(n).starts_with(p) && Path::new(n) != p));
+        match reincluded_below{
+            true => Verdict::Traverse,
+            false => {
+                rule.usage.prune(path);
+                Verdict::Exclude
+            }
+        }
+    }
+
+    /// Forgets what the rules did, so each check of `--interval` counts its
+    /// own scan.
+    pub fn reset_usage(&self) {
+        for rule in &self.rules{
+            rule.usage.reset();
+        }
+    }
+
+    /// Rules, as given, that decided no verdict so far.
+    pub fn unmatched(&self) -> Vec<&str> {
+        self.rules.iter().filter(|r| !r.usage.matched()).map(|r| r.text.as_str()).collect()
+    }
+
+    /// What each rule did so far, in the order they were added.
+    pub fn usage(&self) -> Vec<RuleUsage<'_>> {
+        self.rules.iter().map(|r| RuleUsage{
+            rule: &r.text,
+            matched: r.usage.matched(),
+            pruned: r.usage.pruned(),
+            examples: r.usage.examples(),
+        }).collect()
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &str) -> bool {
+        match &self.pattern{
+            Pattern::Path(p) => path == p,
+            Pattern::Name(glob) => Path::new(path).file_name().is_some_and(|n| glob_match(glob.as_bytes(), n.to_string_lossy().as_bytes())),
+            Pattern::Glob(glob) => glob_match(glob.as_bytes(), path.as_bytes()),
+        }
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for ExcludeSet {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        let mut set = ExcludeSet::default();
+        for rule in iter{
+            set.add(rule.as_ref());
+        }
+        set
+    }
+}
+
+/// `*` and `?` don't match `/`, `**` matches anything.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob{
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let end = text.iter().position(|c| *c == b'/').unwrap_or(text.len());
+            (0..=end).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, ..] if *c != b'/') && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"/home/*/.mozilla", b"/home/ann/.mozilla"));
+        assert!(!glob_match(b"/home/*/.mozilla", b"/home/ann/x/.mozilla"));
+        assert!(glob_match(b"/srv/**/cache", b"/srv/a/b/cache"));
+        assert!(glob_match(b"*.log", b"syslog.log"));
+        assert!(glob_match(b"core.?", b"core.1"));
+        assert!(!glob_match(b"core.?", b"core.12"));
+    }
+
+    #[test]
+    fn test_exclude_set() {
+        let mut set: ExcludeSet = preset_rules("linux-desktop").unwrap().into_iter().collect();
+        set.add("!/var/log/audit");
+        set.add("*.swp");
+
+        assert_eq!(set.verdict("/proc"), Verdict::Exclude);
+        assert_eq!(set.verdict("/etc/passwd"), Verdict::Include);
+        assert_eq!(set.verdict("/home/ann/.cache"), Verdict::Exclude);
+        assert_eq!(set.verdict("/home/ann/.mozilla"), Verdict::Exclude);
+        assert_eq!(set.verdict("/home/ann/notes.txt.swp"), Verdict::Exclude);
+        assert_eq!(set.verdict("/srv/lost+found"), Verdict::Exclude);
+        // /var/log is walked only to reach the re-included audit logs
+        assert_eq!(set.verdict("/var/log"), Verdict::Traverse);
+        assert_eq!(set.verdict("/var/log/syslog"), Verdict::Exclude);
+        assert_eq!(set.verdict("/var/log/audit"), Verdict::Include);
+        assert_eq!(set.verdict("/var/log/audit/audit.log"), Verdict::Include);
+        assert!(set.is_excluded("/proc/1/status"));
+        let proc_usage = |set: &ExcludeSet| set.usage().into_iter().find(|u| u.rule == "/proc").map(|u| (u.matched, u.pruned)).unwrap();
+        assert_eq!(proc_usage(&set), (true, 1));
+        set.reset_usage();
+        assert_eq!(proc_usage(&set), (false, 0));
+        assert!(set.unmatched().contains(&"/proc"));
+
+        assert!(preset_rules("container").unwrap().contains(&"/etc/resolv.conf"));
+        assert!(preset_rules("linux-system").unwrap().len() < preset_rules("linux-desktop").unwrap().len());
+        assert!(preset_rules("windows").is_err());
+    }
+}
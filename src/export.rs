@@ -0,0 +1,269 @@
+//! `--export` and `--import`: a baseline as JSON lines, for moving it between
+//! machines that don't share a redb version or a file system:
+//!
+//! ```text
+//! {"header":{"format":"integrity-checker-export","version":1,"meta":{"no_dirs":"false",..}}}
+//! {"entry":{"path":"/etc/passwd","metadata":{"File":{..}}}}
+//! {"end":{"entries":1}}
+//! ```
+//!
+//! The header carries the settings stored with the baseline, see `dbmeta`, the
+//! last line the entry count, so a dump cut short isn't imported as a smaller
+//! baseline. Annotations, volatile marks and the run history aren't exported.
+//!
+//! Dumps are zstd compressed on request, `import` tells them by their magic.
+
+use super::dbmeta;
+use super::error::IntegrityWatcherError;
+use super::fileops::files_table;
+use super::types::FileMetadataExt;
+use redb::{Database, ReadableDatabase, ReadableTable};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+const FORMAT: &str = "integrity-checker-export";
+const VERSION: u32 = 1;
+
+/// Level of `--compress zstd` when `--compress-level` isn't given.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Start of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Entries imported per DB transaction.
+const IMPORT_BATCH: u64 = 10_000;
+
+/// How `export` compresses its lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression{
+    #[default]
+    None,
+    /// zstd at this level, 1 to 22
+    Zstd(i32),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Line{
+    Header{
+        format: String,
+        version: u32,
+        meta: BTreeMap<String, String>,
+    },
+    Entry{
+        path: String,
+        metadata: Box<FileMetadataExt>,
+    },
+    End{
+        entries: u64,
+    },
+}
+
+/// Writes the baseline `table` of `db` to `out`, named `name` in errors.
+/// Returns the number of entries written.
+pub fn export<W: Write>(db: &Database, table: &str, out: W, name: &str, compression: Compression) -> Result<u64, IntegrityWatcherError> {
+    let io_error = |e| IntegrityWatcherError::IOError { source: e, path: name.to_owned() };
+    match compression{
+        Compression::None => {
+            let mut out = BufWriter::new(out);
+            let written = write_lines(db, table, &mut out, name)?;
+            out.flush().map_err(io_error)?;
+            Ok(written)
+        }
+        Compression::Zstd(level) => {
+            let mut encoder = zstd::Encoder::new(out, level).map_err(io_error)?;
+            let written = write_lines(db, table, &mut encoder, name)?;
+            encoder.finish().and_then(|mut out| out.flush()).map_err(io_error)?;
+            Ok(written)
+        }
+    }
+}
+
+fn write_lines(db: &Database, table: &str, out: &mut impl Write, name: &str) -> Result<u64, IntegrityWatcherError> {
+    let mut write = |line: &Line| {
+        serde_json::to_writer(&mut *out, line).map_err(io::Error::from)
+            .and_then(|_| out.write_all(b"\n"))
+            .map_err(|e| IntegrityWatcherError::IOError { source: e, path: name.to_owned() })
+    };
+    write(&Line::Header{ format: FORMAT.to_owned(), version: VERSION, meta: dbmeta::load_all(db, table)? })?;
+    let read_txn = db.begin_read().map_err(Box::new)?;
+    let mut entries = 0;
+    for k in read_txn.open_table(files_table(table))?.iter()?{
+        let k = k?;
+        write(&Line::Entry{ path: k.0.value(), metadata: Box::new(k.1.value()) })?;
+        entries += 1;
+    }
+    write(&Line::End{ entries })?;
+    Ok(entries)
+}
+
+/// Reads a dump of `export`, compressed or not, from `input`, named `name` in
+/// errors, into the baseline `table` of `db`. Returns the number of entries.
+/// A dump that is cut short or damaged fails the import, with the entries
+/// read so far already stored.
+pub fn import<R: Read>(input: R, name: &str, db: &Database, table: &str) -> Result<u64, IntegrityWatcherError> {
+    let io_error = |e| IntegrityWatcherError::IOError { source: e, path: name.to_owned() };
+    let invalid = |n: usize, reason: &str| io_error(io::Error::new(io::ErrorKind::InvalidData, format!("line {n}: {reason}")));
+    let mut lines = decompressed(input).map_err(io_error)?.lines().enumerate().map(|(i, line)| {
+        let line = line.map_err(io_error)?;
+        serde_json::from_str::<Line>(&line).map(|l| (i + 1, l)).map_err(|e| invalid(i + 1, &e.to_string()))
+    });
+    let meta = match lines.next().transpose()?{
+        Some((_, Line::Header{ format, version, meta })) if format == FORMAT => match version{
+            VERSION => meta,
+            _ => return Err(invalid(1, &format!("export format version {version}, written by a newer integrity-checker?"))),
+        },
+        _ => return Err(invalid(1, "not an integrity-checker export")),
+    };
+    let mut imported = 0;
+    let mut end = None;
+    let mut first = true;
+    while end.is_none(){
+        let write_txn = db.begin_write().map_err(Box::new)?;
+        if first{
+            dbmeta::store_all_in(&write_txn, table, &meta)?;
+            first = false;
+        }
+        {
+            let mut entries = write_txn.open_table(files_table(table))?;
+            loop{
+                match lines.next().transpose()?{
+                    Some((_, Line::Entry{ path, metadata })) => {
+                        entries.insert(path, *metadata)?;
+                        imported += 1;
+                        if imported % IMPORT_BATCH == 0{
+                            break;
+                        }
+                    }
+                    Some((n, Line::End{ entries })) => {
+                        end = Some((n, entries));
+                        break;
+                    }
+                    Some((n, Line::Header{ .. })) => return Err(invalid(n, "header in the middle of the export")),
+                    None => return Err(invalid(imported as usize + 2, "export ends early, cut short?")),
+                }
+            }
+        }
+        write_txn.commit()?;
+    }
+    if let Some((n, entries)) = end && entries != imported{
+        return Err(invalid(n, &format!("{imported} entries read of {entries} exported")));
+    }
+    if let Some((n, _)) = lines.next().transpose()?{
+        return Err(invalid(n, "lines after the end of the export"));
+    }
+    Ok(imported)
+}
+
+/// `input`, decoded when it starts like a zstd frame.
+fn decompressed<'a, R: Read + 'a>(mut input: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut input).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let input = io::Cursor::new(magic).chain(input);
+    Ok(match input.get_ref().0.get_ref().as_slice() == ZSTD_MAGIC{
+        true => Box::new(BufReader::new(zstd::Decoder::new(input)?)),
+        false => Box::new(BufReader::new(input)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbmeta::DBSettings;
+    use crate::fileops::{AddFileInfo, WriteToDB, DEFAULT_TABLE};
+    use crate::types::{DirMetadata, FileMetadata};
+    use std::fs;
+
+    fn entries(n: u32) -> Vec<(String, FileMetadataExt)> {
+        (0..n).map(|i| match i % 10{
+            0 => (format!("/srv/d{i}"), FileMetadataExt::Dir(DirMetadata{ permissions: 0o40755, modified: 1000 + u64::from(i), size: 4096, entries: Some(i), security: None, flags: None, owner: None })),
+            _ => (format!("/srv/d{}/f{i}", i - i % 10), FileMetadataExt::File(FileMetadata{
+                hash: [(i % 256) as u8; 32].into(),
+                permissions: 0o100644,
+                modified: 1000 + u64::from(i),
+                size: u64::from(i).into(),
+                ino: Some(u64::from(i)),
+                strong: None,
+                security: None,
+                streams: None,
+                content_type: Some("text/plain".to_owned()),
+                created: None,
+                flags: None,
+                owner: None,
+            })),
+        }).collect()
+    }
+
+    fn all(db: &Database) -> Vec<(String, FileMetadataExt)> {
+        let read_txn = db.begin_read().unwrap();
+        read_txn.open_table(files_table(DEFAULT_TABLE)).unwrap().iter().unwrap().map(|k| {
+            let k = k.unwrap();
+            (k.0.value(), k.1.value())
+        }).collect()
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_db_export");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let db = Database::create(dir.join("source.redb")).unwrap();
+        let settings = DBSettings{ track_inode: true, detect_type: true, ..Default::default() };
+        settings.store(&db, DEFAULT_TABLE).unwrap();
+        WriteToDB::new(&db).add_file_info(&entries(5000)).unwrap();
+        let source = all(&db);
+
+        let mut sizes = Vec::new();
+        for compression in [Compression::None, Compression::Zstd(DEFAULT_ZSTD_LEVEL)]{
+            let mut dump = Vec::new();
+            assert_eq!(export(&db, DEFAULT_TABLE, &mut dump, "dump", compression).unwrap(), 5000);
+            assert_eq!(dump.starts_with(&ZSTD_MAGIC), compression != Compression::None);
+            sizes.push(dump.len());
+
+            let path = dir.join(format!("import{}.redb", sizes.len()));
+            let imported = Database::create(&path).unwrap();
+            assert_eq!(import(dump.as_slice(), "dump", &imported, DEFAULT_TABLE).unwrap(), 5000);
+            assert_eq!(all(&imported), source);
+            assert_eq!(DBSettings::load(&imported, DEFAULT_TABLE).unwrap(), settings);
+        }
+        assert!(sizes[1] * 5 < sizes[0], "compressed {} of {} bytes", sizes[1], sizes[0]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_damaged() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_db_import_damaged");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let db = Database::create(dir.join("source.redb")).unwrap();
+        WriteToDB::new(&db).add_file_info(&entries(20)).unwrap();
+        let mut dump = Vec::new();
+        export(&db, DEFAULT_TABLE, &mut dump, "dump", Compression::None).unwrap();
+        let text = String::from_utf8(dump).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+
+        let target = Database::create(dir.join("target.redb")).unwrap();
+        let failed = |dump: String| import(dump.as_bytes(), "dump", &target, DEFAULT_TABLE).unwrap_err().to_string();
+        // cut short, with and without the end line
+        assert!(failed(lines[..10].join("\n")).contains("cut short"));
+        assert!(failed([&lines[..10], &lines[lines.len() - 1..]].concat().join("\n")).contains("9 entries read of 20 exported"));
+        assert!(failed(lines[1..].join("\n")).contains("line 1: not an integrity-checker export"));
+        assert!(failed(text.replacen("\"version\":1", "\"version\":2", 1)).contains("newer integrity-checker"));
+        assert!(failed(format!("{text}{}\n", lines[1])).contains("lines after the end"));
+
+        let mut compressed = zstd::encode_all(lines[..10].join("\n").as_bytes(), DEFAULT_ZSTD_LEVEL).unwrap();
+        assert!(import(compressed.as_slice(), "dump", &target, DEFAULT_TABLE).unwrap_err().to_string().contains("cut short"));
+        compressed.truncate(compressed.len() / 2);
+        assert!(import(compressed.as_slice(), "dump", &target, DEFAULT_TABLE).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,25 +1,141 @@
-use super::types::{FileMetadataExt, ByteSize};
+use super::types::{FileMetadataExt, FileMetadata, DirMetadata, ByteSize, EscapedPath, Timestamp, Permissions, DirSize, Security, DataStream, SymlinkMetadata, MODE_PERMS_MASK};
 use super::error::IntegrityWatcherError;
-use log::{debug, error, warn, info, trace};
-use redb::{Database, TableDefinition, ReadableDatabase};
-use std::collections::HashSet;
-use chrono::DateTime;
+use super::inodeflags;
+use super::ownership;
+use super::magic;
+use super::entropy;
+use super::quarantine;
+use super::vfat;
+use super::severity::{self, Change, Changes, SeverityPolicy};
+use super::ack::{self, Ack};
+use super::annotations::{self, Annotations};
+use super::pathfold::{collision_message, KeyFolder, PathFold};
+use super::unvisited::Unvisited;
+use log::Level;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use tracing::{debug, error, info, trace, warn};
+use redb::{Database, TableDefinition, TableHandle, ReadableDatabase, ReadableTable};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
 
-pub const TABLE: TableDefinition<String, FileMetadataExt> = TableDefinition::new("files_database");
+/// Entries table unless `--table` selects another one.
+pub const DEFAULT_TABLE: &str = "files_database";
+pub const TABLE: TableDefinition<String, FileMetadataExt> = files_table(DEFAULT_TABLE);
+
+/// Entries table `name`; one DB can hold several baselines under different names.
+pub const fn files_table(name: &str) -> TableDefinition<'_, String, FileMetadataExt> {
+    TableDefinition::new(name)
+}
+
+/// Entries of host `host` in a DB merged from several hosts, `--host-prefix`
+/// on create/update/merge and `--host` on the other commands.
+pub fn host_table_name(table: &str, host: &str) -> String {
+    format!("{table}@{host}")
+}
+
+/// `--table` names, which must not clash with the tables kept next to the
+/// entries of another baseline.
+pub fn validate_table_name(name: &str) -> Result<(), IntegrityWatcherError> {
+    let reserved = is_sibling_table(name)
+        || name.starts_with("collector_reports_") || name.starts_with("circl_cache") || name.contains('@');
+    if name.is_empty() || reserved{
+        return Err(IntegrityWatcherError::InvalidArgument(format!("--table {}, name is empty or reserved", EscapedPath(name))));
+    }
+    Ok(())
+}
+
+/// Host names of `--host-prefix`, part of a table name as with `--table`.
+pub fn validate_host_prefix(host: &str) -> Result<(), IntegrityWatcherError> {
+    if host.is_empty() || host.len() > 64 || !host.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) || is_sibling_table(host){
+        return Err(IntegrityWatcherError::InvalidArgument(format!("host {}, expected up to 64 of A-Z a-z 0-9 . _ - not ending in {}", EscapedPath(host), SIBLING_SUFFIXES.join(", "))));
+    }
+    Ok(())
+}
+
+/// Suffixes of the tables kept next to the entries of a baseline: settings,
+/// annotations, `--tag-prefix` rules, run history and volatile marks. Overwriting
+/// a baseline clears them and `--merge` copies them with it.
+pub const SIBLING_SUFFIXES: [&str; 5] = ["_meta", "_annotations", "_tag_prefixes", "_runs", "_volatile"];
+
+/// Whether `name` is one of the tables kept next to the entries of a baseline.
+pub fn is_sibling_table(name: &str) -> bool {
+    SIBLING_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
 
 pub trait AddFileInfo {
     fn add_file_info(&mut self, files: &[(String, FileMetadataExt)]) -> Result<(), IntegrityWatcherError>;
+
+    /// Asks the walker to stop early, e.g. `--fail-fast` after the first finding.
+    fn should_stop(&self) -> bool{
+        false
+    }
+
+    /// Ends the walk: writes what is still pending and returns what the sink
+    /// saw. Call once after the last `add_file_info`.
+    fn finish(&mut self) -> Result<ScanOutcome, IntegrityWatcherError>{
+        Ok(ScanOutcome::default())
+    }
+}
+
+/// What a sink saw during a walk, returned by [`AddFileInfo::finish`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScanOutcome{
+    /// entries stored by create, new entries of an update
+    pub added: u64,
+    /// changed entries of an update
+    pub updated: u64,
+    /// entries a check or update compared with the DB
+    pub checked: u64,
+    /// entries of the walk left out, e.g. a second path with the key of another
+    pub errors: u64,
+    /// size of the files and symlinks stored or compared
+    pub bytes: ByteSize,
 }
 
 pub struct WriteToDB<'ldb>{
     counter: u64,
     byte_counter: ByteSize,
     db: &'ldb Database,
+    table: TableDefinition<'ldb, String, FileMetadataExt>,
+    keys: KeyFolder,
+    /// entries per transaction, none for one per `add_file_info` call
+    txn_batch: Option<usize>,
+    txn: Option<redb::WriteTransaction>,
+    /// entries written in `txn` so far
+    pending: usize,
+    errors: u64,
 }
 
 impl<'ldb> WriteToDB<'ldb>{
     pub fn new(db: &'ldb Database) -> Self{
-        WriteToDB{ db, counter: 0, byte_counter: ByteSize::default()}
+        WriteToDB{ db, counter: 0, byte_counter: ByteSize::default(), table: TABLE, keys: KeyFolder::default(), txn_batch: None, txn: None, pending: 0, errors: 0 }
+    }
+
+    /// `--txn-batch`: commits every `entries` entries instead of once per
+    /// `add_file_info` call, the rest with [`AddFileInfo::finish`].
+    pub fn with_txn_batch(mut self, entries: usize) -> Self{
+        self.txn_batch = Some(entries.max(1));
+        self
+    }
+
+    /// `--single-txn`: one transaction for all entries, committed by
+    /// [`AddFileInfo::finish`]. Nothing is written when it isn't called.
+    pub fn single_transaction(mut self) -> Self{
+        self.txn_batch = Some(usize::MAX);
+        self
+    }
+
+    /// Writes to entries table `name` instead of `DEFAULT_TABLE`.
+    pub fn with_table(mut self, name: &'ldb str) -> Self{
+        self.table = files_table(name);
+        self
+    }
+
+    /// Stores paths under their `--path-fold` keys.
+    pub fn with_path_fold(mut self, fold: PathFold) -> Self{
+        self.keys = KeyFolder::new(fold);
+        self
     }
 
     pub fn get_counter(&self) -> u64{
@@ -33,35 +149,306 @@ impl<'ldb> WriteToDB<'ldb>{
 
 impl AddFileInfo for WriteToDB<'_>{
     fn add_file_info(&mut self, data: &[(String, FileMetadataExt)]) -> Result<(), IntegrityWatcherError> {
-        let write_txn = self.db.begin_write().map_err(Box::new)?;
-        {
-            let mut table = write_txn.open_table(TABLE)?;
-            for (k,v) in data{
-                trace!("Adding file {}", k);
-                match v{
-                    FileMetadataExt::Dir(_) => {},
-                    FileMetadataExt::File(file) => self.byte_counter.add_size(&file.size),
-                    FileMetadataExt::Symlink(symlink) => self.byte_counter.add_size(&symlink.size),
+        let mut rest = data;
+        while !rest.is_empty(){
+            let room = self.txn_batch.map_or(rest.len(), |batch| batch - self.pending);
+            let (chunk, next) = rest.split_at(room.min(rest.len()));
+            rest = next;
+            let write_txn = match self.txn.take(){
+                Some(txn) => txn,
+                None => self.db.begin_write().map_err(Box::new)?,
+            };
+            {
+                let mut table = write_txn.open_table(self.table)?;
+                for (path, v) in chunk{
+                    let k = &match self.keys.key(path){
+                        Ok(k) => k.into_owned(),
+                        Err(first) => {
+                            warn!("{}", collision_message(path, &first, self.keys.fold()));
+                            self.errors += 1;
+                            continue;
+                        }
+                    };
+                    trace!("Adding file {}", EscapedPath(k));
+                    // a path reached twice through overlapping roots is counted once
+                    if table.insert(k, v)?.is_some(){
+                        debug!("Already added {}", EscapedPath(k));
+                        continue;
+                    }
+                    match v{
+                        FileMetadataExt::Dir(_) | FileMetadataExt::Whiteout | FileMetadataExt::Opaque => {},
+                        FileMetadataExt::File(file) => self.byte_counter.add_size(&file.size),
+                        FileMetadataExt::Symlink(symlink) => self.byte_counter.add_size(&symlink.size),
+                    }
+                    self.counter+=1;
+                }
+            }
+            self.pending += chunk.len();
+            match self.txn_batch{
+                Some(batch) if self.pending < batch => self.txn = Some(write_txn),
+                _ => {
+                    write_txn.commit()?;
+                    self.pending = 0;
                 }
-                table.insert(k, v)?;
-                self.counter+=1;
             }
         }
-        write_txn.commit()?;
         Ok(())
     }
+
+    fn finish(&mut self) -> Result<ScanOutcome, IntegrityWatcherError> {
+        if let Some(write_txn) = self.txn.take(){
+            write_txn.commit()?;
+            self.pending = 0;
+        }
+        Ok(ScanOutcome{ added: self.counter, errors: self.errors, bytes: self.byte_counter, ..Default::default() })
+    }
+}
+
+/// How a check or update finds the DB entries its walk didn't come across,
+/// `--removal-check`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemovalCheck{
+    /// visited paths in a hash set, looked up for every DB entry
+    #[default]
+    Hash,
+    /// visited paths kept in key order and merge joined with the entries
+    /// table in one pass instead of a lookup per entry; all of them are held
+    /// in memory all the same
+    MergeJoin,
+}
+
+/// Keys a walk came across.
+#[derive(Debug)]
+pub enum VisitedPaths{
+    Hashed(HashSet<String>),
+    Sorted(BTreeSet<String>),
+}
+
+impl VisitedPaths {
+    pub fn new(check: RemovalCheck) -> Self {
+        match check{
+            RemovalCheck::Hash => VisitedPaths::Hashed(HashSet::new()),
+            RemovalCheck::MergeJoin => VisitedPaths::Sorted(BTreeSet::new()),
+        }
+    }
+
+    /// Adds `key`, false when it was visited before.
+    pub fn insert(&mut self, key: String) -> bool {
+        match self{
+            VisitedPaths::Hashed(set) => set.insert(key),
+            VisitedPaths::Sorted(set) => set.insert(key),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        match self{
+            VisitedPaths::Hashed(set) => set.contains(key),
+            VisitedPaths::Sorted(set) => set.contains(key),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self{
+            VisitedPaths::Hashed(set) => set.len(),
+            VisitedPaths::Sorted(set) => set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lookup for the keys of the entries table as it iterates them.
+    pub fn join(&self) -> VisitedJoin<'_> {
+        match self{
+            VisitedPaths::Hashed(set) => VisitedJoin::Lookup(set),
+            VisitedPaths::Sorted(set) => VisitedJoin::Merge(set.iter().peekable()),
+        }
+    }
+}
+
+/// [`VisitedPaths`] joined with the entries table. Keys must be asked in
+/// table order, redb's byte order of strings, which is the order of `String`.
+pub enum VisitedJoin<'a>{
+    Lookup(&'a HashSet<String>),
+    Merge(std::iter::Peekable<std::collections::btree_set::Iter<'a, String>>),
+}
+
+impl VisitedJoin<'_> {
+    pub fn visited(&mut self, key: &str) -> bool {
+        match self{
+            VisitedJoin::Lookup(set) => set.contains(key),
+            VisitedJoin::Merge(paths) => {
+                // visited paths not in the table are new entries, passed over
+                while paths.next_if(|p| p.as_str() < key).is_some(){}
+                paths.next_if(|p| p.as_str() == key).is_some()
+            }
+        }
+    }
+}
+
+/// Entries of each kind of change an `UpdateDelta` lists, the rest are only counted.
+pub const DELTA_SAMPLE: usize = 100;
+
+/// Count of the entries with one kind of change, keeping the first
+/// `DELTA_SAMPLE` of them in path order.
+#[derive(Debug)]
+pub struct Sampled<T>{
+    pub count: u64,
+    // max-heap, the last path kept makes room for a lower one
+    kept: BinaryHeap<SampleEntry<T>>,
+}
+
+#[derive(Debug)]
+struct SampleEntry<T>{
+    path: String,
+    value: T,
+}
+
+impl<T> PartialEq for SampleEntry<T> {
+    fn eq(&self, other: &Self) -> bool{
+        self.path == other.path
+    }
+}
+
+impl<T> Eq for SampleEntry<T> {}
+
+impl<T> PartialOrd for SampleEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering>{
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for SampleEntry<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering{
+        self.path.cmp(&other.path)
+    }
+}
+
+impl<T> Default for Sampled<T> {
+    fn default() -> Self{
+        Sampled{ count: 0, kept: BinaryHeap::new() }
+    }
+}
+
+impl<T> Sampled<T> {
+    fn add(&mut self, path: String, value: T){
+        self.count += 1;
+        self.keep(path, value);
+    }
+
+    /// Offers an entry already counted to the sample.
+    fn keep(&mut self, path: String, value: T){
+        if self.kept.len() < DELTA_SAMPLE{
+            self.kept.push(SampleEntry{ path, value });
+        }
+        else if self.kept.peek().is_some_and(|last| path < last.path){
+            self.kept.pop();
+            self.kept.push(SampleEntry{ path, value });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.count == 0
+    }
+
+    /// The kept entries in path order.
+    pub fn entries(&self) -> Vec<(&str, &T)>{
+        let mut entries: Vec<_> = self.kept.iter().map(|e| (e.path.as_str(), &e.value)).collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    /// Entries counted but not kept.
+    pub fn omitted(&self) -> u64{
+        self.count - self.kept.len() as u64
+    }
+}
+
+/// Net effect of an update, reported as one block once the scan is done.
+#[derive(Debug, Default)]
+pub struct UpdateDelta{
+    pub added: Sampled<FileMetadataExt>,
+    pub updated: Sampled<(FileMetadataExt, FileMetadataExt)>,
+    pub removed: Sampled<FileMetadataExt>,
+}
+
+impl UpdateDelta {
+    pub fn is_empty(&self) -> bool{
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+
+    /// Entries changed in any way.
+    pub fn count(&self) -> u64{
+        self.added.count + self.updated.count + self.removed.count
+    }
+
+    pub fn report(&self) -> Vec<String>{
+        let mut lines = vec![format!("Update delta: {} added {} updated {} removed", self.added.count, self.updated.count, self.removed.count)];
+        for (k, v) in self.added.entries(){
+            lines.push(format!("  + {} {}", EscapedPath(k), v));
+        }
+        if self.added.omitted() > 0{
+            lines.push(format!("  + ... {} more", self.added.omitted()));
+        }
+        for (k, (old, new)) in self.updated.entries(){
+            lines.push(format!("  ~ {} {} -> {}", EscapedPath(k), old, new));
+        }
+        if self.updated.omitted() > 0{
+            lines.push(format!("  ~ ... {} more", self.updated.omitted()));
+        }
+        for (k, v) in self.removed.entries(){
+            lines.push(format!("  - {} {}", EscapedPath(k), v));
+        }
+        if self.removed.omitted() > 0{
+            lines.push(format!("  - ... {} more", self.removed.omitted()));
+        }
+        lines
+    }
 }
 
 pub struct UpdateDB<'ldb>{
     db: &'ldb Database,
     counter: u64,
     byte_counter: ByteSize,
-    pub files: HashSet<String>
+    delta: UpdateDelta,
+    table: TableDefinition<'ldb, String, FileMetadataExt>,
+    keys: KeyFolder,
+    /// with `--case-insensitive-compare`, the keys of the new entries by
+    /// their folded path, sampled only once paired with the removed ones
+    added_folds: Option<HashMap<String, String>>,
+    files: VisitedPaths,
+    errors: u64,
 }
 
 impl<'ldb> UpdateDB<'ldb> {
     pub fn new(db: &'ldb Database) -> Self{
-        UpdateDB{ db, counter: 0, byte_counter: ByteSize::default(), files: HashSet::new() }
+        UpdateDB{ db, counter: 0, byte_counter: ByteSize::default(), delta: UpdateDelta::default(), table: TABLE, keys: KeyFolder::default(), added_folds: None, files: VisitedPaths::new(RemovalCheck::default()), errors: 0 }
+    }
+
+    /// Finds entries to remove with `check`, call before the walk.
+    pub fn with_removal_check(mut self, check: RemovalCheck) -> Self{
+        self.files = VisitedPaths::new(check);
+        self
+    }
+
+    /// Updates entries table `name` instead of `DEFAULT_TABLE`.
+    pub fn with_table(mut self, name: &'ldb str) -> Self{
+        self.table = files_table(name);
+        self
+    }
+
+    /// Looks paths up by the `--path-fold` keys the DB was created with.
+    pub fn with_path_fold(mut self, fold: PathFold) -> Self{
+        self.keys = KeyFolder::new(fold);
+        self
+    }
+
+    /// `--case-insensitive-compare`: an entry removed and one added whose
+    /// paths differ only in case are reported as a case change.
+    pub fn with_case_insensitive_compare(mut self) -> Self{
+        self.added_folds = Some(HashMap::new());
+        self
     }
 
     pub fn get_counter(&self) -> u64{
@@ -71,6 +458,72 @@ impl<'ldb> UpdateDB<'ldb> {
     pub fn get_bytes(&self) -> ByteSize{
         self.byte_counter
     }
+
+    /// Counts the DB entries the walk of `roots` didn't come across, except
+    /// those `keep` returns true for, which [`Self::remove_missing`] deletes.
+    pub fn unvisited(&self, roots: &[String], keep: impl Fn(&str) -> bool) -> Result<Unvisited, IntegrityWatcherError>{
+        let read_txn = self.db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(self.table)?;
+        let mut counts = Unvisited::new(roots);
+        let mut visited = self.files.join();
+        for k in table.iter()?{
+            let path = k?.0.value();
+            counts.add(&path, visited.visited(&path) || keep(&path));
+        }
+        Ok(counts)
+    }
+
+    /// Removes DB entries not seen during the scan, except those `keep`
+    /// returns true for, run after all paths were visited.
+    pub fn remove_missing(&mut self, keep: impl Fn(&str) -> bool) -> Result<(), IntegrityWatcherError>{
+        let mut to_remove = Vec::new();
+        {
+            let read_txn = self.db.begin_read().map_err(Box::new)?;
+            let table = read_txn.open_table(self.table)?;
+            let mut visited = self.files.join();
+            for k in table.iter()?{
+                let k = k?.0.value();
+                if !visited.visited(&k) && !keep(&k){
+                    to_remove.push(k);
+                }
+            }
+        }
+        let write_txn = self.db.begin_write().map_err(Box::new)?;
+        {
+            let mut table = write_txn.open_table(self.table)?;
+            for k in to_remove{
+                let Some(old) = table.remove(&k)?.map(|old| old.value()) else {
+                    continue;
+                };
+                if let Some(a) = annotations::remove_in(&write_txn, self.table.name(), &k)?{
+                    warn!("Removed annotations of {} with the entry: {a}", EscapedPath(&k));
+                }
+                match self.added_folds.as_mut().and_then(|folds| folds.remove(CASE_FOLD.fold(&k).as_ref())){
+                    Some(new_k) => {
+                        info!("Case changed {} -> {}", EscapedPath(&k), EscapedPath(&new_k));
+                        self.delta.added.count -= 1;
+                        if let Some(new) = table.get(&new_k)?.map(|new| new.value())
+                        && old != new{
+                            self.delta.updated.add(new_k, (old, new));
+                        }
+                    }
+                    None => self.delta.removed.add(k, old),
+                }
+            }
+            // the new entries left are no case changes
+            for k in self.added_folds.take().unwrap_or_default().into_values(){
+                if let Some(v) = table.get(&k)?{
+                    self.delta.added.keep(k, v.value());
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn into_delta(self) -> UpdateDelta{
+        self.delta
+    }
 }
 
 impl AddFileInfo for UpdateDB<'_>{
@@ -78,44 +531,450 @@ impl AddFileInfo for UpdateDB<'_>{
 
         let write_txn = self.db.begin_write().map_err(Box::new)?;
         {
-            let mut table = write_txn.open_table(TABLE)?;
-            for (k,v) in files{
+            let mut table = write_txn.open_table(self.table)?;
+            for (path, v) in files{
+                let k = &match self.keys.key(path){
+                    Ok(k) => k.into_owned(),
+                    Err(first) => {
+                        warn!("{}", collision_message(path, &first, self.keys.fold()));
+                        self.errors += 1;
+                        continue;
+                    }
+                };
+                if !self.files.insert(k.to_owned()){
+                    debug!("Already updated {}", EscapedPath(k));
+                    continue;
+                }
                 self.counter+=1;
                 match v{
-                    FileMetadataExt::Dir(_) => {},
+                    FileMetadataExt::Dir(_) | FileMetadataExt::Whiteout | FileMetadataExt::Opaque => {},
                     FileMetadataExt::File(file) => self.byte_counter.add_size(&file.size),
                     FileMetadataExt::Symlink(symlink) => self.byte_counter.add_size(&symlink.size),
                 }
 
-                self.files.insert(k.to_owned());
-                if let Some(old) = table.insert(k, v)?{
-                    if old.value() != *v{
-                        info!("File updated {} {} -> {}", k, old.value(), v);
+                let mut v = v.clone();
+                let old = table.get(k)?.map(|o| o.value());
+                // an update without --verify-matches keeps the SHA-256 of files whose XXH3 still matches
+                if let (Some(FileMetadataExt::File(old)), FileMetadataExt::File(new)) = (&old, &mut v)
+                && new.strong.is_none() && old.hash == new.hash{
+                    new.strong = old.strong.clone();
+                }
+                table.insert(k, &v)?;
+                if let Some(old) = old{
+                    if old != v{
+                        debug!("File updated {}", EscapedPath(k));
+                        self.delta.updated.add(k.to_owned(), (old, v));
                     }
                 }
                 else{
-                    info!("New file {} {}", k, v);
+                    debug!("New file {}", EscapedPath(k));
+                    match &mut self.added_folds{
+                        Some(folds) => {
+                            self.delta.added.count += 1;
+                            folds.insert(CASE_FOLD.fold(k).into_owned(), k.to_owned());
+                        }
+                        None => self.delta.added.add(k.to_owned(), v),
+                    }
                 }
             }
         }
         write_txn.commit()?;
         Ok(())
     }
+
+    fn finish(&mut self) -> Result<ScanOutcome, IntegrityWatcherError> {
+        Ok(ScanOutcome{
+            added: self.delta.added.count,
+            updated: self.delta.updated.count,
+            checked: self.counter,
+            errors: self.errors,
+            bytes: self.byte_counter,
+        })
+    }
+}
+
+/// How `--case-insensitive-compare` matches paths.
+const CASE_FOLD: PathFold = PathFold{ icase: true, unicode: None };
+
+/// `--compare-time`: entry types whose only change being the modification
+/// time is reported. Directory mtimes change with every file added or removed
+/// in them, so they're best left out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompareTime{
+    pub files: bool,
+    pub dirs: bool,
+    pub symlinks: bool,
+}
+
+impl CompareTime {
+    pub const ALL: CompareTime = CompareTime{ files: true, dirs: true, symlinks: true };
+}
+
+impl std::str::FromStr for CompareTime {
+    type Err = IntegrityWatcherError;
+
+    /// Coma separated `files`, `dirs` or `symlinks`, or `all` or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut compare = CompareTime::default();
+        for part in s.split(','){
+            match part.trim(){
+                "none" => {},
+                "all" => compare = CompareTime::ALL,
+                "files" => compare.files = true,
+                "dirs" => compare.dirs = true,
+                "symlinks" => compare.symlinks = true,
+                other => {
+                    return Err(IntegrityWatcherError::InvalidArgument(format!("--compare-time {other:?} isn't files, dirs, symlinks, all or none")));
+                }
+            }
+        }
+        Ok(compare)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CheckOptions{
+    /// report entries of these types whose only change is the modification time
+    pub compare_time: CompareTime,
+    /// mtime differences up to this many seconds are not a change (FAT, NFS)
+    pub mtime_tolerance: u64,
+    /// stop at the first finding
+    pub fail_fast: bool,
+    /// print only this many findings, the rest is just counted
+    pub max_findings: Option<u64>,
+    /// compare only permission bits, ignoring the file type part of the mode
+    pub perms_only: bool,
+    /// collapse directories with more findings than this into one summary line
+    pub group_threshold: Option<usize>,
+    /// report files whose inode changed even when the content is the same
+    pub track_inode: bool,
+    /// `--strict-new`: new entries under these paths, or anywhere when empty,
+    /// are errors that fail the check
+    pub strict_new: Option<Vec<String>>,
+    /// match new entries to DB entries not seen whose paths differ only in
+    /// case, reported as a case change instead of new and removed
+    pub case_insensitive_compare: bool,
+    /// `--compare-only-changed-perms-on-binaries`: permission changes of files
+    /// that aren't executables are logged at debug level only
+    pub perms_on_binaries_only: bool,
+    /// `--sorted-output`: hold up to this many findings back and print them
+    /// sorted by path once the check is done
+    pub sorted_output: Option<usize>,
+    /// `--no-hash`: the walk didn't read contents, file hashes and symlink
+    /// target hashes aren't compared
+    pub metadata_only: bool,
+    /// `--check-max-size`: the walk didn't read files larger than this, only
+    /// their metadata is compared
+    pub max_size: Option<u64>,
+    /// `--vfat-friendly`: files and directories on FAT are compared without
+    /// permissions and with a 2 second mtime tolerance, see `vfat`
+    pub vfat_friendly: bool,
+    /// `--owner-by-name`: owners are compared by user and group name where
+    /// both entries have one, for baselines of systems with other ids
+    pub owner_by_name: bool,
+}
+
+impl CheckOptions {
+    /// Permissions of `fat` entries come from the mount options, they are
+    /// never a change.
+    fn perms_changed(&self, old: u32, new: u32, fat: bool) -> bool{
+        if fat{
+            false
+        }
+        else if self.perms_only{
+            old & MODE_PERMS_MASK != new & MODE_PERMS_MASK
+        }
+        else{
+            old != new
+        }
+    }
+
+    /// Whether a permission change of the file is reported, with
+    /// `perms_on_binaries_only` only when it is or becomes executable, by its
+    /// execute bits or its content type.
+    fn file_perms_reported(&self, old: &FileMetadata, new: &FileMetadata) -> bool{
+        !self.perms_on_binaries_only
+            || (old.permissions | new.permissions) & 0o111 != 0
+            || [&old.content_type, &new.content_type].into_iter().flatten().any(|t| magic::executable(t))
+    }
+
+    fn mtime_changed(&self, old: u64, new: u64, fat: bool) -> bool{
+        let tolerance = match fat{
+            true => self.mtime_tolerance.max(vfat::MTIME_TOLERANCE),
+            false => self.mtime_tolerance,
+        };
+        old.abs_diff(new) > tolerance
+    }
+
+    fn strict_new_at(&self, path: &str) -> bool{
+        self.strict_new.as_ref().is_some_and(|under| under.is_empty() || under.iter().any(|p| std::path::Path::new(path).starts_with(p)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingKind{
+    /// in the DB and on disk, but different
+    Changed,
+    /// on disk, not in the DB
+    New,
+    /// in the DB, gone from disk
+    Removed,
+}
+
+impl std::fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self{
+            FindingKind::Changed => "changed",
+            FindingKind::New => "new",
+            FindingKind::Removed => "removed",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding{
+    pub kind: FindingKind,
+    pub path: String,
+    /// what the entry is now, see `ack::entry_state`
+    pub state: String,
+    pub level: Level,
+    pub message: String,
+    /// the DB entry, none for new entries
+    pub old: Option<FileMetadataExt>,
+    /// the entry found, none for removed ones
+    pub new: Option<FileMetadataExt>,
+    /// `--entropy` of the new content in bits per byte, for files whose hash
+    /// changed
+    pub entropy: Option<f64>,
+    /// what was compared and differs, for changed entries
+    pub changes: Changes,
+}
+
+/// What a finding logged on this thread is, for log formatters styling it
+/// without reading its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoggedFinding{
+    pub kind: FindingKind,
+    pub changes: Changes,
+    /// the permissions changed to set setuid or setgid
+    pub setid_added: bool,
+}
+
+thread_local!{
+    static LOGGED: std::cell::Cell<Option<LoggedFinding>> = const { std::cell::Cell::new(None) };
+}
+
+impl LoggedFinding {
+    pub fn of(finding: &Finding) -> Self {
+        const SETID: u32 = 0o6000;
+        let permissions = |entry: Option<&FileMetadataExt>| match entry{
+            Some(FileMetadataExt::File(FileMetadata{ permissions, .. }) | FileMetadataExt::Dir(DirMetadata{ permissions, .. })) => Some(*permissions),
+            _ => None,
+        };
+        let setid_added = finding.changes.contains(Change::Permissions)
+            && matches!((permissions(finding.old.as_ref()), permissions(finding.new.as_ref())), (Some(old), Some(new)) if new & SETID & !old != 0);
+        LoggedFinding{ kind: finding.kind, changes: finding.changes, setid_added }
+    }
+}
+
+/// The finding logged on this thread, none for other log lines.
+pub fn logged_finding() -> Option<LoggedFinding> {
+    LOGGED.get()
+}
+
+/// Runs `log` with [`logged_finding`] telling formatters about `finding`.
+pub fn logging_finding<R>(finding: LoggedFinding, log: impl FnOnce() -> R) -> R {
+    LOGGED.set(Some(finding));
+    let r = log();
+    LOGGED.set(None);
+    r
+}
+
+/// Logs `finding` at its level, see [`logging_finding`].
+pub fn log_finding(finding: &Finding) {
+    logging_finding(LoggedFinding::of(finding), || log_at(finding.level, &finding.message));
+}
+
+/// `log!(level, ..)` for tracing, whose macros need the level at compile time.
+pub fn log_at(level: Level, message: &str) {
+    match level{
+        Level::Error => error!("{message}"),
+        Level::Warn => warn!("{message}"),
+        Level::Info => info!("{message}"),
+        Level::Debug => debug!("{message}"),
+        Level::Trace => trace!("{message}"),
+    }
+}
+
+/// Renders findings with directories holding more than `threshold` of them
+/// collapsed into a single rollup line. Directories are listed in path order.
+pub fn group_findings(findings: &[Finding], threshold: usize) -> Vec<(Level, String)>{
+    let mut by_dir: BTreeMap<&str, Vec<&Finding>> = BTreeMap::new();
+    for f in findings{
+        let parent = std::path::Path::new(&f.path).parent().and_then(|p| p.to_str()).unwrap_or("");
+        by_dir.entry(parent).or_default().push(f);
+    }
+    let mut lines = Vec::new();
+    for (dir, group) in by_dir{
+        if group.len() > threshold{
+            let count = |kind| group.iter().filter(|f| f.kind == kind).count();
+            let level = group.iter().map(|f| f.level).min().unwrap_or(Level::Warn);
+            lines.push((level, format!("directory {}: {} files changed, {} new, {} removed (run without --group-by-dir to expand)",
+                EscapedPath(dir), count(FindingKind::Changed), count(FindingKind::New), count(FindingKind::Removed))));
+        }
+        else{
+            lines.extend(group.iter().map(|f| (f.level, f.message.clone())));
+        }
+    }
+    lines
 }
 
 pub struct CheckDB<'ldb>{
     db: &'ldb Database,
     counter: u64,
     byte_counter: ByteSize,
-    pub files: HashSet<String>,
-    compare_time: bool,
+    files: VisitedPaths,
+    opts: CheckOptions,
     changes_count: u64,
     new_files_count: u64,
+    findings: u64,
+    printed: u64,
+    suppressed: u64,
+    grouped: Vec<Finding>,
+    /// findings held back for `sorted_output`, none once past its limit
+    held: Option<Vec<Finding>>,
+    annotations: BTreeMap<String, Annotations>,
+    volatile: BTreeSet<String>,
+    acks: BTreeMap<String, Ack>,
+    acked: u64,
+    maintenance: Vec<String>,
+    maintained: u64,
+    strict_new: u64,
+    worst: Option<Level>,
+    sink: Option<Box<dyn FnMut(Finding) + Send>>,
+    recorded: Option<Vec<Finding>>,
+    unverified: u64,
+    /// files over `max_size` whose content wasn't compared
+    content_skipped: u64,
+    /// entries whose only change is the mtime, not reported by `compare_time`
+    time_only: u64,
+    table: TableDefinition<'ldb, String, FileMetadataExt>,
+    keys: KeyFolder,
+    /// case folded DB keys, read with the first path missing from the DB
+    case_index: Option<HashMap<String, Vec<String>>>,
+    /// DB keys matched to a path differing only in case
+    case_changed: HashSet<String>,
+    /// paths not compared, their key taken by another path
+    errors: u64,
+    /// `--entropy` of the files the walk hashed
+    entropy: Option<Arc<entropy::Measured>>,
+    /// of the file whose hash changed, for the finding `report` makes next
+    changed_entropy: Option<f64>,
+    /// `--quarantine-dir` copies of files whose hash changed
+    quarantine: Option<quarantine::Quarantine>,
+    /// directories told FAT or not with `vfat_friendly`
+    fat_dirs: vfat::FatDirs,
+    /// `--severity-policy` deciding the level of findings
+    severity: Option<SeverityPolicy>,
+    /// what changed about the entry being compared, for `severity`
+    changes: Changes,
 }
 
 impl<'ldb> CheckDB<'ldb>{
-    pub fn new(db: &'ldb Database, compare_time: bool) -> Self{
-        CheckDB { db, files: HashSet::new(), compare_time, counter: 0, byte_counter: ByteSize::default(), changes_count: 0, new_files_count: 0 }
+    pub fn new(db: &'ldb Database, opts: CheckOptions) -> Self{
+        let held = opts.sorted_output.map(|_| Vec::new());
+        CheckDB { db, files: VisitedPaths::new(RemovalCheck::default()), opts, counter: 0, byte_counter: ByteSize::default(), changes_count: 0, new_files_count: 0, findings: 0, printed: 0, suppressed: 0, grouped: Vec::new(), held, annotations: BTreeMap::new(), volatile: BTreeSet::new(), acks: BTreeMap::new(), acked: 0, maintenance: Vec::new(), maintained: 0, strict_new: 0, worst: None, sink: None, recorded: None, unverified: 0, content_skipped: 0, time_only: 0, table: TABLE, keys: KeyFolder::default(), case_index: None, case_changed: HashSet::new(), errors: 0, entropy: None, changed_entropy: None, quarantine: None, fat_dirs: vfat::FatDirs::default(), severity: None, changes: Changes::default() }
+    }
+
+    /// Finds removed entries with `check`, call before the walk.
+    pub fn with_removal_check(mut self, check: RemovalCheck) -> Self{
+        self.files = VisitedPaths::new(check);
+        self
+    }
+
+    /// Checks against entries table `name` instead of `DEFAULT_TABLE`.
+    pub fn with_table(mut self, name: &'ldb str) -> Self{
+        self.table = files_table(name);
+        self
+    }
+
+    /// Looks paths up by the `--path-fold` keys the DB was created with.
+    pub fn with_path_fold(mut self, fold: PathFold) -> Self{
+        self.keys = KeyFolder::new(fold);
+        self
+    }
+
+    /// Annotations shown with findings of the annotated paths.
+    pub fn with_annotations(mut self, annotations: BTreeMap<String, Annotations>) -> Self{
+        self.annotations = annotations;
+        self
+    }
+
+    /// Entries marked with `--mark-volatile`: changes of their content, size
+    /// and times are only logged at debug level.
+    pub fn with_volatile(mut self, volatile: BTreeSet<String>) -> Self{
+        self.volatile = volatile;
+        self
+    }
+
+    /// Adds the entropy of the new content to findings of files whose hash
+    /// changed, `measured` being the `ScanOptions::entropy` of the walk.
+    pub fn with_entropy(mut self, measured: Arc<entropy::Measured>) -> Self{
+        self.entropy = Some(measured);
+        self
+    }
+
+    /// Logs findings at the level of the highest severity `policy` gives their
+    /// changes, instead of the built in one.
+    pub fn with_severity_policy(mut self, policy: SeverityPolicy) -> Self{
+        self.severity = Some(policy);
+        self
+    }
+
+    /// Copies files whose hash changed into `quarantine`.
+    pub fn with_quarantine(mut self, quarantine: quarantine::Quarantine) -> Self{
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Findings covered by these acknowledgments are reported at info level
+    /// and don't count as findings.
+    pub fn with_acks(mut self, acks: BTreeMap<String, Ack>) -> Self{
+        self.acks = acks;
+        self
+    }
+
+    /// Findings under these paths are expected during a `--maintenance-until`
+    /// window, they're reported at info level and don't count as findings.
+    pub fn with_maintenance(mut self, paths: Vec<String>) -> Self{
+        self.maintenance = paths;
+        self
+    }
+
+    /// Hands findings to `sink` as values instead of logging them.
+    pub fn with_finding_sink(mut self, sink: impl FnMut(Finding) + Send + 'static) -> Self{
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Keeps a copy of every finding for `take_recorded`, e.g. for `--push`.
+    pub fn recording(mut self) -> Self{
+        self.recorded = Some(Vec::new());
+        self
+    }
+
+    /// DB entries of `paths`, for entries that can't be told in full otherwise
+    /// and take the rest from what's stored.
+    pub fn stored<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> Result<Vec<Option<FileMetadataExt>>, IntegrityWatcherError>{
+        let read_txn = self.db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(self.table)?;
+        let fold = self.keys.fold();
+        paths.into_iter().map(|path| Ok(table.get(fold.fold(path).into_owned())?.map(|v| v.value()))).collect()
+    }
+
+    pub fn take_recorded(&mut self) -> Vec<Finding>{
+        self.recorded.as_mut().map(std::mem::take).unwrap_or_default()
     }
 
     pub fn get_counter(&self) -> u64{
@@ -133,163 +992,696 @@ impl<'ldb> CheckDB<'ldb>{
     pub fn get_new_files_count(&self) -> u64 {
         self.new_files_count
     }
+
+    /// Records a finding about `path`, whose DB entry `old` is now `entry`. It's
+    /// printed right away, or kept for `flush_findings` when sorting or
+    /// grouping by directory. Returns false when an acknowledgment or maintenance window
+    /// covers it.
+    pub fn report(&mut self, kind: FindingKind, path: &str, old: Option<&FileMetadataExt>, entry: Option<&FileMetadataExt>, mut level: Level, mut message: String) -> bool {
+        let entropy = self.changed_entropy.take();
+        // only changed entries were compared
+        let mut changes = match std::mem::take(&mut self.changes){
+            compared if kind == FindingKind::Changed => compared,
+            _ => Changes::default(),
+        };
+        if let Some(policy) = &self.severity{
+            match kind{
+                FindingKind::New => {
+                    changes.insert(Change::New);
+                    if let Some(FileMetadataExt::File(FileMetadata{ permissions, .. }) | FileMetadataExt::Dir(DirMetadata{ permissions, .. })) = entry
+                    && severity::world_writable(*permissions){
+                        changes.insert(Change::WorldWritable);
+                    }
+                }
+                FindingKind::Removed => changes.insert(Change::Removed),
+                FindingKind::Changed => {}
+            }
+            if let Some(severity) = policy.severity(changes){
+                level = severity.level();
+            }
+        }
+        let state = ack::entry_state(entry);
+        let mut counted = false;
+        if self.acks.get(path).is_some_and(|a| a.covers(kind, &state)){
+            self.acked += 1;
+            level = Level::Info;
+            message += " (acknowledged)";
+        }
+        else if self.maintenance.iter().any(|p| std::path::Path::new(path).starts_with(p)){
+            self.maintained += 1;
+            level = Level::Info;
+            message += " (maintenance window)";
+        }
+        else{
+            self.findings += 1;
+            self.worst = Some(self.worst.map_or(level, |w| w.min(level)));
+            counted = true;
+        }
+        if let Some(a) = self.annotations.get(path){
+            message = format!("{message} [{a}]");
+        }
+        let finding = || Finding { kind, path: path.to_owned(), state: state.clone(), level, message: message.clone(), old: old.cloned(), new: entry.cloned(), entropy, changes };
+        if let Some(recorded) = &mut self.recorded{
+            recorded.push(finding());
+        }
+        match &mut self.held{
+            Some(held) if held.len() < self.opts.sorted_output.unwrap_or_default() => held.push(finding()),
+            Some(_) => {
+                warn!("More than {} findings, --sorted-output prints the rest unsorted", self.opts.sorted_output.unwrap_or_default());
+                for held in self.held.take().unwrap_or_default(){
+                    self.deliver(held);
+                }
+                self.deliver(finding());
+            }
+            None => self.deliver(finding()),
+        }
+        counted
+    }
+
+    /// Hands `finding` to the sink, holds it for `--group-by-dir` or prints it.
+    fn deliver(&mut self, finding: Finding) {
+        if let Some(sink) = &mut self.sink{
+            sink(finding);
+        }
+        else if self.opts.group_threshold.is_some(){
+            self.grouped.push(finding);
+        }
+        else if self.may_print(){
+            log_finding(&finding);
+        }
+    }
+
+    /// Whether `--max-findings` lets another line out, counting it.
+    fn may_print(&mut self) -> bool {
+        if self.opts.max_findings.is_some_and(|max| self.printed >= max){
+            self.suppressed += 1;
+            return false;
+        }
+        self.printed += 1;
+        true
+    }
+
+    fn emit(&mut self, level: Level, message: &str) {
+        if self.may_print(){
+            log_at(level, message);
+        }
+    }
+
+    /// Whether the DB entry `key` was checked, directly or as the entry of a
+    /// path whose case changed.
+    pub fn is_checked(&self, key: &str) -> bool {
+        self.files.contains(key) || self.case_changed.contains(key)
+    }
+
+    /// DB entry of `k` missing from the DB under another case, with
+    /// `case_insensitive_compare`. Entries already checked aren't matched.
+    fn case_changed_entry(&mut self, k: &str, table: &redb::ReadOnlyTable<String, FileMetadataExt>) -> Result<Option<FileMetadataExt>, IntegrityWatcherError> {
+        if !self.opts.case_insensitive_compare{
+            return Ok(None);
+        }
+        if self.case_index.is_none(){
+            let mut index: HashMap<String, Vec<String>> = HashMap::new();
+            for e in table.iter()?{
+                let key = e?.0.value();
+                index.entry(CASE_FOLD.fold(&key).into_owned()).or_default().push(key);
+            }
+            self.case_index = Some(index);
+        }
+        let candidates = self.case_index.as_ref().and_then(|index| index.get(CASE_FOLD.fold(k).as_ref()));
+        let Some(old_k) = candidates.and_then(|c| c.iter().find(|c| !self.is_checked(c))).cloned() else {
+            return Ok(None);
+        };
+        let Some(old) = table.get(&old_k)?.map(|o| o.value()) else {
+            return Ok(None);
+        };
+        info!("Case changed {} -> {}", EscapedPath(&old_k), EscapedPath(k));
+        self.case_changed.insert(old_k);
+        Ok(Some(old))
+    }
+
+    /// Counts the DB entries the walk of `roots` didn't come across, except
+    /// those `skip` returns true for, before [`Self::report_removed`].
+    pub fn unvisited(&self, roots: &[String], skip: impl Fn(&str) -> bool) -> Result<Unvisited, IntegrityWatcherError> {
+        let read_txn = self.db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(self.table)?;
+        let mut counts = Unvisited::new(roots);
+        let mut visited = self.files.join();
+        for k in table.iter()?{
+            let path = k?.0.value();
+            counts.add(&path, visited.visited(&path) || self.case_changed.contains(&path) || skip(&path));
+        }
+        Ok(counts)
+    }
+
+    /// Reports DB entries the walk didn't come across as removed, except those
+    /// `skip` returns true for. Returns how many were reported.
+    pub fn report_removed(&mut self, skip: impl Fn(&str) -> bool) -> Result<u64, IntegrityWatcherError> {
+        let read_txn = self.db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(self.table)?;
+        // reporting borrows all of self, the visited paths are put back after
+        let files = std::mem::replace(&mut self.files, VisitedPaths::new(RemovalCheck::default()));
+        let removed = self.report_unvisited(&files, &table, skip);
+        self.files = files;
+        removed
+    }
+
+    fn report_unvisited(&mut self, files: &VisitedPaths, table: &redb::ReadOnlyTable<String, FileMetadataExt>, skip: impl Fn(&str) -> bool) -> Result<u64, IntegrityWatcherError> {
+        let mut removed = 0;
+        let mut visited = files.join();
+        for k in table.iter()?{
+            let k = k?;
+            let path = k.0.value();
+            if !visited.visited(&path) && !self.case_changed.contains(&path) && !skip(&path){
+                removed += 1;
+                let old = k.1.value();
+                self.report(FindingKind::Removed, &path, Some(&old), None, Level::Warn, format!("File removed {} {}", EscapedPath(&path), old));
+                if self.should_stop(){
+                    break;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Prints findings held back for `--sorted-output` and `--group-by-dir`,
+    /// call once the check is done.
+    pub fn flush_findings(&mut self) {
+        if let Some(mut held) = self.held.take(){
+            // stable, the findings of one path keep their order
+            held.sort_by(|a, b| a.path.cmp(&b.path));
+            for finding in held{
+                self.deliver(finding);
+            }
+        }
+        if let Some(threshold) = self.opts.group_threshold{
+            let findings = std::mem::take(&mut self.grouped);
+            for (level, line) in group_findings(&findings, threshold){
+                self.emit(level, &line);
+            }
+        }
+    }
+
+    /// Findings covered by an acknowledgment, also counted as changed, new or
+    /// removed.
+    pub fn get_acked_count(&self) -> u64 {
+        self.acked
+    }
+
+    /// Findings under the paths of a maintenance window, also counted as
+    /// changed, new or removed.
+    pub fn get_maintenance_count(&self) -> u64 {
+        self.maintained
+    }
+
+    /// New entries reported as errors by `--strict-new`, not counting
+    /// acknowledged ones or those in a maintenance window.
+    pub fn get_strict_new_count(&self) -> u64 {
+        self.strict_new
+    }
+
+    /// Level of the most severe finding not covered by an acknowledgment or a
+    /// maintenance window, none when there's no such finding.
+    pub fn get_worst_level(&self) -> Option<Level> {
+        self.worst
+    }
+
+    /// Findings not printed because of `--max-findings`.
+    pub fn get_suppressed_count(&self) -> u64 {
+        self.suppressed
+    }
+
+    /// Entries whose only change is the modified time, not reported for their
+    /// type by `--compare-time`.
+    pub fn get_time_only_count(&self) -> u64 {
+        self.time_only
+    }
+
+    /// `--verify-matches` files whose XXH3 matched but whose DB entry has no
+    /// SHA-256 to confirm it, run `--update --verify-matches` to store them.
+    pub fn get_unverified_count(&self) -> u64 {
+        self.unverified
+    }
+
+    /// Records a reported permission change, and whether it lets anyone write.
+    fn permissions_changed(&mut self, old: u32, new: u32) {
+        self.changes.insert(Change::Permissions);
+        if severity::world_writable(new) && !severity::world_writable(old){
+            self.changes.insert(Change::WorldWritable);
+        }
+    }
+
+    /// The `--quarantine-dir` of the check, with its counts.
+    pub fn get_quarantine(&self) -> Option<&quarantine::Quarantine> {
+        self.quarantine.as_ref()
+    }
+
+    /// Files over `--check-max-size` whose content wasn't read, only their
+    /// size, mtime and permissions were compared.
+    pub fn get_content_skipped_count(&self) -> u64 {
+        self.content_skipped
+    }
+}
+
+/// Finding text for a changed Windows owner or DACL. Entries stored without
+/// them, e.g. before they were recorded, aren't reported.
+fn security_changed(old: &Option<Security>, new: &Option<Security>) -> Option<String> {
+    match (old, new){
+        (Some(old), Some(new)) if old != new => {
+            let mut info = String::new();
+            if old.owner != new.owner{
+                info += &format!(" owner changed {} -> {}", old.owner, new.owner);
+            }
+            if old.descriptor != new.descriptor{
+                info += &format!(" acl changed {} -> {}", old.descriptor, new.descriptor);
+            }
+            Some(info)
+        }
+        (Some(old), None) => Some(format!(" owner {} and acl no longer readable", old.owner)),
+        _ => None,
+    }
+}
+
+/// Finding text for immutable or append-only flags added or removed. Entries
+/// stored without flags, or whose flags can't be read now, aren't reported.
+fn flags_changed(old: Option<u32>, new: Option<u32>) -> Option<String> {
+    inodeflags::changed(old?, new?)
+}
+
+/// `new` with the content, size and times of `old`, for comparing a volatile
+/// entry by the rest. Entries that changed type aren't masked.
+fn volatile_masked(old: &FileMetadataExt, new: &FileMetadataExt) -> Option<FileMetadataExt> {
+    match (old, new){
+        (FileMetadataExt::File(old), FileMetadataExt::File(new)) => Some(FileMetadataExt::File(FileMetadata{
+            hash: old.hash.clone(),
+            strong: old.strong.clone(),
+            size: old.size,
+            modified: old.modified,
+            created: old.created,
+            ino: old.ino,
+            ..new.clone()
+        })),
+        (FileMetadataExt::Dir(old), FileMetadataExt::Dir(new)) => Some(FileMetadataExt::Dir(DirMetadata{
+            size: old.size,
+            modified: old.modified,
+            entries: old.entries,
+            ..new.clone()
+        })),
+        (FileMetadataExt::Symlink(old), FileMetadataExt::Symlink(new)) => Some(FileMetadataExt::Symlink(SymlinkMetadata{
+            data: old.data.clone(),
+            content: old.content.clone(),
+            target_exists: old.target_exists,
+            target_type: old.target_type,
+            size: old.size,
+            modified: old.modified,
+            ..new.clone()
+        })),
+        _ => None,
+    }
+}
+
+/// Finding text for a symlink that stopped resolving, resolves again or now
+/// resolves to another kind of entry, with its target text unchanged. Entries
+/// stored before the target was recorded aren't reported.
+fn target_changed(old: &SymlinkMetadata, new: &SymlinkMetadata) -> Option<String> {
+    if old.target_exists && old.target_type.is_none(){
+        return None;
+    }
+    match (old.target_exists, new.target_exists){
+        (true, false) => Some(" target no longer exists, link is dangling".to_owned()),
+        (false, true) => Some(format!(" dangling link resolves again to {}", new.target_type.map_or("something".to_owned(), |t| t.to_string()))),
+        (true, true) => match (old.target_type, new.target_type){
+            (Some(a), Some(b)) if a != b => Some(format!(" target changed from {a} to {b}")),
+            _ => None,
+        },
+        (false, false) => None,
+    }
+}
+
+/// Finding text for added, removed or modified NTFS alternate data streams,
+/// which are a common place to hide payloads. Like `security_changed` entries
+/// stored without streams aren't reported.
+fn streams_changed(old: &Option<Vec<DataStream>>, new: &Option<Vec<DataStream>>) -> Option<String> {
+    let (Some(old), Some(new)) = (old, new) else {
+        return old.as_ref().map(|_| " streams no longer readable".to_owned());
+    };
+    let mut info = String::new();
+    for stream in new{
+        match old.iter().find(|o| o.name == stream.name){
+            None => info += &format!(" stream added {stream}"),
+            Some(o) if o != stream => info += &format!(" stream :{} changed size {} -> {} hash {} -> {}", EscapedPath(&stream.name), o.size, stream.size, o.hash, stream.hash),
+            Some(_) => {}
+        }
+    }
+    for stream in old.iter().filter(|o| !new.iter().any(|n| n.name == o.name)){
+        info += &format!(" stream removed {stream}");
+    }
+    (!info.is_empty()).then_some(info)
 }
 
 impl AddFileInfo for CheckDB<'_> {
     fn add_file_info(&mut self, files: &[(String, FileMetadataExt)]) -> Result<(), IntegrityWatcherError> {
 
         let read_txn = self.db.begin_read().map_err(Box::new)?;
-        let table = read_txn.open_table(TABLE)?;
-        for (k, v) in files{
-            self.files.insert(k.to_owned());
+        let table = read_txn.open_table(self.table)?;
+        for (path, v) in files{
+            if self.should_stop(){
+                break;
+            }
+            self.changes = Changes::default();
+            // a second path with the key of a checked one could hide behind it
+            let k = &match self.keys.key(path){
+                Ok(k) => k.into_owned(),
+                Err(first) => {
+                    self.new_files_count += 1;
+                    self.errors += 1;
+                    let message = collision_message(path, &first, self.keys.fold());
+                    self.report(FindingKind::New, path, None, Some(v), Level::Error, message);
+                    continue;
+                }
+            };
+            if !self.files.insert(k.to_owned()){
+                debug!("Already checked {}", EscapedPath(k));
+                continue;
+            }
 
             self.counter += 1;
+            // taken whatever the file compares like, the rest isn't needed again
+            let entropy = match v{
+                FileMetadataExt::File(_) => self.entropy.as_ref().and_then(|m| m.take(path)),
+                _ => None,
+            };
+            let fat = self.opts.vfat_friendly && match v{
+                FileMetadataExt::File(_) => self.fat_dirs.contains(path, false),
+                FileMetadataExt::Dir(_) => self.fat_dirs.contains(path, true),
+                _ => false,
+            };
 
             match v{
-                FileMetadataExt::Dir(_) => {},
+                FileMetadataExt::Dir(_) | FileMetadataExt::Whiteout | FileMetadataExt::Opaque => {},
                 FileMetadataExt::File(file) => self.byte_counter.add_size(&file.size),
                 FileMetadataExt::Symlink(symlink) => self.byte_counter.add_size(&symlink.size),
             }
 
-            if let Some(oldv) = table.get(k)?{
-                if oldv.value() != *v{
-                    let old_val = oldv.value();
+            let old = match table.get(k)?{
+                Some(old) => Some(old.value()),
+                None => self.case_changed_entry(k, &table)?,
+            };
+            if let Some(old_val) = old{
+                let masked = self.volatile.contains(k).then(|| volatile_masked(&old_val, v)).flatten();
+                if let Some(masked) = &masked && masked != v{
+                    debug!("Volatile {} changed to {v}", EscapedPath(k));
+                }
+                let compared = masked.as_ref().unwrap_or(v);
+                if old_val != *compared{
+                    let old_entry = old_val.clone();
                     let mut info = String::new();
+                    if std::mem::discriminant(&old_val) != std::mem::discriminant(compared){
+                        self.changes.insert(Change::Type);
+                    }
 
-                    match (old_val, v)
+                    match (old_val, compared)
                     {
                         (FileMetadataExt::Symlink(s), FileMetadataExt::File(f)) => {
-                            error!("{} Symlink {} changed to file {}", k, s, f);
                             self.changes_count += 1;
+                            self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("{} Symlink {} changed to file {}", EscapedPath(k), s, f));
                         },
                          (FileMetadataExt::File(f), FileMetadataExt::Symlink(s)) => {
-                             error!("{} File {} changed to symlink {}", k, f, s);
                              self.changes_count += 1;
+                             self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("{} File {} changed to symlink {}", EscapedPath(k), f, s));
                          },
                          (FileMetadataExt::Dir(f), FileMetadataExt::Symlink(s)) => {
-                             error!("{} Dir {} changed to symlink {}", k, f, s);
                              self.changes_count += 1;
+                             self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("{} Dir {} changed to symlink {}", EscapedPath(k), f, s));
                          },
                          (FileMetadataExt::Dir(f), FileMetadataExt::File(s)) => {
-                             error!("{} Dir {} changed to file {}", k, f, s);
                              self.changes_count += 1;
+                             self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("{} Dir {} changed to file {}", EscapedPath(k), f, s));
                          },
                          (FileMetadataExt::Symlink(f), FileMetadataExt::Dir(s)) => {
-                             error!("{} Symlink {} changed to dir {}", k, f, s);
                              self.changes_count += 1;
+                             self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("{} Symlink {} changed to dir {}", EscapedPath(k), f, s));
                          },
                          (FileMetadataExt::File(f), FileMetadataExt::Dir(s)) => {
-                             error!("{} File {} changed to dir {}", k, f, s);
                              self.changes_count += 1;
+                             self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("{} File {} changed to dir {}", EscapedPath(k), f, s));
                          },
+                        // a file of a lower layer showing again, or one a layer deletes now
+                        (old @ (FileMetadataExt::Whiteout | FileMetadataExt::Opaque), new) | (old, new @ (FileMetadataExt::Whiteout | FileMetadataExt::Opaque)) => {
+                            self.changes_count += 1;
+                            self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("{} {} changed to {}", EscapedPath(k), old, new));
+                        },
                         (FileMetadataExt::Dir(old), FileMetadataExt::Dir(new)) => {
                             let mut only_time_modified = true;
-                            if old.modified != new.modified{
-                                let t1: String = match DateTime::from_timestamp(old.modified as i64, 0){
-                                    Some(t) => t.to_string(),
-                                    None => "#ERROR#".to_owned(),
-                                };
-                                let t2: String = match DateTime::from_timestamp(new.modified as i64, 0){
-                                    Some(t) => t.to_string(),
-                                    None => "#ERROR#".to_owned(),
-                                };
-                                info += &format!(" modified time changed {} -> {}", t1, t2);
-                            }
-                            if old.permissions != new.permissions{
-                                info += &format!(" permissions changed {:o} -> {:o}", old.permissions, new.permissions);
+                            let mut time_modified = false;
+                            if self.opts.mtime_changed(old.modified, new.modified, fat){
+                                info += &format!(" modified time changed {} -> {}", Timestamp::new(old.modified), Timestamp::new(new.modified));
+                                self.changes.insert(Change::Mtime);
+                                time_modified = true;
+                            }
+                            if self.opts.perms_changed(old.permissions, new.permissions, fat){
+                                info += &format!(" permissions changed {} -> {}", Permissions::new(old.permissions), Permissions::new(new.permissions));
+                                self.permissions_changed(old.permissions, new.permissions);
                                 only_time_modified = false;
                             }
                             if old.size != new.size{
-                                info += &format!(" size changed {} -> {}", old.size, new.size);
+                                info += &format!(" size changed {} -> {}", DirSize::new(old.size), DirSize::new(new.size));
+                                self.changes.insert(Change::Size);
+                                only_time_modified = false;
+                            }
+                            if let (Some(old_entries), Some(new_entries)) = (old.entries, new.entries)
+                            && old_entries != new_entries{
+                                info += &format!(" entry count changed {old_entries} -> {new_entries}");
+                                self.changes.insert(Change::Entries);
+                                only_time_modified = false;
+                            }
+                            if let Some(changed) = security_changed(&old.security, &new.security){
+                                info += &changed;
+                                self.changes.insert(Change::Owner);
                                 only_time_modified = false;
                             }
-                            if !only_time_modified || self.compare_time{
-                                error!("Dir {} changed:{}", k, info);
+                            if let Some(changed) = flags_changed(old.flags, new.flags){
+                                info += &changed;
+                                self.changes.insert(Change::Flags);
+                                only_time_modified = false;
+                            }
+                            if let Some(changed) = ownership::changed(old.owner.as_ref(), new.owner.as_ref(), self.opts.owner_by_name){
+                                info += &changed;
+                                self.changes.insert(Change::Owner);
+                                only_time_modified = false;
+                            }
+                            if !only_time_modified || (time_modified && self.opts.compare_time.dirs){
                                 self.changes_count += 1;
+                                self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("Dir {} changed:{}", EscapedPath(k), info));
+                            }
+                            else{
+                                self.time_only += u64::from(time_modified);
+                                debug!("Dir ok {}", EscapedPath(k));
                             }
                         },
                         (FileMetadataExt::File(old), FileMetadataExt::File(new)) => {
                             let mut only_time_modified = true;
-                            if old.hash != new.hash{
+                            let mut time_modified = false;
+                            let content_skipped = self.opts.max_size.is_some_and(|max| u64::from(new.size) > max);
+                            if self.opts.metadata_only{
+                                // contents weren't read
+                            }
+                            else if content_skipped{
+                                self.content_skipped += 1;
+                            }
+                            else if old.hash != new.hash{
                                 info = format!(" hash changed {} -> {}", old.hash, new.hash);
+                                self.changes.insert(Change::Hash);
+                                if let Some(bits) = entropy{
+                                    info += &entropy::describe(bits);
+                                    self.changed_entropy = Some(bits);
+                                }
+                                if let Some(quarantine) = &mut self.quarantine{
+                                    quarantine.keep(path, &old, new);
+                                }
                                 only_time_modified = false;
                             }
-                            if old.modified != new.modified{
-                                let t1: String = match DateTime::from_timestamp(old.modified as i64, 0){
-                                    Some(t) => t.to_string(),
-                                    None => "#ERROR#".to_owned(),
-                                };
-                                let t2: String = match DateTime::from_timestamp(new.modified as i64, 0){
-                                    Some(t) => t.to_string(),
-                                    None => "#ERROR#".to_owned(),
-                                };
-                                info += &format!(" modified time changed {} -> {}", t1, t2);
-                            }
-                            if old.permissions != new.permissions{
-                                info += &format!(" permissions changed {:o} -> {:o}", old.permissions, new.permissions);
+                            else{
+                                match (&old.strong, &new.strong){
+                                    (Some(old_strong), Some(new_strong)) if old_strong != new_strong => {
+                                        info = format!(" SHA-256 changed {old_strong} -> {new_strong}, XXH3 {} unchanged", new.hash);
+                                        self.changes.insert(Change::Hash);
+                                        only_time_modified = false;
+                                    }
+                                    (None, Some(_)) => self.unverified += 1,
+                                    _ => {}
+                                }
+                            }
+                            // replaced, but made to look as old as before: born after
+                            // what the baseline knew while the mtime stayed or went back
+                            if !self.opts.metadata_only && !content_skipped && old.hash != new.hash && new.modified <= old.modified
+                            && let Some(created) = new.created
+                            && created > old.created.unwrap_or(old.modified){
+                                info += &format!(" created {} after modified time {}, likely backdated", Timestamp::new(created), Timestamp::new(new.modified));
+                            }
+                            if self.opts.track_inode
+                            && let (Some(old_ino), Some(new_ino)) = (old.ino, new.ino)
+                            && old_ino != new_ino{
+                                info += &format!(" inode changed {} -> {}", old_ino, new_ino);
+                                self.changes.insert(Change::Inode);
                                 only_time_modified = false;
                             }
+                            if self.opts.mtime_changed(old.modified, new.modified, fat){
+                                info += &format!(" modified time changed {} -> {}", Timestamp::new(old.modified), Timestamp::new(new.modified));
+                                self.changes.insert(Change::Mtime);
+                                time_modified = true;
+                                // the only sign of a content change left when it wasn't read
+                                if content_skipped{
+                                    info += " (content not compared, over --check-max-size)";
+                                    only_time_modified = false;
+                                }
+                            }
+                            if self.opts.perms_changed(old.permissions, new.permissions, fat){
+                                if self.opts.file_perms_reported(&old, new){
+                                    info += &format!(" permissions changed {} -> {}", Permissions::new(old.permissions), Permissions::new(new.permissions));
+                                    self.permissions_changed(old.permissions, new.permissions);
+                                    only_time_modified = false;
+                                }
+                                else{
+                                    debug!("Permissions of {} changed {} -> {}, not an executable", EscapedPath(k), Permissions::new(old.permissions), Permissions::new(new.permissions));
+                                }
+                            }
                             if old.size != new.size{
                                 info += &format!(" size changed {} -> {}", old.size, new.size);
+                                // part of a content change when the hash tells one
+                                if !self.changes.contains(Change::Hash){
+                                    self.changes.insert(Change::Size);
+                                }
                                 only_time_modified = false;
                             }
-                            if !only_time_modified || self.compare_time{
-                                error!("File {} changed:{}", k, info);
+                            if let Some(changed) = security_changed(&old.security, &new.security){
+                                info += &changed;
+                                self.changes.insert(Change::Owner);
+                                only_time_modified = false;
+                            }
+                            if let (Some(old_type), Some(new_type)) = (&old.content_type, &new.content_type)
+                            && old_type != new_type{
+                                info += &format!(" content type changed {old_type} -> {new_type}");
+                                self.changes.insert(Change::Type);
+                                only_time_modified = false;
+                            }
+                            if let Some(changed) = streams_changed(&old.streams, &new.streams){
+                                info += &changed;
+                                self.changes.insert(Change::Hash);
+                                only_time_modified = false;
+                            }
+                            if let Some(changed) = flags_changed(old.flags, new.flags){
+                                info += &changed;
+                                self.changes.insert(Change::Flags);
+                                only_time_modified = false;
+                            }
+                            if let Some(changed) = ownership::changed(old.owner.as_ref(), new.owner.as_ref(), self.opts.owner_by_name){
+                                info += &changed;
+                                self.changes.insert(Change::Owner);
+                                only_time_modified = false;
+                            }
+                            if !only_time_modified || (time_modified && self.opts.compare_time.files){
                                 self.changes_count += 1;
+                                self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("File {} changed:{}", EscapedPath(k), info));
+                            }
+                            else{
+                                self.time_only += u64::from(time_modified);
+                                debug!("File ok {}", EscapedPath(k));
                             }
                         },
                         (FileMetadataExt::Symlink(old), FileMetadataExt::Symlink(new)) => {
                             let mut only_time_modified = true;
+                            let mut time_modified = false;
                             if old.data != new.data{
-                                info = format!(" changed {} -> {}", old.data, new.data);
+                                info = format!(" changed {} -> {}", EscapedPath(&old.data), EscapedPath(&new.data));
+                                self.changes.insert(Change::Target);
+                                only_time_modified = false;
+                            }
+                            match (&old.content, &new.content){
+                                _ if self.opts.metadata_only => {}
+                                (Some(old_content), Some(new_content)) if old_content != new_content => {
+                                    info += &format!(" target content changed {old_content} -> {new_content}");
+                                    self.changes.insert(Change::Hash);
+                                    only_time_modified = false;
+                                }
+                                (Some(old_content), None) => {
+                                    info += &format!(" target content {old_content} no longer a file");
+                                    self.changes.insert(Change::Target);
+                                    only_time_modified = false;
+                                }
+                                (None, Some(new_content)) => {
+                                    info += &format!(" target content {new_content} was not a file");
+                                    self.changes.insert(Change::Target);
+                                    only_time_modified = false;
+                                }
+                                _ => {}
+                            }
+                            if let Some(changed) = target_changed(&old, new){
+                                info += &changed;
+                                self.changes.insert(Change::Target);
                                 only_time_modified = false;
                             }
-                            if old.modified != new.modified{
-                                let t1: String = match DateTime::from_timestamp(old.modified as i64, 0){
-                                    Some(t) => t.to_string(),
-                                    None => "#ERROR#".to_owned(),
-                                };
-                                let t2: String = match DateTime::from_timestamp(new.modified as i64, 0){
-                                    Some(t) => t.to_string(),
-                                    None => "#ERROR#".to_owned(),
-                                };
-                                info += &format!(" modified time changed {} -> {}", t1, t2);
-                            }
-                            if old.permissions != new.permissions{
-                                info += &format!(" permissions changed {:o} -> {:o}", old.permissions, new.permissions);
+                            if self.opts.mtime_changed(old.modified, new.modified, false){
+                                info += &format!(" modified time changed {} -> {}", Timestamp::new(old.modified), Timestamp::new(new.modified));
+                                self.changes.insert(Change::Mtime);
+                                time_modified = true;
+                            }
+                            if self.opts.perms_changed(old.permissions, new.permissions, false){
+                                info += &format!(" permissions changed {} -> {}", Permissions::new(old.permissions), Permissions::new(new.permissions));
+                                self.permissions_changed(old.permissions, new.permissions);
                                 only_time_modified = false;
                             }
                             if old.size != new.size{
                                 info += &format!(" size changed {} -> {}", old.size, new.size);
+                                self.changes.insert(Change::Size);
                                 only_time_modified = false;
                             }
-                            if !only_time_modified || self.compare_time{
-                                error!("Symlink {} changed:{}", k, info);
+                            if !only_time_modified || (time_modified && self.opts.compare_time.symlinks){
                                 self.changes_count += 1;
+                                self.report(FindingKind::Changed, k, Some(&old_entry), Some(v), Level::Error, format!("Symlink {} changed:{}", EscapedPath(k), info));
+                            }
+                            else{
+                                self.time_only += u64::from(time_modified);
+                                debug!("Symlink ok {}", EscapedPath(k));
                             }
                         }
                     }
                 }
                 else {
-                    debug!("File ok {}", k);
+                    debug!("File ok {}", EscapedPath(k));
                 }
             }
             else{
-                warn!("New file {} {}", k, v);
                 self.new_files_count += 1;
+                let strict = self.opts.strict_new_at(k);
+                let level = if strict { Level::Error } else { Level::Warn };
+                if self.report(FindingKind::New, k, None, Some(v), level, format!("New file {} {}", EscapedPath(k), v)) && strict{
+                    self.strict_new += 1;
+                }
             }
         }
         Ok(())
     }
+
+    fn should_stop(&self) -> bool{
+        self.opts.fail_fast && self.findings > 0
+    }
+
+    fn finish(&mut self) -> Result<ScanOutcome, IntegrityWatcherError> {
+        if let Some(quarantine) = &mut self.quarantine{
+            quarantine.finish();
+        }
+        Ok(ScanOutcome{ checked: self.counter, errors: self.errors, bytes: self.byte_counter, ..Default::default() })
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{FileMetadata, FileMetadataExt, Hash, ByteSize, DirMetadata, SymlinkMetadata};
-    use redb::Database;
+    use crate::types::{FileMetadata, FileMetadataExt, Hash, ByteSize, DirMetadata, Security, DataStream, Owner, SymlinkMetadata, TargetType};
+    use redb::{Database, ReadableTableMetadata};
     use std::fs;
+    use std::sync::{Arc, Mutex};
 
     fn setup_test_db(name: &str) -> (Database, std::path::PathBuf) {
         let mut path = std::env::current_dir().unwrap();
@@ -313,6 +1705,14 @@ mod tests {
             permissions: 0o644,
             modified: 123456789,
             size: ByteSize::new(1024),
+            ino: None,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: None,
+            flags: None,
+            owner: None,
         });
 
         let data = vec![
@@ -333,6 +1733,49 @@ mod tests {
         fs::remove_dir_all(path).unwrap();
     }
 
+    #[test]
+    fn test_two_tables_in_one_db() {
+        let (db, path) = setup_test_db("two_tables");
+        let meta = |size| FileMetadataExt::File(FileMetadata {
+            hash: Hash::from([0u8; 32]),
+            permissions: 0o644,
+            modified: 123456789,
+            size: ByteSize::new(size),
+            ino: None,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: None,
+            flags: None,
+            owner: None,
+        });
+
+        WriteToDB::new(&db).with_table("web01").add_file_info(&[("/etc/hosts".to_string(), meta(10))]).unwrap();
+        WriteToDB::new(&db).with_table("web02")
+            .add_file_info(&[("/etc/hosts".to_string(), meta(20)), ("/etc/motd".to_string(), meta(30))]).unwrap();
+
+        let list = |name| -> Vec<(String, FileMetadataExt)> {
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(files_table(name)).unwrap();
+            table.iter().unwrap().map(|e| { let e = e.unwrap(); (e.0.value(), e.1.value()) }).collect()
+        };
+        assert_eq!(list("web01"), vec![("/etc/hosts".to_string(), meta(10))]);
+        assert_eq!(list("web02"), vec![("/etc/hosts".to_string(), meta(20)), ("/etc/motd".to_string(), meta(30))]);
+        assert!(db.begin_read().unwrap().open_table(TABLE).is_err());
+
+        assert!(validate_table_name("web01").is_ok());
+        assert!(validate_table_name("web01_meta").is_err());
+        assert!(validate_table_name("").is_err());
+        assert!(validate_table_name("web@01").is_err());
+        assert!(validate_host_prefix("web01.example.com").is_ok());
+        assert!(validate_host_prefix("web01_annotations").is_err());
+        assert!(validate_host_prefix("web/01").is_err());
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
     #[test]
     fn test_update_db_logic() {
         let (db, path) = setup_test_db("update_logic");
@@ -345,6 +1788,14 @@ mod tests {
                 permissions: 0o644,
                 modified: 123456789,
                 size: ByteSize::new(1024),
+                ino: None,
+                strong: None,
+                security: None,
+                streams: None,
+                content_type: None,
+                created: None,
+                flags: None,
+                owner: None,
             });
             writer.add_file_info(&[("file1.txt".to_string(), file_meta)]).unwrap();
         }
@@ -356,6 +1807,14 @@ mod tests {
             permissions: 0o644,
             modified: 123456789,
             size: ByteSize::new(2048),
+            ino: None,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: None,
+            flags: None,
+            owner: None,
         });
 
         updater.add_file_info(&[("file1.txt".to_string(), updated_meta.clone())]).unwrap();
@@ -373,6 +1832,169 @@ mod tests {
         fs::remove_dir_all(path).unwrap();
     }
 
+    #[test]
+    fn test_write_txn_batch() {
+        let (db, path) = setup_test_db("write_txn_batch");
+        let hash = Hash::from([0u8; 32]);
+        let entries: Vec<_> = (0..5).map(|i| (format!("/batched/{i}"), file_metadata_ext_helper(hash.clone(), 10, 1000))).collect();
+        let stored = |db: &Database| db.begin_read().unwrap().open_table(TABLE).map(|t| t.len().unwrap()).unwrap_or(0);
+
+        // committed every 2 entries, the last one by finish
+        let mut writer = WriteToDB::new(&db).with_txn_batch(2);
+        writer.add_file_info(&entries[..3]).unwrap();
+        assert_eq!(stored(&db), 2);
+        writer.add_file_info(&entries[3..]).unwrap();
+        assert_eq!(stored(&db), 4);
+        writer.finish().unwrap();
+        assert_eq!(stored(&db), 5);
+        assert_eq!(writer.get_counter(), 5);
+        drop(writer);
+
+        // nothing until finish, and nothing at all without it
+        let more: Vec<_> = (0..3).map(|i| (format!("/single/{i}"), file_metadata_ext_helper(hash.clone(), 10, 1000))).collect();
+        let mut writer = WriteToDB::new(&db).single_transaction();
+        writer.add_file_info(&more[..1]).unwrap();
+        writer.add_file_info(&more[1..]).unwrap();
+        assert_eq!(stored(&db), 5);
+        drop(writer);
+        assert_eq!(stored(&db), 5);
+        let mut writer = WriteToDB::new(&db).single_transaction();
+        writer.add_file_info(&more).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(stored(&db), 8);
+        // one transaction per call by default
+        drop(writer);
+        WriteToDB::new(&db).add_file_info(&[("/plain".to_owned(), file_metadata_ext_helper(hash.clone(), 10, 1000))]).unwrap();
+        assert_eq!(stored(&db), 9);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_scan_outcome() {
+        let (db, path) = setup_test_db("scan_outcome");
+        let entry = |path: &str, hash: u8, size: u64| (path.to_owned(), file_metadata_ext_helper(Hash::from([hash; 32]), size, 1000));
+
+        // the second path folds to the key of the first
+        let mut writer = WriteToDB::new(&db).with_path_fold(CASE_FOLD);
+        writer.add_file_info(&[entry("/a", 1, 10), entry("/A", 1, 10), entry("/b", 1, 20)]).unwrap();
+        assert_eq!(writer.finish().unwrap(), ScanOutcome{ added: 2, errors: 1, bytes: ByteSize::new(30), ..Default::default() });
+
+        let mut updater = UpdateDB::new(&db).with_path_fold(CASE_FOLD);
+        updater.add_file_info(&[entry("/a", 1, 10), entry("/b", 2, 20), entry("/c", 1, 5)]).unwrap();
+        assert_eq!(updater.finish().unwrap(), ScanOutcome{ added: 1, updated: 1, checked: 3, errors: 0, bytes: ByteSize::new(35) });
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).with_path_fold(CASE_FOLD).with_finding_sink(|_| {});
+        checker.add_file_info(&[entry("/a", 1, 10), entry("/c", 1, 5), entry("/C", 1, 5)]).unwrap();
+        assert_eq!(checker.finish().unwrap(), ScanOutcome{ checked: 2, errors: 1, bytes: ByteSize::new(15), ..Default::default() });
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_removal_check() {
+        let hash = Hash::from([0u8; 32]);
+        let entry = |path: &str| (path.to_owned(), file_metadata_ext_helper(hash.clone(), 10, 1000));
+        // orders differing between bytes and path components, and non ASCII keys
+        let in_db = ["/a", "/a b", "/a-", "/a/b", "/a/b/c", "/a.d", "/z", "/\u{e9}t\u{e9}", "/\u{e9}", "/0", "/A"];
+        let walked = ["/a", "/a-", "/a/b/c", "/new", "/\u{e9}", "/A", "/a/b/d", "/zz"];
+        let mut results = Vec::new();
+        for check in [RemovalCheck::Hash, RemovalCheck::MergeJoin]{
+            let (db, path) = setup_test_db(&format!("removal_check_{check:?}"));
+            WriteToDB::new(&db).add_file_info(&in_db.map(entry)).unwrap();
+
+            let mut checker = CheckDB::new(&db, CheckOptions::default()).with_removal_check(check).recording().with_finding_sink(|_| {});
+            checker.add_file_info(&walked.map(entry)).unwrap();
+            let unvisited = checker.unvisited(&["/".to_owned()], |p| p == "/z").unwrap();
+            assert_eq!(checker.report_removed(|p| p == "/z").unwrap(), unvisited.unvisited);
+            let mut reported: Vec<_> = checker.take_recorded().into_iter().filter(|f| f.kind == FindingKind::Removed).map(|f| f.path).collect();
+            reported.sort();
+            assert_eq!(checker.files.len(), walked.len());
+
+            let mut updater = UpdateDB::new(&db).with_removal_check(check);
+            updater.add_file_info(&walked.map(entry)).unwrap();
+            assert_eq!(updater.unvisited(&["/".to_owned()], |_| false).unwrap().unvisited, 6);
+            assert_eq!(updater.unvisited(&["/".to_owned()], |p| p == "/z").unwrap().unvisited, 5);
+            updater.remove_missing(|_| false).unwrap();
+            let delta = updater.into_delta();
+            let deleted: Vec<_> = delta.removed.entries().into_iter().map(|(k, _)| k.to_owned()).collect();
+            results.push((reported, deleted));
+            drop(db);
+            fs::remove_dir_all(path).unwrap();
+        }
+        assert_eq!(results[0].0, ["/0", "/a b", "/a.d", "/a/b", "/\u{e9}t\u{e9}"]);
+        assert_eq!(results[0].1, ["/0", "/a b", "/a.d", "/a/b", "/z", "/\u{e9}t\u{e9}"]);
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[test]
+    fn test_update_delta() {
+        let (db, path) = setup_test_db("update_delta");
+        let hash = Hash::from([0u8; 32]);
+
+        {
+            let mut writer = WriteToDB::new(&db);
+            writer.add_file_info(&[
+                ("kept".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)),
+                ("changed".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)),
+                ("gone_b".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)),
+                ("gone_a".to_string(), dir_metadata_helper(100, 1000)),
+            ]).unwrap();
+        }
+
+        let mut updater = UpdateDB::new(&db);
+        updater.add_file_info(&[
+            ("kept".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)),
+            ("changed".to_string(), file_metadata_ext_helper(hash.clone(), 20, 1000)),
+            ("new_b".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)),
+        ]).unwrap();
+        updater.add_file_info(&[
+            ("new_a".to_string(), symlink_metadata_helper("target", 10, 1000)),
+        ]).unwrap();
+        updater.remove_missing(|_| false).unwrap();
+
+        let delta = updater.into_delta();
+        fn names<T>(v: &Sampled<T>) -> Vec<&str> {
+            v.entries().into_iter().map(|(k, _)| k).collect()
+        }
+        assert_eq!(names(&delta.added), ["new_a", "new_b"]);
+        assert_eq!(names(&delta.removed), ["gone_a", "gone_b"]);
+        assert_eq!(names(&delta.updated), ["changed"]);
+        assert_eq!(delta.count(), 5);
+
+        let report = delta.report();
+        assert_eq!(report[0], "Update delta: 2 added 1 updated 2 removed");
+        assert!(report[1].starts_with("  + new_a Symlink"));
+        assert!(report[2].starts_with("  + new_b File"));
+        assert!(report[3].starts_with("  ~ changed File"));
+        assert!(report[4].starts_with("  - gone_a Directory"));
+        assert!(report[5].starts_with("  - gone_b File"));
+        assert_eq!(report.len(), 6);
+
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(TABLE).unwrap();
+        assert!(table.get("gone_a".to_owned()).unwrap().is_none());
+        assert!(table.get("new_a".to_owned()).unwrap().is_some());
+        drop(read_txn);
+
+        // past DELTA_SAMPLE entries are counted, the lowest paths listed
+        let mut updater = UpdateDB::new(&db);
+        let many: Vec<_> = (0..DELTA_SAMPLE + 5).rev().map(|i| (format!("many/{i:04}"), file_metadata_ext_helper(hash.clone(), 10, 1000))).collect();
+        updater.add_file_info(&many).unwrap();
+        let delta = updater.into_delta();
+        assert_eq!(delta.added.count, DELTA_SAMPLE as u64 + 5);
+        let kept = names(&delta.added);
+        assert_eq!((kept.len(), kept[0], kept[DELTA_SAMPLE - 1]), (DELTA_SAMPLE, "many/0000", format!("many/{:04}", DELTA_SAMPLE - 1).as_str()));
+        let report = delta.report();
+        assert_eq!(report.len(), DELTA_SAMPLE + 2);
+        assert_eq!(report[DELTA_SAMPLE + 1], "  + ... 5 more");
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
     #[test]
     fn test_check_db_logic() {
         let (db, path) = setup_test_db("check_logic");
@@ -390,7 +2012,7 @@ mod tests {
         }
 
         {
-            let mut checker = CheckDB::new(&db, false);
+            let mut checker = CheckDB::new(&db, CheckOptions::default());
             let hash = Hash::from([0u8; 32]);
             let changed_hash = Hash::from([1u8; 32]);
 
@@ -407,17 +2029,18 @@ mod tests {
 
             // changes_count should be:
             // file_hash: yes
-            // file_time: no (compare_time = false)
+            // file_time: no (compare_time none)
             // dir_size: yes
             // sym_data: yes
             // type_change: yes
             // new_file: no (it's a new file)
             assert_eq!(checker.get_changes_count(), 4);
             assert_eq!(checker.get_new_files_count(), 1);
+            assert_eq!(checker.get_time_only_count(), 1);
         }
 
-        {
-            let mut checker = CheckDB::new(&db, true);
+        for (types, changes) in [("dirs,symlinks", 4), ("files", 5), ("all", 5)]{
+            let mut checker = CheckDB::new(&db, CheckOptions{ compare_time: types.parse().unwrap(), ..Default::default() });
             let hash = Hash::from([0u8; 32]);
             let changed_hash = Hash::from([1u8; 32]);
 
@@ -432,21 +2055,659 @@ mod tests {
 
             checker.add_file_info(&files).unwrap();
 
-            // changes_count should be 5 now (including file_time)
-assert_eq!(checker.get_changes_count(), 5);
-             assert_eq!(checker.get_new_files_count(), 1);
+            // file_time is a change when files are compared
+            assert_eq!(checker.get_changes_count(), changes, "{types}");
+            assert_eq!(checker.get_new_files_count(), 1);
+            assert_eq!(checker.get_time_only_count(), 5 - changes, "{types}");
+        }
+        assert_eq!("files, dirs".parse::<CompareTime>().unwrap(), CompareTime{ files: true, dirs: true, symlinks: false });
+        assert!("file".parse::<CompareTime>().is_err());
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_worst_level() {
+        let (db, path) = setup_test_db("worst_level");
+        let hash = Hash::from([0u8; 32]);
+        WriteToDB::new(&db).add_file_info(&[
+            ("maint/file".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1000)),
+            ("file".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1000)),
+        ]).unwrap();
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).with_maintenance(vec!["maint".to_string()]);
+        assert_eq!(checker.get_worst_level(), None);
+        // expected changes don't count
+        checker.add_file_info(&[("maint/file".to_string(), file_metadata_ext_helper(Hash::from([1u8; 32]), 1024, 1000))]).unwrap();
+        assert_eq!(checker.get_worst_level(), None);
+        checker.add_file_info(&[("new_file".to_string(), file_metadata_ext_helper(hash.clone(), 512, 1000))]).unwrap();
+        assert_eq!(checker.get_worst_level(), Some(Level::Warn));
+        checker.add_file_info(&[("file".to_string(), file_metadata_ext_helper(Hash::from([1u8; 32]), 1024, 1000))]).unwrap();
+        assert_eq!(checker.get_worst_level(), Some(Level::Error));
+
+        drop(checker);
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_volatile() {
+        let (db, path) = setup_test_db("volatile");
+        let hash = Hash::from([0u8; 32]);
+        let dir = |size, permissions| FileMetadataExt::Dir(DirMetadata { permissions, modified: 1000, size, security: None, entries: Some(3), flags: None, owner: None });
+        WriteToDB::new(&db).add_file_info(&[
+            ("/etc/adjtime".to_string(), file_metadata_ext_helper(hash.clone(), 40, 1000)),
+            ("/etc/resolv.conf".to_string(), file_metadata_ext_helper(hash.clone(), 80, 1000)),
+            ("/etc/hosts".to_string(), file_metadata_ext_helper(hash.clone(), 80, 1000)),
+            ("/var/spool".to_string(), dir(4096, 0o40755)),
+        ]).unwrap();
+        let volatile = BTreeSet::from(["/etc/adjtime".to_string(), "/etc/resolv.conf".to_string(), "/var/spool".to_string()]);
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).with_volatile(volatile).recording();
+        let resolv = match file_metadata_ext_helper(Hash::from([2u8; 32]), 90, 2000){
+            FileMetadataExt::File(f) => FileMetadataExt::File(FileMetadata{ permissions: 0o666, ..f }),
+            _ => unreachable!(),
+        };
+        checker.add_file_info(&[
+            ("/etc/adjtime".to_string(), file_metadata_ext_helper(Hash::from([1u8; 32]), 41, 2000)),
+            ("/etc/resolv.conf".to_string(), resolv.clone()),
+            ("/etc/hosts".to_string(), file_metadata_ext_helper(Hash::from([1u8; 32]), 80, 1000)),
+            ("/var/spool".to_string(), dir(8192, 0o40755)),
+        ]).unwrap();
+        let found = checker.take_recorded();
+        assert_eq!(found.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), ["/etc/resolv.conf", "/etc/hosts"]);
+        // only the permissions of a volatile file are reported, with the entry as found
+        assert!(found[0].message.ends_with(" permissions changed 644 -> 666"), "{}", found[0].message);
+        assert_eq!(found[0].new.as_ref().map(|n| n.to_string()), Some(resolv.to_string()));
+        assert!(found[1].message.contains(" hash changed "), "{}", found[1].message);
+
+        // a volatile entry changing type is reported
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).with_volatile(BTreeSet::from(["/etc/adjtime".to_string()])).recording();
+        checker.add_file_info(&[("/etc/adjtime".to_string(), dir(4096, 0o40755))]).unwrap();
+        assert_eq!(checker.take_recorded().len(), 1);
+
+        drop(checker);
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_backdated() {
+        let (db, path) = setup_test_db("backdated");
+        let born = |hash: u8, modified, created| match file_metadata_ext_helper(Hash::from([hash; 32]), 10, modified){
+            FileMetadataExt::File(f) => FileMetadataExt::File(FileMetadata{ created, ..f }),
+            _ => unreachable!(),
+        };
+        WriteToDB::new(&db).add_file_info(&[
+            ("/bin/kept".to_string(), born(0, 1000, Some(1000))),
+            ("/bin/older".to_string(), born(0, 1000, Some(1000))),
+            ("/bin/touched".to_string(), born(0, 1000, Some(1000))),
+            ("/bin/same".to_string(), born(0, 1000, Some(1000))),
+            ("/bin/no_btime".to_string(), born(0, 1000, None)),
+            ("/bin/legacy".to_string(), born(0, 1000, None)),
+        ]).unwrap();
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        checker.add_file_info(&[
+            ("/bin/kept".to_string(), born(1, 1000, Some(5000))),
+            ("/bin/older".to_string(), born(1, 900, Some(5000))),
+            ("/bin/touched".to_string(), born(1, 5000, Some(5000))),
+            ("/bin/same".to_string(), born(0, 1000, Some(5000))),
+            ("/bin/no_btime".to_string(), born(1, 1000, None)),
+            ("/bin/legacy".to_string(), born(1, 1000, Some(5000))),
+        ]).unwrap();
+        let backdated: Vec<_> = checker.take_recorded().into_iter().filter(|f| f.message.contains("likely backdated")).map(|f| f.path).collect();
+        assert_eq!(backdated, ["/bin/kept", "/bin/older", "/bin/legacy"]);
+        assert_eq!(checker.get_changes_count(), 5);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_mtime_tolerance() {
+        let (db, path) = setup_test_db("mtime_tolerance");
+        let hash = Hash::from([0u8; 32]);
+        let changed_hash = Hash::from([1u8; 32]);
+
+        {
+            let mut writer = WriteToDB::new(&db);
+            writer.add_file_info(&[
+                ("file_within".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1000)),
+                ("file_outside".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1000)),
+                ("file_hash".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1000)),
+                ("file_size".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1000)),
+                ("dir_within".to_string(), dir_metadata_helper(100, 1000)),
+                ("sym_within".to_string(), symlink_metadata_helper("target", 10, 1000)),
+                ("sym_outside".to_string(), symlink_metadata_helper("target", 10, 1000)),
+            ]).unwrap();
+        }
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ compare_time: CompareTime::ALL, mtime_tolerance: 2, ..Default::default() });
+        checker.add_file_info(&[
+            ("file_within".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1002)),
+            ("file_outside".to_string(), file_metadata_ext_helper(hash.clone(), 1024, 1003)),
+            ("file_hash".to_string(), file_metadata_ext_helper(changed_hash, 1024, 1001)),
+            ("file_size".to_string(), file_metadata_ext_helper(hash.clone(), 2048, 999)),
+            ("dir_within".to_string(), dir_metadata_helper(100, 998)),
+            ("sym_within".to_string(), symlink_metadata_helper("target", 10, 1001)),
+            ("sym_outside".to_string(), symlink_metadata_helper("target", 10, 990)),
+        ]).unwrap();
+
+        // file_outside, file_hash, file_size, sym_outside
+        assert_eq!(checker.get_changes_count(), 4);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_vfat_friendly() {
+        let (db, path) = setup_test_db("vfat_friendly");
+        let file = |hash: u8, permissions: u32, modified: u64| match file_metadata_ext_helper(Hash::from([hash; 32]), 1024, modified){
+            FileMetadataExt::File(f) => FileMetadataExt::File(FileMetadata{ permissions, ..f }),
+            _ => unreachable!(),
+        };
+        let dir = |permissions: u32, modified: u64| match dir_metadata_helper(100, modified){
+            FileMetadataExt::Dir(d) => FileMetadataExt::Dir(DirMetadata{ permissions, ..d }),
+            _ => unreachable!(),
+        };
+        let stored = [
+            ("/boot/efi".to_string(), dir(0o40755, 1000)),
+            ("/boot/efi/EFI/grubx64.efi".to_string(), file(0, 0o100755, 1000)),
+            ("/boot/efi/EFI/shimx64.efi".to_string(), file(0, 0o100755, 1000)),
+            ("/boot/vmlinuz".to_string(), file(0, 0o100644, 1000)),
+        ];
+        WriteToDB::new(&db).add_file_info(&stored).unwrap();
+        // remounted with another umask, FAT times in 2 second steps
+        let found = [
+            ("/boot/efi".to_string(), dir(0o40700, 1002)),
+            ("/boot/efi/EFI/grubx64.efi".to_string(), file(0, 0o100700, 1001)),
+            ("/boot/efi/EFI/shimx64.efi".to_string(), file(1, 0o100700, 1002)),
+            ("/boot/vmlinuz".to_string(), file(0, 0o100600, 1000)),
+        ];
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ compare_time: CompareTime::ALL, vfat_friendly: true, ..Default::default() }).recording();
+        checker.fat_dirs.assume(std::path::Path::new("/boot/efi"), true);
+        checker.fat_dirs.assume(std::path::Path::new("/boot/efi/EFI"), true);
+        checker.fat_dirs.assume(std::path::Path::new("/boot"), false);
+        checker.add_file_info(&found).unwrap();
+        let found_paths: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.message)).collect();
+        assert_eq!(found_paths.len(), 2, "{found_paths:?}");
+        assert!(found_paths[0].0.ends_with("shimx64.efi") && found_paths[0].1.contains(" hash changed") && !found_paths[0].1.contains("permissions"));
+        assert!(found_paths[1].0.ends_with("vmlinuz") && found_paths[1].1.contains(" permissions changed"));
+
+        // without the option all of them changed
+        let mut checker = CheckDB::new(&db, CheckOptions{ compare_time: CompareTime::ALL, ..Default::default() });
+        checker.add_file_info(&found).unwrap();
+        assert_eq!(checker.get_changes_count(), 4);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_finding_limits() {
+        let (db, path) = setup_test_db("finding_limits");
+        let hash = Hash::from([0u8; 32]);
+        let changed_hash = Hash::from([1u8; 32]);
+        let names = ["a", "b", "c", "d"];
+
+        {
+            let mut writer = WriteToDB::new(&db);
+            let files: Vec<_> = names.iter().map(|n| (n.to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000))).collect();
+            writer.add_file_info(&files).unwrap();
+        }
+        let changed: Vec<_> = names.iter().map(|n| (n.to_string(), file_metadata_ext_helper(changed_hash.clone(), 10, 1000))).collect();
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ max_findings: Some(1), ..Default::default() });
+        checker.add_file_info(&changed).unwrap();
+        checker.add_file_info(&[("new".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000))]).unwrap();
+        assert_eq!(checker.get_changes_count(), 4);
+        assert_eq!(checker.get_new_files_count(), 1);
+        assert_eq!(checker.get_suppressed_count(), 4);
+        assert!(!checker.should_stop());
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ fail_fast: true, ..Default::default() });
+        assert!(!checker.should_stop());
+        checker.add_file_info(&changed).unwrap();
+        assert_eq!(checker.get_changes_count(), 1);
+        assert_eq!(checker.get_counter(), 1);
+        assert!(checker.should_stop());
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_sorted_output() {
+        let (db, path) = setup_test_db("sorted_output");
+        let hash = Hash::from([0u8; 32]);
+        let stored = ["/b", "/d", "/a/x", "/c"].map(|p| (p.to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)));
+        WriteToDB::new(&db).add_file_info(&stored).unwrap();
+        // found in walk order, the removed entries last
+        let walked = ["/d", "/a/y", "/b"].map(|p| (p.to_string(), file_metadata_ext_helper(hash.clone(), 20, 1000)));
+
+        let check = |limit| {
+            let printed = Arc::new(Mutex::new(Vec::new()));
+            let sink = printed.clone();
+            let mut checker = CheckDB::new(&db, CheckOptions{ sorted_output: limit, ..Default::default() })
+                .with_finding_sink(move |f| sink.lock().unwrap().push(f.path));
+            checker.add_file_info(&walked).unwrap();
+            checker.report_removed(|_| false).unwrap();
+            checker.flush_findings();
+            drop(checker);
+            Arc::try_unwrap(printed).unwrap().into_inner().unwrap()
+        };
+        assert_eq!(check(None), ["/d", "/a/y", "/b", "/a/x", "/c"]);
+        assert_eq!(check(Some(10)), ["/a/x", "/a/y", "/b", "/c", "/d"]);
+        // past the limit the held findings and the rest are printed as found
+        assert_eq!(check(Some(2)), ["/d", "/a/y", "/b", "/a/x", "/c"]);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_annotations_follow_entries() {
+        let (db, path) = setup_test_db("annotations_follow");
+        let hash = Hash::from([0u8; 32]);
+        {
+            let mut writer = WriteToDB::new(&db);
+            writer.add_file_info(&[
+                ("changed".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)),
+                ("gone".to_string(), file_metadata_ext_helper(hash.clone(), 10, 1000)),
+            ]).unwrap();
+        }
+        annotations::annotate(&db, DEFAULT_TABLE, "changed", Some("approved TICKET-123"), Some("vendor")).unwrap();
+        annotations::annotate(&db, DEFAULT_TABLE, "gone", None, Some("temp")).unwrap();
+
+        let changed = [("changed".to_string(), file_metadata_ext_helper(hash.clone(), 20, 1000))];
+        let mut checker = CheckDB::new(&db, CheckOptions{ group_threshold: Some(10), ..Default::default() })
+            .with_annotations(annotations::load_all(&db, DEFAULT_TABLE).unwrap());
+        checker.add_file_info(&changed).unwrap();
+        assert_eq!(checker.grouped.len(), 1);
+        assert!(checker.grouped[0].message.ends_with("[note: approved TICKET-123 tags: vendor]"), "{}", checker.grouped[0].message);
+
+        let mut updater = UpdateDB::new(&db);
+        updater.add_file_info(&changed).unwrap();
+        updater.remove_missing(|_| false).unwrap();
+        let left = annotations::load_all(&db, DEFAULT_TABLE).unwrap();
+        assert_eq!(left.keys().collect::<Vec<_>>(), ["changed"]);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_perms_only() {
+        let (db, path) = setup_test_db("perms_only");
+        let hash = Hash::from([0u8; 32]);
+        let file = |perm: u32| FileMetadataExt::File(FileMetadata {
+            hash: hash.clone(),
+            permissions: perm,
+            modified: 1000,
+            size: ByteSize::new(10),
+            ino: None,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: None,
+            flags: None,
+            owner: None,
+        });
+        let dir = |perm: u32| FileMetadataExt::Dir(DirMetadata {
+            permissions: perm,
+            modified: 1000,
+            size: 10,
+            security: None,
+            entries: None,
+            flags: None,
+            owner: None,
+        });
+
+        {
+            let mut writer = WriteToDB::new(&db);
+            writer.add_file_info(&[
+                ("type_bits".to_string(), file(0o100644)),
+                ("real_perms".to_string(), file(0o100644)),
+                ("dir_to_file".to_string(), dir(0o40755)),
+            ]).unwrap();
         }
 
+        let current = [
+            ("type_bits".to_string(), file(0o644)), // mode stored without type bits
+            ("real_perms".to_string(), file(0o100600)),
+            ("dir_to_file".to_string(), file(0o100755)), // one type change finding, nothing else
+        ];
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        checker.add_file_info(&current).unwrap();
+        assert_eq!(checker.get_changes_count(), 3);
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ perms_only: true, ..Default::default() });
+        checker.add_file_info(&current).unwrap();
+        assert_eq!(checker.get_changes_count(), 2);
+        assert_eq!(checker.findings, 2);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_perms_on_binaries() {
+        let (db, path) = setup_test_db("perms_on_binaries");
+        let file = |permissions: u32, content_type: Option<&str>| match file_metadata_ext_helper(Hash::from([0u8; 32]), 10, 1000){
+            FileMetadataExt::File(f) => FileMetadataExt::File(FileMetadata{ permissions, content_type: content_type.map(str::to_owned), ..f }),
+            _ => unreachable!(),
+        };
+
+        WriteToDB::new(&db).add_file_info(&[
+            ("/etc/app.conf".to_string(), file(0o100644, None)),
+            ("/usr/bin/tool".to_string(), file(0o100755, None)),
+            ("/opt/lib.so".to_string(), file(0o100644, Some("application/x-executable"))),
+            ("/etc/hook".to_string(), file(0o100644, None)),
+            ("/etc/edited.conf".to_string(), file(0o100644, None)),
+        ]).unwrap();
+
+        let current = [
+            ("/etc/app.conf".to_string(), file(0o100666, None)),
+            ("/usr/bin/tool".to_string(), file(0o104777, None)),
+            ("/opt/lib.so".to_string(), file(0o100666, Some("application/x-executable"))),
+            ("/etc/hook".to_string(), file(0o100755, None)),
+            ("/etc/edited.conf".to_string(), file_metadata_ext_helper(Hash::from([1u8; 32]), 10, 1000)),
+        ];
+        let opts = CheckOptions{ perms_on_binaries_only: true, ..Default::default() };
+        let mut checker = CheckDB::new(&db, opts).recording().with_finding_sink(|_| {});
+        checker.add_file_info(&current).unwrap();
+        // the config file's permission change is quiet, other changes of config files aren't
+        let mut changed: Vec<_> = checker.take_recorded().into_iter().map(|f| f.path).collect();
+        changed.sort();
+        assert_eq!(changed, ["/etc/edited.conf", "/etc/hook", "/opt/lib.so", "/usr/bin/tool"]);
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        checker.add_file_info(&current).unwrap();
+        assert_eq!(checker.get_changes_count(), 5);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_security() {
+        let (db, path) = setup_test_db("security");
+        let security = |owner: &str, acl: u8| Some(Security{ owner: owner.to_owned(), descriptor: Hash::from([acl; 32]) });
+        let dir = |security| FileMetadataExt::Dir(DirMetadata { permissions: 0x10, modified: 1000, size: 0, security, entries: None, flags: None, owner: None });
+
+        WriteToDB::new(&db).add_file_info(&[
+            ("same".to_string(), dir(security("S-1-5-18", 1))),
+            ("acl".to_string(), dir(security("S-1-5-18", 1))),
+            ("owner".to_string(), dir(security("S-1-5-18", 1))),
+            ("unreadable".to_string(), dir(security("S-1-5-18", 1))),
+            ("old_entry".to_string(), dir(None)),
+        ]).unwrap();
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        checker.add_file_info(&[
+            ("same".to_string(), dir(security("S-1-5-18", 1))),
+            ("acl".to_string(), dir(security("S-1-5-18", 2))),
+            ("owner".to_string(), dir(security("S-1-5-32-544", 2))),
+            ("unreadable".to_string(), dir(None)),
+            ("old_entry".to_string(), dir(security("S-1-5-18", 1))),
+        ]).unwrap();
+        let found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.message)).collect();
+        assert_eq!(found.len(), 3);
+        assert!(found[0].1.contains(" acl changed ") && !found[0].1.contains("owner"));
+        assert!(found[1].1.contains(" owner changed S-1-5-18 -> S-1-5-32-544") && found[1].1.contains(" acl changed "));
+        assert_eq!(found[2].0, "unreadable");
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_owner_by_name() {
+        let (db, path) = setup_test_db("owner_by_name");
+        let owner = |uid: u32, user: Option<&str>| Some(Owner{ uid, gid: 100, user: user.map(str::to_owned), group: Some("users".to_owned()) });
+        let dir = |owner| FileMetadataExt::Dir(DirMetadata { permissions: 0o40755, modified: 1000, size: 0, security: None, entries: None, flags: None, owner });
+
+        WriteToDB::new(&db).add_file_info(&[
+            ("renumbered".to_string(), dir(owner(1000, Some("alice")))),
+            ("unresolved".to_string(), dir(owner(1000, None))),
+            ("chowned".to_string(), dir(owner(1000, Some("alice")))),
+            ("old_entry".to_string(), dir(None)),
+        ]).unwrap();
+        let scanned = [
+            ("renumbered".to_string(), dir(owner(1001, Some("alice")))),
+            ("unresolved".to_string(), dir(owner(1001, Some("alice")))),
+            ("chowned".to_string(), dir(owner(1000, Some("bob")))),
+            ("old_entry".to_string(), dir(owner(1000, Some("alice")))),
+        ];
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ owner_by_name: true, ..Default::default() }).recording();
+        checker.add_file_info(&scanned).unwrap();
+        let mut found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.message)).collect();
+        found.sort();
+        assert_eq!(found.len(), 2, "{found:?}");
+        assert!(found[0].1.ends_with(" owner changed alice(1000):users(100) -> bob(1000):users(100)"), "{}", found[0].1);
+        assert!(found[1].1.ends_with(" owner changed 1000:users(100) -> alice(1001):users(100)"), "{}", found[1].1);
+        assert_eq!((found[0].0.as_str(), found[1].0.as_str()), ("chowned", "unresolved"));
+
+        // by id the renumbered user is another owner, the renamed one isn't
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        checker.add_file_info(&scanned).unwrap();
+        let mut found: Vec<_> = checker.take_recorded().into_iter().map(|f| f.path).collect();
+        found.sort();
+        assert_eq!(found, ["renumbered", "unresolved"]);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_streams() {
+        let (db, path) = setup_test_db("streams");
+        let stream = |name: &str, content: u8| DataStream{ name: name.to_owned(), size: ByteSize::new(content as u64), hash: Hash::from([content; 32]) };
+        let file = |streams| FileMetadataExt::File(FileMetadata { hash: [0u8; 32].into(), permissions: 0x20, modified: 1000, size: 10.into(), ino: None, strong: None, security: None, streams, content_type: None, created: None, flags: None, owner: None });
+
+        WriteToDB::new(&db).add_file_info(&[
+            ("same".to_string(), file(Some(vec![stream("Zone.Identifier", 1)]))),
+            ("added".to_string(), file(Some(Vec::new()))),
+            ("removed".to_string(), file(Some(vec![stream("Zone.Identifier", 1)]))),
+            ("modified".to_string(), file(Some(vec![stream("payload", 1)]))),
+            ("old_entry".to_string(), file(None)),
+        ]).unwrap();
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        checker.add_file_info(&[
+            ("same".to_string(), file(Some(vec![stream("Zone.Identifier", 1)]))),
+            ("added".to_string(), file(Some(vec![stream("evil.exe", 2)]))),
+            ("removed".to_string(), file(Some(Vec::new()))),
+            ("modified".to_string(), file(Some(vec![stream("payload", 2)]))),
+            ("old_entry".to_string(), file(Some(vec![stream("evil.exe", 2)]))),
+        ]).unwrap();
+        let found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.level, f.message)).collect();
+        assert_eq!(found.len(), 3);
+        assert!(found.iter().all(|f| f.1 == Level::Error));
+        assert_eq!(found[0].0, "added");
+        assert!(found[0].2.contains(" stream added :evil.exe size: 2B"));
+        assert!(found[1].2.contains(" stream removed :Zone.Identifier"));
+        assert!(found[2].2.contains(" stream :payload changed size 1B -> 2B"));
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_entropy() {
+        let (db, path) = setup_test_db("entropy");
+        let file = |content: u8| file_metadata_ext_helper(Hash::from([content; 32]), 10, 1000);
+
+        WriteToDB::new(&db).add_file_info(&[
+            ("packed".to_string(), file(0)),
+            ("text".to_string(), file(0)),
+            ("same".to_string(), file(0)),
+        ]).unwrap();
+
+        let measured = Arc::new(entropy::Measured::default());
+        measured.record("packed", 7.96);
+        measured.record("text", 4.5);
+        measured.record("same", 7.96);
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).with_entropy(measured.clone()).recording();
+        checker.add_file_info(&[
+            ("packed".to_string(), file(1)),
+            ("text".to_string(), file(1)),
+            ("same".to_string(), file(0)),
+        ]).unwrap();
+        let found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.message, f.entropy)).collect();
+        assert_eq!(found.len(), 2);
+        assert!(found[0].1.ends_with(" new content entropy 7.96 bits/byte, likely packed or encrypted"), "{}", found[0].1);
+        assert_eq!(found[0].2, Some(7.96));
+        assert!(found[1].1.ends_with(" new content entropy 4.50 bits/byte"));
+        assert_eq!(found[1].2, Some(4.5));
+        // taken for the unchanged file too
+        assert_eq!(measured.take("same"), None);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_check_db_symlink_targets() {
+        let (db, path) = setup_test_db("symlink_targets");
+        let link = |target_exists, target_type| FileMetadataExt::Symlink(SymlinkMetadata { data: "target".to_owned(), permissions: 0o777, modified: 1000, size: 6.into(), content: None, target_exists, target_type });
+
+        WriteToDB::new(&db).add_file_info(&[
+            ("same".to_string(), link(true, Some(TargetType::Dir))),
+            ("now_dangling".to_string(), link(true, Some(TargetType::Dir))),
+            ("now_dir".to_string(), link(true, Some(TargetType::Other))),
+            ("resolves_again".to_string(), link(false, None)),
+            ("still_dangling".to_string(), link(false, None)),
+            ("old_entry".to_string(), link(true, None)),
+        ]).unwrap();
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        checker.add_file_info(&[
+            ("same".to_string(), link(true, Some(TargetType::Dir))),
+            ("now_dangling".to_string(), link(false, None)),
+            ("now_dir".to_string(), link(true, Some(TargetType::Dir))),
+            ("resolves_again".to_string(), link(true, Some(TargetType::Dir))),
+            ("still_dangling".to_string(), link(false, None)),
+            ("old_entry".to_string(), link(false, None)),
+        ]).unwrap();
+        let found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.message)).collect();
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].0, "now_dangling");
+        assert!(found[0].1.contains(" target no longer exists, link is dangling"));
+        assert!(found[1].1.contains(" target changed from other to dir"));
+        assert!(found[2].1.contains(" dangling link resolves again to dir"));
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_case_insensitive_compare() {
+        let (db, path) = setup_test_db("case_compare");
+        let entry = |path: &str, hash: u8| (path.to_owned(), file_metadata_ext_helper(Hash::from([hash; 32]), 10, 1000));
+        WriteToDB::new(&db).add_file_info(&[entry("/data/Foo", 1), entry("/data/Bar", 1), entry("/data/gone", 1)]).unwrap();
+
+        let opts = CheckOptions{ case_insensitive_compare: true, ..Default::default() };
+        let mut checker = CheckDB::new(&db, opts).recording();
+        checker.add_file_info(&[entry("/data/foo", 1), entry("/data/BAR", 2), entry("/data/new", 1)]).unwrap();
+        assert_eq!(checker.report_removed(|_| false).unwrap(), 1);
+        let found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.kind, f.path)).collect();
+        assert_eq!(found, [
+            (FindingKind::Changed, "/data/BAR".to_owned()),
+            (FindingKind::New, "/data/new".to_owned()),
+            (FindingKind::Removed, "/data/gone".to_owned()),
+        ]);
+        assert!(checker.is_checked("/data/Foo"));
+
+        // byte exact by default, a new and a removed entry
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        checker.add_file_info(&[entry("/data/foo", 1)]).unwrap();
+        assert_eq!(checker.get_new_files_count(), 1);
+        assert!(!checker.is_checked("/data/Foo"));
+
+        let mut updater = UpdateDB::new(&db).with_case_insensitive_compare();
+        updater.add_file_info(&[entry("/data/foo", 1), entry("/data/BAR", 2), entry("/data/gone", 1)]).unwrap();
+        updater.remove_missing(|_| false).unwrap();
+        let delta = updater.into_delta();
+        assert!(delta.added.is_empty() && delta.removed.is_empty(), "{delta:?}");
+        assert_eq!(delta.updated.entries().into_iter().map(|(k, _)| k).collect::<Vec<_>>(), ["/data/BAR"]);
+
         drop(db);
         fs::remove_dir_all(path).unwrap();
     }
 
+    #[test]
+    fn test_check_db_path_fold() {
+        let (db, path) = setup_test_db("path_fold");
+        let fold = "icase,nfc".parse().unwrap();
+        let entry = |path: &str| (path.to_owned(), file_metadata_ext_helper(Hash::from([1u8; 32]), 10, 1000));
+
+        let mut writer = WriteToDB::new(&db).with_path_fold(fold);
+        writer.add_file_info(&[entry("/Data/File.TXT"), entry("/data/cafe\u{301}"), entry("/data/FILE.txt")]).unwrap();
+        assert_eq!(writer.get_counter(), 2);
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).with_path_fold(fold).recording();
+        checker.add_file_info(&[entry("/data/file.txt"), entry("/DATA/CAF\u{c9}"), entry("/data/File.txt")]).unwrap();
+        assert_eq!(checker.report_removed(|_| false).unwrap(), 0);
+        let found = checker.take_recorded();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].kind, found[0].level, found[0].path.as_str()), (FindingKind::New, Level::Error, "/data/File.txt"));
+        assert!(found[0].message.contains("folds to the same key as /data/file.txt"));
+
+        // byte exact keys don't match the folded ones
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        checker.add_file_info(&[entry("/Data/File.TXT")]).unwrap();
+        assert_eq!(checker.get_new_files_count(), 1);
+
+        drop(db);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_group_findings() {
+        let finding = |kind, path: &str| Finding{ kind, path: path.to_owned(), state: String::new(), level: Level::Warn, message: format!("finding {path}"), old: None, new: None, entropy: None, changes: Changes::default() };
+        let mut findings = Vec::new();
+        for i in 0..5{
+            findings.push(finding(FindingKind::Changed, &format!("/opt/app/bin{i}")));
+        }
+        findings.push(finding(FindingKind::New, "/opt/app/new"));
+        findings.push(finding(FindingKind::Removed, "/opt/app/old"));
+        findings.push(Finding{ level: Level::Error, ..finding(FindingKind::Changed, "/opt/app/lib") });
+        findings.push(finding(FindingKind::Changed, "/etc/passwd"));
+        findings.push(finding(FindingKind::New, "/etc/shadow-"));
+
+        let lines = group_findings(&findings, 3);
+        assert_eq!(lines, [
+            (Level::Warn, "finding /etc/passwd".to_owned()),
+            (Level::Warn, "finding /etc/shadow-".to_owned()),
+            (Level::Error, "directory /opt/app: 6 files changed, 1 new, 1 removed (run without --group-by-dir to expand)".to_owned()),
+        ]);
+
+        let lines = group_findings(&findings, 100);
+        assert_eq!(lines.len(), findings.len());
+    }
+
     fn file_metadata_ext_helper(hash: Hash, size: u64, modified: u64) -> FileMetadataExt {
         FileMetadataExt::File(FileMetadata {
             hash,
             permissions: 0o644,
             modified,
             size: ByteSize::new(size),
+            ino: None,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: None,
+            flags: None,
+            owner: None,
         })
     }
 
@@ -456,6 +2717,9 @@ assert_eq!(checker.get_changes_count(), 5);
             permissions: 0o777,
             modified,
             size: ByteSize::new(size),
+            content: None,
+            target_exists: true,
+            target_type: Some(TargetType::Dir),
         })
     }
 
@@ -464,6 +2728,10 @@ assert_eq!(checker.get_changes_count(), 5);
             permissions: 0o755,
             modified,
             size,
+            security: None,
+            entries: None,
+            flags: None,
+            owner: None,
         })
     }
 }
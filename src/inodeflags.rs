@@ -0,0 +1,103 @@
+//! Linux inode flags of `chattr`, stored with `--flags`. Only the immutable
+//! (`i`) and append-only (`a`) flags are kept: clearing them on a protected
+//! file is what an attacker does before changing it, the other flags describe
+//! how the file system lays the file out.
+
+use std::path::Path;
+
+/// `FS_IMMUTABLE_FL`
+pub const IMMUTABLE: u32 = 0x10;
+/// `FS_APPEND_FL`
+pub const APPEND_ONLY: u32 = 0x20;
+
+/// Flags that are stored and compared, with their `lsattr` letter and name.
+pub const FLAG_NAMES: [(u32, char, &str); 2] = [
+    (APPEND_ONLY, 'a', "append-only"),
+    (IMMUTABLE, 'i', "immutable"),
+];
+
+pub const TRACKED_FLAGS: u32 = IMMUTABLE | APPEND_ONLY;
+
+/// `lsattr` letters of the flags set in `flags`, `-` when there are none.
+pub fn letters(flags: u32) -> String {
+    let letters: String = FLAG_NAMES.iter().filter(|(bit, _, _)| flags & bit != 0).map(|(_, letter, _)| *letter).collect();
+    if letters.is_empty() { "-".to_owned() } else { letters }
+}
+
+/// Finding text for flags added or removed, e.g. ` immutable flag removed`.
+pub fn changed(old: u32, new: u32) -> Option<String> {
+    let info: String = FLAG_NAMES.iter()
+        .filter(|(bit, _, _)| (old ^ new) & bit != 0)
+        .map(|(bit, _, name)| format!(" {name} flag {}", if new & bit != 0 { "added" } else { "removed" }))
+        .collect();
+    (!info.is_empty()).then_some(info)
+}
+
+/// Tracked flags of the file or directory at `path`. `None` where they can't
+/// be read: the file system has no inode flags, e.g. tmpfs before Linux 6.0,
+/// the entry can't be opened or the system isn't Linux.
+pub fn read(path: &Path) -> Option<u32> {
+    #[cfg(target_os = "linux")]
+    match ioctl_flags(path, None){
+        Ok(flags) => Some(flags & TRACKED_FLAGS),
+        Err(e) => {
+            tracing::debug!("Inode flags of {} not read: {e}", super::types::EscapedPath(path.to_string_lossy().as_ref()));
+            None
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Reads the flags of `path`, or first sets them to `set`.
+#[cfg(target_os = "linux")]
+fn ioctl_flags(path: &Path, set: Option<u32>) -> std::io::Result<u32> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    // no blocking on a FIFO swapped in for the file, no following a symlink
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW | libc::O_NOCTTY)
+        .open(path)?;
+    // the kernel reads and writes an int, whatever the ioctl number says
+    let mut flags: libc::c_int = 0;
+    if let Some(set) = set{
+        flags = set as libc::c_int;
+        // SAFETY: the fd is open for the call and `flags` outlives it
+        if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) } < 0{
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    // SAFETY: as above, the kernel writes one int to `flags`
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } < 0{
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(flags as u32)
+}
+
+/// Sets or clears `flag` on `path`, keeping the others. Needs
+/// `CAP_LINUX_IMMUTABLE` for the tracked flags.
+#[cfg(all(test, target_os = "linux"))]
+pub(crate) fn toggle(path: &Path, flag: u32, on: bool) -> std::io::Result<()> {
+    let flags = ioctl_flags(path, None)?;
+    ioctl_flags(path, Some(if on { flags | flag } else { flags & !flag }))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flag_text() {
+        assert_eq!(letters(0), "-");
+        assert_eq!(letters(IMMUTABLE), "i");
+        assert_eq!(letters(IMMUTABLE | APPEND_ONLY), "ai");
+        assert_eq!(changed(IMMUTABLE, IMMUTABLE), None);
+        assert_eq!(changed(IMMUTABLE, 0).as_deref(), Some(" immutable flag removed"));
+        assert_eq!(changed(IMMUTABLE, APPEND_ONLY).as_deref(), Some(" append-only flag added immutable flag removed"));
+    }
+}
@@ -0,0 +1,105 @@
+use tracing::Subscriber;
+use tracing_subscriber::filter::{filter_fn, EnvFilter, FilterExt};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Layer;
+
+/// `--log-format json`: one JSON object per event passing `filter` with its
+/// fields and the fields of every span it happened in, outermost first:
+/// `{"timestamp":..,"level":"WARN","fields":{"message":..},"target":..,"spans":[{"command":..,"name":"command"},{"name":"root",..}]}`.
+/// Spans are kept whatever their level, they only show up in the events that
+/// pass the filter.
+pub fn subscriber<W>(filter: EnvFilter, writer: W) -> impl Subscriber + Send + Sync
+    where W: for<'w> MakeWriter<'w> + Send + Sync + 'static {
+    let json = tracing_subscriber::fmt::layer()
+        .json()
+        .with_current_span(false)
+        .with_writer(writer)
+        .with_filter(filter.or(filter_fn(|metadata| metadata.is_span())));
+    tracing_subscriber::registry().with(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing::{debug_span, info, warn, Instrument};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<Value> {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+        }
+    }
+
+    fn buffered(filter: &str) -> (Buffer, impl Subscriber + Send + Sync) {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        (buffer, subscriber(EnvFilter::new(filter), move || writer.clone()))
+    }
+
+    #[test]
+    fn test_span_fields_in_events() {
+        let (buffer, subscriber) = buffered("info");
+        tracing::subscriber::with_default(subscriber, || {
+            let command = debug_span!("command", command = "check");
+            let _c = command.enter();
+            {
+                let root = debug_span!("root", root = "/etc");
+                let _r = root.enter();
+                warn!(entries = 3u64, "File removed {}", "/etc/shadow");
+            }
+            info!("done");
+            tracing::debug!("filtered out");
+        });
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "WARN");
+        assert_eq!(lines[0]["fields"]["message"], "File removed /etc/shadow");
+        assert_eq!(lines[0]["fields"]["entries"], 3);
+        assert_eq!(lines[0]["spans"][0]["name"], "command");
+        assert_eq!(lines[0]["spans"][0]["command"], "check");
+        assert_eq!(lines[0]["spans"][1]["name"], "root");
+        assert_eq!(lines[0]["spans"][1]["root"], "/etc");
+        assert_eq!(lines[1]["spans"].as_array().unwrap().len(), 1);
+        assert_eq!(lines[1]["spans"][0]["name"], "command");
+    }
+
+    #[tokio::test]
+    async fn test_span_follows_instrumented_task() {
+        let (buffer, subscriber) = buffered("info");
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let task = async { info!("inside") }.instrument(debug_span!("root", root = "/boot"));
+        task.await;
+
+        let lines = buffer.lines();
+        assert_eq!(lines[0]["spans"][0]["root"], "/boot");
+    }
+
+    #[test]
+    fn test_env_filter() {
+        let (buffer, subscriber) = buffered("error,integrity_checker=debug");
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("kept");
+            tracing::warn!(target: "hyper", "dropped");
+        });
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["fields"]["message"], "kept");
+    }
+}
@@ -0,0 +1,49 @@
+//! File integrity baselines: walk trees, hash their files and compare them with
+//! a redb database of an earlier scan. The `integrity-checker` binary is a CLI
+//! over this crate.
+//!
+//! - `scan::scan` streams the metadata of every entry under some paths.
+//! - `baseline::Baseline` creates a baseline and checks paths against it,
+//!   streaming `fileops::Finding` values.
+//! - `circl` looks hashes up in the CIRCL hashlookup service.
+//! - `collector` pushes check and update reports to a central `--serve` collector.
+//! - `export` writes a baseline as JSON lines and reads it back.
+//!
+//! Logging, notifications and `--bench` are modules of the binary.
+
+pub mod error;
+pub mod types;
+pub mod fileops;
+pub mod circl;
+pub mod dbmeta;
+pub mod sample;
+pub mod annotations;
+pub mod ack;
+pub mod pkgverify;
+pub mod scan;
+pub mod baseline;
+pub mod collector;
+pub mod winmeta;
+pub(crate) mod inodeflags;
+pub(crate) mod ownership;
+pub mod pathfold;
+pub mod exclude;
+pub(crate) mod magic;
+pub(crate) mod snapshot;
+pub mod runs;
+pub mod listing;
+pub mod policy;
+pub mod events;
+#[cfg(unix)]
+pub mod eventsocket;
+pub mod dbfile;
+pub mod archive;
+pub mod export;
+pub mod treehash;
+pub mod unvisited;
+pub mod whiteout;
+pub mod versions;
+pub mod entropy;
+pub mod quarantine;
+pub(crate) mod vfat;
+pub mod severity;
@@ -0,0 +1,98 @@
+//! `--list --format table`: DB entries in aligned columns instead of one log
+//! line each. The table is built in memory, so big DBs are listed as log lines.
+
+use super::annotations::Annotations;
+use super::types::{DirSize, EscapedPath, FileMetadataExt, Permissions, Timestamp};
+use comfy_table::{presets, Table, TableComponent};
+use std::collections::BTreeMap;
+
+/// Entries up to which `--format table` is used, some hundred bytes each.
+pub const TABLE_MAX_ROWS: u64 = 100_000;
+
+/// Hex digits of a hash shown, enough to tell entries apart.
+pub const HASH_PREFIX: usize = 16;
+
+pub const HEADER: [&str; 6] = ["path", "type", "size", "perms", "mtime", "hash"];
+
+/// Table of `entries`, with a notes column when any of them is annotated.
+pub fn entries_table(entries: impl IntoIterator<Item = (String, FileMetadataExt)>, annotated: &BTreeMap<String, Annotations>) -> Table {
+    let mut table = Table::new();
+    // like `ls -l`, a line under the header only
+    table.load_preset(presets::NOTHING);
+    table.set_style(TableComponent::HeaderLines, '-');
+    table.set_style(TableComponent::MiddleHeaderIntersections, '-');
+    let mut header = HEADER.to_vec();
+    if !annotated.is_empty(){
+        header.push("notes");
+    }
+    table.set_header(header);
+    for (path, entry) in entries{
+        let mut row = match &entry{
+            FileMetadataExt::File(f) => {
+                let hash = f.strong.as_ref().unwrap_or(&f.hash).to_string();
+                let hash = if f.hash.is_none() { "-".to_owned() } else { hash.chars().take(HASH_PREFIX).collect() };
+                vec![EscapedPath(&path).to_string(), "file".to_owned(), f.size.to_string(), Permissions::new(f.permissions).to_string(), Timestamp::new(f.modified).to_string(), hash]
+            }
+            FileMetadataExt::Symlink(s) => {
+                let path = format!("{} -> {}", EscapedPath(&path), EscapedPath(&s.data));
+                let hash = s.content.as_ref().map_or("-".to_owned(), |h| h.to_string().chars().take(HASH_PREFIX).collect());
+                vec![path, "symlink".to_owned(), s.size.to_string(), Permissions::new(s.permissions).to_string(), Timestamp::new(s.modified).to_string(), hash]
+            }
+            FileMetadataExt::Dir(d) => {
+                vec![EscapedPath(&path).to_string(), "dir".to_owned(), DirSize::new(d.size).to_string(), Permissions::new(d.permissions).to_string(), Timestamp::new(d.modified).to_string(), "-".to_owned()]
+            }
+            FileMetadataExt::Whiteout => {
+                vec![EscapedPath(&path).to_string(), "whiteout".to_owned(), "-".to_owned(), "-".to_owned(), "-".to_owned(), "-".to_owned()]
+            }
+            FileMetadataExt::Opaque => {
+                vec![EscapedPath(&path).to_string(), "opaque".to_owned(), "-".to_owned(), "-".to_owned(), "-".to_owned(), "-".to_owned()]
+            }
+        };
+        if !annotated.is_empty(){
+            row.push(annotated.get(&path).map(|a| a.to_string()).unwrap_or_default());
+        }
+        table.add_row(row);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::{files_table, AddFileInfo, WriteToDB, DEFAULT_TABLE};
+    use crate::types::{DirMetadata, FileMetadata, SymlinkMetadata};
+    use redb::{Database, ReadableDatabase, ReadableTable};
+
+    #[test]
+    fn test_entries_table() {
+        let path = std::env::current_dir().unwrap().join("test_db_listing.redb");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::create(&path).unwrap();
+        WriteToDB::new(&db).add_file_info(&[
+            ("/etc".to_owned(), FileMetadataExt::Dir(DirMetadata{ permissions: 0o40755, modified: 1000, size: 4096, entries: None, security: None, flags: None, owner: None })),
+            ("/etc/motd".to_owned(), FileMetadataExt::File(FileMetadata{ hash: [0xab; 32].into(), permissions: 0o100644, modified: 1000, size: 12.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None })),
+            ("/etc/localtime".to_owned(), FileMetadataExt::Symlink(SymlinkMetadata{ data: "/usr/share/zoneinfo/UTC".to_owned(), permissions: 0o777, modified: 1000, size: 23.into(), content: None, target_exists: true, target_type: None })),
+        ]).unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let entries = read_txn.open_table(files_table(DEFAULT_TABLE)).unwrap();
+        let rows = entries.iter().unwrap().map(|k| { let k = k.unwrap(); (k.0.value(), k.1.value()) });
+        let rendered = entries_table(rows, &BTreeMap::new()).to_string();
+        let lines: Vec<_> = rendered.lines().collect();
+        for column in HEADER{
+            assert!(lines[0].contains(column), "{rendered}");
+        }
+        assert!(!lines[0].contains("notes"));
+        assert!(rendered.contains(&format!(" {} ", "ab".repeat(HASH_PREFIX / 2))), "{rendered}");
+        assert!(!rendered.contains(&"ab".repeat(HASH_PREFIX / 2 + 1)));
+        assert!(rendered.contains("/etc/localtime -> /usr/share/zoneinfo/UTC"));
+        // columns line up
+        let at = |needle: &str| lines.iter().find(|l| l.contains(needle)).and_then(|l| l.find("1970")).unwrap();
+        assert_eq!(at("/etc/motd"), at("/etc/localtime"));
+
+        drop(entries);
+        drop(read_txn);
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+}
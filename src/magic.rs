@@ -0,0 +1,60 @@
+//! `--detect-type`: the kind of a file told by its first bytes, so a config
+//! file turning into an executable stands out from an ordinary edit.
+
+/// Bytes read from the start of a file for `detect`.
+pub const HEAD_LEN: usize = 512;
+
+/// Types of files that run as programs.
+pub const EXECUTABLE_TYPES: &[&str] = &["application/x-executable", "application/vnd.microsoft.portable-executable", "application/x-mach-binary", "text/x-shellscript"];
+
+/// Whether a file of type `mime` runs as a program.
+pub fn executable(mime: &str) -> bool {
+    EXECUTABLE_TYPES.contains(&mime)
+}
+
+/// MIME type of a file starting with `head` as the `infer` crate tells it,
+/// `text/plain` for UTF-8 without NUL bytes and `application/octet-stream` for
+/// anything else unknown.
+pub fn detect(head: &[u8]) -> &'static str {
+    if head.is_empty(){
+        return "application/x-empty";
+    }
+    if let Some(kind) = infer::get(head){
+        return kind.mime_type();
+    }
+    // the head may end in the middle of a character
+    let utf8 = match std::str::from_utf8(head){
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    match utf8 && !head.contains(&0){
+        true => "text/plain",
+        false => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(&elf_head()), "application/x-executable");
+        assert!(executable(detect(&elf_head())));
+        assert_eq!(detect(b"MZ\x90\0\x03\0"), "application/vnd.microsoft.portable-executable");
+        assert_eq!(detect(b"#!/bin/sh\necho hi\n"), "text/x-shellscript");
+        assert_eq!(detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(detect(b"PermitRootLogin no\n"), "text/plain");
+        assert_eq!(detect("caf\u{e9}".as_bytes()), "text/plain");
+        assert_eq!(detect(&"caf\u{e9}".as_bytes()[..4]), "text/plain");
+        assert_eq!(detect(b"\0\x01\x02\x03"), "application/octet-stream");
+        assert_eq!(detect(b""), "application/x-empty");
+    }
+
+    /// Start of a 64-bit ELF, long enough for `infer` to tell.
+    fn elf_head() -> Vec<u8> {
+        let mut head = b"\x7fELF\x02\x01\x01".to_vec();
+        head.resize(64, 0);
+        head
+    }
+}
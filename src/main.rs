@@ -1,179 +1,40 @@
 use std::path::PathBuf;
-use std::collections::VecDeque;
-use sha2::{Sha256, Digest};
-use std::io::Read;
-use std::io;
-use tokio::fs;
+use std::io::{self, IsTerminal};
 use tokio::task::JoinSet;
-use redb::{Database, ReadableTable, ReadableDatabase};
-use log::{debug, error, warn, info, trace, LevelFilter};
+use redb::{Database, ReadableTable, ReadableTableMetadata, ReadableDatabase};
+use log::LevelFilter;
+use tracing::{debug, debug_span, error, warn, info, Instrument};
 use env_logger::Builder;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use clap::{Args, Parser};
-use std::collections::HashSet;
 use dirs::cache_dir;
 use std::sync::Arc;
-use std::time::Instant;
-
-mod error;
-mod types;
-mod fileops;
-mod circl;
-use error::IntegrityWatcherError;
-use types::{DirMetadata, FileMetadata, FileMetadataExt, SymlinkMetadata};
-use fileops::{AddFileInfo, CheckDB, UpdateDB, WriteToDB, TABLE};
-
-async fn get_file_hash(path: PathBuf) -> Result<FileMetadata, IntegrityWatcherError> {
-    let mut hasher = Sha256::new();
-    let meta = tokio::task::spawn_blocking(move || -> Result<FileMetadata, IntegrityWatcherError> {
-        let mut file = std::fs::File::open(&path)
-            .map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
-        let mut buffer = [0u8; 65536];
-        loop {
-            let n = file.read(&mut buffer)
-                .map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
-            if n == 0 { break; }
-            hasher.update(&buffer[..n]);
-        }
-        let result = hasher.finalize();
-        let meta = FileMetadata::new(&file.metadata().map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?, result.into())?;
-        Ok(meta)
-    }).await??;
-    Ok(meta)
-}
-
-async fn visit_dirs<F>(dir: PathBuf, exclude: &HashSet<String>, finfo: &mut F) -> Result<(), IntegrityWatcherError>
-    where F: AddFileInfo {
-    type JoinReturn = Result<Option<(String, FileMetadataExt)>, IntegrityWatcherError>;
-    let mut files: JoinSet<JoinReturn> = JoinSet::new();
-    const FILES_OPEN_PRESSURE: usize = 1024;
-    if exclude.contains(dir.to_string_lossy().as_ref()){
-        warn!("Excluding top dir {}", dir.to_string_lossy().as_ref());
-        return Ok(());
-    }
-    if dir.is_dir() && !dir.is_symlink() {
-        let mut dqueue = VecDeque::new();
-        dqueue.push_back(dir.to_owned());
-        while let Some(dir) = dqueue.pop_front() {
-            let mut direntry = match fs::read_dir(&dir).await
-                .map_err(|e| IntegrityWatcherError::IOError { source: e, path: dir.to_string_lossy().to_string().to_owned() }){
-                    Ok(e) => e,
-                    Err(e) => {
-                        error!("{}", e);
-                        continue;
-                    }
-                };
-
-            while let Some(entry) = direntry.next_entry().await
-                    .map_err(|e| IntegrityWatcherError::IOError { source: e, path: dir.to_string_lossy().to_string() })? {
-                let path = entry.path();
-                if exclude.contains(path.to_string_lossy().as_ref()){
-                    debug!("Skipping {}", path.to_string_lossy().as_ref());
-                    continue;
-                }
-                if path.is_dir() && !path.is_symlink() {
-                    dqueue.push_back(path.to_owned());
-                }
-                let path_str = path.to_string_lossy().to_string();
-                files.spawn(async move {
-                    if path.is_file(){
-                        let meta = get_file_hash(path).await?;
-                        Ok(Some((path_str.to_owned(), FileMetadataExt::File(meta))))
-                    }
-                    else if path.is_symlink() {
-                        let data = fs::read_link(&path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path_str.to_owned() })?;
-                        let meta = fs::symlink_metadata(&path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
-                        let sym = SymlinkMetadata::new(&meta, data.to_string_lossy().into_owned())?;
-                        Ok(Some((path_str.to_owned(), FileMetadataExt::Symlink(sym))))
-                    }
-                    else if path.is_dir(){
-                        let meta = fs::metadata(path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path_str.to_owned() })?;
-                        let dir = DirMetadata::new(&meta)?;
-                        Ok(Some((path_str.to_owned(), FileMetadataExt::Dir(dir))))
-                    }
-                    else{
-                        warn!("Path {} unsuported type", path.to_string_lossy().as_ref());
-                        Ok(None)
-                    }
-                });
-
-                let mut results = Vec::with_capacity(FILES_OPEN_PRESSURE);
-                if files.len() > FILES_OPEN_PRESSURE{ // writing to DB in bigger chunks is way faster
-                    let mut count = 0;
-                    while let Some(result) = files.try_join_next() {
-                        let result = result?;
-                        match result{
-                            Ok(Some(r)) => {
-                                results.push(r);
-                            }
-                            Ok(None) => {},
-                            Err(e) => {
-                                error!("{e}");
-                            }
-                        }
-                        count += 1;
-                    }
-                    trace!("Try Joined {count}");
-                }
-                if files.len() > FILES_OPEN_PRESSURE{ //if we have too many files open we can crash need to throttle down
-                    trace!("Too many files, waiting...");
-                    let result = files.join_next().await.expect("we checked this in prev line")?;
-                    match result{
-                        Ok(Some(r)) => {
-                            results.push(r);
-                        }
-                        Ok(None) => {},
-                        Err(e) => {
-                            error!("{e}");
-                        }
-                    }
-                }
-                if !results.is_empty(){
-                    finfo.add_file_info(&results)?;
-                }
-            }
-        }
-    }
-    else{
-        let path = dir.to_string_lossy().into_owned();
-        let is_file = dir.is_file();
-        let is_symlink = dir.is_symlink();
-        files.spawn(async move {
-            if is_file{
-                let meta = get_file_hash(dir).await?;
-                Ok(Some((path, FileMetadataExt::File(meta))))
-            }
-            else if is_symlink {
-                let data = fs::read_link(&dir).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_owned() })?;
-                let meta = fs::symlink_metadata(&dir).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_owned() })?;
-                let sym = SymlinkMetadata::new(&meta, data.to_string_lossy().into_owned())?;
-                Ok(Some((path.to_owned(), FileMetadataExt::Symlink(sym))))
-            }
-            else{
-                Ok(None)
-            }
-        });
-    }
+use std::time::{Duration, Instant};
+use std::process::ExitCode;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs;
+use tokio::net::TcpListener;
 
-    let mut results = Vec::with_capacity(FILES_OPEN_PRESSURE);
-    let res = files.join_all().await;
-    let mut count = 0;
-    for r in res{
-        match r{
-            Ok(Some(r)) => {
-                results.push(r);
-            }
-            Ok(None) => {},
-            Err(e) => {
-                error!("{e}");
-            }
-        }
-        count += 1;
-    };
-    trace!("Final join {count}");
-    finfo.add_file_info(&results)?;
+mod bench;
+mod colorlog;
+mod jsonlog;
+mod notify;
 
-    Ok(())
-}
+use integrity_checker::{ack, annotations, archive, circl, collector, dbfile, entropy, events, exclude, export, listing, pkgverify, quarantine, runs, sample, treehash, types, unvisited, versions};
+#[cfg(unix)]
+use integrity_checker::eventsocket;
+use integrity_checker::baseline::{add_table_atomic, apply_db_settings, apply_rehash, create_db_atomic, diff_tables, ensure_comparable, ensure_table, merge_sources, merge_table, open_baseline, prune_entries, rehash_entries, verify_dpkg, verify_hash_lengths};
+use integrity_checker::error::{ErrorReport, IntegrityWatcherError};
+use integrity_checker::exclude::ExcludeSet;
+use integrity_checker::dbmeta::{self, DBSettings};
+use integrity_checker::policy::{HashMode, Policy};
+use integrity_checker::severity::{Severity, SeverityPolicy};
+use integrity_checker::sample::SampleSpec;
+use integrity_checker::scan::{check_sampled, resolved_paths, symlink_policy_roots, tag_roots, validate_roots, visit_dirs, with_scan_timeout, HashStats, ScanOptions, SinceFilter};
+use integrity_checker::types::{ByteSize, DisplayOptions, EscapedPath, FileMetadataExt, Timestamp};
+use integrity_checker::fileops::{files_table, host_table_name, validate_host_prefix, validate_table_name, log_finding, AddFileInfo, CheckDB, CheckOptions, FindingKind, RemovalCheck, UpdateDB, WriteToDB, DEFAULT_TABLE};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -190,21 +51,341 @@ struct Cli {
     #[clap(long, use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated exlude paths list")]
     exclude: Vec::<String>,
 
-    #[arg(long)]
-    dont_exclude_db: bool,
+    #[arg(long, help = "after a scan warn about --exclude entries that matched nothing, e.g. mistyped paths")]
+    warn_unmatched_excludes: bool,
+
+    #[arg(long, help = "after a scan show every --exclude and --preset rule with how many paths it pruned and examples")]
+    show_excluded: bool,
+
+    #[clap(long, value_name = "NAME", use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated exclude presets applied before --exclude: linux-system, linux-desktop, container")]
+    preset: Vec::<String>,
+
+    #[arg(long, alias = "dont-exclude-db", help = "scan the DB, --db2, the CIRCL cache and their sidecar files too")]
+    no_self_exclude: bool,
+
+    #[arg(long, help = "only warn about --path entries that don't exist")]
+    allow_missing_paths: bool,
 
     #[arg(long)]
     overwrite: bool,
 
+    #[arg(long, requires = "overwrite", help = "--overwrite without asking on a terminal")]
+    yes: bool,
+
+    #[arg(long, requires = "create", conflicts_with = "db", help = "create: write the baseline as an --export dump to stdout instead of into --db, e.g. piped into --import -")]
+    stdout: bool,
+
+    #[arg(long, value_name = "ALGORITHM", value_parser = ["zstd"], help = "compress the dump of --export or --create --stdout, zstd is the only one")]
+    compress: Option<String>,
+
+    #[arg(long, value_name = "LEVEL", default_value_t = export::DEFAULT_ZSTD_LEVEL, value_parser = clap::value_parser!(i32).range(1..=22), help = "zstd level of a compressed dump")]
+    compress_level: i32,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), help = "create: entries written per DB transaction, instead of one transaction per batch of the walk")]
+    txn_batch: Option<u64>,
+
+    #[arg(long, conflicts_with = "txn_batch", help = "create: write all entries in one DB transaction committed at the end, fastest but holding everything until then")]
+    single_txn: bool,
+
     #[arg(long, help = "second DB for compare")]
     db2: Option<String>,
 
-    #[arg(long, default_value_t = false)]
-    compare_time: bool,
+    #[arg(long, value_name = "NAME", help = "entries table, one DB can hold several baselines [default: files_database]")]
+    table: Option<String>,
+
+    #[arg(long, value_name = "HOST", conflicts_with = "host", help = "--create/--update/--merge: keep entries apart per host in a shared DB")]
+    host_prefix: Option<String>,
+
+    #[arg(long, value_name = "HOST", help = "work on the entries of this --host-prefix host")]
+    host: Option<String>,
+
+    #[arg(long, value_name = "HOST", requires = "host", help = "--compare --host with the entries of this host, in --db2 or --db")]
+    host2: Option<String>,
+
+    #[arg(long, value_name = "TYPES", num_args = 0..=1, default_missing_value = "all", help = "report entries whose only change is the mtime, coma separated files, dirs and symlinks, all when given alone [default: none]")]
+    compare_time: Option<String>,
+
+    #[arg(long, default_value_t = 0, help = "ignore mtime differences up to this many seconds")]
+    mtime_tolerance: u64,
+
+    #[arg(long, help = "abort the whole scan after this many seconds, a --create then stores nothing")]
+    timeout: Option<u64>,
+
+    #[arg(long, help = "skip files that can't be read within this many seconds")]
+    file_timeout: Option<u64>,
+
+    #[arg(long, value_name = "SECS", help = "wait this many seconds for a DB another process has open instead of failing")]
+    wait_for_lock: Option<u64>,
+
+    #[arg(long, value_name = "PATH", help = "key of encrypted DBs, new DBs are encrypted with it [default: passphrase in INTEGRITY_CHECKER_PASSPHRASE]")]
+    key_file: Option<String>,
+
+    #[arg(long, requires = "check", help = "stop at the first finding and exit with code 2")]
+    fail_fast: bool,
+
+    #[arg(long, value_name = "PATH", help = "DB of acknowledged findings, reported at info level until the entry changes again")]
+    ack_file: Option<String>,
+
+    #[clap(long, value_name = "PATH", requires = "maintenance_until", use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated paths whose findings are expected until --maintenance-until")]
+    maintenance_paths: Vec<String>,
+
+    #[arg(long, requires = "check", help = "print nothing when the check is clean, else the findings and a summary line, and exit with code 2")]
+    cron: bool,
+
+    #[arg(long, help = "don't warn when the baseline was created by another major or minor version")]
+    ignore_version_warnings: bool,
+
+    #[arg(long, value_name = "DURATION", requires = "check", help = "stay running and check again every DURATION (90s, 5m, 1h) until SIGTERM, with --cron printing only cycles with findings")]
+    interval: Option<String>,
+
+    #[arg(long, requires = "check", help = "measure the Shannon entropy of changed files over the bytes read for hashing and add it to their findings")]
+    entropy: bool,
+
+    #[arg(long, value_name = "BYTES", requires = "check", help = "compare only size, mtime and permissions of files larger than BYTES, without reading them")]
+    check_max_size: Option<u64>,
+
+    #[arg(long, value_name = "PATH", requires = "check", help = "copy files whose content changed into this directory, named by their SHA-256, with a JSON record of the finding")]
+    quarantine_dir: Option<String>,
+
+    #[arg(long, value_name = "BYTES", default_value_t = quarantine::DEFAULT_MAX_SIZE, requires = "quarantine_dir", help = "don't quarantine files larger than BYTES")]
+    quarantine_max_size: u64,
+
+    #[arg(long, requires = "check", help = "compare files and directories on FAT file systems, like /boot/efi, without permissions and with a 2s mtime tolerance")]
+    vfat_friendly: bool,
+
+    #[arg(long, value_name = "PATH", requires = "check", help = "severities of the kinds of change, one \"CHANGE info|warning|critical\" per line; the check exits with 1 for warnings and 2 for critical findings")]
+    severity_policy: Option<String>,
+
+    #[arg(long, requires = "check", help = "show a desktop notification summarizing the findings, built with the notify feature")]
+    notify: bool,
+
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH", requires = "check", conflicts_with_all = ["group_by_dir", "max_findings", "sorted_output"], help = "write findings as --format ndjson lines to this Unix socket as they are found")]
+    event_socket: Option<String>,
+
+    #[cfg(unix)]
+    #[arg(long, default_value = "connect", value_parser = ["connect", "listen"], value_name = "MODE", requires = "event_socket", help = "connect to an agent listening on --event-socket, or listen on it for agents")]
+    event_socket_mode: String,
+
+    #[cfg(unix)]
+    #[arg(long, default_value = "block", value_parser = ["block", "drop"], value_name = "POLICY", requires = "event_socket", help = "when the agent reads slowly, wait for it or drop findings and count them")]
+    event_backpressure: String,
+
+    #[arg(long, requires = "check", help = "new files and dirs are errors and the check exits with code 2")]
+    strict_new: bool,
+
+    #[clap(long, value_name = "PATH", requires = "check", use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated paths --strict-new applies to, new files elsewhere stay warnings")]
+    strict_new_under: Vec<String>,
+
+    #[arg(long, help = "report new and removed paths differing only in case as a case change, for baselines of another file system")]
+    case_insensitive_compare: bool,
+
+    #[arg(long, help = "report permission changes only of executables, by their execute bits or --detect-type, others are logged at debug level")]
+    compare_only_changed_perms_on_binaries: bool,
+
+    #[arg(long, default_value_t = 100, value_name = "N", help = "more DB entries not found under --path than this and --removed-threshold-percent are only summed up")]
+    removed_threshold: u64,
+
+    #[arg(long, default_value_t = 20, value_name = "PERCENT", value_parser = clap::value_parser!(u64).range(0..=100), help = "percentage of the DB entries for --removed-threshold")]
+    removed_threshold_percent: u64,
+
+    #[arg(long, visible_alias = "force-removals", help = "report or delete DB entries not found under --path even past --removed-threshold or --max-removals")]
+    confirm_removed: bool,
+
+    #[arg(long, value_name = "N", requires = "update", help = "fail the update instead of removing more DB entries than this")]
+    max_removals: Option<u64>,
+
+    #[arg(long, default_value_t = 10, value_name = "PERCENT", value_parser = clap::value_parser!(u64).range(0..=100), requires = "update", help = "fail the update instead of removing more than this percentage of the DB entries")]
+    max_removals_percent: u64,
+
+    #[arg(long, default_value = "hash", value_parser = ["hash", "merge-join"], value_name = "STRATEGY", help = "find DB entries not found under --path by hash lookups, or by a merge join of the sorted paths with the DB")]
+    removal_check: String,
+
+    #[arg(long, help = "print at most this many findings, count the rest")]
+    max_findings: Option<u64>,
+
+    #[arg(long, help = "collapse findings of directories with many of them into one line")]
+    group_by_dir: bool,
+
+    #[arg(long, default_value_t = 10, requires = "group_by_dir", help = "findings per directory before it is collapsed")]
+    group_threshold: usize,
+
+    #[arg(long, help = "print findings sorted by path once the check is done, for diffing the output of two runs")]
+    sorted_output: bool,
+
+    #[arg(long, value_name = "N", default_value_t = 100_000, requires = "sorted_output", help = "findings --sorted-output holds back, a few hundred bytes each, past it the rest is printed unsorted")]
+    sorted_output_max: usize,
+
+    #[arg(long, requires = "check", conflicts_with = "sample_count", help = "check only a random percentage of DB entries, e.g. 2%")]
+    sample: Option<String>,
+
+    #[arg(long, requires = "check", help = "check only this many random DB entries")]
+    sample_count: Option<u64>,
+
+    #[arg(long, help = "seed for --sample selection, printed when not given")]
+    seed: Option<u64>,
+
+    #[clap(long, use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated paths always checked in sampling mode")]
+    sample_always_prefix: Vec::<String>,
+
+    #[arg(long, requires = "check", help = "don't rehash files with unchanged size and mtime older than this (2h, 7d, 2024-01-31, RFC 3339)")]
+    since: Option<String>,
+
+    #[arg(long, help = "don't store directory entries, only files and symlinks")]
+    no_dirs: bool,
+
+    #[arg(long, value_name = "N", help = "don't go further than N levels below each --path, the directories there are stored without their entry count")]
+    max_depth: Option<usize>,
+
+    #[arg(long, help = "store inode numbers and report files replaced by a new inode")]
+    track_inode: bool,
+
+    #[arg(long, default_value = pkgverify::DPKG_INFO_DIR, help = "dpkg md5sums directory, rpm -Va is used when it doesn't exist")]
+    dpkg_info: String,
+
+    #[arg(long, requires = "check", help = "check only entries with this tag")]
+    only_tag: Option<String>,
+
+    #[arg(long, value_name = "FROM=TO", requires = "check_archive", help = "checks archive members under FROM as the DB entries under TO, by default members are relative to /")]
+    map_prefix: Option<String>,
+
+    #[arg(long, requires = "annotate", help = "note text for --annotate")]
+    note: Option<String>,
+
+    #[arg(long, requires = "label", help = "tag for --label")]
+    tag: Option<String>,
+
+    #[arg(long, requires = "prune", help = "with --prune only list the entries that would be removed")]
+    dry_run: bool,
+
+    #[arg(long, requires = "list", help = "show notes and tags with --list")]
+    show_annotations: bool,
+
+    #[arg(long, requires = "list", help = "list only entries marked with --mark-volatile")]
+    volatile: bool,
+
+    #[arg(long, default_value = "log", value_parser = ["log", "table", "ndjson"], value_name = "FORMAT", help = "log lines, a table aligned in columns with --list, or JSON lines of findings as found with --check")]
+    format: String,
+
+    #[arg(long, requires = "bench", help = "stop each --bench pass after this long (30s, 5m)")]
+    duration: Option<String>,
+
+    #[arg(long, help = "hash files with an external command instead of SHA-256, {} is replaced by the path")]
+    hash_command: Option<String>,
+
+    #[arg(long, conflicts_with = "hash_command", help = "hash with fast XXH3-64, detects changes but not deliberate collisions")]
+    prefilter: bool,
+
+    #[arg(long, help = "with --prefilter also store and compare SHA-256")]
+    verify_matches: bool,
+
+    #[arg(long, help = "store symlinks to files as links with the SHA-256 of their target")]
+    hash_link_content: bool,
+
+    #[arg(long, help = "store the content type of files told by their first bytes and report when it changes")]
+    detect_type: bool,
+
+    #[arg(long, help = "store the immutable and append-only inode flags (chattr +i, +a) and report when they change")]
+    flags: bool,
+
+    #[arg(long, help = "store the owning user and group of files and directories, by id and name, and report when they change")]
+    owner: bool,
+
+    #[arg(long, help = "compare owners by user and group name where both entries have one, for baselines of systems with other ids")]
+    owner_by_name: bool,
+
+    #[arg(long, conflicts_with_all = ["hash_command", "prefilter", "hash_link_content"], help = "don't read files, compare only size, mtime, permissions and the other metadata")]
+    stat_only: bool,
+
+    #[arg(long, requires = "check", conflicts_with = "stat_only", help = "quick check of a hashed DB without reading files, comparing size, mtime, permissions and the other metadata")]
+    no_hash: bool,
+
+    #[arg(long, conflicts_with_all = ["hash_command", "prefilter", "stat_only"], help = "hash files as a SHA-256 tree of --stripe-size stripes, those of large files in parallel")]
+    parallel_file_hash: bool,
+
+    #[arg(long, value_name = "MIB", default_value_t = treehash::DEFAULT_STRIPE_MIB, value_parser = clap::value_parser!(u32).range(1..), requires = "parallel_file_hash", help = "stripe size of --parallel-file-hash")]
+    stripe_size: u32,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "stat_only", help = "hash files by path: lines of a path or glob and full, stat-only, head-hash or skip")]
+    policy: Option<String>,
+
+    #[arg(long, default_value = "any", value_parser = ["any", "within-roots"], value_name = "POLICY", help = "within-roots: warn about symlinks pointing outside the scanned --path roots")]
+    symlink_policy: String,
+
+    #[arg(long, help = "same as --symlink-policy within-roots")]
+    flag_external_targets: bool,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), help = "files hashed at once, directories and symlinks are read alongside [default: 1024]")]
+    hash_jobs: Option<u32>,
+
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), help = "directories listed at once, for network file systems [default: 1]")]
+    walk_jobs: Option<u32>,
+
+    #[arg(long, help = "scanning an overlay layer, store whiteouts as deletions of the paths they hide")]
+    overlay_aware: bool,
+
+    #[arg(long, default_value = "any", value_parser = ["chardev", "prefix", "any"], value_name = "FORMAT", requires = "overlay_aware", help = "whiteouts of --overlay-aware: chardev (overlayfs 0:0 devices), prefix (aufs and OCI .wh. files) or any")]
+    whiteout_format: String,
+
+    #[arg(long, value_name = "FOLD", help = "coma separated icase, nfc or nfd: match paths like a case-insensitive or normalizing file system")]
+    path_fold: Option<String>,
+
+    #[arg(long, value_name = "URL", requires = "host_id", help = "send check/update findings to a --serve collector")]
+    push: Option<String>,
+
+    #[arg(long, value_name = "HOST", requires = "push", help = "name of this host for --push")]
+    host_id: Option<String>,
+
+    #[arg(long, value_name = "PATH", help = "bearer token of --push, or the HOST-ID TOKEN lines --serve accepts")]
+    token_file: Option<String>,
+
+    #[arg(long, value_name = "PATH", requires = "tls_key", help = "--serve certificate, or --push client certificate (PEM)")]
+    tls_cert: Option<String>,
+
+    #[arg(long, value_name = "PATH", requires = "tls_cert", help = "key of --tls-cert (PEM)")]
+    tls_key: Option<String>,
+
+    #[arg(long, value_name = "PATH", help = "--serve: require client certificates signed by this CA")]
+    client_ca: Option<String>,
+
+    #[arg(long, value_name = "PATH", requires = "push", help = "--push: trust only this CA for the collector")]
+    collector_ca: Option<String>,
+
+    #[arg(long, default_value = "text", value_parser = ["text", "json"], value_name = "FORMAT", hide_possible_values = true, help = "text or json, json lines carry command/root spans")]
+    log_format: String,
+
+    #[arg(long, help = "write an error ending the run as a JSON object with kind, message and path to stderr")]
+    json_errors: bool,
+
+    #[arg(long, help = "show timestamps in local time zone instead of UTC")]
+    local_time: bool,
+
+    #[arg(long, help = "show directory sizes in findings and listings in KiB/MiB/... as file sizes always are")]
+    human_sizes: bool,
+
+    #[arg(long, help = "show permissions also in rwxr-xr-x notation")]
+    symbolic_permissions: bool,
+
+    #[arg(long, help = "compare and show only permission bits, without file type bits")]
+    perms_only: bool,
+
+    #[arg(long, default_value = "30d", help = "how long CIRCL found answers are cached (30d, 12h)")]
+    circl_found_ttl: String,
+
+    #[arg(long, default_value = "7d", help = "how long CIRCL not found answers are cached")]
+    circl_notfound_ttl: String,
 
    #[arg(long, default_value_t = cache_dir().unwrap_or(std::path::PathBuf::from(".")).to_string_lossy().to_string() + std::path::MAIN_SEPARATOR_STR + "cicrl_cache.redb")]
 
     cache: String,
+
+    /// command line as given, kept in the DB with each run
+    #[arg(skip)]
+    argv: Vec<String>,
+
+    /// `runs::new_run_id` of this invocation
+    #[arg(skip)]
+    run_id: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -216,263 +397,2352 @@ struct Cmd {
     #[arg(long, requires = "pathgroup", help = "checks current files metadata compared to DB")]
     check: bool,
 
+    #[arg(long, value_name = "ARCHIVE", help = "checks the files of a tar archive, also gzip or zstd compressed, against DB")]
+    check_archive: Option<String>,
+
     #[arg(long, requires = "pathgroup", help = "updates DB")]
     update: bool,
 
+    #[arg(long, requires = "pathgroup", help = "checks against DB, or creates it when it has no baseline yet")]
+    monitor: bool,
+
     #[arg(long, help = "lists all files in DB")]
     list: bool,
 
     #[arg(long, help = "compares 2 databases (simmilar to check)")]
     compare: bool,
 
+    #[arg(long, requires = "db2", help = "compares --db and --db2 without reading files, reports entries only in either and fields that differ")]
+    db_equal: bool,
+
     #[arg(long, help = "check DB against CIRCL hashes https://www.circl.lu/services/hashlookup/")]
     circl_check: bool,
-}
-
-async fn main_fun() -> Result<(),IntegrityWatcherError> {
-    let mut args = Cli::parse();
-    Builder::new()
-        .filter_level(LevelFilter::Info)
-        .parse_default_env()
-        .format_timestamp(Some(env_logger::TimestampPrecision::Micros))
-        .target(env_logger::Target::Stdout)
-        .init();
-
-    if !args.dont_exclude_db{
-        let db_path = std::path::PathBuf::from(&args.db);
-        match fs::canonicalize(db_path).await{
-            Err(e) => {
-                if e.kind() != std::io::ErrorKind::NotFound{
-                    return Err(IntegrityWatcherError::IOError { source: e, path: args.db });
-                }
-            }
-            Ok(f) => {
-                args.exclude.push(f.to_string_lossy().to_string());
-            }
-        };
-        args.exclude.push(args.db.to_owned());
-    }
-    debug!("Paths {:?}", args.path);
-    debug!("Excluded {:?}", args.exclude);
 
-    let mut exlude = HashSet::new();
+    #[arg(long, help = "check newline separated SHA-256 hashes from stdin against CIRCL")]
+    circl_stdin: bool,
 
-    for i in args.exclude{
-        exlude.insert(i);
-    }
-    let time = Instant::now();
+    #[arg(long, requires = "pathgroup", help = "hashes files without a DB and reports throughput")]
+    bench: bool,
 
-    if args.cmd.create{
-        if args.overwrite{
-            if let Err(e) = fs::remove_file(&args.db).await
-            && e.kind() != std::io::ErrorKind::NotFound{
-                return Err(IntegrityWatcherError::IOError { source: e, path: args.db });
-            }
-        }
-        else if fs::try_exists(&args.db).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: args.db.to_owned() })?{
-            error!("database {} already exists", &args.db);
-            return Err(IntegrityWatcherError::IOError { source: io::Error::new(io::ErrorKind::AlreadyExists, "Already exists".to_owned()), path: args.db});
-        }
-        info!("Creating db {}", args.db);
-        let db = Database::create(&args.db)?;
-        let mut writer = WriteToDB::new(&db);
-        for path in args.path.iter(){
-            visit_dirs(PathBuf::from(path), &exlude, &mut writer).await?;
-        }
-        let elapsed = time.elapsed();
-        let bytes = writer.get_bytes();
-        info!("Added {} files total {} in {:.3}s {}", writer.get_counter(), bytes, elapsed.as_secs_f32(), bytes.bandwidth(elapsed));
-    }
+    #[arg(long, help = "recompute stored hashes with --hash-command, --prefilter, --parallel-file-hash or SHA-256")]
+    rehash: bool,
 
-    if args.cmd.check{
-        let db = Database::open(&args.db)?;
-        let mut writer = CheckDB::new(&db, args.compare_time);
+    #[arg(long, help = "check DB files owned by dpkg/rpm packages against the package hashes")]
+    verify_against_package_manager: bool,
 
-        for path in args.path.iter(){
-            visit_dirs(PathBuf::from(path), &exlude, &mut writer).await?;
-        }
+    #[arg(long, help = "checks that the hash of every DB entry has the length of the DB's hash algorithm")]
+    db_verify: bool,
 
-        let read_txn = db.begin_read().map_err(Box::new)?;
-        let table = read_txn.open_table(TABLE)?;
-        let iter = table.iter()?;
+    #[clap(long, value_name = "PREFIX=TAG", use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated rules tagging every entry under PREFIX")]
+    tag_prefix: Vec<String>,
 
-        let mut removed_counter: u64 = 0;
-        for k in iter{
-            let k = k?;
-            if !writer.files.contains(&k.0.value()){
-                removed_counter += 1;
-                warn!("File removed {} {}", k.0.value(), k.1.value())
-            }
-        }
-        let elapsed = time.elapsed();
-        let bytes = writer.get_bytes();
-        info!("Checked {} files total {} in {:.3}s {} new files {} modified {} removed {removed_counter}",
-            writer.get_counter(),
-            bytes,
-            elapsed.as_secs_f32(),
-            bytes.bandwidth(elapsed),
-            writer.get_new_files_count(),
-            writer.get_changes_count()
-        );
-    }
+    #[arg(long, help = "shows entry counts per type and tag and the last runs")]
+    stats: bool,
 
-    if args.cmd.update{
-        let db = Database::open(&args.db)?;
-        let mut writer = UpdateDB::new(&db);
+    #[arg(long, value_name = "PATH", requires = "note", help = "attach --note to a DB entry")]
+    annotate: Option<String>,
 
-        for path in args.path.iter(){
-            visit_dirs(PathBuf::from(path), &exlude, &mut writer).await?;
-        }
+    #[arg(long, value_name = "PATH", requires = "tag", help = "attach --tag to a DB entry")]
+    label: Option<String>,
 
-        let mut to_remove = Vec::new();
-            {
-            let read_txn = db.begin_read().map_err(Box::new)?;
-            let table = read_txn.open_table(TABLE)?;
-            let iter = table.iter()?;
+    #[arg(long, value_name = "PATH", help = "marks a DB entry volatile, changes of its content, size and times are only logged at debug level")]
+    mark_volatile: Option<String>,
 
-            for k in iter{
-                let k = k?;
-                if !writer.files.contains(&k.0.value()){
-                    to_remove.push(k.0.value());
-                }
-            }
-        }
-        let write_txn = db.begin_write().map_err(Box::new)?;
-        {
-            let mut table = write_txn.open_table(TABLE)?;
-            for k in to_remove{
-                info!("Removing file {}", k);
-                table.remove(k)?;
-            }
-        }
-        write_txn.commit()?;
-        let elapsed = time.elapsed();
-        let bytes = writer.get_bytes();
-        info!("Updated {} files total {} in {:.3}s {}", writer.get_counter(), bytes, elapsed.as_secs_f32(), bytes.bandwidth(elapsed));
-    }
+    #[arg(long, value_name = "PATH", help = "clears the --mark-volatile mark of a DB entry")]
+    unmark_volatile: Option<String>,
 
-    if args.cmd.compare{
-        let db2 = if let Some(dbname) = args.db2{
-            Database::open(dbname)?
-        }
-        else{
-            error!("Compare need db2 parameter");
-            return Err(IntegrityWatcherError::IOError { source: io::Error::new(io::ErrorKind::InvalidData, "".to_owned()), path: "".to_owned()});
-        };
+    #[arg(long, value_name = "ADDR", help = "collect --push reports over HTTPS into --db")]
+    serve: Option<String>,
 
-        let db = Database::open(&args.db)?;
+    #[arg(long, help = "lists hosts that pushed reports to the collector DB")]
+    list_hosts: bool,
 
-        let mut orig_files = Vec::new();
+    #[arg(long, value_names = ["HOST", "HOST"], num_args = 2, help = "compares the latest reports of two hosts")]
+    diff_host: Vec<String>,
 
-        let read_txn2 = db2.begin_read().map_err(Box::new)?;
-        let table2 = read_txn2.open_table(TABLE)?;
-        let iter2 = table2.iter()?;
+    #[arg(long, value_name = "DB", help = "copies a DB of one host into --db under --host-prefix")]
+    merge: Option<String>,
 
-        for k in iter2{
-            let k = k?;
-            orig_files.push((k.0.value(), k.1.value()));
-        }
+    #[arg(long, value_name = "PATH", help = "writes the baseline as JSON lines to PATH or - for stdout, zstd compressed when PATH ends in .zst")]
+    export: Option<String>,
 
-        let mut writer = CheckDB::new(&db, args.compare_time);
-        writer.add_file_info(&orig_files)?;
+    #[arg(long, value_name = "PATH", help = "creates the baseline from an --export dump in PATH or - for stdin, compressed or not")]
+    import: Option<String>,
 
-        let read_txn = db.begin_read().map_err(Box::new)?;
-        let table = read_txn.open_table(TABLE)?;
-        let iter = table.iter()?;
+    #[arg(long, value_name = "NAME", help = "prints the exclude rules of a --preset")]
+    print_preset: Option<String>,
 
-        for k in iter{
-            let k = k?;
-            if !writer.files.contains(&k.0.value()){
-                warn!("File removed {} {}", k.0.value(), k.1.value())
-            }
-        }
-        info!("Checked {} files in {}", writer.files.len(), time.elapsed().as_secs_f32());
-    }
+    #[arg(long, help = "prints the JSON Schema of --format ndjson lines")]
+    print_schema: bool,
 
-    if args.cmd.list{
-        let db = Database::open(&args.db)?;
-        let read_txn = db.begin_read().map_err(Box::new)?;
-        let table = read_txn.open_table(TABLE)?;
+    #[clap(long, value_name = "GLOB", use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated paths or globs, removes matching DB entries whatever is on disk")]
+    prune: Vec<String>,
 
-        let iter = table.iter()?;
+    #[clap(long, value_name = "PATH", requires_all = ["pathgroup", "ack_file"], use_value_delimiter = true, value_delimiter = ',', num_args = 1.., help = "coma separated paths, checks and acknowledges the findings under them")]
+    ack: Vec<String>,
 
-        for k in  iter{
-            let k = k?;
-            info!("File: {}: {}", k.0.value(), k.1.value());
-        }
-    }
+    #[arg(long, requires_all = ["pathgroup", "ack_file"], help = "checks and acknowledges all findings")]
+    ack_all: bool,
 
-    if args.cmd.circl_check{
-        let db = Database::open(&args.db)?;
-        let read_txn = db.begin_read().map_err(Box::new)?;
-        let table = read_txn.open_table(TABLE)?;
+    #[arg(long, requires = "ack_file", help = "lists acknowledged findings")]
+    show_acked: bool,
 
-        let iter = table.iter()?;
+    #[arg(long, help = "encrypts --db in place with --key-file or INTEGRITY_CHECKER_PASSPHRASE")]
+    encrypt: bool,
 
-        let circl = Arc::new(circl::CirclQuery::new(&args.cache)?);
-        type JoinReturn = Result<(String, types::Hash, Option<u8>), IntegrityWatcherError>;
-        let mut queries: JoinSet<JoinReturn> = JoinSet::new();
+    #[arg(long, value_name = "TIME", requires = "maintenance_paths", help = "until this time (2024-06-01T06:00Z, 6h) checks report findings under --maintenance-paths at info level")]
+    maintenance_until: Option<String>,
+}
 
-        let fun = |q: JoinReturn| {
-            match q{
-                Ok((f, h, Some(v))) => {
-                    info!("File {f} hash {h} found with score {v}");
-                }
-                Ok((f, h, None)) => {
-                    warn!("File {f} hash {h} not found");
-                }
-                Err(e) => {
-                    error!("Error query {e}");
-                }
-            }
-        };
-        for k in  iter{
-            let k = k?;
-            let meta = k.1.value();
-
-            let fname = k.0.value().to_owned();
-            if let FileMetadataExt::File(file_meta) = meta{
-                let cc = circl.clone();
-                queries.spawn( async move{
-                    let h = file_meta.hash.clone();
-                    let r = cc.query(&h).await?;
-                    Ok((fname, h, r))
-                });
-
-                if queries.len() > 32{
-                    loop {
-                        if let Some(x) = queries.join_next().await{
-                            let x = x?;
-                            fun(x);
-                        }
-                        else{
-                            break;
-                        }
-                        if queries.len() < 8{
-                            break;
-                        }
-                    }
-                }
-            }
-        }
-        let r = queries.join_all().await;
-        for i in r{
-            fun(i);
+impl Cmd {
+    /// Name of the selected command, as given on the command line.
+    fn name(&self) -> &'static str {
+        match self{
+            Cmd{ create: true, .. } => "create",
+            Cmd{ check: true, .. } => "check",
+            Cmd{ check_archive: Some(_), .. } => "check-archive",
+            Cmd{ update: true, .. } => "update",
+            Cmd{ monitor: true, .. } => "monitor",
+            Cmd{ list: true, .. } => "list",
+            Cmd{ compare: true, .. } => "compare",
+            Cmd{ db_equal: true, .. } => "db-equal",
+            Cmd{ circl_check: true, .. } => "circl-check",
+            Cmd{ circl_stdin: true, .. } => "circl-stdin",
+            Cmd{ bench: true, .. } => "bench",
+            Cmd{ rehash: true, .. } => "rehash",
+            Cmd{ verify_against_package_manager: true, .. } => "verify-against-package-manager",
+            Cmd{ db_verify: true, .. } => "db-verify",
+            Cmd{ stats: true, .. } => "stats",
+            Cmd{ annotate: Some(_), .. } => "annotate",
+            Cmd{ label: Some(_), .. } => "label",
+            Cmd{ mark_volatile: Some(_), .. } => "mark-volatile",
+            Cmd{ unmark_volatile: Some(_), .. } => "unmark-volatile",
+            Cmd{ serve: Some(_), .. } => "serve",
+            Cmd{ list_hosts: true, .. } => "list-hosts",
+            Cmd{ diff_host, .. } if !diff_host.is_empty() => "diff-host",
+            Cmd{ merge: Some(_), .. } => "merge",
+            Cmd{ export: Some(_), .. } => "export",
+            Cmd{ import: Some(_), .. } => "import",
+            Cmd{ print_preset: Some(_), .. } => "print-preset",
+            Cmd{ print_schema: true, .. } => "print-schema",
+            Cmd{ prune, .. } if !prune.is_empty() => "prune",
+            Cmd{ ack, .. } if !ack.is_empty() => "ack",
+            Cmd{ ack_all: true, .. } => "ack-all",
+            Cmd{ show_acked: true, .. } => "show-acked",
+            Cmd{ encrypt: true, .. } => "encrypt",
+            Cmd{ maintenance_until: Some(_), .. } => "maintenance-until",
+            _ => "tag-prefix",
         }
     }
+}
 
-    Ok(())
+/// `--prefilter` is for change detection only, say so on every scan using it.
+fn warn_prefilter(opts: &ScanOptions) {
+    if opts.prefilter && opts.verify_matches{
+        warn!("Prefilter mode: files compared by XXH3-64, SHA-256 verified where the DB has it");
+    }
+    else if opts.prefilter{
+        warn!("Prefilter mode: files compared by XXH3-64 only, not tamper-proof, use --verify-matches for SHA-256");
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(),IntegrityWatcherError> {
-    match main_fun().await{
-        Err(e) => {
-            error!("Error {}", e);
-            Err(e)
+/// Warns when another major or minor version wrote the baseline, whose
+/// entries may differ from a scan now for that alone.
+fn warn_baseline_version(db: &Database, table: &str, args: &Cli) -> Result<(), IntegrityWatcherError> {
+    if args.ignore_version_warnings{
+        return Ok(());
+    }
+    match versions::baseline_version(db, table)?{
+        Some(version) => if let Some(notice) = versions::notice(&version, versions::CURRENT, versions::BEHAVIOR_CHANGES){
+            warn!("{notice}");
         },
-        Ok(()) => {
-            Ok(())
-        }
+        None => debug!("No version recorded for the baseline of {}", EscapedPath(table)),
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+/// Warns about `--exclude` rules the walk never applied: with a typo in a
+/// path the files meant stay monitored, or worse, the intended ones don't.
+fn warn_unmatched_excludes(exclude: &ExcludeSet, rules: &[String]) {
+    for rule in exclude.unmatched(){
+        if rules.iter().any(|r| r == rule){
+            warn!("Exclude {} matched no path, mistyped?", EscapedPath(rule));
+        }
+    }
+}
+
+/// SIGTERM, listened for from the first `--interval` cycle on so one arriving
+/// mid-check ends the loop once the check is done.
+#[cfg(unix)]
+fn terminate_signal() -> Result<tokio::signal::unix::Signal, IntegrityWatcherError> {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .map_err(|source| IntegrityWatcherError::IOError{ source, path: "SIGTERM".to_owned() })
+}
+
+/// Ctrl-C where there is no SIGTERM.
+#[cfg(windows)]
+fn terminate_signal() -> Result<tokio::signal::windows::CtrlC, IntegrityWatcherError> {
+    tokio::signal::windows::ctrl_c()
+        .map_err(|source| IntegrityWatcherError::IOError{ source, path: "Ctrl-C".to_owned() })
+}
+
+/// `--show-excluded`: every `--preset` and `--exclude` rule with the paths it
+/// pruned, to catch one excluding more than meant, and those that matched
+/// nothing. Under `--log-format json` the counts and examples are also fields
+/// of the line.
+fn show_excluded(exclude: &ExcludeSet, args: &Cli) {
+    let mut given: Vec<&str> = args.preset.iter().flat_map(|name| exclude::preset_rules(name).unwrap_or_default()).collect();
+    given.extend(args.exclude.iter().map(String::as_str));
+    let json = args.log_format == "json";
+    for usage in exclude.usage().into_iter().filter(|u| given.contains(&u.rule)){
+        let rule = EscapedPath(usage.rule);
+        if !usage.matched{
+            warn!("Exclude {rule} matched no path, mistyped?");
+            continue;
+        }
+        let examples: Vec<_> = usage.examples.iter().map(|p| EscapedPath(p).to_string()).collect();
+        let message = match usage.pruned{
+            0 if usage.rule.starts_with('!') => format!("Exclude {rule} re-included paths"),
+            0 => format!("Exclude {rule} pruned no path"),
+            n => format!("Exclude {rule} pruned {n} paths, e.g. {}", examples.join(", ")),
+        };
+        if json{
+            info!(rule = usage.rule, pruned = usage.pruned, examples = examples.join("\n"), "{message}");
+        }
+        else{
+            info!("{message}");
+        }
+    }
+}
+
+/// `--create --overwrite` of a baseline that exists: the question asked on a
+/// terminal before replacing it, with its entry count and when it was created.
+/// None when there's nothing to ask, with `--yes` or when not `interactive`,
+/// so scripts and timers overwrite as before.
+fn overwrite_prompt(args: &Cli, table: &str, interactive: bool) -> Result<Option<String>, IntegrityWatcherError> {
+    if !args.overwrite || args.yes || !interactive || !Path::new(&args.db).exists(){
+        return Ok(None);
+    }
+    let db = dbfile::open(&args.db)?;
+    let entries = {
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        match read_txn.open_table(files_table(table)){
+            Ok(t) => t.len()?,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    };
+    let created = runs::load(&db, table, runs::RUNS_KEPT as usize)?.into_iter().rfind(|r| r.command == "create")
+        .map_or("at an unknown time".to_owned(), |r| format!("on {}", Timestamp::new(r.started)));
+    Ok(Some(format!("Database {} table {} has {entries} entries, created {created}. Replace it? [y/N] ", EscapedPath(&args.db), EscapedPath(table))))
+}
+
+/// Compression of a dump written to `path`, zstd with `--compress` or when
+/// it's named `*.zst`.
+fn compression(args: &Cli, path: &str) -> export::Compression {
+    match args.compress.is_some() || path.ends_with(".zst"){
+        true => export::Compression::Zstd(args.compress_level),
+        false => export::Compression::None,
+    }
+}
+
+/// Asks `prompt` on `output`, true when the answer read from `input` is yes.
+fn confirm(prompt: &str, mut input: impl io::BufRead, mut output: impl io::Write) -> io::Result<bool> {
+    output.write_all(prompt.as_bytes())?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// The key of `path` in baseline `table`, folded like its entries, for the
+/// commands working on one entry. Fails when it has none.
+fn entry_key(db: &Database, table: &str, db_name: &str, path: &str) -> Result<String, IntegrityWatcherError> {
+    let key = DBSettings::load(db, table)?.path_fold.fold(path).into_owned();
+    let in_db = {
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        read_txn.open_table(files_table(table))?.get(&key)?.is_some()
+    };
+    if !in_db{
+        return Err(IntegrityWatcherError::InvalidArgument(format!("{} is not in database {}", EscapedPath(&key), EscapedPath(db_name))));
+    }
+    Ok(key)
+}
+
+/// Appends this run to the runs of `table` for `--stats`.
+fn record_run(db: &Database, table: &str, args: &Cli, elapsed: Duration, files: u64, bytes: ByteSize, findings: u64) -> Result<(), IntegrityWatcherError> {
+    let now: u64 = chrono::Utc::now().timestamp().try_into().unwrap_or_default();
+    let run = runs::Run{
+        started: now.saturating_sub(elapsed.as_secs()),
+        command: args.cmd.name().to_owned(),
+        duration_ms: elapsed.as_millis().try_into().unwrap_or(u64::MAX),
+        files,
+        bytes: bytes.into(),
+        findings,
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        args: args.argv.iter().map(|a| runs::redact(a)).collect(),
+        run_id: args.run_id.clone().unwrap_or_default(),
+    };
+    runs::record(db, table, &run, runs::RUNS_KEPT)
+}
+
+/// Queries CIRCL for every hash, keeping a bounded number of requests in flight.
+/// `what` names the source of the entry in the printed results.
+async fn circl_lookup<I>(circl: circl::CirclQuery, what: &'static str, hashes: I) -> Result<(), IntegrityWatcherError>
+    where I: IntoIterator<Item = Result<(String, types::Hash), IntegrityWatcherError>> {
+    let circl = Arc::new(circl);
+    type JoinReturn = Result<(String, types::Hash, Option<u8>), IntegrityWatcherError>;
+    let mut queries: JoinSet<JoinReturn> = JoinSet::new();
+
+    let fun = |q: JoinReturn| {
+        match q{
+            Ok((f, h, Some(v))) => {
+                info!("{what} {} hash {h} found with score {v}", EscapedPath(&f));
+            }
+            Ok((f, h, None)) => {
+                warn!("{what} {} hash {h} not found", EscapedPath(&f));
+            }
+            Err(e) => {
+                error!("Error query {e}");
+            }
+        }
+    };
+    for k in hashes{
+        let (fname, h) = k?;
+        let cc = circl.clone();
+        queries.spawn( async move{
+            let r = cc.query(&h).await?;
+            Ok((fname, h, r))
+        }.in_current_span());
+
+        if queries.len() > 32{
+            loop {
+                if let Some(x) = queries.join_next().await{
+                    let x = x?;
+                    fun(x);
+                }
+                else{
+                    break;
+                }
+                if queries.len() < 8{
+                    break;
+                }
+            }
+        }
+    }
+    let r = queries.join_all().await;
+    for i in r{
+        fun(i);
+    }
+    let circl = Arc::into_inner(circl).expect("queries are done");
+    debug!("Cached {} CIRCL answers", circl.finish()?);
+    Ok(())
+}
+
+/// Reads one hex SHA-256 per line; blank lines are ignored and invalid ones
+/// reported and skipped. Entries are named by their line number.
+fn parse_hash_lines<R: io::BufRead>(reader: R) -> Result<Vec<(String, types::Hash)>, IntegrityWatcherError> {
+    let mut hashes = Vec::new();
+    for (n, line) in reader.lines().enumerate(){
+        let line = line.map_err(|e| IntegrityWatcherError::IOError { source: e, path: "stdin".to_owned() })?;
+        if line.trim().is_empty(){
+            continue;
+        }
+        match line.parse(){
+            Ok(h) => hashes.push(((n + 1).to_string(), h)),
+            Err(e) => error!("Line {} skipped: {e}", n + 1),
+        }
+    }
+    Ok(hashes)
+}
+
+/// Exit status when `--fail-fast` stopped on a finding, `--cron` found changes,
+/// `--strict-new` found new files or files differ from their package; errors
+/// exit with 1.
+const EXIT_CHANGES: u8 = 2;
+
+async fn main_fun(mut args: Cli) -> Result<ExitCode, IntegrityWatcherError> {
+    args.argv = std::env::args().skip(1).collect();
+    let run_id = args.run_id.insert(runs::new_run_id()).clone();
+    // --cron leaves only findings, which are warnings and errors
+    let (level, write_style) = match args.cron{
+        true => (LevelFilter::Warn, env_logger::WriteStyle::Never),
+        false => (LevelFilter::Info, env_logger::WriteStyle::Auto),
+    };
+    // --format ndjson keeps stdout for the events, --export - and --stdout for the dump
+    let stdout_taken = args.format == "ndjson" || args.cmd.export.as_deref() == Some("-") || args.stdout;
+    if args.log_format == "json"{
+        let default_level = match args.cron{
+            true => tracing::level_filters::LevelFilter::WARN,
+            false => tracing::level_filters::LevelFilter::INFO,
+        };
+        let out = match stdout_taken{
+            true => BoxMakeWriter::new(std::io::stderr),
+            false => BoxMakeWriter::new(std::io::stdout),
+        };
+        let filter = EnvFilter::builder().with_default_directive(default_level.into()).from_env_lossy();
+        tracing::subscriber::set_global_default(jsonlog::subscriber(filter, out))
+            .map_err(|e| IntegrityWatcherError::InvalidArgument(format!("--log-format json: {e}")))?;
+    }
+    else{
+        Builder::new()
+            .filter_level(level)
+            .parse_default_env()
+            .write_style(write_style)
+            .format(colorlog::format)
+            .target(if stdout_taken { env_logger::Target::Stderr } else { env_logger::Target::Stdout })
+            .init();
+    }
+
+    let span = debug_span!("command", command = args.cmd.name(), run_id = run_id.as_str());
+    run(args).instrument(span).await
+}
+
+async fn run(mut args: Cli) -> Result<ExitCode, IntegrityWatcherError> {
+    DisplayOptions{
+        local_time: args.local_time,
+        human_sizes: args.human_sizes,
+        symbolic_permissions: args.symbolic_permissions,
+        perms_only: args.perms_only,
+    }.set_global();
+    if let Some(secs) = args.wait_for_lock{
+        dbfile::set_lock_wait(Duration::from_secs(secs));
+    }
+    let secret = match &args.key_file{
+        Some(path) => Some(dbfile::Secret::from_key_file(path)?),
+        None => dbfile::Secret::from_env(),
+    };
+    if let Some(secret) = &secret{
+        dbfile::set_secret(secret.clone());
+    }
+    let stale = dbfile::remove_stale_plain_copies();
+    if stale > 0{
+        info!("Removed {stale} decrypted databases left in the temp dir by killed runs");
+    }
+
+    if let Some(name) = &args.cmd.print_preset{
+        let rules = exclude::preset_rules(name)?;
+        if let Some(preset) = exclude::PRESETS.iter().find(|p| p.name == name.as_str()){
+            println!("# {}: {}", preset.name, preset.description);
+        }
+        for rule in rules{
+            println!("{rule}");
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.cmd.print_schema{
+        println!("{}", serde_json::to_string_pretty(&events::schema()).expect("schema serializes"));
+        return Ok(ExitCode::SUCCESS);
+    }
+    let run_id = args.run_id.get_or_insert_with(runs::new_run_id).clone();
+    info!("Run {run_id}: integrity-checker {} --{}", env!("CARGO_PKG_VERSION"), args.cmd.name());
+    if args.compress.is_some() && args.cmd.export.is_none() && !args.stdout{
+        return Err(IntegrityWatcherError::InvalidArgument("--compress works with --export and --create --stdout".to_owned()));
+    }
+    // redb needs a file, --stdout builds the baseline in a temp DB and dumps that
+    if args.stdout{
+        args.db = std::env::temp_dir().join(format!("integrity-checker-{run_id}.redb")).to_string_lossy().into_owned();
+    }
+
+    if args.cmd.encrypt{
+        let Some(secret) = &secret else {
+            return Err(IntegrityWatcherError::InvalidArgument(format!("--encrypt needs --key-file or {}", dbfile::PASSPHRASE_ENV)));
+        };
+        dbfile::encrypt(&args.db, secret)?;
+        info!("Encrypted {}", EscapedPath(&args.db));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // presets first, so --exclude can re-include what they leave out
+    let mut exclude = ExcludeSet::default();
+    for name in &args.preset{
+        for rule in exclude::preset_rules(name)?{
+            exclude.add(rule);
+        }
+    }
+    for rule in &args.exclude{
+        exclude.add(rule);
+    }
+    let tmp_db = format!("{}.tmp", args.db);
+    if !args.no_self_exclude{
+        // files we write ourselves would show up as changed on every run
+        let mut own = vec![args.db.clone(), tmp_db.clone(), args.cache.clone()];
+        own.extend(args.db2.iter().cloned());
+        own.extend(args.ack_file.iter().cloned());
+        for file in own.clone(){
+            own.extend([".sig", ".lock"].map(|ext| format!("{file}{ext}")));
+        }
+        for file in &own{
+            match fs::canonicalize(file).await{
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::NotFound{
+                        return Err(IntegrityWatcherError::IOError { source: e, path: file.to_owned() });
+                    }
+                }
+                Ok(f) => {
+                    exclude.add_path(&f.to_string_lossy());
+                }
+            };
+            for path in resolved_paths(file).await?{
+                exclude.add_path(&path);
+            }
+        }
+        // decrypted copies of encrypted DBs
+        if secret.is_some(){
+            exclude.add(&dbfile::plain_copies());
+        }
+    }
+    // --ack and --ack-all check first, then record what the check found
+    let acking = args.cmd.ack_all || !args.cmd.ack.is_empty();
+    if acking{
+        args.cmd.check = true;
+    }
+    if args.cmd.create || args.cmd.check || args.cmd.update || args.cmd.monitor || args.cmd.bench{
+        args.path = validate_roots(&args.path, args.allow_missing_paths).await?;
+    }
+    debug!("Paths {:?}", args.path);
+    debug!("Excluded {:?}", exclude);
+    let base_table = args.table.as_deref().unwrap_or(DEFAULT_TABLE);
+    validate_table_name(base_table)?;
+    if args.host_prefix.is_some() && !args.cmd.create && !args.cmd.update && args.cmd.merge.is_none(){
+        return Err(IntegrityWatcherError::InvalidArgument("--host-prefix works with --create, --update and --merge, select a host with --host".to_owned()));
+    }
+    if args.host.is_some() && (args.cmd.create || args.cmd.update || args.cmd.merge.is_some()){
+        return Err(IntegrityWatcherError::InvalidArgument("--create, --update and --merge take --host-prefix instead of --host".to_owned()));
+    }
+    for host in [&args.host_prefix, &args.host, &args.host2].into_iter().flatten(){
+        validate_host_prefix(host)?;
+    }
+    let table_name = match args.host_prefix.as_ref().or(args.host.as_ref()){
+        Some(host) => host_table_name(base_table, host),
+        None => base_table.to_owned(),
+    };
+    let table = table_name.as_str();
+    // --monitor checks an established baseline and creates it on the first run
+    if args.cmd.monitor{
+        let established = fs::try_exists(&args.db).await.unwrap_or(false) && match open_baseline(&args.db, table){
+            Ok(_) => true,
+            Err(IntegrityWatcherError::NoBaseline { .. }) => false,
+            Err(e) => return Err(e),
+        };
+        args.cmd.check = established;
+        args.cmd.create = !established;
+    }
+    let push_opts = match (&args.push, &args.host_id){
+        (Some(_), _) if !args.cmd.check && !args.cmd.update => {
+            return Err(IntegrityWatcherError::InvalidArgument("--push works with --check and --update".to_owned()));
+        }
+        (Some(_), Some(host_id)) => {
+            collector::validate_host_id(host_id)?;
+            collector::PushOptions{
+                token: args.token_file.as_deref().map(collector::read_token).transpose()?,
+                ca: args.collector_ca.as_ref().map(PathBuf::from),
+                identity: args.tls_cert.as_ref().zip(args.tls_key.as_ref()).map(|(c, k)| (PathBuf::from(c), PathBuf::from(k))),
+            }
+        }
+        _ => Default::default(),
+    };
+
+    let time = Instant::now();
+    let mut exit_code = ExitCode::SUCCESS;
+    let mut scan_opts = ScanOptions{
+        no_dirs: args.no_dirs,
+        max_depth: args.max_depth,
+        track_inode: args.track_inode,
+        timeout: args.timeout.map(Duration::from_secs),
+        file_timeout: args.file_timeout.map(Duration::from_secs),
+        hash_command: args.hash_command.as_deref().map(Into::into),
+        prefilter: args.prefilter,
+        verify_matches: args.verify_matches,
+        hash_link_content: args.hash_link_content,
+        hash_jobs: args.hash_jobs.map(|n| n as usize),
+        walk_jobs: args.walk_jobs.map(|n| n as usize),
+        whiteouts: args.overlay_aware.then(|| args.whiteout_format.parse()).transpose().map_err(IntegrityWatcherError::InvalidArgument)?,
+        detect_type: args.detect_type,
+        inode_flags: args.flags,
+        owner: args.owner,
+        stat_only: args.stat_only,
+        stripe_size: args.parallel_file_hash.then(|| u64::from(args.stripe_size) << 20),
+        policy: match &args.policy{
+            Some(file) => Some(Arc::new(fs::read_to_string(file).await
+                .map_err(|e| IntegrityWatcherError::IOError { source: e, path: file.to_owned() })?
+                .parse()?)),
+            None => None,
+        },
+        path_fold: args.path_fold.as_deref().map(str::parse).transpose()?.unwrap_or_default(),
+        symlink_roots: match args.flag_external_targets || args.symlink_policy == "within-roots"{
+            true => Some(symlink_policy_roots(&args.path).await),
+            false => None,
+        },
+        ..Default::default()
+    };
+    let circl_ttl = circl::CacheTtl{
+        found: types::parse_duration(&args.circl_found_ttl, "--circl-found-ttl")?,
+        not_found: types::parse_duration(&args.circl_notfound_ttl, "--circl-notfound-ttl")?,
+    };
+    let removal_check = match args.removal_check.as_str(){
+        "merge-join" => RemovalCheck::MergeJoin,
+        _ => RemovalCheck::Hash,
+    };
+    let removed_threshold = unvisited::Threshold{ entries: args.removed_threshold, percent: args.removed_threshold_percent };
+    let check_opts = CheckOptions{
+        compare_time: args.compare_time.as_deref().map(str::parse).transpose()?.unwrap_or_default(),
+        mtime_tolerance: args.mtime_tolerance,
+        fail_fast: args.fail_fast,
+        max_findings: args.max_findings,
+        perms_only: args.perms_only,
+        group_threshold: args.group_by_dir.then_some(args.group_threshold),
+        track_inode: args.track_inode,
+        strict_new: (args.strict_new || !args.strict_new_under.is_empty()).then(|| args.strict_new_under.clone()),
+        case_insensitive_compare: args.case_insensitive_compare,
+        perms_on_binaries_only: args.compare_only_changed_perms_on_binaries,
+        sorted_output: args.sorted_output.then_some(args.sorted_output_max),
+        metadata_only: args.no_hash,
+        max_size: args.check_max_size,
+        vfat_friendly: args.vfat_friendly,
+        owner_by_name: args.owner_by_name,
+    };
+
+    match args.format.as_str(){
+        "table" if !args.cmd.list => return Err(IntegrityWatcherError::InvalidArgument("--format table needs --list".to_owned())),
+        "ndjson" if !args.cmd.check => return Err(IntegrityWatcherError::InvalidArgument("--format ndjson needs --check".to_owned())),
+        _ => {}
+    }
+    if args.cmd.create && args.verify_matches && !args.prefilter{
+        return Err(IntegrityWatcherError::InvalidArgument("--verify-matches needs --prefilter".to_owned()));
+    }
+    if args.cmd.create{
+        if let Some(prompt) = overwrite_prompt(&args, table, io::stdin().is_terminal())?
+        && !confirm(&prompt, io::stdin().lock(), io::stderr()).map_err(|e| IntegrityWatcherError::IOError { source: e, path: "stdin".to_owned() })?{
+            return Err(IntegrityWatcherError::InvalidArgument(format!("not replacing {}", EscapedPath(&args.db))));
+        }
+        info!("Creating db {} table {}", EscapedPath(&args.db), EscapedPath(table));
+        warn_prefilter(&scan_opts);
+        let build = async |db: &Database| {
+            DBSettings{
+                no_dirs: scan_opts.no_dirs,
+                track_inode: scan_opts.track_inode,
+                hash_command: args.hash_command.clone(),
+                prefilter: scan_opts.prefilter,
+                hash_link_content: scan_opts.hash_link_content,
+                path_fold: scan_opts.path_fold,
+                detect_type: scan_opts.detect_type,
+                stat_only: scan_opts.stat_only,
+                stripe_size: scan_opts.stripe_size,
+                policy: scan_opts.policy.as_ref().map(|p| p.to_string()),
+                flags: scan_opts.inode_flags,
+                max_depth: scan_opts.max_depth,
+                owner: scan_opts.owner,
+            }.store(db, table)?;
+            if let Some(host) = &args.host_prefix{
+                dbmeta::store_host(db, table, host)?;
+            }
+            let mut writer = WriteToDB::new(db).with_table(table).with_path_fold(scan_opts.path_fold);
+            if let Some(entries) = args.txn_batch{
+                writer = writer.with_txn_batch(entries.try_into().unwrap_or(usize::MAX));
+            }
+            if args.single_txn{
+                writer = writer.single_transaction();
+            }
+            with_scan_timeout(scan_opts.timeout, "create", async {
+                for path in args.path.iter(){
+                    visit_dirs(PathBuf::from(path), &exclude, &scan_opts, &mut writer).await?;
+                }
+                Ok(())
+            }).await?;
+            let outcome = writer.finish()?;
+            Ok((outcome.added, outcome.bytes))
+        };
+        // a named table or host joins the baselines already in the DB, as does
+        // a --monitor baseline created in a DB without it
+        let (counter, bytes) = if (args.table.is_some() || args.host_prefix.is_some() || args.cmd.monitor) && fs::try_exists(&args.db).await.unwrap_or(false){
+            add_table_atomic(&args.db, &tmp_db, &[table], args.overwrite, build).await?
+        }
+        else{
+            create_db_atomic(&args.db, &tmp_db, args.overwrite, build).await?
+        };
+        let elapsed = time.elapsed();
+        info!("Added {} files total {} in {:.3}s {}", counter, bytes, elapsed.as_secs_f32(), bytes.bandwidth(elapsed));
+        let db = dbfile::open(&args.db)?;
+        record_run(&db, table, &args, elapsed, counter, bytes, 0)?;
+        dbmeta::store_version(&db, table, versions::CURRENT)?;
+        let exported = args.stdout.then(|| export::export(&db, table, io::stdout().lock(), "stdout", compression(&args, "-")));
+        db.close()?;
+        if let Some(exported) = exported{
+            if let Err(e) = std::fs::remove_file(&args.db){
+                warn!("Can't remove {}: {e}", EscapedPath(&args.db));
+            }
+            info!("Wrote {} entries to stdout", exported?);
+        }
+        if args.show_excluded{
+            show_excluded(&exclude, &args);
+        }
+        else if args.warn_unmatched_excludes{
+            warn_unmatched_excludes(&exclude, &args.exclude);
+        }
+        if args.cmd.monitor{
+            info!("No baseline in db {} table {}, baseline established", EscapedPath(&args.db), EscapedPath(table));
+        }
+    }
+
+    if args.cmd.check{
+        let db = open_baseline(&args.db, table)?;
+        apply_db_settings(&db, table, &args.db, &mut scan_opts)?;
+        // --cron tells these caveats only along findings
+        if !args.cron{
+            warn_prefilter(&scan_opts);
+            warn_baseline_version(&db, table, &args)?;
+        }
+        if args.no_hash{
+            info!("Metadata-only check, file contents are not read or compared");
+            scan_opts = ScanOptions{ stat_only: true, detect_type: false, ..scan_opts };
+        }
+        if let Some(since) = &args.since{
+            let cutoff = types::parse_since(since, chrono::Utc::now().timestamp())?;
+            scan_opts.since = Some(Arc::new(SinceFilter::load(&db, table, cutoff)?));
+        }
+        scan_opts.max_hash_size = args.check_max_size;
+        let severity_policy = args.severity_policy.as_ref().map(|path| {
+            std::fs::read_to_string(path).map_err(|source| IntegrityWatcherError::IOError{ source, path: path.clone() })?.parse::<SeverityPolicy>()
+        }).transpose()?;
+        if args.entropy{
+            scan_opts.entropy = Some(Arc::new(entropy::Measured::default()));
+        }
+        // --interval checks again each period until SIGTERM, the DB stays open
+        let mut schedule = match &args.interval{
+            Some(every) => {
+                let every = types::parse_duration(every, "--interval")?;
+                if every.is_zero(){
+                    return Err(IntegrityWatcherError::InvalidArgument("--interval 0".to_owned()));
+                }
+                let mut ticker = tokio::time::interval(every);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                ticker.tick().await;
+                info!("Checking every {}s until SIGTERM", every.as_secs());
+                Some((ticker, terminate_signal()?))
+            }
+            None => None,
+        };
+        // the socket outlives the cycles of --interval, agents listening stay connected
+        #[cfg(unix)]
+        let event_socket = args.event_socket.as_ref().map(|path| {
+            let mode = match args.event_socket_mode.as_str(){
+                "listen" => eventsocket::SocketMode::Listen,
+                _ => eventsocket::SocketMode::Connect,
+            };
+            let backpressure = match args.event_backpressure.as_str(){
+                "drop" => eventsocket::Backpressure::Drop,
+                _ => eventsocket::Backpressure::Block,
+            };
+            eventsocket::EventSocket::open(Path::new(path), mode, backpressure).map_err(|source| IntegrityWatcherError::IOError{ source, path: path.clone() })
+        }).transpose()?;
+        let mut started = time;
+        loop{
+            // a new sample each cycle unless --seed asks for the same one
+            let mut sample_seed = args.seed;
+            // an error ends a single check, --interval tries again at the next tick
+            let cycle: Result<bool, IntegrityWatcherError> = async {
+                exclude.reset_usage();
+                let mut writer = CheckDB::new(&db, CheckOptions{ track_inode: scan_opts.track_inode, ..check_opts.clone() })
+                    .with_table(table)
+                    .with_path_fold(scan_opts.path_fold)
+                    .with_annotations(annotations::load_all(&db, table)?)
+                    .with_volatile(annotations::load_volatile(&db, table)?)
+                    .with_removal_check(removal_check);
+                if let Some(measured) = &scan_opts.entropy{
+                    writer = writer.with_entropy(measured.clone());
+                }
+                if let Some(policy) = &severity_policy{
+                    writer = writer.with_severity_policy(policy.clone());
+                }
+                if let Some(dir) = &args.quarantine_dir{
+                    writer = writer.with_quarantine(quarantine::Quarantine::open(Path::new(dir), args.quarantine_max_size)?);
+                }
+                if let Some(ack_file) = &args.ack_file{
+                    writer = writer.with_acks(ack::load(ack_file, table)?);
+                }
+                if args.push.is_some() || acking{
+                    writer = writer.recording();
+                }
+                let ndjson = args.format == "ndjson";
+                #[cfg(unix)]
+                let socket = event_socket.as_ref().map(|s| s.sender().with_run_id(&run_id));
+                #[cfg(not(unix))]
+                let socket = None::<()>;
+                if ndjson || socket.is_some(){
+                    let mut events = ndjson.then(|| events::EventWriter::new(std::io::stdout()).with_run_id(&run_id));
+                    writer = writer.with_finding_sink(move |f| {
+                        #[cfg(unix)]
+                        if let Some(socket) = &socket{
+                            socket.send(&f);
+                        }
+                        match &mut events{
+                            Some(events) => if let Err(e) = events.write(&f){
+                                error!("Writing event of {}: {e}", EscapedPath(&f.path));
+                            },
+                            // the sink takes the place of logging
+                            None => log_finding(&f),
+                        }
+                    });
+                }
+                let now = chrono::Utc::now().timestamp().try_into().unwrap_or_default();
+                let maintenance = dbmeta::Maintenance::load(&db, table)?;
+                if let Some(m) = maintenance.as_ref().filter(|m| m.is_active(now)){
+                    info!("Maintenance window until {}, findings under {} are expected", Timestamp::new(m.until), m.paths.join(", "));
+                    writer = writer.with_maintenance(m.paths.clone());
+                }
+                let tags = annotations::TagIndex::load(&db, table)?;
+                let in_scope = |path: &str| args.only_tag.as_ref().is_none_or(|tag| tags.has(path, tag));
+
+                let sample = match (&args.sample, args.sample_count){
+                    (Some(p), _) => Some(SampleSpec::parse_percent(p)?),
+                    (None, Some(c)) => Some(SampleSpec::Count(c)),
+                    (None, None) => None,
+                };
+                let removed_counter = if let Some(spec) = sample{
+                    let seed = *sample_seed.get_or_insert_with(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64);
+                    let mut candidates = Vec::new();
+                    let roots: Vec<_> = args.path.iter().map(|root| scan_opts.path_fold.fold(root).into_owned()).collect();
+                    {
+                        let read_txn = db.begin_read().map_err(Box::new)?;
+                        let table = read_txn.open_table(files_table(table))?;
+                        for k in table.iter()?{
+                            let k = k?.0.value();
+                            let p = Path::new(&k);
+                            if roots.iter().any(|root| p.starts_with(root)) && !exclude.is_excluded(&k) && in_scope(&k){
+                                candidates.push(k);
+                            }
+                        }
+                    }
+                    let total = candidates.len();
+                    let selected = sample::select(candidates, spec, seed, &args.sample_always_prefix);
+                    info!("Sampling {spec} of {total} entries with seed {seed}, always checking {:?}, {} selected", args.sample_always_prefix, selected.len());
+                    with_scan_timeout(scan_opts.timeout, "check", check_sampled(selected, &scan_opts, &mut writer)).await?
+                }
+                else{
+                    let roots = match &args.only_tag{
+                        Some(tag) => {
+                            let roots = tag_roots(&tags.scope(tag), &args.path);
+                            info!("Checking tag {tag} under {:?}", roots);
+                            roots
+                        }
+                        None => args.path.clone(),
+                    };
+                    with_scan_timeout(scan_opts.timeout, "check", async {
+                        for path in roots.iter(){
+                            visit_dirs(PathBuf::from(path), &exclude, &scan_opts, &mut writer).await?;
+                            if writer.should_stop(){
+                                break;
+                            }
+                        }
+                        Ok(())
+                    }).await?;
+
+                    if writer.should_stop(){
+                        0
+                    }
+                    else{
+                        let skip = |path: &str| {
+                            // roots aren't visited as entries themselves, only their content
+                            let walked_root = args.only_tag.is_some() && roots.iter().any(|r| r == path) && Path::new(path).exists();
+                            !in_scope(path) || walked_root
+                        };
+                        let folded: Vec<_> = roots.iter().map(|root| scan_opts.path_fold.fold(root).into_owned()).collect();
+                        let unvisited = writer.unvisited(&folded, skip)?;
+                        if unvisited.exceeds(removed_threshold) && !args.confirm_removed{
+                            warn!("{}", unvisited.warning("reports them as removed"));
+                            unvisited.unvisited
+                        }
+                        else{
+                            writer.report_removed(skip)?
+                        }
+                    }
+                };
+                let outcome = writer.finish()?;
+                writer.flush_findings();
+                #[cfg(unix)]
+                if let (Some(socket), Some(path)) = (&event_socket, &args.event_socket){
+                    let dropped = socket.take_dropped();
+                    if dropped > 0{
+                        warn!("{dropped} findings not written to --event-socket {}", EscapedPath(path));
+                    }
+                }
+                let expected = writer.get_acked_count() + writer.get_maintenance_count();
+                let clean = writer.get_new_files_count() + writer.get_changes_count() + removed_counter == expected && !writer.should_stop();
+                // caveats of a clean --cron run would still mail the output
+                let quiet = args.cron && clean;
+                if args.cron && !quiet{
+                    warn_prefilter(&scan_opts);
+                    warn_baseline_version(&db, table, &args)?;
+                }
+                // a stopped walk left rules untried
+                if args.show_excluded && !writer.should_stop(){
+                    show_excluded(&exclude, &args);
+                }
+                else if args.warn_unmatched_excludes && !writer.should_stop() && !quiet{
+                    warn_unmatched_excludes(&exclude, &args.exclude);
+                }
+                if writer.get_suppressed_count() > 0{
+                    warn!("... and {} more findings suppressed", writer.get_suppressed_count());
+                }
+                if writer.get_unverified_count() > 0 && !quiet{
+                    warn!("{} files matched by XXH3 only, the DB has no SHA-256 for them, --update --verify-matches stores it",
+                        writer.get_unverified_count()
+                    );
+                }
+                let elapsed = started.elapsed();
+                let bytes = outcome.bytes;
+                let mut summary = format!("{}Checked {} files total {} in {:.3}s {} new files {} modified {} removed {removed_counter}",
+                    if args.no_hash { "Metadata-only check: " } else { "" },
+                    outcome.checked,
+                    bytes,
+                    elapsed.as_secs_f32(),
+                    bytes.bandwidth(elapsed),
+                    writer.get_new_files_count(),
+                    writer.get_changes_count()
+                );
+                if writer.get_content_skipped_count() > 0{
+                    summary += &format!(", content not verified of {} files over --check-max-size", writer.get_content_skipped_count());
+                }
+                record_run(&db, table, &args, elapsed, outcome.checked, bytes, writer.get_new_files_count() + writer.get_changes_count() + removed_counter)?;
+                if args.cron && !clean{
+                    warn!("{summary}");
+                    if severity_policy.is_none(){
+                        exit_code = ExitCode::from(EXIT_CHANGES);
+                    }
+                }
+                else{
+                    info!("{summary}");
+                }
+                // the policy tells the result, a critical cycle of --interval stays critical
+                if severity_policy.is_some(){
+                    let code = writer.get_worst_level().map_or(0, |level| Severity::of_level(level).exit_code());
+                    if code == EXIT_CHANGES || (code > 0 && exit_code == ExitCode::SUCCESS){
+                        exit_code = ExitCode::from(code);
+                    }
+                }
+                if writer.get_acked_count() > 0{
+                    info!("{} findings acknowledged earlier, --show-acked lists them", writer.get_acked_count());
+                }
+                if writer.get_time_only_count() > 0{
+                    info!("{} entries changed only their modified time, --compare-time reports them", writer.get_time_only_count());
+                }
+                if let Some(quarantine) = writer.get_quarantine(){
+                    if quarantine.kept() > 0{
+                        info!("{} changed files quarantined in {}", quarantine.kept(), EscapedPath(&quarantine.dir().to_string_lossy()));
+                    }
+                    if quarantine.failed() > 0{
+                        warn!("{} changed files could not be quarantined", quarantine.failed());
+                    }
+                }
+                if let (true, Some(level)) = (args.notify, writer.get_worst_level()){
+                    let summary = notify::CheckSummary{
+                        changed: writer.get_changes_count(),
+                        new: writer.get_new_files_count(),
+                        removed: removed_counter,
+                        level,
+                        roots: args.path.clone(),
+                        db: args.db.clone(),
+                    };
+                    tokio::task::spawn_blocking(move || notify::send(&summary)).await?;
+                }
+                match &maintenance{
+                    Some(m) if m.is_active(now) && writer.get_maintenance_count() > 0 => {
+                        info!("{} findings expected in the maintenance window", writer.get_maintenance_count());
+                        dbmeta::Maintenance{ suppressed: m.suppressed + writer.get_maintenance_count(), ..m.clone() }.store(&db, table)?;
+                    }
+                    Some(m) if !m.is_active(now) && m.suppressed > 0 => {
+                        warn!("Maintenance window ended {}, {} findings were expected during it, run --update to take them into the baseline",
+                            Timestamp::new(m.until),
+                            m.suppressed
+                        );
+                    }
+                    _ => {}
+                }
+                if let Some(spec) = sample && !quiet{
+                    warn!("Sampled check: {spec} of DB entries with seed {}, only sampled entries were verified, removed files pass skipped",
+                        sample_seed.unwrap_or_default()
+                    );
+                }
+                if let Some(since) = &scan_opts.since && !quiet{
+                    warn!("Skipped hashing {} files unchanged since {}, their content was not verified",
+                        since.get_skipped(),
+                        Timestamp::new(since.cutoff)
+                    );
+                }
+                if writer.get_strict_new_count() > 0{
+                    error!("{} new files where --strict-new allows none", writer.get_strict_new_count());
+                    exit_code = ExitCode::from(EXIT_CHANGES);
+                }
+                if writer.should_stop(){
+                    error!("Stopped at first finding (--fail-fast), check incomplete");
+                    exit_code = ExitCode::from(EXIT_CHANGES);
+                }
+                // buffered anyway, pushed reports are sorted whatever --sorted-output
+                let mut recorded = writer.take_recorded();
+                recorded.sort_by(|a, b| a.path.cmp(&b.path));
+                if let Some(ack_file) = args.ack_file.as_ref().filter(|_| acking){
+                    let acked: Vec<_> = recorded.iter()
+                        .filter(|f| args.cmd.ack_all || args.cmd.ack.iter().any(|p| Path::new(&f.path).starts_with(p)))
+                        .cloned()
+                        .collect();
+                    ack::record(ack_file, table, &acked, chrono::Utc::now().timestamp().try_into().unwrap_or_default())?;
+                    info!("Acknowledged {} findings in {}", acked.len(), EscapedPath(ack_file));
+                }
+                if let (Some(url), Some(host_id)) = (&args.push, &args.host_id){
+                    let report = collector::Report{
+                        files: outcome.checked,
+                        changed: writer.get_changes_count(),
+                        new: writer.get_new_files_count(),
+                        removed: removed_counter,
+                        findings: recorded.into_iter().map(Into::into).collect(),
+                        run_id: run_id.clone(),
+                        ..collector::Report::new(host_id, if args.no_hash { "metadata-only check" } else { "check" })
+                    };
+                    collector::push(url, &report, &push_opts).await?;
+                    info!("Pushed report of {host_id} to {url}");
+                }
+                Ok(writer.should_stop())
+            }.await;
+            let stopped = match cycle{
+                Ok(stopped) => stopped,
+                Err(e) if schedule.is_some() => {
+                    error!("Check failed, trying again at the next --interval tick: {e}");
+                    if exit_code == ExitCode::SUCCESS{
+                        exit_code = ExitCode::FAILURE;
+                    }
+                    false
+                }
+                Err(e) => return Err(e),
+            };
+            let Some((ticker, terminate)) = &mut schedule else {
+                break;
+            };
+            if stopped{
+                break;
+            }
+            tokio::select!{
+                _ = ticker.tick() => {}
+                _ = terminate.recv() => {
+                    info!("Terminated, stopping the --interval checks");
+                    break;
+                }
+            }
+            started = Instant::now();
+        }
+        #[cfg(unix)]
+        if let (Some(socket), Some(path)) = (event_socket, &args.event_socket){
+            let dropped = socket.finish();
+            if dropped > 0{
+                warn!("{dropped} findings not written to --event-socket {}", EscapedPath(path));
+            }
+        }
+    }
+
+    if let Some(archive_path) = &args.cmd.check_archive{
+        let db = open_baseline(&args.db, table)?;
+        apply_db_settings(&db, table, &args.db, &mut scan_opts)?;
+        warn_baseline_version(&db, table, &args)?;
+        let map: archive::PrefixMap = args.map_prefix.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+        info!("Checking archive {} as {} against db {} table {}", EscapedPath(archive_path), map.root().display(), EscapedPath(&args.db), EscapedPath(table));
+        let mut writer = CheckDB::new(&db, check_opts.clone())
+            .with_table(table)
+            .with_path_fold(scan_opts.path_fold)
+            .with_annotations(annotations::load_all(&db, table)?)
+            .with_volatile(annotations::load_volatile(&db, table)?);
+        if let Some(ack_file) = &args.ack_file{
+            writer = writer.with_acks(ack::load(ack_file, table)?);
+        }
+        // tar is read sequentially, there's nothing to run alongside
+        let unresolved = archive::check_archive(archive::open(archive_path)?, archive_path, &map, &exclude, &scan_opts, &mut writer)?;
+        let removed_counter = match writer.should_stop(){
+            true => 0,
+            false => writer.report_removed(|path| !Path::new(path).starts_with(map.root()))?,
+        };
+        let outcome = writer.finish()?;
+        writer.flush_findings();
+        if unresolved > 0{
+            info!("{unresolved} symlinks resolve outside the archive, their targets were not compared");
+        }
+        let elapsed = time.elapsed();
+        let bytes = outcome.bytes;
+        info!("Checked {} files total {} in {:.3}s {} new files {} modified {} removed {removed_counter}",
+            outcome.checked,
+            bytes,
+            elapsed.as_secs_f32(),
+            bytes.bandwidth(elapsed),
+            writer.get_new_files_count(),
+            writer.get_changes_count()
+        );
+        record_run(&db, table, &args, elapsed, outcome.checked, bytes, writer.get_new_files_count() + writer.get_changes_count() + removed_counter)?;
+        if writer.should_stop(){
+            error!("Stopped at first finding (--fail-fast), check incomplete");
+            exit_code = ExitCode::from(EXIT_CHANGES);
+        }
+    }
+
+    if args.cmd.update{
+        let db = open_baseline(&args.db, table)?;
+        apply_db_settings(&db, table, &args.db, &mut scan_opts)?;
+        warn_prefilter(&scan_opts);
+        let mut writer = UpdateDB::new(&db).with_table(table).with_path_fold(scan_opts.path_fold).with_removal_check(removal_check);
+        if args.case_insensitive_compare{
+            writer = writer.with_case_insensitive_compare();
+        }
+
+        // on timeout entries found so far stay committed, but nothing is removed
+        with_scan_timeout(scan_opts.timeout, "update", async {
+            for path in args.path.iter(){
+                visit_dirs(PathBuf::from(path), &exclude, &scan_opts, &mut writer).await?;
+            }
+            Ok(())
+        }).await?;
+
+        let outcome = writer.finish()?;
+        // entries outside the walked roots aren't this update's to remove
+        let roots: Vec<_> = args.path.iter().map(|root| scan_opts.path_fold.fold(root).into_owned()).collect();
+        let outside_roots = |path: &str| !roots.iter().any(|root| Path::new(path).starts_with(root));
+        // roots aren't visited as entries themselves, only their content
+        let keep = |path: &str| outside_roots(path) || (roots.iter().any(|root| root == path) && Path::new(path).exists());
+        // entries the walk didn't come near all go through keep
+        let outside = std::cell::Cell::new(0u64);
+        let unvisited = writer.unvisited(&roots, |path| {
+            outside.set(outside.get() + u64::from(outside_roots(path)));
+            keep(path)
+        })?;
+        let limit = unvisited::Threshold{ entries: args.max_removals.unwrap_or(u64::MAX), percent: args.max_removals_percent };
+        if unvisited.exceeds_any(limit) && !args.confirm_removed{
+            return Err(IntegrityWatcherError::MassRemoval{ db: args.db.clone(), removed: unvisited.unvisited, entries: unvisited.entries, prefixes: unvisited.prefix_list() });
+        }
+        info!("{} of {} DB entries not found under --path, removing them", unvisited.unvisited, unvisited.entries);
+        writer.remove_missing(keep)?;
+        if args.show_excluded{
+            show_excluded(&exclude, &args);
+        }
+        else if args.warn_unmatched_excludes{
+            warn_unmatched_excludes(&exclude, &args.exclude);
+        }
+        let elapsed = time.elapsed();
+        let bytes = outcome.bytes;
+        info!("Updated {} files total {} in {:.3}s {}", outcome.checked, bytes, elapsed.as_secs_f32(), bytes.bandwidth(elapsed));
+        let files = outcome.checked;
+        let delta = writer.into_delta();
+        let previous = versions::baseline_version(&db, table)?;
+        record_run(&db, table, &args, elapsed, files, bytes, delta.count())?;
+        // entries of other roots are still as the recorded version stored them
+        match (outside.get(), previous){
+            (0, _) => dbmeta::store_version(&db, table, versions::CURRENT)?,
+            (n, previous) => {
+                debug!("{n} entries outside --path not updated, the baseline version stays as recorded");
+                // a baseline without one would take it from this run from now on
+                if let Some(previous) = previous && dbmeta::load_version(&db, table)?.is_none(){
+                    dbmeta::store_version(&db, table, &previous)?;
+                }
+            }
+        }
+        if delta.is_empty(){
+            info!("Update delta: no changes");
+        }
+        else{
+            for line in delta.report(){
+                info!("{line}");
+            }
+        }
+        if let (Some(url), Some(host_id)) = (&args.push, &args.host_id){
+            let report = collector::Report{ run_id: run_id.clone(), ..collector::Report::from_delta(host_id, files, &delta) };
+            collector::push(url, &report, &push_opts).await?;
+            info!("Pushed report of {host_id} to {url}");
+        }
+        // the changes expected so far are in the baseline now
+        if let Some(m) = dbmeta::Maintenance::load(&db, table)?{
+            match m.is_active(chrono::Utc::now().timestamp().try_into().unwrap_or_default()){
+                true => dbmeta::Maintenance{ suppressed: 0, ..m }.store(&db, table)?,
+                false => dbmeta::Maintenance::clear(&db, table)?,
+            }
+        }
+        db.close()?;
+    }
+
+    if let (true, Some(host2)) = (args.cmd.compare, &args.host2){
+        let host = args.host.as_deref().unwrap_or_default();
+        let table2 = host_table_name(base_table, host2);
+        let db = open_baseline(&args.db, table)?;
+        let db2 = match &args.db2{
+            Some(dbname) => Some(open_baseline(dbname, &table2)?),
+            None => {
+                ensure_table(&db, &args.db, &table2)?;
+                None
+            }
+        };
+        ensure_comparable(&DBSettings::load(&db, table)?, &DBSettings::load(db2.as_ref().unwrap_or(&db), &table2)?, args.db2.as_ref().unwrap_or(&args.db))?;
+        let findings = diff_tables(&db, table, db2.as_ref().unwrap_or(&db), &table2, check_opts.clone())?;
+        for f in &findings{
+            match f.kind{
+                FindingKind::Changed => error!("{} differs between {host} and {host2}: {}", EscapedPath(&f.path), f.message),
+                FindingKind::New => warn!("{} only on {host2}", EscapedPath(&f.path)),
+                FindingKind::Removed => warn!("{} only on {host}", EscapedPath(&f.path)),
+            }
+        }
+        info!("{} paths differ between {host} and {host2} in {:.3}s", findings.len(), time.elapsed().as_secs_f32());
+    }
+
+    if args.cmd.compare && args.host2.is_none(){
+        let db2 = if let Some(dbname) = &args.db2{
+            open_baseline(dbname, table)?
+        }
+        else{
+            error!("Compare need db2 parameter");
+            return Err(IntegrityWatcherError::IOError { source: io::Error::new(io::ErrorKind::InvalidData, "".to_owned()), path: "".to_owned()});
+        };
+
+        let db = open_baseline(&args.db, table)?;
+        ensure_comparable(&DBSettings::load(&db, table)?, &DBSettings::load(&db2, table)?, args.db2.as_deref().unwrap_or_default())?;
+
+        let mut orig_files = Vec::new();
+
+        let read_txn2 = db2.begin_read().map_err(Box::new)?;
+        let table2 = read_txn2.open_table(files_table(table))?;
+        let iter2 = table2.iter()?;
+
+        for k in iter2{
+            let k = k?;
+            orig_files.push((k.0.value(), k.1.value()));
+        }
+
+        let mut writer = CheckDB::new(&db, check_opts.clone())
+            .with_table(table)
+            .with_annotations(annotations::load_all(&db, table)?)
+            .with_volatile(annotations::load_volatile(&db, table)?);
+        writer.add_file_info(&orig_files)?;
+        writer.flush_findings();
+
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(files_table(table))?;
+        let iter = table.iter()?;
+
+        for k in iter{
+            let k = k?;
+            if !writer.is_checked(&k.0.value()){
+                warn!("File removed {} {}", EscapedPath(&k.0.value()), k.1.value())
+            }
+        }
+        info!("Checked {} files in {}", writer.finish()?.checked, time.elapsed().as_secs_f32());
+    }
+
+    if let (true, Some(db2_path)) = (args.cmd.db_equal, &args.db2){
+        let db = open_baseline(&args.db, table)?;
+        let db2 = open_baseline(db2_path, table)?;
+        ensure_comparable(&DBSettings::load(&db, table)?, &DBSettings::load(&db2, table)?, db2_path)?;
+        let findings = diff_tables(&db, table, &db2, table, check_opts.clone())?;
+        let (a, b) = (EscapedPath(&args.db), EscapedPath(db2_path));
+        for f in &findings{
+            match f.kind{
+                FindingKind::Changed => error!("{} differs between {a} and {b}: {}", EscapedPath(&f.path), f.message),
+                FindingKind::New => warn!("{} only in {b}", EscapedPath(&f.path)),
+                FindingKind::Removed => warn!("{} only in {a}", EscapedPath(&f.path)),
+            }
+        }
+        if findings.is_empty(){
+            info!("Databases {a} and {b} are identical");
+        }
+        else{
+            info!("{} paths differ between {a} and {b}", findings.len());
+            exit_code = ExitCode::from(EXIT_CHANGES);
+        }
+    }
+
+    if args.cmd.list{
+        let db = open_baseline(&args.db, table)?;
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let annotated = if args.show_annotations { annotations::load_all(&db, table)? } else { Default::default() };
+        let volatile = args.volatile.then(|| annotations::load_volatile(&db, table)).transpose()?;
+        let listed = |path: &String| volatile.as_ref().is_none_or(|v| v.contains(path));
+        let table = read_txn.open_table(files_table(table))?;
+
+        let as_table = args.format == "table" && match table.len()?{
+            len if len > listing::TABLE_MAX_ROWS => {
+                warn!("{len} entries are too many to align in memory, listing them as log lines");
+                false
+            }
+            _ => true,
+        };
+        if as_table{
+            let mut rows = table.iter()?.map(|k| k.map(|k| (k.0.value(), k.1.value()))).collect::<Result<Vec<_>, _>>()?;
+            rows.retain(|(path, _)| listed(path));
+            println!("{}", listing::entries_table(rows, &annotated));
+        }
+        else{
+            let iter = table.iter()?;
+
+            for k in  iter{
+                let k = k?;
+                if !listed(&k.0.value()){
+                    continue;
+                }
+                match annotated.get(&k.0.value()){
+                    Some(a) => info!("File: {}: {} [{a}]", EscapedPath(&k.0.value()), k.1.value()),
+                    None => info!("File: {}: {}", EscapedPath(&k.0.value()), k.1.value()),
+                }
+            }
+        }
+    }
+
+    if args.cmd.show_acked && let Some(ack_file) = &args.ack_file{
+        for (path, ack) in ack::load(ack_file, table)?{
+            info!("{}: {ack}", EscapedPath(&path));
+        }
+    }
+
+    if !args.cmd.tag_prefix.is_empty(){
+        let db = open_baseline(&args.db, table)?;
+        let rules = args.cmd.tag_prefix.iter().map(|r| annotations::parse_tag_prefix(r)).collect::<Result<Vec<_>, _>>()?;
+        annotations::add_tag_prefixes(&db, table, &rules)?;
+        for (prefix, tag) in &rules{
+            info!("Entries under {} tagged {tag}", EscapedPath(prefix));
+        }
+        db.close()?;
+    }
+
+    if args.cmd.stats{
+        let db = open_baseline(&args.db, table)?;
+        let tags = annotations::TagIndex::load(&db, table)?;
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let entries = read_txn.open_table(files_table(table))?;
+        let (mut files, mut dirs, mut symlinks, mut whiteouts, mut opaque, mut untagged) = (0u64, 0u64, 0u64, 0u64, 0u64, 0u64);
+        let mut per_tag: BTreeMap<String, u64> = BTreeMap::new();
+        for k in entries.iter()?{
+            let k = k?;
+            match k.1.value(){
+                FileMetadataExt::File(_) => files += 1,
+                FileMetadataExt::Dir(_) => dirs += 1,
+                FileMetadataExt::Symlink(_) => symlinks += 1,
+                FileMetadataExt::Whiteout => whiteouts += 1,
+                FileMetadataExt::Opaque => opaque += 1,
+            }
+            let entry_tags = tags.tags(&k.0.value());
+            if entry_tags.is_empty(){
+                untagged += 1;
+            }
+            for tag in entry_tags{
+                *per_tag.entry(tag.to_owned()).or_default() += 1;
+            }
+        }
+        info!("Entries {} files {files} directories {dirs} symlinks {symlinks}", files + dirs + symlinks + whiteouts + opaque);
+        if whiteouts > 0{
+            info!("Whiteouts: {whiteouts} entries deleted by a layer");
+        }
+        if opaque > 0{
+            info!("Opaque: {opaque} directories a layer hides the lower content of");
+        }
+        for (tag, count) in &per_tag{
+            info!("Tag {tag}: {count} entries");
+        }
+        info!("Untagged: {untagged} entries");
+        for run in runs::load(&db, table, 10)?{
+            info!("Run {run}");
+        }
+    }
+
+    if let Some(path) = args.cmd.annotate.as_ref().or(args.cmd.label.as_ref()){
+        let db = open_baseline(&args.db, table)?;
+        let path = &entry_key(&db, table, &args.db, path)?;
+        let a = annotations::annotate(&db, table, path, args.note.as_deref(), args.tag.as_deref())?;
+        info!("Annotations of {}: {a}", EscapedPath(path));
+        db.close()?;
+    }
+
+    if let Some(path) = &args.cmd.mark_volatile{
+        let db = open_baseline(&args.db, table)?;
+        let path = &entry_key(&db, table, &args.db, path)?;
+        match annotations::mark_volatile(&db, table, path, true)?{
+            true => info!("Marked {} volatile, changes of its content, size and times are only logged at debug level", EscapedPath(path)),
+            false => info!("{} already marked volatile", EscapedPath(path)),
+        }
+        db.close()?;
+    }
+
+    if let Some(path) = &args.cmd.unmark_volatile{
+        let db = open_baseline(&args.db, table)?;
+        let path = &DBSettings::load(&db, table)?.path_fold.fold(path).into_owned();
+        match annotations::mark_volatile(&db, table, path, false)?{
+            true => info!("{} no longer volatile", EscapedPath(path)),
+            false => info!("{} wasn't marked volatile", EscapedPath(path)),
+        }
+        db.close()?;
+    }
+
+    if let Some(addr) = &args.cmd.serve{
+        if secret.is_some() || dbfile::is_encrypted(&args.db){
+            return Err(IntegrityWatcherError::InvalidArgument("--serve keeps its DB open until killed, it can't be encrypted".to_owned()));
+        }
+        let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) else {
+            return Err(IntegrityWatcherError::InvalidArgument("--serve needs --tls-cert and --tls-key".to_owned()));
+        };
+        let tokens = args.token_file.as_deref().map(collector::read_host_tokens).transpose()?;
+        if tokens.is_none() && args.client_ca.is_none(){
+            return Err(IntegrityWatcherError::InvalidArgument("--serve needs --token-file or --client-ca to authenticate agents".to_owned()));
+        }
+        let tls = collector::tls_acceptor(Path::new(cert), Path::new(key), args.client_ca.as_deref().map(Path::new))?;
+        let db = dbfile::create(&args.db)?;
+        let listener = TcpListener::bind(addr).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: addr.clone() })?;
+        info!("Collecting reports on {addr} into {}", EscapedPath(&args.db));
+        collector::serve(listener, Some(tls), Arc::new(collector::Collector::new(db, tokens))).await?;
+    }
+
+    if let Some(src_path) = &args.cmd.merge{
+        let src = dbfile::open(src_path)?;
+        let sources = merge_sources(&src, base_table, args.host_prefix.as_deref())?;
+        if sources.is_empty(){
+            return Err(IntegrityWatcherError::InvalidArgument(format!("{} has no host name, give one with --host-prefix", EscapedPath(src_path))));
+        }
+        let tables: Vec<_> = sources.iter().map(|(_, host)| host_table_name(base_table, host)).collect();
+        let build = async |db: &Database| {
+            for ((src_table, host), table) in sources.iter().zip(&tables){
+                let copied = merge_table(&src, src_table, db, table, host)?;
+                info!("Merged {copied} entries of {host} from {} into {}", EscapedPath(src_path), EscapedPath(&args.db));
+            }
+            Ok(())
+        };
+        if fs::try_exists(&args.db).await.unwrap_or(false){
+            let names: Vec<_> = tables.iter().map(String::as_str).collect();
+            add_table_atomic(&args.db, &tmp_db, &names, args.overwrite, build).await?;
+        }
+        else{
+            create_db_atomic(&args.db, &tmp_db, args.overwrite, build).await?;
+        }
+    }
+
+    if let Some(path) = &args.cmd.export{
+        let db = open_baseline(&args.db, table)?;
+        let compression = compression(&args, path);
+        let exported = match path.as_str(){
+            "-" => export::export(&db, table, io::stdout().lock(), "stdout", compression)?,
+            _ => {
+                let out = std::fs::File::create(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.clone() })?;
+                export::export(&db, table, out, path, compression)?
+            }
+        };
+        info!("Exported {exported} entries of table {} to {}", EscapedPath(table), EscapedPath(path));
+    }
+
+    if let Some(path) = &args.cmd.import{
+        let input: Box<dyn io::Read> = match path.as_str(){
+            "-" => Box::new(io::stdin().lock()),
+            _ => Box::new(std::fs::File::open(path).map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.clone() })?),
+        };
+        let name = if path == "-" { "stdin" } else { path.as_str() };
+        let build = async |db: &Database| export::import(input, name, db, table);
+        let imported = if fs::try_exists(&args.db).await.unwrap_or(false){
+            add_table_atomic(&args.db, &tmp_db, &[table], args.overwrite, build).await?
+        }
+        else{
+            create_db_atomic(&args.db, &tmp_db, args.overwrite, build).await?
+        };
+        info!("Imported {imported} entries from {} into {} table {}", EscapedPath(path), EscapedPath(&args.db), EscapedPath(table));
+    }
+
+    if args.cmd.list_hosts{
+        let db = dbfile::open(&args.db)?;
+        for line in collector::host_lines(&collector::list_hosts(&db)?){
+            info!("{line}");
+        }
+    }
+
+    if let [a, b] = args.cmd.diff_host.as_slice(){
+        let db = dbfile::open(&args.db)?;
+        for line in collector::diff_reports(&collector::latest_report(&db, a)?, &collector::latest_report(&db, b)?){
+            info!("{line}");
+        }
+    }
+
+    if args.cmd.circl_check{
+        let db = open_baseline(&args.db, table)?;
+        let settings = DBSettings::load(&db, table)?;
+        if settings.hash_command.is_some() || settings.prefilter || settings.stat_only || settings.stripe_size.is_some(){
+            return Err(IntegrityWatcherError::IncompatibleDB {
+                db: args.db,
+                reason: format!("hashes made by {:?}, CIRCL needs SHA-256", settings.algorithm()),
+            });
+        }
+        // stat-only and head-hash entries have no hash of the whole file
+        let policy = settings.policy.as_deref().map(str::parse::<Policy>).transpose()?;
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(files_table(table))?;
+
+        let hashes = table.iter()?.filter_map(|k| {
+            match k{
+                Ok((name, meta)) => match meta.value(){
+                    FileMetadataExt::File(file_meta) if policy.as_ref().is_none_or(|p| p.mode(&name.value()) == HashMode::Full) => Some(Ok((name.value(), file_meta.hash))),
+                    _ => None,
+                },
+                Err(e) => Some(Err(e.into())),
+            }
+        });
+
+        let circl = circl::CirclQuery::new(&args.cache, circl_ttl)?;
+        circl_lookup(circl, "File", hashes).await?;
+    }
+
+    if args.cmd.circl_stdin{
+        let hashes = tokio::task::spawn_blocking(|| parse_hash_lines(io::stdin().lock())).await??;
+        info!("Read {} hashes from stdin", hashes.len());
+
+        let circl = circl::CirclQuery::new(&args.cache, circl_ttl)?;
+        circl_lookup(circl, "Line", hashes.into_iter().map(Ok)).await?;
+    }
+
+    if args.cmd.verify_against_package_manager{
+        let db = open_baseline(&args.db, table)?;
+        let info_dir = Path::new(&args.dpkg_info);
+        // package managers list real paths, keys may be folded by --path-fold
+        let path_fold = DBSettings::load(&db, table)?.path_fold;
+        let differing = if info_dir.is_dir(){
+            let packages = pkgverify::load_dpkg(info_dir, Path::new("/"))?;
+            info!("Loaded {} package files from {}", packages.len(), EscapedPath(&args.dpkg_info));
+            let opts = ScanOptions{ hash_command: Some("md5sum".into()), file_timeout: scan_opts.file_timeout, path_fold, ..Default::default() };
+            let (verified, differing) = with_scan_timeout(scan_opts.timeout, "package verify", verify_dpkg(&db, table, &packages, &opts)).await?;
+            info!("Verified {verified} DB files owned by packages, {} differ", differing.len());
+            differing
+        }
+        else{
+            info!("No {}, asking rpm", EscapedPath(&args.dpkg_info));
+            let mismatched = tokio::task::spawn_blocking(pkgverify::rpm_verify_all).await??;
+            let read_txn = db.begin_read().map_err(Box::new)?;
+            let table = read_txn.open_table(files_table(table))?;
+            let mut differing = Vec::new();
+            for path in mismatched{
+                if table.get(&path_fold.fold(&path).into_owned())?.is_some(){
+                    differing.push((path, "differs from its rpm package".to_owned()));
+                }
+            }
+            differing
+        };
+        for (path, message) in &differing{
+            error!("File {} {message}", EscapedPath(path));
+        }
+        if !differing.is_empty(){
+            exit_code = ExitCode::from(EXIT_CHANGES);
+        }
+    }
+
+    if args.cmd.db_verify{
+        let db = open_baseline(&args.db, table)?;
+        let settings = DBSettings::load(&db, table)?;
+        let (checked, mismatched) = verify_hash_lengths(&db, table, &settings)?;
+        for (path, message) in &mismatched{
+            error!("Entry {} {message}, the DB is corrupt", EscapedPath(path));
+        }
+        info!("Verified the length of {checked} {} hashes, {} wrong", settings.algorithm(), mismatched.len());
+        if !mismatched.is_empty(){
+            exit_code = ExitCode::from(EXIT_CHANGES);
+        }
+    }
+
+    if args.cmd.rehash{
+        let db = open_baseline(&args.db, table)?;
+        let settings = DBSettings::load(&db, table)?;
+        let target = DBSettings{ hash_command: args.hash_command.clone(), prefilter: args.prefilter, stat_only: args.stat_only, stripe_size: scan_opts.stripe_size, ..settings.clone() };
+        if target == settings{
+            info!("Database {} already uses {}", EscapedPath(&args.db), settings.algorithm());
+            return Ok(exit_code);
+        }
+        info!("Rehashing {} from {} to {}", EscapedPath(&args.db), settings.algorithm(), target.algorithm());
+        let opts = ScanOptions{
+            track_inode: settings.track_inode,
+            file_timeout: scan_opts.file_timeout,
+            hash_command: target.hash_command.as_deref().map(Into::into),
+            prefilter: target.prefilter,
+            stat_only: target.stat_only,
+            stripe_size: target.stripe_size,
+            policy: target.policy.as_deref().map(str::parse).transpose()?.map(Arc::new),
+            path_fold: settings.path_fold,
+            ..Default::default()
+        };
+        let (migrated, failed) = with_scan_timeout(scan_opts.timeout, "rehash", rehash_entries(&db, table, &opts)).await?;
+        if !failed.is_empty(){
+            return Err(IntegrityWatcherError::IncompatibleDB {
+                db: args.db,
+                reason: format!("{} files changed since the baseline, --update them before --rehash, nothing was migrated", failed.len()),
+            });
+        }
+        apply_rehash(&db, table, &migrated, &target)?;
+        info!("Rehashed {} files in {:.3}s, database now uses {}", migrated.len(), time.elapsed().as_secs_f32(), target.algorithm());
+        db.close()?;
+    }
+
+    if let Some(until) = &args.cmd.maintenance_until{
+        let db = open_baseline(&args.db, table)?;
+        let until = types::parse_deadline(until, chrono::Utc::now().timestamp())?;
+        dbmeta::Maintenance{ until, paths: args.maintenance_paths.clone(), suppressed: 0 }.store(&db, table)?;
+        info!("Maintenance window until {}, findings under {} are expected", Timestamp::new(until), args.maintenance_paths.join(", "));
+        db.close()?;
+    }
+
+    if !args.cmd.prune.is_empty(){
+        let db = open_baseline(&args.db, table)?;
+        let pattern: ExcludeSet = args.cmd.prune.iter().collect();
+        let pruned = prune_entries(&db, table, &pattern, args.dry_run)?;
+        for path in &pruned{
+            info!("{} {}", if args.dry_run { "Would prune" } else { "Pruned" }, EscapedPath(path));
+        }
+        match args.dry_run{
+            true => info!("{} entries match, run without --dry-run to remove them", pruned.len()),
+            false => info!("Pruned {} entries from {} table {table}", pruned.len(), EscapedPath(&args.db)),
+        }
+        db.close()?;
+    }
+
+    if args.cmd.bench{
+        let duration = args.duration.as_deref().map(|d| types::parse_duration(d, "--duration")).transpose()?;
+        // sha256 runs first, so later passes may profit from a warm page cache
+        let mut algorithms = vec![("sha256".to_owned(), None)];
+        if let Some(cmd) = &args.hash_command{
+            algorithms.push((format!("command {cmd:?}"), Some(Arc::from(cmd.as_str()))));
+        }
+        let mut results = Vec::with_capacity(algorithms.len());
+        for (name, hash_command) in algorithms{
+            let stats = Arc::new(HashStats::default());
+            let opts = ScanOptions{
+                no_dirs: true,
+                file_timeout: scan_opts.file_timeout,
+                hash_command,
+                stats: Some(stats.clone()),
+                ..Default::default()
+            };
+            let mut sink = bench::BenchSink::new(duration);
+            let start = Instant::now();
+            for path in args.path.iter(){
+                visit_dirs(PathBuf::from(path), &exclude, &opts, &mut sink).await?;
+                if sink.should_stop(){
+                    break;
+                }
+            }
+            let result = bench::BenchResult::new(name, &sink, &stats, start.elapsed());
+            for line in result.report(){
+                info!("{line}");
+            }
+            results.push(result);
+        }
+        if let [base, others @ ..] = results.as_slice(){
+            for other in others{
+                info!("{} is {:.2}x the throughput of {}", other.algorithm, other.bytes_per_sec / base.bytes_per_sec.max(f64::EPSILON), base.algorithm);
+            }
+        }
+        println!("{}", serde_json::to_string_pretty(&results).expect("bench results serialize"));
+    }
+
+    Ok(exit_code)
+}
+
+/// `--json-errors`: the error ending the run as one JSON line.
+fn write_json_error(out: &mut impl std::io::Write, e: &IntegrityWatcherError) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *out, &ErrorReport::from(e))?;
+    out.write_all(b"\n")?;
+    out.flush()
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, IntegrityWatcherError> {
+    let args = Cli::parse();
+    let json_errors = args.json_errors;
+    match main_fun(args).await{
+        // returned errors are printed once more by the runtime
+        Err(e) if json_errors => {
+            let _ = write_json_error(&mut std::io::stderr().lock(), &e);
+            Ok(ExitCode::FAILURE)
+        }
+        Err(e) => {
+            error!("Error {}", e);
+            Err(e)
+        },
+        Ok(code) => {
+            Ok(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hash_lines() {
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let abc = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        let input = format!("{empty}\nnot a hash\n\n{abc}\r\n{}\n", &empty[1..]);
+        let hashes = parse_hash_lines(io::Cursor::new(input)).unwrap();
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes[0].0, "1");
+        assert_eq!(hashes[0].1.to_string(), empty);
+        assert_eq!(hashes[1].0, "4");
+        assert_eq!(hashes[1].1.to_string(), abc.to_lowercase());
+    }
+
+    #[tokio::test]
+    async fn test_monitor() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_monitor");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"first").unwrap();
+        let db = dir.join("monitor.redb").to_string_lossy().to_string();
+        let monitor = || Cli::try_parse_from(["integrity-checker", "--monitor", "--fail-fast", "--db", &db, "--path", &tree.to_string_lossy()]).unwrap();
+
+        assert_eq!(run(monitor()).await.unwrap(), ExitCode::SUCCESS);
+        assert!(open_baseline(&db, DEFAULT_TABLE).is_ok());
+        assert_eq!(run(monitor()).await.unwrap(), ExitCode::SUCCESS);
+
+        std::fs::write(tree.join("a"), b"second").unwrap();
+        assert_eq!(run(monitor()).await.unwrap(), ExitCode::from(EXIT_CHANGES));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_import_zst() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_export_zst");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+        std::fs::write(tree.join("a"), b"first").unwrap();
+        std::fs::write(tree.join("sub/b"), b"second").unwrap();
+        let (db, imported) = (dir.join("source.redb").to_string_lossy().to_string(), dir.join("imported.redb").to_string_lossy().to_string());
+        let dump = dir.join("baseline.json.zst").to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+
+        run(cli(&["--create", "--track-inode", "--db", &db, "--path", &tree.to_string_lossy()])).await.unwrap();
+        run(cli(&["--export", &dump, "--db", &db])).await.unwrap();
+        assert!(std::fs::read(&dump).unwrap().starts_with(&[0x28, 0xb5, 0x2f, 0xfd]));
+        run(cli(&["--import", &dump, "--db", &imported])).await.unwrap();
+        assert!(run(cli(&["--import", &dump, "--db", &imported])).await.is_err());
+
+        assert_eq!(run(cli(&["--db-equal", "--db", &db, "--db2", &imported])).await.unwrap(), ExitCode::SUCCESS);
+        let settings = DBSettings::load(&open_baseline(&imported, DEFAULT_TABLE).unwrap(), DEFAULT_TABLE).unwrap();
+        assert!(settings.track_inode);
+        assert_eq!(run(cli(&["--check", "--fail-fast", "--db", &imported, "--path", &tree.to_string_lossy()])).await.unwrap(), ExitCode::SUCCESS);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hash_algorithm_mismatch() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_algorithm_mismatch");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"content").unwrap();
+        let sha256 = dir.join("sha256.redb").to_string_lossy().to_string();
+        let xxh3 = dir.join("xxh3.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        run(cli(&["--create", "--db", &sha256, "--path", &path])).await.unwrap();
+        run(cli(&["--create", "--prefilter", "--db", &xxh3, "--path", &path])).await.unwrap();
+
+        // an error naming both algorithms, not every file reported changed
+        let mismatch = |result: Result<ExitCode, IntegrityWatcherError>| match result{
+            Err(IntegrityWatcherError::IncompatibleDB { reason, .. }) => reason,
+            other => panic!("expected a mismatch, got {other:?}"),
+        };
+        let reason = mismatch(run(cli(&["--compare", "--db", &sha256, "--db2", &xxh3])).await);
+        assert!(reason.contains("XXH3-64") && reason.contains("SHA-256"), "{reason}");
+        let reason = mismatch(run(cli(&["--check", "--hash-command", "b3sum {}", "--db", &sha256, "--path", &path])).await);
+        assert!(reason.contains("SHA-256") && reason.contains("b3sum"), "{reason}");
+        let reason = mismatch(run(cli(&["--update", "--prefilter", "--db", &sha256, "--path", &path])).await);
+        assert!(reason.contains("SHA-256"), "{reason}");
+
+        let copy = dir.join("copy.redb").to_string_lossy().to_string();
+        std::fs::copy(&xxh3, &copy).unwrap();
+        assert_eq!(run(cli(&["--compare", "--db", &xxh3, "--db2", &copy])).await.unwrap(), ExitCode::SUCCESS);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_db_equal() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_db_equal");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"content").unwrap();
+        std::fs::write(tree.join("b"), b"content").unwrap();
+        let first = dir.join("first.redb").to_string_lossy().to_string();
+        let second = dir.join("second.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        assert!(Cli::try_parse_from(["integrity-checker", "--db-equal", "--db", &first]).is_err());
+        run(cli(&["--create", "--db", &first, "--path", &path])).await.unwrap();
+        std::fs::copy(&first, &second).unwrap();
+        assert_eq!(run(cli(&["--db-equal", "--db", &first, "--db2", &second])).await.unwrap(), ExitCode::SUCCESS);
+
+        // one entry only in each DB and one differing, the files themselves aren't read
+        std::fs::write(tree.join("a"), b"changed").unwrap();
+        std::fs::remove_file(tree.join("b")).unwrap();
+        std::fs::write(tree.join("c"), b"content").unwrap();
+        run(cli(&["--create", "--overwrite", "--db", &second, "--path", &path])).await.unwrap();
+        std::fs::remove_dir_all(&tree).unwrap();
+        assert_eq!(run(cli(&["--db-equal", "--db", &first, "--db2", &second])).await.unwrap(), ExitCode::from(EXIT_CHANGES));
+        let db = open_baseline(&first, DEFAULT_TABLE).unwrap();
+        let db2 = open_baseline(&second, DEFAULT_TABLE).unwrap();
+        let opts = CheckOptions::default();
+        let mut findings: Vec<_> = diff_tables(&db, DEFAULT_TABLE, &db2, DEFAULT_TABLE, opts).unwrap().into_iter().map(|f| (f.path.rsplit('/').next().unwrap().to_owned(), f.kind)).collect();
+        findings.sort_by(|x, y| x.0.cmp(&y.0));
+        assert_eq!(findings, [("a".to_owned(), FindingKind::Changed), ("b".to_owned(), FindingKind::Removed), ("c".to_owned(), FindingKind::New)]);
+        drop((db, db2));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_hash() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_no_hash");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        for name in ["same_size", "grown"]{
+            std::fs::write(tree.join(name), b"content").unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("same_size", tree.join("link")).unwrap();
+        let db = dir.join("db.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        assert!(Cli::try_parse_from(["integrity-checker", "--create", "--no-hash", "--db", &db]).is_err());
+        run(cli(&["--create", "--no-dirs", "--detect-type", "--hash-link-content", "--db", &db, "--path", &path])).await.unwrap();
+        let check = async |extra: &[&str]| run(cli(&[&["--check", "--cron", "--db", &db, "--path", &path], extra].concat())).await.unwrap();
+        assert_eq!(check(&["--no-hash"]).await, ExitCode::SUCCESS);
+
+        // a content change keeping size and mtime is only found by a full check
+        let mtime = std::fs::metadata(tree.join("same_size")).unwrap().modified().unwrap();
+        let mut file = std::fs::File::options().write(true).open(tree.join("same_size")).unwrap();
+        std::io::Write::write_all(&mut file, b"CONTENT").unwrap();
+        file.set_modified(mtime).unwrap();
+        drop(file);
+        assert_eq!(check(&["--no-hash"]).await, ExitCode::SUCCESS);
+        assert_eq!(check(&[]).await, ExitCode::from(EXIT_CHANGES));
+        std::fs::write(tree.join("same_size"), b"content").unwrap();
+        std::fs::File::options().write(true).open(tree.join("same_size")).unwrap().set_modified(mtime).unwrap();
+        std::fs::write(tree.join("grown"), b"more content").unwrap();
+        assert_eq!(check(&["--no-hash"]).await, ExitCode::from(EXIT_CHANGES));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_removed_threshold() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_removed_threshold");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        for root in ["a", "b"]{
+            std::fs::create_dir_all(dir.join(root)).unwrap();
+            for i in 0..3{
+                std::fs::write(dir.join(root).join(i.to_string()), b"data").unwrap();
+            }
+        }
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+        let db_path = path("db.redb");
+        let cli = |cmd: &str, roots: &[&str], extra: &[&str]| {
+            let mut args = vec!["integrity-checker".to_owned(), cmd.to_owned(), "--db".to_owned(), db_path.clone(), "--removed-threshold".to_owned(), "2".to_owned()];
+            for root in roots{
+                args.extend(["--path".to_owned(), path(root)]);
+            }
+            args.extend(extra.iter().map(|e| e.to_string()));
+            Cli::try_parse_from(args).unwrap()
+        };
+        let entries = || {
+            let db = open_baseline(&db_path, DEFAULT_TABLE).unwrap();
+            let read_txn = db.begin_read().unwrap();
+            read_txn.open_table(files_table(DEFAULT_TABLE)).unwrap().len().unwrap()
+        };
+
+        assert_eq!(run(cli("--create", &["a", "b"], &[])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(entries(), 6);
+        // the 3 entries of the forgotten root are summed up, but still counted
+        assert_eq!(run(cli("--check", &["a"], &["--cron"])).await.unwrap(), ExitCode::from(EXIT_CHANGES));
+        // an update leaves entries outside its roots alone
+        assert_eq!(run(cli("--update", &["a"], &["--confirm-removed"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(entries(), 6);
+
+        // more than 10% of the DB gone under the roots
+        for i in 0..3{
+            std::fs::remove_file(dir.join("b").join(i.to_string())).unwrap();
+        }
+        match run(cli("--update", &["a", "b"], &[])).await{
+            Err(IntegrityWatcherError::MassRemoval{ removed: 3, entries: 6, prefixes, .. }) => assert!(prefixes.contains("b/0 (1)"), "{prefixes}"),
+            other => panic!("expected a mass removal, got {other:?}"),
+        }
+        assert_eq!(entries(), 6);
+        assert_eq!(run(cli("--update", &["a", "b"], &["--max-removals-percent", "50"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(entries(), 3);
+        std::fs::write(dir.join("b").join("0"), b"data").unwrap();
+        run(cli("--update", &["a", "b"], &[])).await.unwrap();
+        std::fs::remove_file(dir.join("b").join("0")).unwrap();
+        assert!(run(cli("--update", &["a", "b"], &["--max-removals", "0", "--max-removals-percent", "100"])).await.is_err());
+        assert_eq!(run(cli("--update", &["a", "b"], &["--force-removals"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(entries(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl LogBuffer {
+        /// Writer of a subscriber logging into the buffer.
+        fn make(&self) -> impl Fn() -> LogBuffer + Send + Sync + 'static {
+            let log = self.clone();
+            move || log.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_id() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_run_id");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"data").unwrap();
+        let db = dir.join("db.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        run(cli(&["--create", "--db", &db, "--path", &path])).await.unwrap();
+
+        let collector = Arc::new(collector::Collector::new(dbfile::create(dir.join("collector.redb")).unwrap(), None));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(collector::serve(listener, None, collector.clone()));
+        let log = LogBuffer::default();
+        let logged = {
+            let _subscriber = tracing::subscriber::set_default(jsonlog::subscriber(EnvFilter::new("info"), log.make()));
+            run(cli(&["--check", "--db", &db, "--path", &path, "--push", &url, "--host-id", "web01"])).await.unwrap();
+            String::from_utf8(log.0.lock().unwrap().clone()).unwrap()
+        };
+        server.abort();
+        drop(collector);
+
+        let messages: Vec<String> = logged.lines().map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["fields"]["message"].as_str().unwrap().to_owned()).collect();
+        let run_id = messages[0].strip_prefix("Run ").and_then(|m| m.split(':').next()).unwrap();
+        assert_eq!(run_id.len(), 36, "{}", messages[0]);
+        // the collector logs the id of the report it stored
+        let received = format!("Report from web01 run {run_id}: check 1 files");
+        assert!(messages.iter().any(|m| m.starts_with(&received)), "{messages:?}");
+        // and the run record of the check
+        let recorded = runs::load(&Database::open(&db).unwrap(), DEFAULT_TABLE, 1).unwrap();
+        assert_eq!(recorded[0].run_id, run_id);
+        // a new one each run
+        assert_ne!(runs::new_run_id(), run_id);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_interval() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_interval");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"data").unwrap();
+        let db = dir.join("db.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        run(cli(&["--create", "--db", &db, "--path", &path])).await.unwrap();
+
+        let log = LogBuffer::default();
+        let logged = {
+            let _subscriber = tracing::subscriber::set_default(jsonlog::subscriber(EnvFilter::new("info"), log.make()));
+            // SIGTERM while waiting for the third cycle
+            let terminate = tokio::spawn(async {
+                tokio::time::sleep(Duration::from_millis(1500)).await;
+                // SAFETY: signals this process, whose handler --interval installed
+                unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+            });
+            let code = run(cli(&["--check", "--db", &db, "--path", &path, "--interval", "1"])).await.unwrap();
+            terminate.await.unwrap();
+            assert_eq!(code, ExitCode::SUCCESS);
+            String::from_utf8(log.0.lock().unwrap().clone()).unwrap()
+        };
+        let messages: Vec<String> = logged.lines().map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["fields"]["message"].as_str().unwrap().to_owned()).collect();
+        assert_eq!(messages.iter().filter(|m| m.starts_with("Checked 1 files")).count(), 2, "{messages:?}");
+        assert_eq!(messages.last().map(String::as_str), Some("Terminated, stopping the --interval checks"));
+        assert!(Cli::try_parse_from(["integrity-checker", "--update", "--interval", "5m"]).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_version_warning() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_version_warning");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+        std::fs::write(tree.join("a"), b"data").unwrap();
+        std::fs::write(tree.join("sub").join("b"), b"data").unwrap();
+        let db = dir.join("db.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        run(cli(&["--create", "--db", &db, "--path", &path])).await.unwrap();
+        let database = dbfile::open(&db).unwrap();
+        assert_eq!(dbmeta::load_version(&database, DEFAULT_TABLE).unwrap().as_deref(), Some(versions::CURRENT));
+        dbmeta::store_version(&database, DEFAULT_TABLE, "0.0.1").unwrap();
+        database.close().unwrap();
+
+        let warnings = async |extra: &[&str]| {
+            let log = LogBuffer::default();
+            let _subscriber = tracing::subscriber::set_default(jsonlog::subscriber(EnvFilter::new("warn"), log.make()));
+            run(cli(&[&["--check", "--db", &db, "--path", &path], extra].concat())).await.unwrap();
+            let logged = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+            logged.lines().map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["fields"]["message"].as_str().unwrap().to_owned()).collect::<Vec<_>>()
+        };
+        let logged = warnings(&[]).await;
+        assert!(logged.iter().any(|m| m.starts_with(&format!("Baseline created by 0.0.1, you are running {}", versions::CURRENT))), "{logged:?}");
+        assert!(warnings(&["--ignore-version-warnings"]).await.is_empty());
+
+        // an update of part of the baseline leaves the rest as the old version stored it
+        let sub = tree.join("sub").to_string_lossy().to_string();
+        run(cli(&["--update", "--db", &db, "--path", &sub])).await.unwrap();
+        assert!(warnings(&[]).await.iter().any(|m| m.starts_with("Baseline created by 0.0.1")));
+        // an update of all of it stamps the running version
+        run(cli(&["--update", "--db", &db, "--path", &path])).await.unwrap();
+        assert!(warnings(&[]).await.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_fail_fast");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        // more than one batch of the walk
+        for i in 0..3000{
+            std::fs::write(tree.join(format!("f{i}")), b"old").unwrap();
+        }
+        let db = dir.join("db.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        run(cli(&["--create", "--no-dirs", "--db", &db, "--path", &path])).await.unwrap();
+        for i in 0..3000{
+            std::fs::write(tree.join(format!("f{i}")), b"new").unwrap();
+        }
+
+        let log = LogBuffer::default();
+        let _subscriber = tracing::subscriber::set_default(jsonlog::subscriber(EnvFilter::new("info"), log.make()));
+        let code = run(cli(&["--check", "--no-dirs", "--fail-fast", "--db", &db, "--path", &path])).await.unwrap();
+        assert_eq!(code, ExitCode::from(EXIT_CHANGES));
+        let logged = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let messages: Vec<_> = logged.lines().map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["fields"]["message"].as_str().unwrap().to_owned()).collect();
+        assert_eq!(messages.iter().filter(|m| m.starts_with("File ") && m.contains(" changed")).count(), 1, "{messages:?}");
+        assert!(messages.iter().any(|m| m.starts_with("Checked 1 files ")), "{messages:?}");
+        assert!(messages.iter().any(|m| m == "Stopped at first finding (--fail-fast), check incomplete"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_severity_policy() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_severity_policy");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"old").unwrap();
+        std::fs::write(tree.join("b"), b"old").unwrap();
+        let db = dir.join("db.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let policy = dir.join("severity").to_string_lossy().to_string();
+        std::fs::write(&policy, "# content changes are expected\nhash warning\n").unwrap();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        run(cli(&["--create", "--no-dirs", "--db", &db, "--path", &path])).await.unwrap();
+        std::fs::write(tree.join("a"), b"new content").unwrap();
+
+        let check = async |extra: &[&str]| run(cli(&[&["--check", "--no-dirs", "--db", &db, "--path", &path], extra].concat())).await.unwrap();
+        assert_eq!(check(&[]).await, ExitCode::SUCCESS);
+        assert_eq!(check(&["--severity-policy", &policy]).await, ExitCode::from(1));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(tree.join("b"), std::fs::Permissions::from_mode(0o666)).unwrap();
+            // a world-writable file stays critical
+            assert_eq!(check(&["--severity-policy", &policy]).await, ExitCode::from(EXIT_CHANGES));
+            std::fs::write(&policy, "hash warning\nworld-writable warning\npermissions info\n").unwrap();
+            assert_eq!(check(&["--severity-policy", &policy]).await, ExitCode::from(1));
+        }
+        std::fs::write(&policy, "hash info\nworld-writable info\npermissions info\n").unwrap();
+        assert_eq!(check(&["--severity-policy", &policy, "--cron"]).await, ExitCode::SUCCESS);
+        std::fs::write(&policy, "hash loud\n").unwrap();
+        assert!(run(cli(&["--check", "--db", &db, "--path", &path, "--severity-policy", &policy])).await.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_json_errors() {
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        let args = cli(&["--create", "--db", "test_db_json_errors.redb", "--path", "test_tree_json_errors_missing", "--json-errors"]);
+        assert!(args.json_errors);
+        let e = run(args).await.unwrap_err();
+        let mut out = Vec::new();
+        write_json_error(&mut out, &e).unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert_eq!(line.lines().count(), 1);
+        let report: ErrorReport = serde_json::from_str(&line).unwrap();
+        assert_eq!(report.kind, "io");
+        assert_eq!(report.path.as_deref(), Some("test_tree_json_errors_missing"));
+        assert_eq!(report.message, e.to_string());
+        assert!(!std::path::Path::new("test_db_json_errors.redb").exists());
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_prompt() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_overwrite_prompt");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"content").unwrap();
+        let db = dir.join("db.redb").to_string_lossy().to_string();
+        let path = tree.to_string_lossy().to_string();
+        let cli = |args: &[&str]| Cli::try_parse_from(["integrity-checker"].iter().chain(args)).unwrap();
+        let overwrite = cli(&["--create", "--overwrite", "--db", &db, "--path", &path]);
+        // nothing to replace yet
+        assert_eq!(overwrite_prompt(&overwrite, DEFAULT_TABLE, true).unwrap(), None);
+        run(cli(&["--create", "--db", &db, "--path", &path])).await.unwrap();
+
+        let prompt = overwrite_prompt(&overwrite, DEFAULT_TABLE, true).unwrap().unwrap();
+        assert!(prompt.contains("has 1 entries, created on "), "{prompt}");
+        // scripts and timers aren't asked
+        assert_eq!(overwrite_prompt(&overwrite, DEFAULT_TABLE, false).unwrap(), None);
+        let yes = cli(&["--create", "--overwrite", "--yes", "--db", &db, "--path", &path]);
+        assert_eq!(overwrite_prompt(&yes, DEFAULT_TABLE, true).unwrap(), None);
+        std::fs::write(tree.join("b"), b"content").unwrap();
+        run(yes).await.unwrap();
+        assert!(overwrite_prompt(&overwrite, DEFAULT_TABLE, true).unwrap().unwrap().contains("has 2 entries"));
+        assert!(Cli::try_parse_from(["integrity-checker", "--create", "--yes", "--db", &db, "--path", &path]).is_err());
+
+        for (answer, confirmed) in [("y\n", true), ("YES\n", true), ("\n", false), ("n\n", false), ("", false)]{
+            let mut shown = Vec::new();
+            assert_eq!(confirm(&prompt, io::Cursor::new(answer), &mut shown).unwrap(), confirmed, "{answer:?}");
+            assert_eq!(shown, prompt.as_bytes());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_self_exclude() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_self_exclude");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a"), b"data").unwrap();
+        let own = ["other.redb", "cache.redb", "db.redb.sig", "db.redb.lock"];
+        for file in own{
+            std::fs::write(dir.join(file), b"ours").unwrap();
+        }
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+        let cli = |cmd: &str, extra: &[&str]| {
+            let args = ["integrity-checker", cmd, "--fail-fast", "--db", &path("db.redb"), "--db2", &path("other.redb"), "--cache", &path("cache.redb"), "--path", &path("")];
+            Cli::try_parse_from(args.iter().chain(extra)).unwrap()
+        };
+
+        assert_eq!(run(cli("--create", &[])).await.unwrap(), ExitCode::SUCCESS);
+        for file in own{
+            std::fs::write(dir.join(file), b"changed by us").unwrap();
+        }
+        assert_eq!(run(cli("--check", &[])).await.unwrap(), ExitCode::SUCCESS);
+        // the old flag still turns it off
+        assert_eq!(run(cli("--check", &["--dont-exclude-db"])).await.unwrap(), ExitCode::from(EXIT_CHANGES));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cron() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_cron");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"first").unwrap();
+        let db = dir.join("cron.redb").to_string_lossy().to_string();
+        let cli = |cmd: &str, extra: &[&str]| {
+            let args = ["integrity-checker", cmd, "--db", &db, "--path", &tree.to_string_lossy()];
+            Cli::try_parse_from(args.iter().chain(extra)).unwrap()
+        };
+
+        assert_eq!(run(cli("--create", &["--prefilter"])).await.unwrap(), ExitCode::SUCCESS);
+        let check_logged = async |expected: ExitCode| {
+            let log = LogBuffer::default();
+            let _subscriber = tracing::subscriber::set_default(jsonlog::subscriber(EnvFilter::new("warn"), log.make()));
+            assert_eq!(run(cli("--check", &["--cron", "--warn-unmatched-excludes", "--exclude", "/nowhere"])).await.unwrap(), expected);
+            String::from_utf8(log.0.lock().unwrap().clone()).unwrap()
+        };
+        // a clean run mails nothing, not even the --prefilter caveat
+        assert_eq!(check_logged(ExitCode::SUCCESS).await, "");
+        std::fs::write(tree.join("a"), b"second").unwrap();
+        let logged = check_logged(ExitCode::from(EXIT_CHANGES)).await;
+        assert!(logged.contains("Prefilter mode"), "{logged}");
+        // without --cron findings alone don't change the exit code
+        assert_eq!(run(cli("--check", &[])).await.unwrap(), ExitCode::SUCCESS);
+        let recorded: Vec<_> = runs::load(&Database::open(&db).unwrap(), DEFAULT_TABLE, 10).unwrap().into_iter().map(|r| (r.command, r.files, r.findings)).collect();
+        assert_eq!(recorded, [("create".to_owned(), 1, 0), ("check".to_owned(), 1, 0), ("check".to_owned(), 1, 1), ("check".to_owned(), 1, 1)]);
+        assert!(Cli::try_parse_from(["integrity-checker", "--list", "--cron"]).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ack() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_ack");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::fs::write(tree.join("a"), b"first").unwrap();
+        std::fs::write(tree.join("b"), b"first").unwrap();
+        let db = dir.join("ack.redb").to_string_lossy().to_string();
+        let acks = dir.join("state.redb").to_string_lossy().to_string();
+        let cli = |cmd: &[&str]| {
+            let args = ["integrity-checker", "--db", &db, "--ack-file", &acks, "--path", &tree.to_string_lossy()];
+            Cli::try_parse_from(args.iter().chain(cmd)).unwrap()
+        };
+        let a = tree.join("a").to_string_lossy().to_string();
+        let changes = ExitCode::from(EXIT_CHANGES);
+
+        assert_eq!(run(cli(&["--create"])).await.unwrap(), ExitCode::SUCCESS);
+        std::fs::write(tree.join("a"), b"second").unwrap();
+        std::fs::write(tree.join("b"), b"second").unwrap();
+        assert_eq!(run(cli(&["--ack", &a])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(ack::load(&acks, DEFAULT_TABLE).unwrap().len(), 1);
+        // b is still unacknowledged
+        assert_eq!(run(cli(&["--check", "--cron"])).await.unwrap(), changes);
+        assert_eq!(run(cli(&["--ack-all"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(run(cli(&["--check", "--cron"])).await.unwrap(), ExitCode::SUCCESS);
+        // a new hash isn't covered by the acknowledgment
+        std::fs::write(tree.join("a"), b"third").unwrap();
+        assert_eq!(run(cli(&["--check", "--cron"])).await.unwrap(), changes);
+        assert_eq!(run(cli(&["--show-acked"])).await.unwrap(), ExitCode::SUCCESS);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_window() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_maintenance");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join("usr")).unwrap();
+        std::fs::write(tree.join("usr").join("ls"), b"first").unwrap();
+        std::fs::write(tree.join("motd"), b"first").unwrap();
+        let db = dir.join("maintenance.redb").to_string_lossy().to_string();
+        let usr = tree.join("usr").to_string_lossy().to_string();
+        let cli = |cmd: &[&str]| {
+            let args = ["integrity-checker", "--db", &db, "--path", &tree.to_string_lossy()];
+            Cli::try_parse_from(args.iter().chain(cmd)).unwrap()
+        };
+        let changes = ExitCode::from(EXIT_CHANGES);
+        let maintenance = || dbmeta::Maintenance::load(&Database::open(&db).unwrap(), DEFAULT_TABLE).unwrap();
+
+        assert_eq!(run(cli(&["--create"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(run(cli(&["--maintenance-until", "6h", "--maintenance-paths", &usr])).await.unwrap(), ExitCode::SUCCESS);
+        std::fs::write(tree.join("usr").join("ls"), b"patched").unwrap();
+        assert_eq!(run(cli(&["--check", "--cron"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(maintenance().unwrap().suppressed, 1);
+        // outside the paths findings still count
+        std::fs::write(tree.join("motd"), b"changed").unwrap();
+        assert_eq!(run(cli(&["--check", "--cron"])).await.unwrap(), changes);
+        std::fs::write(tree.join("motd"), b"first").unwrap();
+
+        // once the window is over the same change fails the check again
+        let ended = dbmeta::Maintenance{ until: 1_000_000_000, ..maintenance().unwrap() };
+        ended.store(&Database::open(&db).unwrap(), DEFAULT_TABLE).unwrap();
+        assert_eq!(run(cli(&["--check", "--cron"])).await.unwrap(), changes);
+        assert_eq!(run(cli(&["--update"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(maintenance(), None);
+        assert_eq!(run(cli(&["--check", "--cron"])).await.unwrap(), ExitCode::SUCCESS);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_strict_new() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_strict_new");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join("usr")).unwrap();
+        std::fs::create_dir_all(tree.join("tmp")).unwrap();
+        let db = dir.join("strict.redb").to_string_lossy().to_string();
+        let acks = dir.join("acks.redb").to_string_lossy().to_string();
+        let usr = tree.join("usr").to_string_lossy().to_string();
+        let cli = |cmd: &[&str]| {
+            let args = ["integrity-checker", "--db", &db, "--path", &tree.to_string_lossy()];
+            Cli::try_parse_from(args.iter().chain(cmd)).unwrap()
+        };
+        let changes = ExitCode::from(EXIT_CHANGES);
+
+        assert_eq!(run(cli(&["--create"])).await.unwrap(), ExitCode::SUCCESS);
+        std::fs::write(tree.join("tmp").join("scratch"), b"new").unwrap();
+        assert_eq!(run(cli(&["--check"])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(run(cli(&["--check", "--strict-new"])).await.unwrap(), changes);
+        // new files outside the scope stay warnings
+        assert_eq!(run(cli(&["--check", "--strict-new-under", &usr])).await.unwrap(), ExitCode::SUCCESS);
+        std::fs::create_dir(tree.join("usr").join("lib")).unwrap();
+        assert_eq!(run(cli(&["--check", "--strict-new-under", &usr])).await.unwrap(), changes);
+        // acknowledged ones don't fail it
+        assert_eq!(run(cli(&["--ack-file", &acks, "--ack", &usr])).await.unwrap(), ExitCode::SUCCESS);
+        assert_eq!(run(cli(&["--check", "--ack-file", &acks, "--strict-new-under", &usr])).await.unwrap(), ExitCode::SUCCESS);
+        assert!(Cli::try_parse_from(["integrity-checker", "--update", "--strict-new"]).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
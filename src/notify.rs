@@ -0,0 +1,93 @@
+//! `--notify`: one desktop notification per check with findings, for checks
+//! run from a user timer on a workstation. Shown over D-Bus when built with
+//! the `notify` feature; without a session bus, e.g. headless, nothing is shown.
+
+// without the feature the texts of a notification are only tested
+#![cfg_attr(not(feature = "notify"), allow(dead_code))]
+
+use log::Level;
+use tracing::debug;
+
+/// What a check found, as counted in its summary line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckSummary{
+    pub changed: u64,
+    pub new: u64,
+    pub removed: u64,
+    /// level of the worst finding not acknowledged or expected
+    pub level: Level,
+    /// the checked `--path` roots
+    pub roots: Vec<String>,
+    pub db: String,
+}
+
+impl CheckSummary {
+    pub fn findings(&self) -> u64 {
+        self.changed + self.new + self.removed
+    }
+
+    /// Title of the notification.
+    pub fn summary(&self) -> String {
+        let files = if self.findings() == 1 { "file" } else { "files" };
+        format!("integrity-checker: {} {files} changed under {}", self.findings(), self.roots.join(", "))
+    }
+
+    pub fn body(&self) -> String {
+        format!("{} modified, {} new, {} removed compared to {}", self.changed, self.new, self.removed, self.db)
+    }
+}
+
+/// Shows `summary`, when nothing was found there's nothing to show. Failures
+/// are only logged at debug level, a check doesn't fail for lack of a desktop.
+pub fn send(summary: &CheckSummary) {
+    if summary.findings() == 0{
+        return;
+    }
+    if !has_session_bus(){
+        debug!("No D-Bus session, not notifying");
+        return;
+    }
+    show(summary);
+}
+
+fn has_session_bus() -> bool {
+    std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some_and(|a| !a.is_empty())
+        || std::env::var_os("XDG_RUNTIME_DIR").is_some_and(|dir| std::path::Path::new(&dir).join("bus").exists())
+}
+
+#[cfg(feature = "notify")]
+fn show(summary: &CheckSummary) {
+    let mut notification = notify_rust::Notification::new();
+    notification.appname("integrity-checker").summary(&summary.summary()).body(&summary.body());
+    #[cfg(all(unix, not(target_os = "macos")))]
+    notification.urgency(match summary.level{
+        Level::Error => notify_rust::Urgency::Critical,
+        Level::Warn => notify_rust::Urgency::Normal,
+        _ => notify_rust::Urgency::Low,
+    });
+    if let Err(e) = notification.show(){
+        debug!("Desktop notification failed: {e}");
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+fn show(_summary: &CheckSummary) {
+    tracing::warn!("--notify needs integrity-checker built with the notify feature");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut summary = CheckSummary{ changed: 2, new: 1, removed: 0, level: Level::Error, roots: vec!["/etc".to_owned()], db: "db.redb".to_owned() };
+        assert_eq!(summary.summary(), "integrity-checker: 3 files changed under /etc");
+        assert_eq!(summary.body(), "2 modified, 1 new, 0 removed compared to db.redb");
+        summary.changed = 0;
+        summary.new = 0;
+        summary.removed = 1;
+        summary.roots.push("/usr/bin".to_owned());
+        assert_eq!(summary.summary(), "integrity-checker: 1 file changed under /etc, /usr/bin");
+    }
+}
@@ -0,0 +1,128 @@
+//! Owning user and group of files and directories, stored with `--owner`.
+//! Numeric ids aren't portable: the same user can have another uid on another
+//! system. So the names are stored along, and `--owner-by-name` compares them
+//! instead where both sides resolved.
+
+use super::types::Owner;
+#[cfg(target_os = "linux")]
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::sync::{LazyLock, Mutex};
+
+/// Names by whether it's a group and id.
+#[cfg(target_os = "linux")]
+type Names = HashMap<(bool, u32), Option<String>>;
+
+/// Names already looked up; a scan asks for the same few owners over and over.
+#[cfg(target_os = "linux")]
+static NAMES: LazyLock<Mutex<Names>> = LazyLock::new(Default::default);
+
+/// Buffer `getpwuid_r` and `getgrgid_r` may grow to, for groups with many members.
+#[cfg(target_os = "linux")]
+const MAX_BUF: usize = 1 << 20;
+
+/// Owner of the entry `meta` was read from, with the names of its ids. `None`
+/// on systems without Unix ownership.
+pub fn of(meta: &std::fs::Metadata) -> Option<Owner> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(Owner{ uid: meta.uid(), gid: meta.gid(), user: name(false, meta.uid()), group: name(true, meta.gid()) })
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = meta;
+        None
+    }
+}
+
+/// Finding text for another user or group owning the entry, e.g.
+/// ` owner changed root(0):root(0) -> alice(1000):root(0)`. With `by_name` ids that
+/// differ are the same owner when both resolved to the same name. Entries
+/// stored without owner, or whose owner isn't read now, aren't reported.
+pub fn changed(old: Option<&Owner>, new: Option<&Owner>, by_name: bool) -> Option<String> {
+    let (old, new) = (old?, new?);
+    let same = |old_id: u32, new_id: u32, old_name: &Option<String>, new_name: &Option<String>| match (by_name, old_name, new_name){
+        (true, Some(old_name), Some(new_name)) => old_name == new_name,
+        _ => old_id == new_id,
+    };
+    match same(old.uid, new.uid, &old.user, &new.user) && same(old.gid, new.gid, &old.group, &new.group){
+        true => None,
+        false => Some(format!(" owner changed {old} -> {new}")),
+    }
+}
+
+/// Name of group or user `id`, `None` when the system has no such one.
+#[cfg(target_os = "linux")]
+fn name(group: bool, id: u32) -> Option<String> {
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    names.entry((group, id)).or_insert_with(|| {
+        let name = lookup(group, id);
+        if name.is_none(){
+            tracing::debug!("No name for {} {id}", if group { "gid" } else { "uid" });
+        }
+        name
+    }).clone()
+}
+
+#[cfg(target_os = "linux")]
+fn lookup(group: bool, id: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop{
+        // SAFETY: the structs are plain C data written by the calls, whose
+        // strings point into `buf`, which outlives reading them
+        let (rc, name) = unsafe {
+            match group{
+                true => {
+                    let mut entry: libc::group = std::mem::zeroed();
+                    let mut result = std::ptr::null_mut();
+                    let rc = libc::getgrgid_r(id, &mut entry, buf.as_mut_ptr(), buf.len(), &mut result);
+                    (rc, (!result.is_null()).then(|| std::ffi::CStr::from_ptr(entry.gr_name).to_string_lossy().into_owned()))
+                }
+                false => {
+                    let mut entry: libc::passwd = std::mem::zeroed();
+                    let mut result = std::ptr::null_mut();
+                    let rc = libc::getpwuid_r(id, &mut entry, buf.as_mut_ptr(), buf.len(), &mut result);
+                    (rc, (!result.is_null()).then(|| std::ffi::CStr::from_ptr(entry.pw_name).to_string_lossy().into_owned()))
+                }
+            }
+        };
+        match rc{
+            libc::ERANGE if buf.len() < MAX_BUF => buf.resize(buf.len() * 2, 0),
+            0 => return name,
+            _ => {
+                tracing::debug!("Looking up {} {id}: {}", if group { "gid" } else { "uid" }, std::io::Error::from_raw_os_error(rc));
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owner(uid: u32, user: Option<&str>) -> Owner {
+        Owner{ uid, gid: 100, user: user.map(str::to_owned), group: Some("users".to_owned()) }
+    }
+
+    #[test]
+    fn test_changed_by_name() {
+        let (alice, alice_elsewhere) = (owner(1000, Some("alice")), owner(1001, Some("alice")));
+        assert_eq!(changed(Some(&alice), Some(&alice_elsewhere), false).as_deref(), Some(" owner changed alice(1000):users(100) -> alice(1001):users(100)"));
+        assert_eq!(changed(Some(&alice), Some(&alice_elsewhere), true), None);
+        assert!(changed(Some(&alice), Some(&owner(1000, Some("bob"))), true).is_some());
+        // unresolved ids fall back to comparing them
+        assert!(changed(Some(&alice), Some(&owner(1001, None)), true).is_some());
+        assert_eq!(changed(Some(&owner(1001, None)), Some(&owner(1001, Some("alice"))), true), None);
+        assert_eq!(changed(None, Some(&alice), false), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_of() {
+        let meta = std::fs::metadata("/").unwrap();
+        assert_eq!(of(&meta), Some(Owner{ uid: 0, gid: 0, user: Some("root".to_owned()), group: Some("root".to_owned()) }));
+        assert_eq!(name(false, u32::MAX - 1), None);
+    }
+}
@@ -0,0 +1,226 @@
+//! `--path-fold`: DB keys folded the way case-insensitive or normalizing file
+//! systems compare names, so a baseline of macOS (APFS) or Windows doesn't
+//! turn into new/removed pairs when checked elsewhere.
+
+use super::error::IntegrityWatcherError;
+use super::types::EscapedPath;
+use icu_normalizer::{ComposingNormalizerBorrowed, DecomposingNormalizerBorrowed};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm{
+    Nfc,
+    Nfd,
+}
+
+/// How paths are folded into DB keys, byte exact by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PathFold{
+    /// compare names case insensitively, keys are lowercase
+    pub icase: bool,
+    pub unicode: Option<UnicodeForm>,
+}
+
+impl PathFold {
+    pub fn is_exact(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Key of `path`. Folding a key again gives the same key.
+    pub fn fold<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        let path = match self.icase{
+            true => Cow::Owned(path.to_lowercase()),
+            false => Cow::Borrowed(path),
+        };
+        let normalized = match self.unicode{
+            Some(UnicodeForm::Nfc) => ComposingNormalizerBorrowed::new_nfc().normalize(&path),
+            Some(UnicodeForm::Nfd) => DecomposingNormalizerBorrowed::new_nfd().normalize(&path),
+            None => return path,
+        };
+        match normalized{
+            Cow::Owned(n) => Cow::Owned(n),
+            Cow::Borrowed(_) => path,
+        }
+    }
+
+    /// Path on disk whose key is `key`, None when nothing folds to it. A key
+    /// keeps neither case nor normal form, so each of its names is looked up
+    /// as is and else among the entries of its directory folding to it.
+    pub fn resolve(&self, key: &str) -> io::Result<Option<PathBuf>> {
+        if self.is_exact(){
+            return Ok(Some(PathBuf::from(key)));
+        }
+        let mut resolved = PathBuf::new();
+        for component in Path::new(key).components(){
+            let Component::Normal(name) = component else {
+                resolved.push(component);
+                continue;
+            };
+            let candidate = resolved.join(name);
+            if std::fs::symlink_metadata(&candidate).is_ok(){
+                resolved = candidate;
+                continue;
+            }
+            let dir = if resolved.as_os_str().is_empty() { Path::new(".") } else { resolved.as_path() };
+            let entries = match std::fs::read_dir(dir){
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let name = name.to_string_lossy();
+            let mut found = None;
+            for entry in entries{
+                let entry = entry?.file_name();
+                if self.fold(&entry.to_string_lossy()) == name{
+                    found = Some(entry);
+                    break;
+                }
+            }
+            match found{
+                Some(entry) => resolved.push(entry),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(resolved))
+    }
+}
+
+impl std::str::FromStr for PathFold {
+    type Err = IntegrityWatcherError;
+
+    /// Coma separated `icase`, `nfc` or `nfd`, or `exact`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fold = PathFold::default();
+        for part in s.split(','){
+            match part.trim(){
+                "exact" => {},
+                "icase" => fold.icase = true,
+                "nfc" | "nfd" if fold.unicode.is_some() => {
+                    return Err(IntegrityWatcherError::InvalidArgument("--path-fold takes only one of nfc and nfd".to_owned()));
+                }
+                "nfc" => fold.unicode = Some(UnicodeForm::Nfc),
+                "nfd" => fold.unicode = Some(UnicodeForm::Nfd),
+                other => {
+                    return Err(IntegrityWatcherError::InvalidArgument(format!("--path-fold {other:?} isn't icase, nfc, nfd or exact")));
+                }
+            }
+        }
+        Ok(fold)
+    }
+}
+
+impl std::fmt::Display for PathFold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unicode = match self.unicode{
+            Some(UnicodeForm::Nfc) => Some("nfc"),
+            Some(UnicodeForm::Nfd) => Some("nfd"),
+            None => None,
+        };
+        match (self.icase, unicode){
+            (false, None) => write!(f, "exact"),
+            (true, None) => write!(f, "icase"),
+            (false, Some(u)) => write!(f, "{u}"),
+            (true, Some(u)) => write!(f, "icase,{u}"),
+        }
+    }
+}
+
+/// Folds the keys of one scan and remembers which path each key came from, so
+/// two distinct paths folding to the same key are caught instead of one
+/// silently replacing the other.
+#[derive(Debug, Default)]
+pub struct KeyFolder{
+    fold: PathFold,
+    seen: HashMap<String, String>,
+}
+
+impl KeyFolder {
+    pub fn new(fold: PathFold) -> Self {
+        KeyFolder { fold, seen: HashMap::new() }
+    }
+
+    pub fn fold(&self) -> PathFold {
+        self.fold
+    }
+
+    /// Key of `path`, or `Err` with the path that already has that key. The
+    /// same path seen twice, e.g. through overlapping roots, isn't a collision.
+    pub fn key<'a>(&mut self, path: &'a str) -> Result<Cow<'a, str>, String> {
+        if self.fold.is_exact(){
+            return Ok(Cow::Borrowed(path));
+        }
+        let key = self.fold.fold(path);
+        match self.seen.get(key.as_ref()){
+            Some(first) if first != path => Err(first.clone()),
+            Some(_) => Ok(key),
+            None => {
+                self.seen.insert(key.clone().into_owned(), path.to_owned());
+                Ok(key)
+            }
+        }
+    }
+}
+
+/// Message for `path` refused because it folds to the key of `first`.
+pub fn collision_message(path: &str, first: &str, fold: PathFold) -> String {
+    format!("{} folds to the same key as {} with --path-fold {fold}, skipped", EscapedPath(path), EscapedPath(first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_fold() {
+        let nfc = "/data/caf\u{e9}.txt";
+        let nfd = "/data/cafe\u{301}.txt";
+        assert_eq!("icase".parse::<PathFold>().unwrap().fold("/Data/File.TXT"), "/data/file.txt");
+        assert_eq!("nfc".parse::<PathFold>().unwrap().fold(nfd), nfc);
+        assert_eq!("nfd".parse::<PathFold>().unwrap().fold(nfc), nfd);
+        let both: PathFold = "icase,nfc".parse().unwrap();
+        assert_eq!(both.fold("/DATA/CAFE\u{301}.txt"), nfc);
+        assert_eq!(both.fold(&both.fold("/DATA/CAFE\u{301}.txt")), nfc);
+        assert!(matches!(PathFold::default().fold("/Data"), Cow::Borrowed("/Data")));
+        assert!(matches!(both.fold("/data/a"), Cow::Owned(_)));
+
+        for fold in ["exact", "icase", "nfd", "icase,nfc"]{
+            assert_eq!(fold.parse::<PathFold>().unwrap().to_string(), fold);
+        }
+        for bad in ["", "nfkc", "nfc,nfd", "icase,"]{
+            assert!(bad.parse::<PathFold>().is_err(), "Parsed invalid fold {bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_key_collisions() {
+        let mut keys = KeyFolder::new("icase,nfc".parse().unwrap());
+        assert_eq!(keys.key("/Data/File.TXT").unwrap(), "/data/file.txt");
+        assert_eq!(keys.key("/Data/File.TXT").unwrap(), "/data/file.txt");
+        assert_eq!(keys.key("/data/file.txt").unwrap_err(), "/Data/File.TXT");
+        assert_eq!(keys.key("/data/caf\u{e9}").unwrap(), "/data/caf\u{e9}");
+        assert_eq!(keys.key("/data/cafe\u{301}").unwrap_err(), "/data/caf\u{e9}");
+
+        let mut exact = KeyFolder::default();
+        assert_eq!(exact.key("/Data").unwrap(), "/Data");
+        assert_eq!(exact.key("/data").unwrap(), "/data");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::current_dir().unwrap().join("test_tree_pathfold_resolve");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("Sub")).unwrap();
+        std::fs::write(dir.join("Sub").join("Cafe\u{301}.TXT"), b"").unwrap();
+        let root = dir.to_string_lossy();
+        let fold: PathFold = "icase,nfc".parse().unwrap();
+
+        let key = fold.fold(&format!("{root}/Sub/Cafe\u{301}.TXT")).into_owned();
+        assert_eq!(fold.resolve(&key).unwrap(), Some(dir.join("Sub").join("Cafe\u{301}.TXT")));
+        assert_eq!(fold.resolve(&fold.fold(&format!("{root}/Sub/gone"))).unwrap(), None);
+        assert_eq!(PathFold::default().resolve("/Data").unwrap(), Some(PathBuf::from("/Data")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use super::error::IntegrityWatcherError;
+use super::dbfile;
+use super::types::{EscapedPath, Hash};
+use tracing::warn;
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+pub const DPKG_INFO_DIR: &str = "/var/lib/dpkg/info";
+
+/// File shipped by a package with the MD5 recorded for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFile{
+    pub package: String,
+    pub md5: Hash,
+}
+
+/// Parses one dpkg `<package>.md5sums` file, lines of `<md5>  <path relative to />`.
+/// Paths are joined to `root`; malformed lines are reported and skipped.
+pub fn parse_dpkg_md5sums<R: BufRead>(reader: R, package: &str, root: &Path, files: &mut HashMap<String, PackageFile>) -> Result<(), IntegrityWatcherError> {
+    for line in reader.lines(){
+        let line = line.map_err(|e| IntegrityWatcherError::IOError { source: e, path: package.to_owned() })?;
+        let Some((md5, path)) = line.split_once(char::is_whitespace) else {
+            if !line.trim().is_empty(){
+                warn!("Package {package} md5sums line skipped: {}", EscapedPath(&line));
+            }
+            continue;
+        };
+        let md5 = match Hash::from_hex(md5){
+            Ok(h) => h,
+            Err(e) => {
+                warn!("Package {package} md5sums line skipped: {e}");
+                continue;
+            }
+        };
+        let path = root.join(path.trim_start().trim_start_matches('/'));
+        files.insert(path.to_string_lossy().to_string(), PackageFile { package: package.to_owned(), md5 });
+    }
+    Ok(())
+}
+
+/// MD5 of every file listed in `info_dir/*.md5sums`.
+pub fn load_dpkg(info_dir: &Path, root: &Path) -> Result<HashMap<String, PackageFile>, IntegrityWatcherError> {
+    let io_err = |e, p: &Path| IntegrityWatcherError::IOError { source: e, path: p.to_string_lossy().to_string() };
+    let mut files = HashMap::new();
+    for entry in std::fs::read_dir(info_dir).map_err(|e| io_err(e, info_dir))?{
+        let path = entry.map_err(|e| io_err(e, info_dir))?.path();
+        if path.extension().is_none_or(|e| e != "md5sums"){
+            continue;
+        }
+        let package = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let file = std::fs::File::open(&path).map_err(|e| io_err(e, &path))?;
+        parse_dpkg_md5sums(io::BufReader::new(file), &package, root, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// Paths whose digest check failed in `rpm -Va` output. Lines look like
+/// `S.5....T.  c /etc/foo`, the third flag being `5` for a digest mismatch.
+pub fn parse_rpm_verify<R: BufRead>(reader: R) -> Result<Vec<String>, IntegrityWatcherError> {
+    let mut mismatched = Vec::new();
+    for line in reader.lines(){
+        let line = line.map_err(|e| IntegrityWatcherError::IOError { source: e, path: "rpm -Va".to_owned() })?;
+        let Some((flags, rest)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        if flags.len() < 3 || flags.as_bytes()[2] != b'5'{
+            continue;
+        }
+        // optional single letter file attribute (c, d, g, l, r) before the path
+        let rest = rest.trim_start();
+        let path = match rest.split_once(char::is_whitespace){
+            Some((attr, path)) if attr.len() == 1 && !attr.starts_with('/') => path.trim_start(),
+            _ => rest,
+        };
+        mismatched.push(path.to_owned());
+    }
+    Ok(mismatched)
+}
+
+/// Runs `rpm -Va`; its exit status is non zero whenever anything differs, so
+/// only a failure to start it is an error.
+pub fn rpm_verify_all() -> Result<Vec<String>, IntegrityWatcherError> {
+    let out = std::process::Command::new("rpm").args(["-Va", "--nomtime"]).env_remove(dbfile::PASSPHRASE_ENV).output()
+        .map_err(|e| IntegrityWatcherError::IOError { source: e, path: "rpm".to_owned() })?;
+    parse_rpm_verify(out.stdout.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dpkg_md5sums(){
+        let data = "d41d8cd98f00b204e9800998ecf8427e  usr/bin/empty\n\
+                    b28354b543375bfa94dabaeda722927f  usr/share/doc/with space\n\
+                    garbage\n\
+                    \n\
+                    zz  usr/bin/bad\n";
+        let mut files = HashMap::new();
+        parse_dpkg_md5sums(data.as_bytes(), "coreutils:amd64", Path::new("/"), &mut files).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files["/usr/bin/empty"], PackageFile{ package: "coreutils:amd64".to_owned(), md5: Hash::from_hex("d41d8cd98f00b204e9800998ecf8427e").unwrap() });
+        assert!(files.contains_key("/usr/share/doc/with space"));
+    }
+
+    #[test]
+    fn test_parse_rpm_verify(){
+        let data = "S.5....T.  c /etc/ssh/sshd_config\n\
+                    ..5......    /usr/bin/ls\n\
+                    .M.......    /var/log/wtmp\n\
+                    missing     /usr/share/doc/x\n";
+        assert_eq!(parse_rpm_verify(data.as_bytes()).unwrap(), ["/etc/ssh/sshd_config", "/usr/bin/ls"]);
+    }
+}
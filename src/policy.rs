@@ -0,0 +1,146 @@
+//! `--policy` files: how entries under a path are hashed, like AIDE rule
+//! groups. Each line is a path or glob in the `--exclude` syntax and a mode:
+//!
+//! ```text
+//! # config is fully hashed, the cache only stat-tracked
+//! /etc                  full
+//! /var/lib/large-cache  stat-only
+//! /srv/media/**/*.mkv   head-hash
+//! /var/lib/docker       skip
+//! ```
+//!
+//! The last matching line wins, entries no line matches are fully hashed. The
+//! policy is stored with the baseline, so checks and updates hash every entry
+//! the way it was hashed when the baseline was made.
+
+use super::error::IntegrityWatcherError;
+use super::exclude::ExcludeSet;
+use std::str::FromStr;
+
+/// Bytes of a file `head-hash` reads.
+pub const HEAD_HASH_LEN: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashMode{
+    /// the whole content, with the hash algorithm of the DB
+    Full,
+    /// no content hash, only size, mtime, permissions and the other metadata
+    StatOnly,
+    /// SHA-256 of the first `HEAD_HASH_LEN` bytes, for big append-only or
+    /// media files
+    HeadHash,
+    /// neither stored nor walked into
+    Skip,
+}
+
+impl FromStr for HashMode {
+    type Err = IntegrityWatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s{
+            "full" => Ok(HashMode::Full),
+            "stat-only" => Ok(HashMode::StatOnly),
+            "head-hash" => Ok(HashMode::HeadHash),
+            "skip" => Ok(HashMode::Skip),
+            _ => Err(IntegrityWatcherError::InvalidArgument(format!("Unknown hashing mode {s:?}, expected full, stat-only, head-hash or skip"))),
+        }
+    }
+}
+
+impl std::fmt::Display for HashMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self{
+            HashMode::Full => "full",
+            HashMode::StatOnly => "stat-only",
+            HashMode::HeadHash => "head-hash",
+            HashMode::Skip => "skip",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule{
+    pattern: String,
+    matcher: ExcludeSet,
+    mode: HashMode,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Policy{
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Hashing mode of `path`, by the last rule matching it or a parent.
+    pub fn mode(&self, path: &str) -> HashMode {
+        self.rules.iter().rev().find(|r| r.matcher.is_excluded(path)).map_or(HashMode::Full, |r| r.mode)
+    }
+
+    /// Whether any rule hashes less than the full content.
+    pub fn is_partial(&self) -> bool {
+        self.rules.iter().any(|r| r.mode != HashMode::Full)
+    }
+}
+
+impl FromStr for Policy {
+    type Err = IntegrityWatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for (n, line) in s.lines().enumerate(){
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#'){
+                continue;
+            }
+            let invalid = |reason: &str| IntegrityWatcherError::InvalidArgument(format!("Policy line {}: {reason}", n + 1));
+            let Some((pattern, mode)) = line.rsplit_once(char::is_whitespace) else {
+                return Err(invalid("expected a path and a mode"));
+            };
+            let pattern = pattern.trim_end();
+            if pattern.starts_with('!'){
+                return Err(invalid("! isn't supported, a later line overrides earlier ones"));
+            }
+            let mode = mode.parse().map_err(|e: IntegrityWatcherError| invalid(&e.to_string()))?;
+            let mut matcher = ExcludeSet::default();
+            matcher.add(pattern);
+            rules.push(Rule{ pattern: pattern.to_owned(), matcher, mode });
+        }
+        Ok(Policy{ rules })
+    }
+}
+
+/// One rule per line, how the policy is stored in the DB.
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for rule in &self.rules{
+            writeln!(f, "{} {}", rule.pattern, rule.mode)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy: Policy = "# comment\n\n/etc full\n/var/lib/large-cache   stat-only\n/var/lib/large-cache/index head-hash\n*.iso skip\n".parse().unwrap();
+        assert_eq!(policy.mode("/etc/passwd"), HashMode::Full);
+        assert_eq!(policy.mode("/var/lib/large-cache"), HashMode::StatOnly);
+        assert_eq!(policy.mode("/var/lib/large-cache/a/b"), HashMode::StatOnly);
+        assert_eq!(policy.mode("/var/lib/large-cache/index/x"), HashMode::HeadHash);
+        assert_eq!(policy.mode("/srv/disk.iso"), HashMode::Skip);
+        assert_eq!(policy.mode("/usr/bin/ls"), HashMode::Full);
+        assert!(policy.is_partial());
+        // stored normalized and read back the same
+        let stored = policy.to_string();
+        assert_eq!(stored, "/etc full\n/var/lib/large-cache stat-only\n/var/lib/large-cache/index head-hash\n*.iso skip\n");
+        assert_eq!(stored.parse::<Policy>().unwrap().to_string(), stored);
+
+        assert!("/etc fast".parse::<Policy>().is_err());
+        assert!("/etc".parse::<Policy>().is_err());
+        assert!("!/etc full".parse::<Policy>().is_err());
+        assert!(!"/etc full".parse::<Policy>().unwrap().is_partial());
+    }
+}
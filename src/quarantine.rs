@@ -0,0 +1,305 @@
+//! `--quarantine-dir`: copies of files whose content a check found changed,
+//! kept for forensics before the change can be reverted. A copy is named by
+//! the SHA-256 of what was copied, next to a `<sha256>.json` sidecar with one
+//! [`Record`] line for every path the content was found at. Content already
+//! quarantined is never overwritten, a path it turns up at again is added to
+//! the sidecar, which is created anew when it's missing. Only the user running
+//! the check can read the directory and the copies.
+//!
+//! Copies are made by a thread of their own, the check only queues them and
+//! waits for a slow disk once [`QUEUED_COPIES`] are queued. Files over
+//! `--quarantine-max-size` aren't copied. A copy that fails is warned about
+//! and counted, the check goes on.
+
+use super::error::IntegrityWatcherError;
+use super::types::{EscapedPath, FileMetadata, Hash};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::JoinHandle;
+
+/// Default of `--quarantine-max-size`, 100 MiB.
+pub const DEFAULT_MAX_SIZE: u64 = 100 << 20;
+
+/// Copies queued before the check waits for the copying thread.
+pub const QUEUED_COPIES: usize = 256;
+
+const CHUNK: usize = 64 * 1024;
+
+/// A line of the `<sha256>.json` sidecar of a copy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record{
+    /// the path as walked
+    pub path: String,
+    /// unix seconds of the copy
+    pub quarantined: u64,
+    /// mtime of the DB entry
+    pub old_modified: u64,
+    /// mtime of the file found
+    pub new_modified: u64,
+    /// hashes as the DB stores them
+    pub old_hash: String,
+    pub new_hash: String,
+}
+
+/// A copy to make, see [`Quarantine::keep`].
+struct Job{
+    path: String,
+    old: FileMetadata,
+    new: FileMetadata,
+}
+
+#[derive(Debug, Default)]
+struct Counts{
+    kept: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct Quarantine{
+    dir: PathBuf,
+    max_size: u64,
+    counts: Arc<Counts>,
+    /// dropped by [`Quarantine::finish`], ending the copier once it's done
+    tx: Option<SyncSender<Job>>,
+    copier: Option<JoinHandle<()>>,
+}
+
+impl Quarantine {
+    /// Creates `dir` if needed and restricts it to the user.
+    pub fn open(dir: &Path, max_size: u64) -> Result<Self, IntegrityWatcherError> {
+        let failed = |e| IntegrityWatcherError::IOError { source: e, path: dir.to_string_lossy().to_string() };
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir).map_err(failed)?;
+        #[cfg(unix)]
+        fs::set_permissions(dir, std::os::unix::fs::PermissionsExt::from_mode(0o700)).map_err(failed)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUED_COPIES);
+        let counts = Arc::new(Counts::default());
+        let (copier_dir, copier_counts) = (dir.to_owned(), counts.clone());
+        let copier = std::thread::spawn(move || copy_queued(&copier_dir, rx, &copier_counts));
+        Ok(Quarantine{ dir: dir.to_owned(), max_size, counts, tx: Some(tx), copier: Some(copier) })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Files copied by this check, all of them once [`Quarantine::finish`] returned.
+    pub fn kept(&self) -> u64 {
+        self.counts.kept.load(Ordering::Relaxed)
+    }
+
+    /// Files that couldn't be copied.
+    pub fn failed(&self) -> u64 {
+        self.counts.failed.load(Ordering::Relaxed)
+    }
+
+    /// Queues a copy of the file at `path`, whose content changed from `old`
+    /// to `new`, unless it's too large.
+    pub fn keep(&mut self, path: &str, old: &FileMetadata, new: &FileMetadata) {
+        if u64::from(new.size) > self.max_size{
+            tracing::debug!("Not quarantining {}, larger than --quarantine-max-size", EscapedPath(path));
+            return;
+        }
+        let job = Job{ path: path.to_owned(), old: old.clone(), new: new.clone() };
+        if self.tx.as_ref().is_none_or(|tx| tx.send(job).is_err()){
+            self.counts.failed.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("Quarantining {}: the copying thread is gone", EscapedPath(path));
+        }
+    }
+
+    /// Waits for the queued copies. Files kept afterwards count as failed.
+    pub fn finish(&mut self) {
+        self.tx = None;
+        if let Some(copier) = self.copier.take(){
+            let _ = copier.join();
+        }
+    }
+}
+
+impl Drop for Quarantine {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// The copying thread: copies every queued file until the queue is closed.
+/// Failures are only warned about.
+fn copy_queued(dir: &Path, rx: Receiver<Job>, counts: &Counts) {
+    for (partials, job) in rx.into_iter().enumerate(){
+        let path = EscapedPath(&job.path);
+        match copy(dir, partials, &job){
+            Ok((name, true)) => {
+                counts.kept.fetch_add(1, Ordering::Relaxed);
+                tracing::info!("Quarantined {path} as {name}");
+            }
+            Ok((name, false)) => tracing::debug!("Content of {path} already quarantined as {name}"),
+            Err(e) => {
+                counts.failed.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("Quarantining {path}: {e}");
+            }
+        }
+    }
+}
+
+/// Copies to a partial file first, as the name is only known once the
+/// content is read, then records the path in the sidecar. Returns the name
+/// and whether the content is new to the quarantine.
+fn copy(dir: &Path, partials: usize, job: &Job) -> io::Result<(String, bool)> {
+    let partial = dir.join(format!(".partial-{}-{partials}", std::process::id()));
+    let copied = copy_hashed(Path::new(&job.path), &partial);
+    let name = match copied{
+        Ok(name) => name,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+    // a link fails where the name exists, a rename would replace it
+    let linked = fs::hard_link(&partial, dir.join(&name));
+    let _ = fs::remove_file(&partial);
+    let new = match linked{
+        Ok(()) => true,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => false,
+        Err(e) => return Err(e),
+    };
+    let record = Record{
+        path: job.path.clone(),
+        quarantined: chrono::Utc::now().timestamp().try_into().unwrap_or_default(),
+        old_modified: job.old.modified,
+        new_modified: job.new.modified,
+        old_hash: job.old.hash.to_string(),
+        new_hash: job.new.hash.to_string(),
+    };
+    add_record(&dir.join(format!("{name}.json")), &record)?;
+    Ok((name, new))
+}
+
+/// Appends `record` to `sidecar`, creating it when missing, unless a line of
+/// it has the same path already.
+fn add_record(sidecar: &Path, record: &Record) -> io::Result<()> {
+    match fs::read_to_string(sidecar){
+        Ok(lines) => if lines.lines().filter_map(|l| serde_json::from_str::<Record>(l).ok()).any(|r| r.path == record.path){
+            return Ok(());
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e),
+    }
+    let mut line = serde_json::to_vec(record).map_err(io::Error::other)?;
+    line.push(b'\n');
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(sidecar)?;
+    file.write_all(&line)?;
+    file.flush()
+}
+
+/// The records of `sidecar`, oldest first.
+pub fn records(sidecar: &Path) -> io::Result<Vec<Record>> {
+    fs::read_to_string(sidecar)?.lines().map(|l| serde_json::from_str(l).map_err(io::Error::other)).collect()
+}
+
+/// Copies `from` to the new file `to` and returns the hex SHA-256 of the copy.
+fn copy_hashed(from: &Path, to: &Path) -> io::Result<String> {
+    let mut input = File::open(from)?;
+    let mut output = create_private(to)?;
+    let mut sha256 = Sha256::new();
+    let mut buf = vec![0; CHUNK];
+    loop{
+        match input.read(&mut buf){
+            Ok(0) => break,
+            Ok(n) => {
+                sha256.update(&buf[..n]);
+                output.write_all(&buf[..n])?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    output.sync_all()?;
+    let digest: [u8; 32] = sha256.finalize().into();
+    Ok(Hash::from(digest).to_string())
+}
+
+/// A new file only the user can read.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_quarantine");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("changed");
+        fs::write(&file, b"new content").unwrap();
+        let meta = |hash: u8, size: u64| FileMetadata{ hash: Hash::from([hash; 32]), permissions: 0o100644, modified: 1000 + hash as u64, size: size.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None };
+        let path = file.to_string_lossy().to_string();
+
+        let store = dir.join("quarantine");
+        let mut quarantine = Quarantine::open(&store, 64).unwrap();
+        quarantine.keep(&path, &meta(1, 3), &meta(2, 11));
+        // the same content again, and a file over the limit
+        quarantine.keep(&path, &meta(1, 3), &meta(2, 11));
+        quarantine.keep(&path, &meta(1, 3), &meta(2, 65));
+        quarantine.keep(&dir.join("missing").to_string_lossy(), &meta(1, 3), &meta(2, 11));
+        quarantine.finish();
+        assert_eq!((quarantine.kept(), quarantine.failed()), (1, 1));
+
+        let name: [u8; 32] = Sha256::digest(b"new content").into();
+        let name = Hash::from(name).to_string();
+        assert_eq!(fs::read(store.join(&name)).unwrap(), b"new content");
+        let sidecar = store.join(format!("{name}.json"));
+        let recorded = records(&sidecar).unwrap();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].path, path);
+        assert_eq!((recorded[0].old_modified, recorded[0].new_modified), (1001, 1002));
+        assert_eq!(recorded[0].new_hash, Hash::from([2u8; 32]).to_string());
+
+        // the same content at another path adds it to the record, a lost sidecar is written again
+        let copy = dir.join("copy");
+        fs::write(&copy, b"new content").unwrap();
+        let mut quarantine = Quarantine::open(&store, 64).unwrap();
+        quarantine.keep(&copy.to_string_lossy(), &meta(3, 3), &meta(2, 11));
+        quarantine.finish();
+        assert_eq!(quarantine.kept(), 0);
+        assert_eq!(records(&sidecar).unwrap().iter().map(|r| r.path.clone()).collect::<Vec<_>>(), [path.clone(), copy.to_string_lossy().to_string()]);
+        fs::remove_file(&sidecar).unwrap();
+        let mut quarantine = Quarantine::open(&store, 64).unwrap();
+        quarantine.keep(&path, &meta(1, 3), &meta(2, 11));
+        drop(quarantine);
+        assert_eq!(records(&sidecar).unwrap().iter().map(|r| r.path.clone()).collect::<Vec<_>>(), [path]);
+        let mut left: Vec<_> = fs::read_dir(&store).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        left.sort();
+        assert_eq!(left, [name.clone(), format!("{name}.json")]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&store).unwrap().permissions().mode() & 0o777, 0o700);
+            assert_eq!(fs::metadata(store.join(&name)).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use super::error::IntegrityWatcherError;
+use sha2::{Sha256, Digest};
+use std::collections::BinaryHeap;
+use std::path::Path;
+
+/// How many DB entries a sampled check verifies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleSpec{
+    Percent(f64),
+    Count(u64),
+}
+
+impl SampleSpec {
+    /// Parses `--sample` values like `2%` or `0.5%`.
+    pub fn parse_percent(s: &str) -> Result<Self, IntegrityWatcherError> {
+        let invalid = || IntegrityWatcherError::InvalidArgument(format!("--sample {s}"));
+        let p: f64 = s.trim().strip_suffix('%').ok_or_else(invalid)?.trim().parse().map_err(|_| invalid())?;
+        if !(p > 0.0 && p <= 100.0){
+            return Err(invalid());
+        }
+        Ok(SampleSpec::Percent(p))
+    }
+}
+
+impl std::fmt::Display for SampleSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            SampleSpec::Percent(p) => write!(f, "{p}%"),
+            SampleSpec::Count(c) => write!(f, "{c} entries"),
+        }
+    }
+}
+
+/// Per path pseudo random score, stable for a given seed so a run can be repeated.
+fn score(seed: u64, path: &str) -> u64{
+    let mut hasher = Sha256::new();
+    hasher.update(seed.to_le_bytes());
+    hasher.update(path.as_bytes());
+    let h = hasher.finalize();
+    u64::from_le_bytes(h[..8].try_into().expect("sha256 is 32 bytes"))
+}
+
+/// Picks the entries to verify out of `paths`. Entries under one of `always`
+/// are always included on top of the random sample.
+pub fn select<I>(paths: I, spec: SampleSpec, seed: u64, always: &[String]) -> Vec<String>
+    where I: IntoIterator<Item = String> {
+    let mut selected = Vec::new();
+    let mut heap = BinaryHeap::new();
+    let threshold = match spec{
+        SampleSpec::Percent(p) => (u64::MAX as f64 * (p / 100.0)) as u64,
+        SampleSpec::Count(_) => u64::MAX,
+    };
+    for path in paths{
+        if always.iter().any(|prefix| Path::new(&path).starts_with(prefix)){
+            selected.push(path);
+            continue;
+        }
+        let s = score(seed, &path);
+        match spec{
+            SampleSpec::Percent(_) => {
+                if s <= threshold{
+                    selected.push(path);
+                }
+            }
+            SampleSpec::Count(count) => {
+                heap.push((s, path));
+                if heap.len() as u64 > count{
+                    heap.pop();
+                }
+            }
+        }
+    }
+    selected.extend(heap.into_iter().map(|(_, p)| p));
+    selected.sort();
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths() -> Vec<String> {
+        (0..10000).map(|i| format!("/data/file{i}")).chain(
+            (0..20).map(|i| format!("/boot/file{i}"))
+        ).collect()
+    }
+
+    #[test]
+    fn test_parse_percent(){
+        assert_eq!(SampleSpec::parse_percent("2%").unwrap(), SampleSpec::Percent(2.0));
+        assert_eq!(SampleSpec::parse_percent(" 0.5 %").unwrap(), SampleSpec::Percent(0.5));
+        for bad in ["2", "%", "0%", "101%", "-1%", "abc%", "NaN%"]{
+            assert!(SampleSpec::parse_percent(bad).is_err(), "Parsed invalid sample {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_select_is_seeded(){
+        let a = select(paths(), SampleSpec::Percent(5.0), 42, &[]);
+        let b = select(paths(), SampleSpec::Percent(5.0), 42, &[]);
+        let c = select(paths(), SampleSpec::Percent(5.0), 43, &[]);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(a.len() > 350 && a.len() < 700, "unexpected sample size {}", a.len());
+        assert_eq!(select(paths(), SampleSpec::Percent(100.0), 1, &[]).len(), 10020);
+    }
+
+    #[test]
+    fn test_select_count_and_always(){
+        let always = vec!["/boot".to_owned()];
+        let selected = select(paths(), SampleSpec::Count(100), 7, &always);
+        assert_eq!(selected.len(), 120);
+        assert_eq!(selected.iter().filter(|p| p.starts_with("/boot/")).count(), 20);
+        assert_eq!(select(paths(), SampleSpec::Count(100), 7, &always), selected);
+
+        // prefix matches whole components only
+        let selected = select(vec!["/bootstrap/x".to_owned()], SampleSpec::Count(0), 7, &always);
+        assert!(selected.is_empty());
+    }
+}
@@ -0,0 +1,2115 @@
+use super::entropy;
+use super::error::IntegrityWatcherError;
+use super::dbfile;
+use super::fileops::{AddFileInfo, CheckDB, FindingKind};
+use super::inodeflags;
+use super::ownership;
+use super::exclude::{ExcludeSet, Verdict};
+use super::magic;
+use super::pathfold::PathFold;
+use super::policy::{self, HashMode, Policy};
+use super::snapshot::{self, BaselineSnapshot};
+use super::treehash;
+use super::types::{self, DirMetadata, EscapedPath, FileMetadata, FileMetadataExt, SymlinkMetadata};
+use super::whiteout::{Layered, WhiteoutFormat};
+#[cfg(windows)]
+use super::winmeta;
+use redb::Database;
+use sha2::{Sha256, Digest};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{debug, debug_span, error, trace, warn, Instrument};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Per file limit for `--hash-command` when `--file-timeout` isn't given.
+const HASH_COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// `--hash-command` support: runs `template` with a `{}` word replaced by the
+/// path (appended when there is none) and takes the first word of its stdout as
+/// a hex digest. The template is split into words like a shell does, quotes
+/// included, but run without one, so paths are never interpreted. The child is
+/// killed after `timeout`.
+fn run_hash_command(template: &str, path: &Path, timeout: Duration) -> Result<types::Hash, IntegrityWatcherError> {
+    let path_str = path.to_string_lossy().to_string();
+    let failed = |reason: String| IntegrityWatcherError::HashCommand { reason, path: path_str.clone() };
+    let words = shell_words::split(template).map_err(|e| failed(format!("can't be split into words: {e}")))?;
+    let mut words = words.iter().map(String::as_str);
+    let program = words.next().ok_or_else(|| failed("is empty".to_owned()))?;
+    let mut cmd = std::process::Command::new(program);
+    cmd.env_remove(dbfile::PASSPHRASE_ENV);
+    let mut substituted = false;
+    for word in words{
+        if word == "{}"{
+            cmd.arg(path);
+            substituted = true;
+        }
+        else{
+            cmd.arg(word);
+        }
+    }
+    if !substituted{
+        cmd.arg(path);
+    }
+    let mut child = cmd
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| IntegrityWatcherError::IOError { source: e, path: program.to_owned() })?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        stdout.read_to_end(&mut out).map(|_| out)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let mut poll = Duration::from_millis(1);
+    let status = loop{
+        if let Some(status) = child.try_wait().map_err(|e| IntegrityWatcherError::IOError { source: e, path: program.to_owned() })?{
+            break status;
+        }
+        if Instant::now() >= deadline{
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(IntegrityWatcherError::Timeout { timeout, path: path_str });
+        }
+        std::thread::sleep(poll);
+        poll = (poll * 2).min(Duration::from_millis(50));
+    };
+    if !status.success(){
+        return Err(failed(format!("{program} {status}")));
+    }
+    let out = reader.join().map_err(|_| failed(format!("{program} output reader panicked")))?
+        .map_err(|e| IntegrityWatcherError::IOError { source: e, path: program.to_owned() })?;
+    let out = String::from_utf8_lossy(&out);
+    let digest = out.split_whitespace().next().unwrap_or_default();
+    types::Hash::from_hex(digest).map_err(|_| failed(format!("{program} printed no hex digest")))
+}
+
+/// Reads and hashes one file, with the built in SHA-256 or `--hash-command`.
+/// `--prefilter` hashes with XXH3-64 instead, adding the SHA-256 in the same
+/// pass with `verify_matches`. With `detect_type` the content type is told
+/// from the first bytes of the same read.
+fn hash_file(path: &Path, opts: &ScanOptions) -> Result<FileMetadata, IntegrityWatcherError> {
+    let (track_inode, prefilter, verify_matches, detect_type) = (opts.track_inode, opts.prefilter, opts.verify_matches, opts.detect_type);
+    if let Some(template) = &opts.hash_command{
+        let command_timeout = opts.file_timeout.unwrap_or(HASH_COMMAND_TIMEOUT);
+        let meta = std::fs::metadata(path)
+            .map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
+        let hash = run_hash_command(template, path, command_timeout)?;
+        let mut meta = FileMetadata::new(&meta, hash, track_inode)?;
+        if detect_type{
+            meta.content_type = Some(read_content_type(path)?);
+        }
+        return Ok(meta);
+    }
+    let mut sha256 = (!prefilter || verify_matches).then(Sha256::new);
+    let mut xxh3 = prefilter.then(Xxh3::new);
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
+    let mut buffer = [0u8; 65536];
+    let mut head = Vec::new();
+    let mut counter = opts.entropy.is_some().then(entropy::Counter::default);
+    loop {
+        let n = file.read(&mut buffer)
+            .map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
+        if n == 0 { break; }
+        if detect_type && head.len() < magic::HEAD_LEN{
+            head.extend_from_slice(&buffer[..n.min(magic::HEAD_LEN - head.len())]);
+        }
+        if let Some(counter) = &mut counter{
+            counter.update(&buffer[..n]);
+        }
+        if let Some(hasher) = &mut sha256{
+            hasher.update(&buffer[..n]);
+        }
+        if let Some(hasher) = &mut xxh3{
+            hasher.update(&buffer[..n]);
+        }
+    }
+    let sha256 = sha256.map(|hasher| -> types::Hash {
+        let result: [u8; 32] = hasher.finalize().into();
+        result.into()
+    });
+    let (hash, strong) = match (xxh3, sha256){
+        (Some(fast), strong) => (fast.digest().to_be_bytes().into(), strong),
+        (None, Some(hash)) => (hash, None),
+        (None, None) => unreachable!("SHA-256 is computed without prefilter"),
+    };
+    let mut meta = FileMetadata::new(&file.metadata().map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?, hash, track_inode)?;
+    meta.strong = strong;
+    meta.content_type = detect_type.then(|| magic::detect(&head).to_owned());
+    if let (Some(measured), Some(bits)) = (&opts.entropy, counter.and_then(|c| c.bits_per_byte())){
+        measured.record(&path.to_string_lossy(), bits);
+    }
+    Ok(meta)
+}
+
+/// `--stat-only`: the metadata of a file without reading its content, except
+/// for the first bytes with `detect_type`.
+fn stat_file(path: &Path, track_inode: bool, detect_type: bool) -> Result<FileMetadata, IntegrityWatcherError> {
+    let meta = std::fs::metadata(path)
+        .map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
+    let mut meta = FileMetadata::new(&meta, types::Hash::none(), track_inode)?;
+    if detect_type{
+        meta.content_type = Some(read_content_type(path)?);
+    }
+    Ok(meta)
+}
+
+/// `head-hash` policy: the SHA-256 of the first `policy::HEAD_HASH_LEN` bytes,
+/// whatever the hash algorithm of the DB.
+fn head_hash_file(path: &Path, track_inode: bool, detect_type: bool) -> Result<FileMetadata, IntegrityWatcherError> {
+    let failed = |e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() };
+    let file = std::fs::File::open(path).map_err(failed)?;
+    let meta = file.metadata().map_err(failed)?;
+    let mut head = file.take(policy::HEAD_HASH_LEN);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 65536];
+    let mut start = Vec::new();
+    loop {
+        let n = head.read(&mut buffer).map_err(failed)?;
+        if n == 0 { break; }
+        if detect_type && start.len() < magic::HEAD_LEN{
+            start.extend_from_slice(&buffer[..n.min(magic::HEAD_LEN - start.len())]);
+        }
+        hasher.update(&buffer[..n]);
+    }
+    let hash: [u8; 32] = hasher.finalize().into();
+    let mut meta = FileMetadata::new(&meta, hash.into(), track_inode)?;
+    meta.content_type = detect_type.then(|| magic::detect(&start).to_owned());
+    Ok(meta)
+}
+
+/// `--parallel-file-hash`: the tree hash of the file, see `treehash`.
+fn tree_hash_file(path: &Path, stripe: u64, track_inode: bool, detect_type: bool) -> Result<FileMetadata, IntegrityWatcherError> {
+    let failed = |e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() };
+    let hash = treehash::hash_file(path, stripe).map_err(failed)?;
+    let mut meta = FileMetadata::new(&std::fs::metadata(path).map_err(failed)?, hash, track_inode)?;
+    if detect_type{
+        meta.content_type = Some(read_content_type(path)?);
+    }
+    Ok(meta)
+}
+
+/// Content type of a file whose content isn't read otherwise.
+fn read_content_type(path: &Path) -> Result<String, IntegrityWatcherError> {
+    let mut head = Vec::with_capacity(magic::HEAD_LEN);
+    std::fs::File::open(path).and_then(|f| f.take(magic::HEAD_LEN as u64).read_to_end(&mut head))
+        .map_err(|e| IntegrityWatcherError::IOError { source: e, path: path.to_string_lossy().to_string() })?;
+    Ok(magic::detect(&head).to_owned())
+}
+
+/// Hashes the file on the blocking pool. With a `timeout` a file stuck on a hung
+/// mount is given up on; its blocking thread is left behind as it can't be cancelled.
+pub async fn get_file_hash(path: PathBuf, opts: &ScanOptions) -> Result<FileMetadata, IntegrityWatcherError> {
+    let path_str = path.to_string_lossy().to_string();
+    let track_inode = opts.track_inode;
+    let full = opts.clone();
+    let stats = opts.stats.clone();
+    let (detect_type, stripe_size, inode_flags, owner, max_hash_size) = (opts.detect_type, opts.stripe_size, opts.inode_flags, opts.owner, opts.max_hash_size);
+    let mode = match &opts.policy{
+        _ if opts.stat_only => HashMode::StatOnly,
+        Some(policy) => policy.mode(&path_str),
+        None => HashMode::Full,
+    };
+    let task = tokio::task::spawn_blocking(move || -> Result<FileMetadata, IntegrityWatcherError> {
+        let start = Instant::now();
+        let mode = match max_hash_size{
+            Some(max) if matches!(mode, HashMode::Full | HashMode::Skip) && std::fs::metadata(&path).is_ok_and(|m| m.len() > max) => HashMode::StatOnly,
+            _ => mode,
+        };
+        let meta = match mode{
+            HashMode::StatOnly => stat_file(&path, track_inode, detect_type),
+            HashMode::HeadHash => head_hash_file(&path, track_inode, detect_type),
+            // skipped entries aren't walked, files asked for directly are hashed
+            HashMode::Full | HashMode::Skip if let Some(stripe) = stripe_size => tree_hash_file(&path, stripe, track_inode, detect_type),
+            HashMode::Full | HashMode::Skip => hash_file(&path, &full),
+        };
+        #[cfg(windows)]
+        let meta = meta.map(|m| FileMetadata{ security: winmeta::security(&path), streams: winmeta::streams(&path), ..m });
+        let meta = meta.map(|m| FileMetadata{ flags: inode_flags.then(|| inodeflags::read(&path)).flatten(), ..m });
+        let meta = meta.map(|m| FileMetadata{ owner: owner.then(|| std::fs::metadata(&path).ok().and_then(|m| ownership::of(&m))).flatten(), ..m });
+        if let Some(stats) = stats{
+            stats.record(start.elapsed());
+        }
+        meta
+    });
+    let meta = match opts.file_timeout{
+        Some(timeout) => tokio::time::timeout(timeout, task).await
+            .map_err(|_| IntegrityWatcherError::Timeout { timeout, path: path_str })???,
+        None => task.await??,
+    };
+    Ok(meta)
+}
+
+/// Runs a whole scan under the optional `--timeout`.
+pub async fn with_scan_timeout<T>(timeout: Option<Duration>, what: &str, scan: impl Future<Output = Result<T, IntegrityWatcherError>>) -> Result<T, IntegrityWatcherError> {
+    match timeout{
+        Some(timeout) => tokio::time::timeout(timeout, scan).await
+            .map_err(|_| IntegrityWatcherError::Timeout { timeout, path: what.to_owned() })?,
+        None => scan.await,
+    }
+}
+
+/// `--since` support: DB entries last modified before the cutoff whose
+/// current mtime and size still match keep their stored hash instead of
+/// being re-read.
+#[derive(Debug)]
+pub struct SinceFilter{
+    pub cutoff: u64,
+    baseline: BaselineSnapshot,
+    skipped: AtomicU64,
+}
+
+impl SinceFilter {
+    pub fn load(db: &Database, table: &str, cutoff: u64) -> Result<Self, IntegrityWatcherError> {
+        let baseline = BaselineSnapshot::open(db, table, snapshot::PRELOAD_LIMIT)?;
+        Ok(SinceFilter { cutoff, baseline, skipped: AtomicU64::new(0) })
+    }
+
+    async fn reuse_hash(&self, path: &Path, path_str: &str, opts: &ScanOptions) -> Result<Option<FileMetadata>, IntegrityWatcherError> {
+        let Some(FileMetadataExt::File(old)) = self.baseline.get(path_str)? else {
+            return Ok(None);
+        };
+        if old.modified >= self.cutoff{
+            return Ok(None);
+        }
+        let meta = fs::metadata(path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path_str.to_owned() })?;
+        let current = FileMetadata{ strong: old.strong.clone(), content_type: old.content_type.clone(), ..FileMetadata::new(&meta, old.hash.clone(), opts.track_inode)? };
+        #[cfg(windows)]
+        let current = FileMetadata{ security: winmeta::security(path), streams: winmeta::streams(path), ..current };
+        let current = FileMetadata{ flags: opts.inode_flags.then(|| inodeflags::read(path)).flatten(), owner: opts.owner.then(|| ownership::of(&meta)).flatten(), ..current };
+        if current.modified != old.modified || current.size != old.size || current.modified >= self.cutoff{
+            return Ok(None);
+        }
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(current))
+    }
+
+    pub fn get_skipped(&self) -> u64{
+        self.skipped.load(Ordering::Relaxed)
+    }
+}
+
+/// Time spent hashing on each blocking pool thread, see `ScanOptions::stats`.
+#[derive(Debug, Default)]
+pub struct HashStats{
+    busy: std::sync::Mutex<std::collections::HashMap<std::thread::ThreadId, Duration>>,
+}
+
+impl HashStats {
+    pub fn record(&self, busy: Duration){
+        let mut threads = self.busy.lock().expect("hash stats lock poisoned");
+        *threads.entry(std::thread::current().id()).or_default() += busy;
+    }
+
+    /// Busy time of every thread that hashed something, busiest first.
+    pub fn per_thread(&self) -> Vec<Duration>{
+        let mut busy: Vec<_> = self.busy.lock().expect("hash stats lock poisoned").values().copied().collect();
+        busy.sort_by(|a, b| b.cmp(a));
+        busy
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions{
+    /// don't report directory entries, only files and symlinks
+    pub no_dirs: bool,
+    /// reuse stored hashes of files unchanged since a cutoff
+    pub since: Option<Arc<SinceFilter>>,
+    /// limit for a whole scan, see `with_scan_timeout`
+    pub timeout: Option<Duration>,
+    /// files that can't be hashed within this long are skipped
+    pub file_timeout: Option<Duration>,
+    /// record inode numbers
+    pub track_inode: bool,
+    /// external hash program instead of SHA-256, `{}` is replaced by the path
+    pub hash_command: Option<Arc<str>>,
+    /// hashing time per thread, only collected by `--bench`
+    pub stats: Option<Arc<HashStats>>,
+    /// hash with XXH3-64 instead of SHA-256
+    pub prefilter: bool,
+    /// with `prefilter`, also compute the SHA-256
+    pub verify_matches: bool,
+    /// keep symlinks to regular files as links, with the SHA-256 of the file
+    /// they resolve to, instead of storing them as that file
+    pub hash_link_content: bool,
+    /// how paths are folded into DB keys, the sinks fold them with `with_path_fold`
+    pub path_fold: PathFold,
+    /// files hashed at once, 1024 when not set
+    pub hash_jobs: Option<usize>,
+    /// store the content type told by the first bytes of files
+    pub detect_type: bool,
+    /// don't read files, their entries have no content hash
+    pub stat_only: bool,
+    /// hash files as a tree of stripes this long, see `treehash`
+    pub stripe_size: Option<u64>,
+    /// record the immutable and append-only inode flags, see `inodeflags`
+    pub inode_flags: bool,
+    /// record the owning user and group, see `ownership`
+    pub owner: bool,
+    /// `--symlink-policy within-roots`: canonical roots symlinks must point
+    /// into, links escaping them are warned about
+    pub symlink_roots: Option<Arc<[PathBuf]>>,
+    /// `--policy`: how files under a path are hashed, `stat_only` still
+    /// applies to all of them
+    pub policy: Option<Arc<Policy>>,
+    /// directories listed at once, one when not set
+    pub walk_jobs: Option<usize>,
+    /// `--overlay-aware`: store whiteouts of this format as deletions, see
+    /// `whiteout`
+    pub whiteouts: Option<WhiteoutFormat>,
+    /// `--entropy`: the entropy of files hashed in full, for the check to
+    /// take, see `entropy`
+    pub entropy: Option<Arc<entropy::Measured>>,
+    /// `--check-max-size`: files larger than this are only stat'ed, like
+    /// `stat_only` ones
+    pub max_hash_size: Option<u64>,
+    /// `--max-depth`: levels below a root the walk goes down, directories at
+    /// the last one are recorded without their entry count
+    pub max_depth: Option<usize>,
+}
+
+async fn get_file_meta(path: PathBuf, path_str: &str, opts: &ScanOptions) -> Result<FileMetadata, IntegrityWatcherError> {
+    if let Some(since) = &opts.since
+    && let Some(meta) = since.reuse_hash(&path, &opts.path_fold.fold(path_str), opts).await?{
+        trace!("Unchanged since cutoff {}", EscapedPath(path_str));
+        return Ok(meta);
+    }
+    get_file_hash(path, opts).await
+}
+
+/// Symlink entry with what it resolves to, `target` being its metadata, with
+/// `hash_link_content` also the SHA-256 of the regular file it resolves to.
+/// Dangling links, links to anything else and `stat_only` scans get no content
+/// hash.
+async fn symlink_meta(path: &Path, path_str: &str, target: Option<std::fs::Metadata>, opts: &ScanOptions) -> Result<SymlinkMetadata, IntegrityWatcherError> {
+    let data = fs::read_link(path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path_str.to_owned() })?;
+    let meta = fs::symlink_metadata(path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path_str.to_owned() })?;
+    let mut sym = SymlinkMetadata::new(&meta, data.to_string_lossy().into_owned())?;
+    sym.target_exists = target.is_some();
+    sym.target_type = target.as_ref().map(|m| m.file_type().into());
+    if opts.hash_link_content && !opts.stat_only && target.is_some_and(|m| m.is_file()){
+        let sha256 = ScanOptions{ file_timeout: opts.file_timeout, ..Default::default() };
+        sym.content = Some(get_file_hash(path.to_owned(), &sha256).await?.hash);
+    }
+    Ok(sym)
+}
+
+/// Target of the symlink at `path`, resolved against the link's directory, when
+/// it lies outside all `roots`. The target is resolved lexically, it doesn't
+/// need to exist.
+pub async fn escaping_target(path: &Path, roots: &[PathBuf]) -> io::Result<Option<PathBuf>> {
+    let target = fs::read_link(path).await?;
+    let parent = match path.parent(){
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut resolved = PathBuf::new();
+    for part in fs::canonicalize(parent).await?.join(target).components(){
+        match part{
+            Component::CurDir => {},
+            Component::ParentDir => { resolved.pop(); },
+            part => resolved.push(part),
+        }
+    }
+    Ok((!roots.iter().any(|root| resolved.starts_with(root))).then_some(resolved))
+}
+
+/// Warns about a symlink breaking `--symlink-policy`.
+async fn check_symlink_policy(path: &Path, path_str: &str, opts: &ScanOptions) {
+    let Some(roots) = &opts.symlink_roots else {
+        return;
+    };
+    match escaping_target(path, roots).await{
+        Ok(Some(target)) => warn!("Symlink {} points outside the scanned roots to {}", EscapedPath(path_str), EscapedPath(target.to_string_lossy().as_ref())),
+        Ok(None) => {},
+        Err(e) => debug!("Symlink {} not resolved: {e}", EscapedPath(path_str)),
+    }
+}
+
+/// Roots for `ScanOptions::symlink_roots`, resolved like the links pointing
+/// into them. Missing roots are left out.
+pub async fn symlink_policy_roots(paths: &[String]) -> Arc<[PathBuf]> {
+    let mut roots = Vec::with_capacity(paths.len());
+    for path in paths{
+        if let Ok(root) = fs::canonicalize(path).await{
+            roots.push(root);
+        }
+    }
+    roots.into()
+}
+
+type JoinReturn = Result<Option<(String, FileMetadataExt)>, IntegrityWatcherError>;
+
+/// Entry of `path`, dispatched on its `file_type` as listed, not following
+/// symlinks. Only symlinks are stat'ed once more, for what they point to; the
+/// metadata itself comes from one stat, which std issues as `statx` on Linux.
+/// Directories store `entries`, their children as the walk listed them.
+async fn entry_metadata(path: PathBuf, path_str: String, file_type: std::fs::FileType, entries: Option<u32>, opts: ScanOptions) -> JoinReturn {
+    if let Some(format) = opts.whiteouts{
+        match format.classify(&path, file_type).await{
+            Layered::Entry => {},
+            Layered::Deletes(deleted) => {
+                debug!("Whiteout {} deletes {}", EscapedPath(&path_str), EscapedPath(&deleted));
+                return Ok(Some((deleted, FileMetadataExt::Whiteout)));
+            }
+            Layered::Opaque(marker) => {
+                debug!("Opaque directory marked by {}", EscapedPath(&marker));
+                return Ok(Some((marker, FileMetadataExt::Opaque)));
+            }
+            Layered::Meta => {
+                debug!("Skipping aufs file {}", EscapedPath(&path_str));
+                return Ok(None);
+            }
+        }
+    }
+    let target = match file_type.is_symlink(){
+        true => {
+            check_symlink_policy(&path, &path_str, &opts).await;
+            fs::metadata(&path).await.ok()
+        }
+        false => None,
+    };
+    let links_file = target.as_ref().is_some_and(|t| t.is_file());
+    if file_type.is_file() || (links_file && !opts.hash_link_content){
+        let meta = get_file_meta(path, &path_str, &opts).await?;
+        Ok(Some((path_str, FileMetadataExt::File(meta))))
+    }
+    else if file_type.is_symlink() {
+        let sym = symlink_meta(&path, &path_str, target, &opts).await?;
+        Ok(Some((path_str, FileMetadataExt::Symlink(sym))))
+    }
+    else if file_type.is_dir(){
+        let meta = fs::metadata(&path).await.map_err(|e| IntegrityWatcherError::IOError { source: e, path: path_str.to_owned() })?;
+        let dir = DirMetadata{ entries, ..DirMetadata::new(&meta)? };
+        #[cfg(windows)]
+        let dir = DirMetadata{ security: winmeta::security(&path), ..dir };
+        let dir = DirMetadata{ flags: opts.inode_flags.then(|| inodeflags::read(&path)).flatten(), owner: opts.owner.then(|| ownership::of(&meta)).flatten(), ..dir };
+        Ok(Some((path_str, FileMetadataExt::Dir(dir))))
+    }
+    else{
+        warn!("Path {} unsuported type", EscapedPath(&path_str));
+        Ok(None)
+    }
+}
+
+/// Scans `dir` inside a `root` span, so every event about it names the root.
+pub async fn visit_dirs<F>(dir: PathBuf, exclude: &ExcludeSet, opts: &ScanOptions, finfo: &mut F) -> Result<(), IntegrityWatcherError>
+    where F: AddFileInfo {
+    let span = debug_span!("root", root = %EscapedPath(dir.to_string_lossy().as_ref()));
+    walk_root(dir, exclude, opts, finfo).instrument(span).await
+}
+
+/// Files hashed at once unless `ScanOptions::hash_jobs` says otherwise, each
+/// holds an open file or a hash command.
+const FILES_OPEN_PRESSURE: usize = 1024;
+
+/// Entry tasks waiting for an open file slot beyond those running, a few KB each.
+const QUEUED_TASKS: usize = 4 * FILES_OPEN_PRESSURE;
+
+/// Directory listings a walk keeps open, one per level it went down. Deeper
+/// directories wait in a list until the walk is back at the root.
+const OPEN_DIRS: usize = 64;
+
+/// Entries written to the DB together, writing in bigger chunks is way faster.
+const BATCH_ENTRIES: usize = 1000;
+
+/// Entries gathered for longer than this are written even when fewer.
+const BATCH_AGE: Duration = Duration::from_millis(500);
+
+/// Entries of finished tasks waiting to be handed to the sink. Flushed by count
+/// or age, so memory stays bounded whatever the fan-out of a directory,
+/// independent of how many tasks the open file throttling lets run.
+struct Batch{
+    entries: Vec<(String, FileMetadataExt)>,
+    started: Instant,
+}
+
+impl Batch {
+    fn new() -> Self {
+        Batch { entries: Vec::with_capacity(BATCH_ENTRIES), started: Instant::now() }
+    }
+
+    /// Adds the entry of a finished task, entries that couldn't be read are
+    /// logged and skipped.
+    fn collect(&mut self, joined: Result<JoinReturn, tokio::task::JoinError>) -> Result<(), IntegrityWatcherError> {
+        match joined?{
+            Ok(Some(r)) => {
+                if self.entries.is_empty(){
+                    self.started = Instant::now();
+                }
+                self.entries.push(r);
+            }
+            Ok(None) => {},
+            Err(e) => error!("{e}"),
+        }
+        Ok(())
+    }
+
+    /// Collects `joined`, flushing when due. Returns true when the walk should stop.
+    fn push<F: AddFileInfo>(&mut self, joined: Result<JoinReturn, tokio::task::JoinError>, finfo: &mut F) -> Result<bool, IntegrityWatcherError> {
+        self.collect(joined)?;
+        match self.is_due(){
+            true => self.flush(finfo),
+            false => Ok(false),
+        }
+    }
+
+    fn is_due(&self) -> bool {
+        self.entries.len() >= BATCH_ENTRIES || (!self.entries.is_empty() && self.started.elapsed() >= BATCH_AGE)
+    }
+
+    /// Hands the entries to `finfo`. Returns true when it wants the walk stopped.
+    fn flush<F: AddFileInfo>(&mut self, finfo: &mut F) -> Result<bool, IntegrityWatcherError> {
+        if self.entries.is_empty(){
+            return Ok(false);
+        }
+        finfo.add_file_info(&self.entries)?;
+        self.entries.clear();
+        Ok(finfo.should_stop())
+    }
+}
+
+/// A directory for the walk to list, with its type when the walk records it
+/// once listed.
+struct Enter{
+    dir: PathBuf,
+    record: Option<std::fs::FileType>,
+}
+
+/// Listing of a directory the serial walk is in.
+struct OpenDir{
+    enter: Enter,
+    path: String,
+    /// none when the directory couldn't be opened
+    listing: Option<fs::ReadDir>,
+    count: u32,
+}
+
+/// Opens the listing of `enter` as the next level of the walk, or defers it
+/// when `OPEN_DIRS` are open. Unreadable directories are logged and listed as
+/// empty, without a count.
+async fn enter_dir(enter: Enter, open: &mut Vec<OpenDir>, deferred: &mut Vec<Enter>) {
+    if open.len() >= OPEN_DIRS{
+        deferred.push(enter);
+        return;
+    }
+    let path = enter.dir.to_string_lossy().to_string();
+    let listing = match fs::read_dir(&enter.dir).await{
+        Ok(listing) => Some(listing),
+        Err(e) => {
+            error!("{}", IntegrityWatcherError::IOError { source: e, path: path.clone() });
+            None
+        }
+    };
+    open.push(OpenDir{ enter, path, listing, count: 0 });
+}
+
+/// Type of `entry` not following symlinks, mostly told by the listing itself
+/// without a stat. Entries whose type can't be read are logged and skipped.
+async fn entry_type(entry: &fs::DirEntry) -> Option<std::fs::FileType> {
+    match entry.file_type().await{
+        Ok(t) => Some(t),
+        Err(e) => {
+            error!("{}", IntegrityWatcherError::IOError { source: e, path: entry.path().to_string_lossy().to_string() });
+            None
+        }
+    }
+}
+
+/// What a `--walk-jobs` worker found.
+enum Listed{
+    /// an entry of a listed directory and its type
+    Entry(PathBuf, std::fs::FileType),
+    /// a directory was listed to the end with this many entries, or couldn't
+    /// be opened
+    Done(Enter, Option<u32>),
+    Failed(IntegrityWatcherError),
+}
+
+/// What the walk takes from `Listings`.
+enum Walked{
+    /// an entry of a listed directory and its type
+    Entry(PathBuf, std::fs::FileType),
+    /// a directory to record was listed, with its entry count unless it
+    /// couldn't be opened
+    Listed(PathBuf, std::fs::FileType, Option<u32>),
+}
+
+/// Entries listed by a `--walk-jobs` worker waiting for the walk, so workers
+/// don't read far ahead of the hashing.
+const LISTED_ENTRIES: usize = 1024;
+
+/// Directories queued for the `--walk-jobs` workers. The walk can't wait for
+/// room, the workers may be waiting for it to take their entries, so more
+/// directories wait in a list it hands over deepest first.
+#[cfg(not(test))]
+const QUEUED_DIRS: usize = 1024;
+#[cfg(test)]
+const QUEUED_DIRS: usize = 4;
+
+/// Lists the directories taken from `queue` until it's closed, each exactly
+/// once and to the end.
+async fn list_dirs(queue: Arc<tokio::sync::Mutex<mpsc::Receiver<Enter>>>, listed: mpsc::Sender<Listed>) {
+    loop{
+        let enter = queue.lock().await.recv().await;
+        let Some(enter) = enter else {
+            return;
+        };
+        let path = enter.dir.to_string_lossy().to_string();
+        let count = match fs::read_dir(&enter.dir).await{
+            Ok(mut listing) => {
+                let mut count = 0u32;
+                loop{
+                    let found = match listing.next_entry().await{
+                        Ok(Some(entry)) => {
+                            count = count.saturating_add(1);
+                            match entry_type(&entry).await{
+                                Some(file_type) => Listed::Entry(entry.path(), file_type),
+                                None => continue,
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => Listed::Failed(IntegrityWatcherError::IOError { source: e, path: path.clone() }),
+                    };
+                    let failed = matches!(found, Listed::Failed(_));
+                    if listed.send(found).await.is_err() || failed{
+                        return;
+                    }
+                }
+                Some(count)
+            }
+            Err(e) => {
+                error!("{}", IntegrityWatcherError::IOError { source: e, path });
+                None
+            }
+        };
+        if listed.send(Listed::Done(enter, count)).await.is_err(){
+            return;
+        }
+    }
+}
+
+/// Where the walk takes the entries of the tree from.
+enum Listings{
+    /// depth first through open listings, memory grows with the depth of the
+    /// tree, not with the number of directories in it
+    Serial{
+        open: Vec<OpenDir>,
+        deferred: Vec<Enter>,
+    },
+    /// `--walk-jobs` workers list directories from a shared queue at once, for
+    /// file systems where each listing waits on the network
+    Parallel{
+        queue: mpsc::Sender<Enter>,
+        /// directories the queue had no room for
+        deferred: Vec<Enter>,
+        listed: mpsc::Receiver<Listed>,
+        /// directories queued or deferred and not listed to the end yet
+        pending: usize,
+        _workers: JoinSet<()>,
+    },
+}
+
+impl Listings {
+    fn new(walk_jobs: usize) -> Self {
+        if walk_jobs <= 1{
+            return Listings::Serial { open: Vec::new(), deferred: Vec::new() };
+        }
+        let (queue, queued) = mpsc::channel(QUEUED_DIRS);
+        let (found, listed) = mpsc::channel(LISTED_ENTRIES);
+        let queued = Arc::new(tokio::sync::Mutex::new(queued));
+        let mut workers = JoinSet::new();
+        for _ in 0..walk_jobs{
+            workers.spawn(list_dirs(queued.clone(), found.clone()).in_current_span());
+        }
+        Listings::Parallel { queue, deferred: Vec::new(), listed, pending: 0, _workers: workers }
+    }
+
+    /// Queues `dir` to be listed, handed back as `Walked::Listed` once it is
+    /// when `record` is its type.
+    async fn enter(&mut self, dir: PathBuf, record: Option<std::fs::FileType>) {
+        let enter = Enter{ dir, record };
+        match self{
+            Listings::Serial { open, deferred } => enter_dir(enter, open, deferred).await,
+            Listings::Parallel { queue, deferred, pending, .. } => {
+                // the workers only stop once the queue is dropped
+                if let Err(mpsc::error::TrySendError::Full(enter)) = queue.try_send(enter){
+                    deferred.push(enter);
+                }
+                *pending += 1;
+            }
+        }
+    }
+
+    /// The next entry or listed directory, None once every queued directory
+    /// is listed.
+    async fn next(&mut self) -> Result<Option<Walked>, IntegrityWatcherError> {
+        match self{
+            Listings::Serial { open, deferred } => loop{
+                let Some(dir) = open.last_mut() else {
+                    let Some(enter) = deferred.pop() else {
+                        return Ok(None);
+                    };
+                    enter_dir(enter, open, deferred).await;
+                    continue;
+                };
+                let next = match &mut dir.listing{
+                    Some(listing) => listing.next_entry().await
+                        .map_err(|e| IntegrityWatcherError::IOError { source: e, path: dir.path.clone() })?,
+                    None => None,
+                };
+                match next{
+                    Some(entry) => {
+                        dir.count = dir.count.saturating_add(1);
+                        if let Some(file_type) = entry_type(&entry).await{
+                            return Ok(Some(Walked::Entry(entry.path(), file_type)));
+                        }
+                    }
+                    None => {
+                        let OpenDir{ enter, listing, count, .. } = open.pop().expect("listing is open");
+                        if let Some(file_type) = enter.record{
+                            return Ok(Some(Walked::Listed(enter.dir, file_type, listing.map(|_| count))));
+                        }
+                    }
+                }
+            },
+            Listings::Parallel { queue, deferred, listed, pending, .. } => loop{
+                if *pending == 0{
+                    return Ok(None);
+                }
+                while let Some(enter) = deferred.pop(){
+                    if let Err(mpsc::error::TrySendError::Full(enter)) = queue.try_send(enter){
+                        deferred.push(enter);
+                        break;
+                    }
+                }
+                match listed.recv().await.expect("list workers are running"){
+                    Listed::Entry(path, file_type) => return Ok(Some(Walked::Entry(path, file_type))),
+                    Listed::Done(enter, count) => {
+                        *pending -= 1;
+                        if let Some(file_type) = enter.record{
+                            return Ok(Some(Walked::Listed(enter.dir, file_type, count)));
+                        }
+                    }
+                    Listed::Failed(e) => return Err(e),
+                }
+            },
+        }
+    }
+}
+
+async fn walk_root<F>(dir: PathBuf, exclude: &ExcludeSet, opts: &ScanOptions, finfo: &mut F) -> Result<(), IntegrityWatcherError>
+    where F: AddFileInfo {
+    let mut tasks: JoinSet<JoinReturn> = JoinSet::new();
+    // files wait inside their task for one of hash_jobs open files, so
+    // directories and symlinks queued behind them aren't held back; the walk
+    // itself waits once QUEUED_TASKS more are queued
+    let hash_jobs = opts.hash_jobs.unwrap_or(FILES_OPEN_PRESSURE).max(1);
+    let open_files = Arc::new(Semaphore::new(hash_jobs));
+    let queued = Arc::new(Semaphore::new(hash_jobs.max(FILES_OPEN_PRESSURE) + QUEUED_TASKS));
+    let mut batch = Batch::new();
+    if exclude.verdict(dir.to_string_lossy().as_ref()) == Verdict::Exclude{
+        warn!("Excluding top dir {}", EscapedPath(dir.to_string_lossy().as_ref()));
+        return Ok(());
+    }
+    // a missing root has no entries
+    let root_type = fs::symlink_metadata(&dir).await.ok().map(|m| m.file_type());
+    if root_type.is_some_and(|t| t.is_dir()) {
+        let mut listings = Listings::new(opts.walk_jobs.unwrap_or(1));
+        listings.enter(dir.to_owned(), None).await;
+        while let Some(walked) = listings.next().await?{
+            // directories the walk goes into are recorded once listed, with their entry count
+            let (path, file_type, entries) = match walked{
+                Walked::Listed(path, file_type, entries) => (path, file_type, entries),
+                Walked::Entry(path, file_type) => {
+                    let is_dir = file_type.is_dir();
+                    let descend = is_dir && opts.max_depth.is_none_or(|max| path.strip_prefix(&dir).map_or(0, |p| p.components().count()) < max);
+                    match exclude.verdict(path.to_string_lossy().as_ref()){
+                        Verdict::Include => {},
+                        Verdict::Exclude => {
+                            debug!("Skipping {}", EscapedPath(path.to_string_lossy().as_ref()));
+                            continue;
+                        }
+                        Verdict::Traverse => {
+                            debug!("Walking excluded {} for re-included paths", EscapedPath(path.to_string_lossy().as_ref()));
+                            if descend{
+                                listings.enter(path, None).await;
+                            }
+                            continue;
+                        }
+                    }
+                    if opts.policy.as_ref().is_some_and(|p| p.mode(path.to_string_lossy().as_ref()) == HashMode::Skip){
+                        debug!("Skipping {} by policy", EscapedPath(path.to_string_lossy().as_ref()));
+                        continue;
+                    }
+                    if is_dir {
+                        // overlayfs marks opaque directories by an xattr, not an entry of their own
+                        if let Some(format) = opts.whiteouts{
+                            let dir = path.clone();
+                            tasks.spawn(async move { Ok(format.opaque_marker(&dir).map(|marker| (marker, FileMetadataExt::Opaque))) }.in_current_span());
+                        }
+                        if descend{
+                            listings.enter(path, (!opts.no_dirs).then_some(file_type)).await;
+                            continue;
+                        }
+                        if opts.no_dirs{
+                            continue;
+                        }
+                        trace!("Not descending below --max-depth into {}", EscapedPath(path.to_string_lossy().as_ref()));
+                    }
+                    (path, file_type, None)
+                }
+            };
+
+            let permit = match queued.clone().try_acquire_owned(){
+                Ok(permit) => permit,
+                Err(_) => {
+                    // hand over the entries gathered meanwhile before waiting on the hashing
+                    while let Some(joined) = tasks.try_join_next(){
+                        if batch.push(joined, finfo)?{
+                            debug!("Stopping walk early");
+                            return Ok(());
+                        }
+                    }
+                    if batch.flush(finfo)?{
+                        debug!("Stopping walk early");
+                        return Ok(());
+                    }
+                    trace!("Too many queued tasks, waiting...");
+                    queued.clone().acquire_owned().await.expect("semaphore is never closed")
+                }
+            };
+            let path_str = path.to_string_lossy().to_string();
+            let (open_files, opts) = (open_files.clone(), opts.clone());
+            tasks.spawn(async move {
+                let _queued = permit;
+                // symlinks to files are hashed too, as the file or for hash_link_content,
+                // their target is stat'ed here rather than holding up the walk
+                let hashed = file_type.is_file() || (file_type.is_symlink() && fs::metadata(&path).await.is_ok_and(|m| m.is_file()));
+                let _open = match hashed{
+                    true => Some(open_files.acquire_owned().await.expect("semaphore is never closed")),
+                    false => None,
+                };
+                entry_metadata(path, path_str, file_type, entries, opts).await
+            }.in_current_span());
+
+            while let Some(joined) = tasks.try_join_next(){
+                if batch.push(joined, finfo)?{
+                    debug!("Stopping walk early");
+                    return Ok(());
+                }
+            }
+            if batch.is_due() && batch.flush(finfo)?{
+                debug!("Stopping walk early");
+                return Ok(());
+            }
+        }
+    }
+    else if let Some(file_type) = root_type{
+        let path = dir.to_string_lossy().into_owned();
+        tasks.spawn(entry_metadata(dir, path, file_type, None, opts.clone()).in_current_span());
+    }
+
+    let mut count = 0;
+    while let Some(joined) = tasks.join_next().await{
+        count += 1;
+        if batch.push(joined, finfo)?{
+            debug!("Stopping walk early");
+            return Ok(());
+        }
+    }
+    trace!("Final join {count}");
+    // also when empty, writers create their table with the first call
+    finfo.add_file_info(&batch.entries)?;
+
+    Ok(())
+}
+
+/// Forwards scanned entries to a channel, stopping the walk once the receiver
+/// is gone.
+struct ChannelSink{
+    tx: mpsc::UnboundedSender<ScanItem>,
+}
+
+impl AddFileInfo for ChannelSink {
+    fn add_file_info(&mut self, files: &[(String, FileMetadataExt)]) -> Result<(), IntegrityWatcherError> {
+        for entry in files{
+            // a dropped receiver is noticed by should_stop
+            let _ = self.tx.send(Ok(entry.clone()));
+        }
+        Ok(())
+    }
+
+    fn should_stop(&self) -> bool{
+        self.tx.is_closed()
+    }
+}
+
+/// Scanned entry, or the error that ended the scan.
+pub type ScanItem = Result<(String, FileMetadataExt), IntegrityWatcherError>;
+
+/// Walks `paths` in the background and streams the metadata of every entry
+/// found, without a DB. Unreadable files are logged and skipped; an error that
+/// stops the walk, like `ScanOptions::timeout`, is the last item. Dropping the
+/// stream stops the walk.
+///
+/// ```
+/// use integrity_checker::scan::{scan, ScanOptions};
+/// use integrity_checker::types::FileMetadataExt;
+/// use integrity_checker::exclude::ExcludeSet;
+/// use tokio_stream::StreamExt;
+///
+/// # #[tokio::main] async fn main() {
+/// let dir = std::env::temp_dir().join("integrity_checker_doc_scan");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(dir.join("a.txt"), b"abc").unwrap();
+///
+/// let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+/// let mut entries = scan(vec![dir.clone()], ExcludeSet::default(), opts);
+/// while let Some(entry) = entries.next().await {
+///     let (path, meta) = entry.unwrap();
+///     if let FileMetadataExt::File(file) = meta {
+///         assert!(path.ends_with("a.txt"));
+///         assert_eq!(file.hash.to_string(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+///     }
+/// }
+/// # std::fs::remove_dir_all(dir).unwrap();
+/// # }
+/// ```
+pub fn scan(paths: Vec<PathBuf>, exclude: ExcludeSet, opts: ScanOptions) -> impl Stream<Item = ScanItem> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut sink = ChannelSink{ tx };
+        let walk = with_scan_timeout(opts.timeout, "scan", async {
+            for path in paths{
+                visit_dirs(path, &exclude, &opts, &mut sink).await?;
+                if sink.should_stop(){
+                    break;
+                }
+            }
+            Ok(())
+        }).await;
+        if let Err(e) = walk{
+            let _ = sink.tx.send(Err(e));
+        }
+    }.in_current_span());
+    UnboundedReceiverStream::new(rx)
+}
+
+/// Path on disk of the DB key `key`, which `--path-fold` may have lowercased
+/// or normalized, see `PathFold::resolve`.
+pub async fn resolve_key(fold: PathFold, key: &str) -> io::Result<Option<PathBuf>> {
+    if fold.is_exact(){
+        return Ok(Some(PathBuf::from(key)));
+    }
+    let key = key.to_owned();
+    tokio::task::spawn_blocking(move || fold.resolve(&key)).await.map_err(io::Error::other)?
+}
+
+/// Re-reads the given DB entries instead of walking the tree. Entries that no
+/// longer exist are reported as removed and counted in the returned value.
+pub async fn check_sampled(entries: Vec<String>, opts: &ScanOptions, finfo: &mut CheckDB<'_>) -> Result<u64, IntegrityWatcherError> {
+    const CHUNK: usize = 1024;
+    let mut removed = 0;
+    for chunk in entries.chunks(CHUNK){
+        let mut files: JoinSet<JoinReturn> = JoinSet::new();
+        for key in chunk{
+            let found = match resolve_key(opts.path_fold, key).await{
+                Ok(Some(path)) => fs::symlink_metadata(&path).await.map(|meta| (path, meta.file_type())),
+                Ok(None) => Err(io::ErrorKind::NotFound.into()),
+                Err(e) => Err(e),
+            };
+            let (path, file_type) = match found{
+                Ok(found) => found,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    removed += 1;
+                    finfo.report(FindingKind::Removed, key, None, None, log::Level::Warn, format!("File removed {}", EscapedPath(key)));
+                    continue;
+                }
+                Err(e) => {
+                    error!("{}", IntegrityWatcherError::IOError { source: e, path: key.to_owned() });
+                    continue;
+                }
+            };
+            let path_str = path.to_string_lossy().to_string();
+            // unlisted, directories aren't compared by their entry count
+            files.spawn(entry_metadata(path, path_str, file_type, None, opts.clone()).in_current_span());
+        }
+        let mut results = Vec::with_capacity(CHUNK);
+        for r in files.join_all().await{
+            match r{
+                Ok(Some(r)) => results.push(r),
+                Ok(None) => {},
+                Err(e) => error!("{e}"),
+            }
+        }
+        finfo.add_file_info(&results)?;
+        if finfo.should_stop(){
+            break;
+        }
+    }
+    Ok(removed)
+}
+
+/// `path` as given plus resolved through its parent directory, which exists
+/// even when the file itself doesn't yet, so it matches paths seen by the walk.
+pub async fn resolved_paths(path: &str) -> Result<Vec<String>, IntegrityWatcherError> {
+    let mut paths = vec![path.to_owned()];
+    let p = PathBuf::from(path);
+    let (Some(parent), Some(name)) = (p.parent(), p.file_name()) else {
+        return Ok(paths);
+    };
+    let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+    match fs::canonicalize(parent).await{
+        Ok(dir) => paths.push(dir.join(name).to_string_lossy().to_string()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(IntegrityWatcherError::IOError { source: e, path: path.to_owned() }),
+    }
+    Ok(paths)
+}
+
+/// Location of a scan root for overlap detection. A symlink root is recorded
+/// as the link itself and not followed, so only its parent is resolved.
+async fn canonical_root(path: &str) -> io::Result<PathBuf> {
+    let meta = fs::symlink_metadata(path).await?;
+    if !meta.is_symlink(){
+        return fs::canonicalize(path).await;
+    }
+    let p = Path::new(path);
+    let parent = match p.parent(){
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = p.file_name().ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    Ok(fs::canonicalize(parent).await?.join(name))
+}
+
+/// Checks scan roots before starting. Missing roots are an error unless
+/// `allow_missing` (check would report everything under them removed), roots
+/// inside another root are dropped so nothing is scanned twice.
+pub async fn validate_roots(paths: &[String], allow_missing: bool) -> Result<Vec<String>, IntegrityWatcherError> {
+    let mut roots = Vec::with_capacity(paths.len());
+    for path in paths{
+        match canonical_root(path).await{
+            Ok(c) => roots.push((path, Some(c))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if !allow_missing{
+                    error!("Path {} doesn't exist, use --allow-missing-paths to scan anyway", EscapedPath(path));
+                    return Err(IntegrityWatcherError::IOError { source: e, path: path.to_owned() });
+                }
+                warn!("Path {} doesn't exist", EscapedPath(path));
+                roots.push((path, None));
+            }
+            Err(e) => return Err(IntegrityWatcherError::IOError { source: e, path: path.to_owned() }),
+        }
+    }
+
+    let mut result = Vec::with_capacity(roots.len());
+    for (i, (path, canonical)) in roots.iter().enumerate(){
+        let Some(canonical) = canonical else {
+            result.push(path.to_string());
+            continue;
+        };
+        // equal roots: keep the first one
+        let outer = roots.iter().enumerate().find(|(j, (_, other))| {
+            other.as_ref().is_some_and(|o| canonical.starts_with(o) && (o != canonical || *j < i))
+        });
+        match outer{
+            Some((_, (other, _))) => warn!("Path {} is inside {}, not scanning it twice", EscapedPath(path), EscapedPath(other)),
+            None => result.push(path.to_string()),
+        }
+    }
+    Ok(result)
+}
+
+/// Roots to walk for `--only-tag`: the tag's prefixes and labeled paths that are
+/// under a `--path` root, and roots lying inside one of the prefixes.
+pub fn tag_roots(scope: &[String], paths: &[String]) -> Vec<String> {
+    let mut roots = Vec::new();
+    for s in scope{
+        for root in paths{
+            if Path::new(s).starts_with(root){
+                roots.push(s.clone());
+            }
+            else if Path::new(root).starts_with(s){
+                roots.push(root.clone());
+            }
+        }
+    }
+    roots.sort();
+    roots.dedup();
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::{CheckOptions, WriteToDB, DEFAULT_TABLE, TABLE};
+    use redb::{ReadableDatabase, ReadableTable};
+    use std::fs;
+
+    fn setup_test_tree(name: &str) -> PathBuf {
+        let mut path = std::env::current_dir().unwrap();
+        path.push(format!("test_tree_{}", name));
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(path.join("sub").join("deeper")).unwrap();
+        fs::write(path.join("top.txt"), b"top").unwrap();
+        fs::write(path.join("sub").join("mid.txt"), b"mid").unwrap();
+        fs::write(path.join("sub").join("deeper").join("low.txt"), b"low").unwrap();
+        path
+    }
+
+    fn db_entries(db_path: &Path) -> Vec<String> {
+        let db = Database::open(db_path).unwrap();
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(TABLE).unwrap();
+        table.iter().unwrap().map(|k| k.unwrap().0.value()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_scan_timeout() {
+        let r = with_scan_timeout(Some(Duration::from_millis(50)), "slow", async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        }).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::Timeout { .. })));
+
+        let r = with_scan_timeout(Some(Duration::from_secs(10)), "fast", async { Ok(1) }).await;
+        assert_eq!(r.unwrap(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_file_timeout() {
+        let dir = setup_test_tree("file_timeout");
+        let fifo = dir.join("stuck");
+        // opening a FIFO for reading blocks until a writer shows up, like a hung mount
+        let status = std::process::Command::new("mkfifo").arg(&fifo).status().unwrap();
+        assert!(status.success());
+
+        let opts = ScanOptions{ file_timeout: Some(Duration::from_millis(100)), ..Default::default() };
+        let r = get_file_hash(fifo.clone(), &opts).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::Timeout { .. })));
+        // release the blocked thread so the runtime can shut down
+        drop(fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+
+        let r = get_file_hash(dir.join("top.txt"), &opts).await;
+        assert!(r.is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hash_stats() {
+        let tree = setup_test_tree("hash_stats");
+        let stats = Arc::new(HashStats::default());
+        let opts = ScanOptions{ no_dirs: true, stats: Some(stats.clone()), ..Default::default() };
+        let mut sink = BatchSink::default();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut sink).await.unwrap();
+
+        assert_eq!(sink.batches.concat().len(), 3);
+        assert!(!stats.per_thread().is_empty());
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_hash_command() {
+        let dir = setup_test_tree("hash_command");
+        let file = dir.join("top.txt");
+        let builtin = get_file_hash(file.clone(), &ScanOptions::default()).await.unwrap();
+
+        let opts = ScanOptions{ hash_command: Some("sha256sum {}".into()), ..Default::default() };
+        assert_eq!(get_file_hash(file.clone(), &opts).await.unwrap(), builtin);
+        // path appended when there is no {}
+        let opts = ScanOptions{ hash_command: Some("md5sum".into()), ..Default::default() };
+        let md5 = get_file_hash(file.clone(), &opts).await.unwrap();
+        assert_eq!(md5.hash.to_string(), "b28354b543375bfa94dabaeda722927f");
+
+        // quoted words stay whole
+        let opts = ScanOptions{ hash_command: Some("sh -c 'sha256sum \"$0\"' {}".into()), ..Default::default() };
+        assert_eq!(get_file_hash(file.clone(), &opts).await.unwrap(), builtin);
+        let opts = ScanOptions{ hash_command: Some("sha256sum 'unterminated".into()), ..Default::default() };
+        let r = get_file_hash(file.clone(), &opts).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::HashCommand { .. })), "{r:?}");
+
+        let opts = ScanOptions{ hash_command: Some("false {}".into()), ..Default::default() };
+        let r = get_file_hash(file.clone(), &opts).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::HashCommand { .. })), "{r:?}");
+        let opts = ScanOptions{ hash_command: Some("echo not-hex".into()), ..Default::default() };
+        let r = get_file_hash(file.clone(), &opts).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::HashCommand { .. })), "{r:?}");
+
+        let script = dir.join("slow.sh");
+        fs::write(&script, "sleep 5\n").unwrap();
+        let opts = ScanOptions{
+            hash_command: Some(format!("sh {}", script.display()).into()),
+            file_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let r = get_file_hash(file, &opts).await;
+        assert!(matches!(r, Err(IntegrityWatcherError::Timeout { .. })), "{r:?}");
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Records which entries each `add_file_info` call got.
+    #[derive(Default)]
+    struct BatchSink{
+        batches: Vec<Vec<(String, FileMetadataExt)>>,
+    }
+
+    impl AddFileInfo for BatchSink {
+        fn add_file_info(&mut self, files: &[(String, FileMetadataExt)]) -> Result<(), IntegrityWatcherError> {
+            self.batches.push(files.to_vec());
+            Ok(())
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_metadata_not_held_back_by_hashing() {
+        let dir = setup_test_tree("hash_jobs");
+        let tree = dir.join("tree");
+        fs::create_dir_all(tree.join("big")).unwrap();
+        for i in 0..8{
+            fs::write(tree.join("big").join(format!("f{i}")), b"large").unwrap();
+            fs::create_dir(tree.join(format!("d{i}"))).unwrap();
+        }
+        let script = dir.join("slow.sh");
+        fs::write(&script, "sleep 0.1\nsha256sum \"$1\"\n").unwrap();
+        let opts = ScanOptions{ hash_command: Some(format!("sh {} {{}}", script.display()).into()), hash_jobs: Some(1), ..Default::default() };
+        let mut sink = BatchSink::default();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut sink).await.unwrap();
+
+        let entries: Vec<_> = sink.batches.iter().flatten().collect();
+        assert_eq!(entries.len(), 17);
+        // every directory is handed over while files are still queued for hashing
+        let last_dir = entries.iter().rposition(|(_, e)| matches!(e, FileMetadataExt::Dir(_))).unwrap();
+        let files_before = entries[..last_dir].iter().filter(|(_, e)| matches!(e, FileMetadataExt::File(_))).count();
+        assert!(files_before < 8, "directories came after {files_before} hashed files");
+        assert!(sink.batches.len() > 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wide_dir_batches_bounded() {
+        let dir = setup_test_tree("wide_dir");
+        let tree = dir.join("wide");
+        fs::create_dir_all(&tree).unwrap();
+        for i in 0..3000{
+            fs::write(tree.join(format!("f{i}")), b"x").unwrap();
+            if i % 10 == 0{
+                fs::create_dir(tree.join(format!("d{i}"))).unwrap();
+            }
+        }
+        // the hashing throttle alone would let every file pile up
+        let opts = ScanOptions{ hash_jobs: Some(10_000), ..Default::default() };
+        let mut sink = BatchSink::default();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut sink).await.unwrap();
+
+        assert_eq!(sink.batches.iter().map(Vec::len).sum::<usize>(), 3300);
+        let peak = sink.batches.iter().map(Vec::len).max().unwrap();
+        assert!(peak <= BATCH_ENTRIES, "{peak} entries in one batch");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_walk_jobs() {
+        let tree = setup_test_tree("walk_jobs");
+        for i in 0..20{
+            let dir = tree.join(format!("d{i}")).join("a").join("b");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("f.txt"), format!("{i}")).unwrap();
+            fs::write(dir.join("f.skip"), b"skip").unwrap();
+        }
+        let mut exclude = ExcludeSet::default();
+        exclude.add(&tree.join("d3").to_string_lossy());
+        exclude.add("*.skip");
+        let walk = async |walk_jobs| {
+            let opts = ScanOptions{ walk_jobs, ..Default::default() };
+            let mut sink = BatchSink::default();
+            visit_dirs(tree.clone(), &exclude, &opts, &mut sink).await.unwrap();
+            let mut entries: Vec<_> = sink.batches.into_iter().flatten().map(|(p, _)| p).collect();
+            entries.sort();
+            entries
+        };
+        let serial = walk(None).await;
+        // every directory listed once, none left out, also the 20 at the top the queue has no room for
+        assert_eq!(serial.len(), 5 + 19 * 4);
+        assert_eq!(walk(Some(8)).await, serial);
+        assert_eq!(walk(Some(2)).await, serial);
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    /// Peak resident set size of this process in KiB.
+    #[cfg(target_os = "linux")]
+    fn peak_rss_kib() -> u64 {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|l| l.starts_with("VmHWM:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    /// Creates 100k directories and measures the peak RSS of the whole process,
+    /// which tests running alongside raise too: run it alone with
+    /// `cargo test many_dirs -- --ignored --test-threads 1`.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore = "slow, and needs the process to itself"]
+    async fn test_many_dirs_bounded_memory() {
+        #[derive(Default)]
+        struct CountSink(usize);
+
+        impl AddFileInfo for CountSink {
+            fn add_file_info(&mut self, files: &[(String, FileMetadataExt)]) -> Result<(), IntegrityWatcherError> {
+                self.0 += files.len();
+                Ok(())
+            }
+        }
+
+        // tmpfs when there is one, 100k directories are slow to create on disk
+        let base = match Path::new("/dev/shm").is_dir(){
+            true => PathBuf::from("/dev/shm"),
+            false => std::env::current_dir().unwrap(),
+        };
+        let tree = base.join(format!("test_tree_many_dirs_{}", std::process::id()));
+        for i in 0..100{
+            for j in 0..1000{
+                fs::create_dir_all(tree.join(format!("d{i}")).join(format!("{j}"))).unwrap();
+            }
+        }
+        // deeper than the listings kept open
+        let deep = (0..2 * OPEN_DIRS).fold(tree.clone(), |p, i| p.join(format!("l{i}")));
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(deep.join("bottom"), b"x").unwrap();
+
+        let before = peak_rss_kib();
+        let mut sink = CountSink::default();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &ScanOptions::default(), &mut sink).await.unwrap();
+        let grown = peak_rss_kib() - before;
+        fs::remove_dir_all(&tree).unwrap();
+
+        assert_eq!(sink.0, 100 + 100_000 + 2 * OPEN_DIRS + 1);
+        assert!(grown < 32 * 1024, "peak RSS grew by {grown} KiB");
+    }
+
+    #[tokio::test]
+    async fn test_prefilter_catches_content_change() {
+        let tree = setup_test_tree("prefilter");
+        let db_path = tree.with_extension("redb");
+        let db = Database::create(&db_path).unwrap();
+        let opts = ScanOptions{ no_dirs: true, prefilter: true, ..Default::default() };
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+        let top = get_file_hash(tree.join("top.txt"), &opts).await.unwrap();
+        assert_eq!(top.hash.to_string().len(), 16);
+        assert_eq!(top.strong, None);
+
+        // same size, only the content differs
+        fs::write(tree.join("sub").join("mid.txt"), b"MID").unwrap();
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 1);
+
+        // entries without a stored SHA-256 are counted, not reported
+        let verify = ScanOptions{ verify_matches: true, ..opts.clone() };
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &verify, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 1);
+        assert_eq!(checker.get_unverified_count(), 2);
+
+        // an XXH3 collision is caught by the stored SHA-256
+        let mut collision = get_file_hash(tree.join("top.txt"), &verify).await.unwrap();
+        assert_eq!(collision.hash, top.hash);
+        collision.strong = Some(types::Hash::from([7u8; 32]));
+        WriteToDB::new(&db).add_file_info(&[(tree.join("top.txt").to_string_lossy().to_string(), FileMetadataExt::File(collision))]).unwrap();
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &verify, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 2);
+        assert_eq!(checker.get_unverified_count(), 1);
+
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_hash_link_content() {
+        let tree = setup_test_tree("hash_link_content");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        std::os::unix::fs::symlink("top.txt", tree.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", tree.join("dangling")).unwrap();
+        let db = Database::create(&db_path).unwrap();
+        let opts = ScanOptions{ no_dirs: true, hash_link_content: true, ..Default::default() };
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+        {
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(TABLE).unwrap();
+            let stored = |name: &str| table.get(tree.join(name).to_string_lossy().to_string()).unwrap().unwrap().value();
+            let top = get_file_hash(tree.join("top.txt"), &opts).await.unwrap();
+            assert!(matches!(stored("link"), FileMetadataExt::Symlink(s) if s.data == "top.txt" && s.content == Some(top.hash)));
+            assert!(matches!(stored("dangling"), FileMetadataExt::Symlink(s) if s.content.is_none()));
+        }
+
+        // the pointed at file is swapped, the link stays the same
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 0);
+        fs::write(tree.join("top.txt"), b"TOP").unwrap();
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        let mut changed: Vec<_> = checker.take_recorded().into_iter().map(|f| f.path).collect();
+        changed.sort();
+        let path = |name: &str| tree.join(name).to_string_lossy().to_string();
+        assert_eq!(changed, [path("link"), path("top.txt")]);
+
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_entry_types() {
+        let tree = setup_test_tree("entry_types");
+        std::os::unix::fs::symlink("top.txt", tree.join("link")).unwrap();
+        std::os::unix::fs::symlink("sub", tree.join("dir_link")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", tree.join("dangling")).unwrap();
+        assert!(std::process::Command::new("mkfifo").arg(tree.join("fifo")).status().unwrap().success());
+        let mut sink = BatchSink::default();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &ScanOptions::default(), &mut sink).await.unwrap();
+        let entries: std::collections::BTreeMap<_, _> = sink.batches.into_iter().flatten().collect();
+        let kind = |name: &str| match entries.get(tree.join(name).to_string_lossy().as_ref()){
+            Some(FileMetadataExt::File(_)) => "file",
+            Some(FileMetadataExt::Symlink(_)) => "symlink",
+            Some(FileMetadataExt::Dir(_)) => "dir",
+            Some(FileMetadataExt::Whiteout) => "whiteout",
+            Some(FileMetadataExt::Opaque) => "opaque",
+            None => "none",
+        };
+        // links to files are stored as the file, others as links not followed
+        assert_eq!(["top.txt", "link", "sub", "dir_link", "dangling", "fifo", "dir_link/mid.txt"].map(kind),
+            ["file", "file", "dir", "symlink", "symlink", "none", "none"]);
+        assert_eq!(entries.len(), 8);
+
+        // a root is dispatched the same way
+        let mut sink = BatchSink::default();
+        visit_dirs(tree.join("link"), &ExcludeSet::default(), &ScanOptions::default(), &mut sink).await.unwrap();
+        assert!(matches!(sink.batches.concat().as_slice(), [(_, FileMetadataExt::File(_))]));
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overlay_aware() {
+        let tree = setup_test_tree("overlay_aware");
+        // an upper layer deleting gone.txt and making sub opaque, aufs style
+        fs::write(tree.join(".wh.gone.txt"), b"").unwrap();
+        fs::write(tree.join("sub").join(".wh..wh..opq"), b"").unwrap();
+        let scan = async |whiteouts| {
+            let mut sink = BatchSink::default();
+            let opts = ScanOptions{ no_dirs: true, whiteouts, ..Default::default() };
+            visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut sink).await.unwrap();
+            sink.batches.concat().into_iter().map(|(p, e)| (p.strip_prefix(tree.to_str().unwrap()).unwrap().to_owned(), e)).collect::<std::collections::BTreeMap<_, _>>()
+        };
+
+        let entries = scan(Some(WhiteoutFormat::Prefix)).await;
+        assert_eq!(entries.keys().map(String::as_str).collect::<Vec<_>>(), ["/gone.txt", "/sub/.wh..wh..opq", "/sub/deeper/low.txt", "/sub/mid.txt", "/top.txt"]);
+        assert_eq!(entries["/gone.txt"], FileMetadataExt::Whiteout);
+        assert_eq!(entries["/sub/.wh..wh..opq"], FileMetadataExt::Opaque);
+        // only overlayfs devices
+        let entries = scan(Some(WhiteoutFormat::CharDevice)).await;
+        assert!(matches!(entries.get("/.wh.gone.txt"), Some(FileMetadataExt::File(_))));
+        assert!(matches!(entries.get("/sub/.wh..wh..opq"), Some(FileMetadataExt::File(_))));
+        assert_eq!(scan(None).await, entries);
+
+        // overlayfs marks the directory itself, user xattrs need a file system keeping them
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let deeper = std::ffi::CString::new(tree.join("sub").join("deeper").as_os_str().as_bytes()).unwrap();
+            // SAFETY: both strings are NUL terminated, the value is one byte
+            let set = unsafe { libc::lsetxattr(deeper.as_ptr(), c"user.overlay.opaque".as_ptr(), b"y".as_ptr().cast(), 1, 0) } == 0;
+            if set{
+                let entries = scan(Some(WhiteoutFormat::CharDevice)).await;
+                assert_eq!(entries.get("/sub/deeper/.wh..wh..opq"), Some(&FileMetadataExt::Opaque));
+                assert!(!scan(Some(WhiteoutFormat::Prefix)).await.contains_key("/sub/deeper/.wh..wh..opq"));
+            }
+        }
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_track_inode_replacement() {
+        let tree = setup_test_tree("track_inode");
+        let db_path = tree.with_extension("redb");
+        let db = Database::create(&db_path).unwrap();
+        let opts = ScanOptions{ no_dirs: true, track_inode: true, ..Default::default() };
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+
+        // mv newfile oldpath: same content, size and permissions, new inode
+        let target = tree.join("top.txt");
+        let replacement = tree.join("top.txt.new");
+        fs::copy(&target, &replacement).unwrap();
+        fs::rename(&replacement, &target).unwrap();
+
+        let mut checker = CheckDB::new(&db, CheckOptions{ track_inode: true, ..Default::default() });
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_counter(), 3);
+        assert_eq!(checker.get_changes_count(), 1);
+
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 0);
+
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validate_roots_missing() {
+        let tree = setup_test_tree("roots_missing");
+        let root = tree.to_string_lossy().to_string();
+        let typo = tree.join("sbu").to_string_lossy().to_string();
+        let paths = [root.clone(), typo.clone()];
+
+        assert!(validate_roots(&paths, false).await.is_err());
+        assert_eq!(validate_roots(&paths, true).await.unwrap(), paths);
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validate_roots_nested() {
+        let tree = setup_test_tree("roots_nested");
+        let root = tree.to_string_lossy().to_string();
+        let sub = tree.join("sub").to_string_lossy().to_string();
+        let deeper = format!("{}/./sub/../sub/deeper", root);
+        let sibling = tree.join("top.txt").to_string_lossy().to_string();
+
+        let paths = [sub.clone(), root.clone(), deeper, root.clone(), sibling];
+        assert_eq!(validate_roots(&paths, false).await.unwrap(), [root]);
+
+        let paths = [sub.clone(), tree.join("top.txt").to_string_lossy().to_string()];
+        assert_eq!(validate_roots(&paths, false).await.unwrap(), paths);
+
+        // a symlink root is the link itself, not where it points
+        #[cfg(target_os = "linux")]
+        {
+            let link = tree.with_extension("link");
+            let _ = fs::remove_file(&link);
+            std::os::unix::fs::symlink(&sub, &link).unwrap();
+            let paths = [sub.clone(), link.to_string_lossy().to_string()];
+            assert_eq!(validate_roots(&paths, false).await.unwrap(), paths);
+            fs::remove_file(link).unwrap();
+        }
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_roots_counted_once() {
+        let tree = setup_test_tree("roots_overlap");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let roots = [tree.to_string_lossy().to_string(), tree.join("sub").to_string_lossy().to_string()];
+        let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+        let scan = async |roots: &[String], db: &Database| {
+            let mut writer = WriteToDB::new(db);
+            for root in roots{
+                visit_dirs(PathBuf::from(root), &ExcludeSet::default(), &opts, &mut writer).await.unwrap();
+            }
+            writer.get_counter()
+        };
+
+        let deduped = validate_roots(&roots, false).await.unwrap();
+        assert_eq!(deduped, roots[..1]);
+        let db = Database::create(&db_path).unwrap();
+        assert_eq!(scan(&deduped, &db).await, 3);
+        drop(db);
+        fs::remove_file(&db_path).unwrap();
+
+        // walking both roots anyway still counts and checks every file once
+        let db = Database::create(&db_path).unwrap();
+        assert_eq!(scan(&roots, &db).await, 3);
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        for root in &roots{
+            visit_dirs(PathBuf::from(root), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        }
+        assert_eq!(checker.get_counter(), 3);
+        drop(db);
+        assert_eq!(db_entries(&db_path).len(), 3);
+
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_escaping_symlinks() {
+        let tree = setup_test_tree("escaping_symlinks");
+        std::os::unix::fs::symlink("/tmp/evil", tree.join("sub").join("abs")).unwrap();
+        std::os::unix::fs::symlink("../../outside", tree.join("sub").join("up")).unwrap();
+        std::os::unix::fs::symlink("../top.txt", tree.join("sub").join("in_tree")).unwrap();
+        std::os::unix::fs::symlink("./deeper/../mid.txt", tree.join("sub").join("dotted")).unwrap();
+        let roots = symlink_policy_roots(&[tree.to_string_lossy().to_string()]).await;
+        let canonical = fs::canonicalize(&tree).unwrap();
+
+        let escaping = |name: &'static str| {
+            let roots = roots.clone();
+            let link = tree.join("sub").join(name);
+            async move { escaping_target(&link, &roots).await.unwrap() }
+        };
+        assert_eq!(escaping("abs").await, Some(PathBuf::from("/tmp/evil")));
+        assert_eq!(escaping("up").await, Some(canonical.parent().unwrap().join("outside")));
+        assert_eq!(escaping("in_tree").await, None);
+        assert_eq!(escaping("dotted").await, None);
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dir_entry_count() {
+        let tree = setup_test_tree("dir_entry_count");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let exclude: ExcludeSet = ["*.log"].into_iter().collect();
+
+        for (walk_jobs, dropped) in [(None, "dropped.log"), (Some(4), "dropped_too.log")]{
+            let _ = fs::remove_file(&db_path);
+            let opts = ScanOptions{ walk_jobs, ..Default::default() };
+            let db = Database::create(&db_path).unwrap();
+            visit_dirs(tree.clone(), &exclude, &opts, &mut WriteToDB::new(&db)).await.unwrap();
+            // dropped where nothing is scanned
+            fs::write(tree.join("sub").join(dropped), b"x").unwrap();
+            let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+            visit_dirs(tree.clone(), &exclude, &opts, &mut checker).await.unwrap();
+            let found = checker.take_recorded();
+            assert_eq!(found.len(), 1);
+            assert!(found[0].path.ends_with("sub"));
+            let counts = if walk_jobs.is_none() { "2 -> 3" } else { "3 -> 4" };
+            assert!(found[0].message.contains(&format!(" entry count changed {counts}")), "{}", found[0].message);
+        }
+
+        // directories the walk doesn't go into have no count to compare
+        let _ = fs::remove_file(&db_path);
+        let opts = ScanOptions{ max_depth: Some(1), ..Default::default() };
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &exclude, &opts, &mut WriteToDB::new(&db)).await.unwrap();
+        let stored = |name: &str| {
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(TABLE).unwrap();
+            table.get(tree.join(name).to_string_lossy().to_string()).unwrap().map(|v| v.value())
+        };
+        assert!(matches!(stored("sub"), Some(FileMetadataExt::Dir(DirMetadata{ entries: None, .. }))), "{:?}", stored("sub"));
+        assert!(stored("sub/mid.txt").is_none());
+        fs::write(tree.join("sub").join("new.txt"), b"x").unwrap();
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &exclude, &opts, &mut checker).await.unwrap();
+        let found = checker.take_recorded();
+        assert!(!found.iter().any(|f| f.message.contains("entry count")), "{found:?}");
+        assert!(!found.iter().any(|f| f.kind == FindingKind::New), "{found:?}");
+
+        drop(db);
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_detect_type() {
+        let tree = setup_test_tree("detect_type");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let opts = ScanOptions{ detect_type: true, ..Default::default() };
+
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+        let mut elf = b"\x7fELF\x02\x01\x01".to_vec();
+        elf.resize(64, 0);
+        fs::write(tree.join("top.txt"), elf).unwrap();
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        let found = checker.take_recorded();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains(" content type changed text/plain -> application/x-executable"), "{}", found[0].message);
+        drop(db);
+
+        // entries stored without a type compare as unknown
+        fs::remove_file(&db_path).unwrap();
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &ScanOptions::default(), &mut WriteToDB::new(&db)).await.unwrap();
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 0);
+
+        drop(db);
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_owner() {
+        use std::os::unix::fs::MetadataExt;
+        let tree = setup_test_tree("owner");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &ScanOptions{ owner: true, ..Default::default() }, &mut WriteToDB::new(&db)).await.unwrap();
+        let uid = fs::metadata(&tree).unwrap().uid();
+
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(TABLE).unwrap();
+        let owner = |path: &Path| match table.get(path.to_string_lossy().to_string()).unwrap().unwrap().value(){
+            FileMetadataExt::File(f) => f.owner,
+            FileMetadataExt::Dir(d) => d.owner,
+            e => panic!("{e}"),
+        };
+        for path in [tree.join("top.txt"), tree.join("sub")]{
+            let meta = fs::metadata(&path).unwrap();
+            assert_eq!(owner(&path).map(|o| o.uid), Some(uid));
+            assert_eq!(owner(&path), ownership::of(&meta));
+        }
+        drop(table);
+        drop(read_txn);
+
+        // not recorded without --owner
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &ScanOptions::default(), &mut checker).await.unwrap();
+        assert!(checker.take_recorded().is_empty());
+
+        drop(db);
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_inode_flags() {
+        let tree = setup_test_tree("inode_flags");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let (file, dir) = (tree.join("top.txt"), tree.join("sub"));
+        // the flags need a file system with them and CAP_LINUX_IMMUTABLE
+        if let Err(e) = inodeflags::toggle(&file, inodeflags::IMMUTABLE, true){
+            eprintln!("Skipping, immutable flag not settable here: {e}");
+            fs::remove_dir_all(tree).unwrap();
+            return;
+        }
+        /// Clears the flags again, or the tree can't be removed.
+        struct Clear<'a>(&'a [&'a PathBuf]);
+        impl Drop for Clear<'_> {
+            fn drop(&mut self) {
+                for path in self.0{
+                    let _ = inodeflags::toggle(path, inodeflags::TRACKED_FLAGS, false);
+                }
+            }
+        }
+        let _clear = Clear(&[&file, &dir]);
+        let opts = ScanOptions{ inode_flags: true, ..Default::default() };
+
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+        inodeflags::toggle(&file, inodeflags::IMMUTABLE, false).unwrap();
+        inodeflags::toggle(&dir, inodeflags::APPEND_ONLY, true).unwrap();
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        let mut found: Vec<_> = checker.take_recorded().into_iter().map(|f| (f.path, f.message)).collect();
+        found.sort();
+        assert_eq!(found.len(), 2, "{found:?}");
+        assert!(found[0].1.ends_with(" append-only flag added"), "{}", found[0].1);
+        assert!(found[1].1.ends_with(" immutable flag removed"), "{}", found[1].1);
+        assert_eq!((found[0].0.as_str(), found[1].0.as_str()), (dir.to_str().unwrap(), file.to_str().unwrap()));
+
+        // flags not read this time aren't compared
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &ScanOptions::default(), &mut checker).await.unwrap();
+        assert!(checker.take_recorded().is_empty());
+
+        drop(_clear);
+        drop(db);
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_exclude_reinclude() {
+        let tree = setup_test_tree("exclude_reinclude");
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let root = tree.to_string_lossy().to_string();
+        let exclude: ExcludeSet = [format!("{root}/sub"), format!("!{root}/sub/deeper"), "*.txt".to_owned(), format!("!{root}/sub/deeper/low.txt")].into_iter().collect();
+
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &exclude, &ScanOptions::default(), &mut WriteToDB::new(&db)).await.unwrap();
+        drop(db);
+        // sub is walked for sub/deeper but not stored, top.txt and mid.txt are left out
+        let mut entries = db_entries(&db_path);
+        entries.sort();
+        assert_eq!(entries, [format!("{root}/sub/deeper"), format!("{root}/sub/deeper/low.txt")]);
+
+        fs::remove_dir_all(tree).unwrap();
+        fs::remove_file(db_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_excludes() {
+        let tree = setup_test_tree("unmatched_excludes");
+        let root = tree.to_string_lossy().to_string();
+        let typo = format!("{root}/sbu");
+        let exclude: ExcludeSet = [format!("{root}/sub"), typo.clone(), "*.txt".to_owned(), "*.log".to_owned()].into_iter().collect();
+        assert_eq!(exclude.unmatched().len(), 4);
+
+        visit_dirs(tree.clone(), &exclude, &ScanOptions::default(), &mut BatchSink::default()).await.unwrap();
+        assert_eq!(exclude.unmatched(), [typo.as_str(), "*.log"]);
+        // sub pruned as a whole, the *.txt files under it never walked
+        let usage: Vec<_> = exclude.usage().into_iter().map(|u| (u.pruned, u.examples)).collect();
+        assert_eq!(usage, [(1, vec![format!("{root}/sub")]), (0, vec![]), (1, vec![format!("{root}/top.txt")]), (0, vec![])]);
+
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[test]
+    fn test_tag_roots() {
+        let scope = ["/boot".to_owned(), "/etc/ssh".to_owned(), "/srv/x/file".to_owned()];
+        assert_eq!(tag_roots(&scope, &["/".to_owned()]), scope);
+        assert_eq!(tag_roots(&scope, &["/etc".to_owned(), "/boot/grub".to_owned()]), ["/boot/grub", "/etc/ssh"]);
+        assert!(tag_roots(&scope, &["/usr".to_owned()]).is_empty());
+    }
+
+    fn set_mtime(path: &Path, secs: u64) {
+        let file = fs::File::options().append(true).open(path).unwrap();
+        file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_since_reuses_old_hashes() {
+        let tree = setup_test_tree("since");
+        let files = [tree.join("top.txt"), tree.join("sub").join("mid.txt"), tree.join("sub").join("deeper").join("low.txt")];
+        for f in &files{
+            set_mtime(f, 1_000_000_000);
+        }
+        let db_path = tree.with_extension("redb");
+        let db = Database::create(&db_path).unwrap();
+        let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+
+        // same size and restored mtime, only a full hash notices
+        fs::write(&files[0], b"TOP").unwrap();
+        set_mtime(&files[0], 1_000_000_000);
+        // new mtime, rehashed even with the filter
+        fs::write(&files[1], b"MID").unwrap();
+
+        let since = Arc::new(SinceFilter::load(&db, DEFAULT_TABLE, 1_500_000_000).unwrap());
+        let opts = ScanOptions{ no_dirs: true, since: Some(since.clone()), ..Default::default() };
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(since.get_skipped(), 2);
+        assert_eq!(checker.get_counter(), 3);
+        assert_eq!(checker.get_changes_count(), 1);
+
+        let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 2);
+
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stat_only() {
+        let tree = setup_test_tree("stat_only");
+        let top = tree.join("top.txt");
+        set_mtime(&top, 1_000_000_000);
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let opts = ScanOptions{ no_dirs: true, stat_only: true, ..Default::default() };
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+        {
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(TABLE).unwrap();
+            let entry = table.get(top.to_string_lossy().to_string()).unwrap().unwrap().value();
+            assert!(matches!(entry, FileMetadataExt::File(f) if f.hash.is_none()));
+        }
+
+        // same size and mtime, only the content differs
+        let len = fs::metadata(&top).unwrap().len() as usize;
+        fs::write(&top, "x".repeat(len)).unwrap();
+        set_mtime(&top, 1_000_000_000);
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 0);
+
+        fs::write(&top, "x".repeat(len + 1)).unwrap();
+        set_mtime(&top, 1_000_000_000);
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        let found = checker.take_recorded();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains(" size changed"), "{}", found[0].message);
+        assert!(!found[0].message.contains("hash"), "{}", found[0].message);
+
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_max_size() {
+        let tree = setup_test_tree("check_max_size");
+        let big = tree.join("big.bin");
+        fs::write(&big, vec![0u8; 4096]).unwrap();
+        set_mtime(&big, 1_000_000_000);
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+
+        // same size and mtime, only the content differs
+        fs::write(&big, vec![1u8; 4096]).unwrap();
+        set_mtime(&big, 1_000_000_000);
+        fs::write(tree.join("sub").join("mid.txt"), b"MID").unwrap();
+        let check = ScanOptions{ max_hash_size: Some(1024), ..opts.clone() };
+        let mut checker = CheckDB::new(&db, CheckOptions{ max_size: Some(1024), ..Default::default() }).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &check, &mut checker).await.unwrap();
+        let found = checker.take_recorded();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].path.ends_with("mid.txt"), "{}", found[0].path);
+        assert_eq!(checker.get_content_skipped_count(), 1);
+
+        // edited at the same size, the mtime tells what the content can't
+        fs::write(&big, vec![2u8; 4096]).unwrap();
+        set_mtime(&big, 1_000_000_100);
+        let mut checker = CheckDB::new(&db, CheckOptions{ max_size: Some(1024), ..Default::default() }).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &check, &mut checker).await.unwrap();
+        let mut found: Vec<_> = checker.take_recorded().into_iter().map(|f| f.path).collect();
+        found.sort();
+        assert_eq!(found.len(), 2);
+        assert!(found[0].ends_with("big.bin"), "{}", found[0]);
+        assert_eq!(checker.get_content_skipped_count(), 1);
+        set_mtime(&big, 1_000_000_000);
+
+        // the stored hash is still the full one
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 2);
+        assert_eq!(checker.get_content_skipped_count(), 0);
+
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_policy_modes() {
+        let tree = setup_test_tree("policy");
+        for dir in ["etc", "cache", "media", "docker"]{
+            fs::create_dir_all(tree.join(dir)).unwrap();
+        }
+        let (conf, cached, video) = (tree.join("etc").join("app.conf"), tree.join("cache").join("blob"), tree.join("media").join("a.mkv"));
+        fs::write(&conf, b"port=1").unwrap();
+        fs::write(&cached, b"cached").unwrap();
+        fs::write(&video, vec![0u8; policy::HEAD_HASH_LEN as usize + 10]).unwrap();
+        fs::write(tree.join("docker").join("layer"), b"layer").unwrap();
+        for file in [&conf, &cached, &video]{
+            set_mtime(file, 1_000_000_000);
+        }
+        let root = tree.to_string_lossy();
+        let policy: Policy = format!("{root}/etc full\n{root}/cache stat-only\n*.mkv head-hash\n{root}/docker skip\n").parse().unwrap();
+        let opts = ScanOptions{ no_dirs: true, policy: Some(Arc::new(policy)), ..Default::default() };
+        let db_path = tree.with_extension("redb");
+        let _ = fs::remove_file(&db_path);
+        let db = Database::create(&db_path).unwrap();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut WriteToDB::new(&db)).await.unwrap();
+        {
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(TABLE).unwrap();
+            let entries: Vec<_> = table.iter().unwrap().map(|k| k.unwrap().0.value()).collect();
+            assert!(!entries.iter().any(|p| p.contains("docker")), "{entries:?}");
+            let hash = |path: &Path| match table.get(path.to_string_lossy().to_string()).unwrap().unwrap().value(){
+                FileMetadataExt::File(f) => f.hash,
+                other => panic!("{other}"),
+            };
+            assert!(!hash(&conf).is_none());
+            assert!(hash(&cached).is_none());
+            let head: [u8; 32] = Sha256::digest(vec![0u8; policy::HEAD_HASH_LEN as usize]).into();
+            assert_eq!(hash(&video), head.into());
+        }
+
+        // same size and mtime, only the content differs
+        let rewrite = |path: &Path, content: Vec<u8>| {
+            fs::write(path, content).unwrap();
+            set_mtime(path, 1_000_000_000);
+        };
+        rewrite(&conf, b"port=2".to_vec());
+        rewrite(&cached, b"CACHED".to_vec());
+        let mut tail_changed = vec![0u8; policy::HEAD_HASH_LEN as usize + 10];
+        tail_changed[policy::HEAD_HASH_LEN as usize + 1] = 1;
+        rewrite(&video, tail_changed);
+        let mut checker = CheckDB::new(&db, CheckOptions::default()).recording();
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        let found: Vec<_> = checker.take_recorded().into_iter().map(|f| f.path).collect();
+        assert_eq!(found, [conf.to_string_lossy().to_string()]);
+
+        let mut head_changed = vec![0u8; policy::HEAD_HASH_LEN as usize + 10];
+        head_changed[0] = 1;
+        rewrite(&video, head_changed);
+        let mut checker = CheckDB::new(&db, CheckOptions::default());
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut checker).await.unwrap();
+        assert_eq!(checker.get_changes_count(), 2);
+
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_dirs_skips_dir_entries() {
+        let tree = setup_test_tree("no_dirs");
+        let db_path = tree.with_extension("redb");
+        let db = Database::create(&db_path).unwrap();
+        let mut writer = WriteToDB::new(&db);
+        let opts = ScanOptions{ no_dirs: true, ..Default::default() };
+        visit_dirs(tree.clone(), &ExcludeSet::default(), &opts, &mut writer).await.unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(TABLE).unwrap();
+        let mut files = 0;
+        for k in table.iter().unwrap(){
+            let k = k.unwrap();
+            assert!(!matches!(k.1.value(), FileMetadataExt::Dir(_)), "Dir entry stored for {}", k.0.value());
+            files += 1;
+        }
+        assert_eq!(files, 3);
+
+        drop(read_txn);
+        drop(db);
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(tree).unwrap();
+    }
+}
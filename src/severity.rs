@@ -0,0 +1,241 @@
+//! `--severity-policy` files: how serious each kind of change a check finds
+//! is, and from the most serious one, the result of the check. Each line is a
+//! change and a severity:
+//!
+//! ```text
+//! # content changes come with package updates here
+//! hash            warning
+//! mtime           info
+//! world-writable  critical
+//! ```
+//!
+//! A finding takes the highest severity of its changes and is logged at info,
+//! warn or error level. The check exits with 0 when nothing above info was
+//! found, 1 for warnings and 2 for critical findings. Changes no line names
+//! keep their default, see [`Change::default_severity`].
+
+use super::error::IntegrityWatcherError;
+use log::Level;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity{
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    /// Log level of findings of this severity.
+    pub fn level(self) -> Level {
+        match self{
+            Severity::Info => Level::Info,
+            Severity::Warning => Level::Warn,
+            Severity::Critical => Level::Error,
+        }
+    }
+
+    /// Severity of findings logged at `level`, the inverse of `level`.
+    pub fn of_level(level: Level) -> Self {
+        match level{
+            Level::Error => Severity::Critical,
+            Level::Warn => Severity::Warning,
+            _ => Severity::Info,
+        }
+    }
+
+    /// Exit code of a check whose worst finding is of this severity.
+    pub fn exit_code(self) -> u8 {
+        match self{
+            Severity::Info => 0,
+            Severity::Warning => 1,
+            Severity::Critical => 2,
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = IntegrityWatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s{
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(IntegrityWatcherError::InvalidArgument(format!("Unknown severity {s:?}, expected info, warning or critical"))),
+        }
+    }
+}
+
+/// What a finding says changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change{
+    /// an entry not in the DB
+    New,
+    /// a DB entry gone from disk
+    Removed,
+    /// the entry type, e.g. a file turned symlink, or the `--detect-type` content type
+    Type,
+    /// the content: hash, SHA-256, data streams or the content of a symlink target
+    Hash,
+    /// the size of a file whose content wasn't compared, or of a directory or symlink
+    Size,
+    /// the modification time
+    Mtime,
+    Permissions,
+    /// permissions newly letting anyone write, or a new entry anyone can write
+    WorldWritable,
+    /// the `--owner` user and group, or the owner or ACL of Windows entries
+    Owner,
+    /// the inode number of `--track-inode`
+    Inode,
+    /// the immutable and append-only flags of `--flags`
+    Flags,
+    /// where a symlink points, or what it points to
+    Target,
+    /// the number of entries of a directory
+    Entries,
+}
+
+impl Change {
+    pub const ALL: [Change; 13] = [Change::New, Change::Removed, Change::Type, Change::Hash, Change::Size, Change::Mtime, Change::Permissions,
+        Change::WorldWritable, Change::Owner, Change::Inode, Change::Flags, Change::Target, Change::Entries];
+
+    /// Name in policy files.
+    pub fn name(self) -> &'static str {
+        match self{
+            Change::New => "new",
+            Change::Removed => "removed",
+            Change::Type => "type",
+            Change::Hash => "hash",
+            Change::Size => "size",
+            Change::Mtime => "mtime",
+            Change::Permissions => "permissions",
+            Change::WorldWritable => "world-writable",
+            Change::Owner => "owner",
+            Change::Inode => "inode",
+            Change::Flags => "flags",
+            Change::Target => "target",
+            Change::Entries => "entries",
+        }
+    }
+
+    /// Modification times are info, new and removed entries warnings, as they
+    /// are logged without a policy, and the other changes critical.
+    pub fn default_severity(self) -> Severity {
+        match self{
+            Change::Mtime => Severity::Info,
+            Change::New | Change::Removed => Severity::Warning,
+            _ => Severity::Critical,
+        }
+    }
+}
+
+impl FromStr for Change {
+    type Err = IntegrityWatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Change::ALL.into_iter().find(|c| c.name() == s).ok_or_else(|| {
+            let names: Vec<_> = Change::ALL.iter().map(|c| c.name()).collect();
+            IntegrityWatcherError::InvalidArgument(format!("Unknown change {s:?}, expected one of {}", names.join(", ")))
+        })
+    }
+}
+
+/// The changes of one finding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Changes(u16);
+
+impl Changes {
+    pub fn insert(&mut self, change: Change) {
+        self.0 |= 1 << change as u16;
+    }
+
+    pub fn contains(self, change: Change) -> bool {
+        self.0 & (1 << change as u16) != 0
+    }
+}
+
+/// Whether Unix `permissions` let anyone write. Modes without file type bits
+/// are Windows attributes or the read-only flag of other systems.
+pub fn world_writable(permissions: u32) -> bool {
+    const TYPE_BITS: u32 = 0o170000;
+    cfg!(unix) && permissions & TYPE_BITS != 0 && permissions & 0o002 != 0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeverityPolicy{
+    severities: [Severity; Change::ALL.len()],
+}
+
+impl Default for SeverityPolicy {
+    fn default() -> Self {
+        SeverityPolicy{ severities: Change::ALL.map(Change::default_severity) }
+    }
+}
+
+impl SeverityPolicy {
+    pub fn severity_of(&self, change: Change) -> Severity {
+        self.severities[change as usize]
+    }
+
+    /// The highest severity of `changes`, none when there are none.
+    pub fn severity(&self, changes: Changes) -> Option<Severity> {
+        Change::ALL.into_iter().filter(|c| changes.contains(*c)).map(|c| self.severity_of(c)).max()
+    }
+}
+
+impl FromStr for SeverityPolicy {
+    type Err = IntegrityWatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = SeverityPolicy::default();
+        for (n, line) in s.lines().enumerate(){
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#'){
+                continue;
+            }
+            let invalid = |reason: &str| IntegrityWatcherError::InvalidArgument(format!("Severity policy line {}: {reason}", n + 1));
+            let mut words = line.split_whitespace();
+            let (Some(change), Some(severity), None) = (words.next(), words.next(), words.next()) else {
+                return Err(invalid("expected a change and a severity"));
+            };
+            let change: Change = change.parse().map_err(|e: IntegrityWatcherError| invalid(&e.to_string()))?;
+            policy.severities[change as usize] = severity.parse().map_err(|e: IntegrityWatcherError| invalid(&e.to_string()))?;
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_severity_policy() {
+        let policy: SeverityPolicy = "# quiet content\nhash warning\n\n  world-writable   critical\nnew info\n".parse().unwrap();
+        assert_eq!(policy.severity_of(Change::Hash), Severity::Warning);
+        assert_eq!(policy.severity_of(Change::New), Severity::Info);
+        assert_eq!(policy.severity_of(Change::Permissions), Severity::Critical);
+        assert_eq!(policy.severity_of(Change::Mtime), Severity::Info);
+
+        let mut changes = Changes::default();
+        assert_eq!(policy.severity(changes), None);
+        changes.insert(Change::Mtime);
+        changes.insert(Change::Hash);
+        assert!(changes.contains(Change::Hash) && !changes.contains(Change::Size));
+        assert_eq!(policy.severity(changes), Some(Severity::Warning));
+        changes.insert(Change::Owner);
+        assert_eq!(policy.severity(changes), Some(Severity::Critical));
+
+        assert!("hash".parse::<SeverityPolicy>().is_err());
+        assert!("hash loud".parse::<SeverityPolicy>().is_err());
+        let e = "sizes info".parse::<SeverityPolicy>().unwrap_err().to_string();
+        assert!(e.contains("line 1") && e.contains("world-writable"), "{e}");
+
+        assert_eq!(Severity::of_level(Level::Warn).exit_code(), 1);
+        assert_eq!(Severity::Critical.level(), Level::Error);
+        assert_eq!(world_writable(0o100666), cfg!(unix));
+        assert!(!world_writable(0o100644) && !world_writable(0o2));
+    }
+}
@@ -0,0 +1,103 @@
+//! One read view of a baseline shared by all hash tasks of a scan, so prefilter
+//! lookups like `--since` don't each begin their own read transaction.
+//!
+//! Small tables are loaded into a map: a lookup is then a hash probe and the
+//! snapshot holds no transaction that would keep redb from reclaiming pages
+//! freed by later writes. Above `PRELOAD_LIMIT` entries the map would cost more
+//! memory than it saves, so lookups go through one read-only table handle,
+//! which redb lets any thread use for the life of its transaction.
+
+use super::error::IntegrityWatcherError;
+use super::fileops::files_table;
+use super::types::FileMetadataExt;
+use redb::{Database, ReadOnlyTable, ReadableDatabase, ReadableTableMetadata};
+use std::collections::HashMap;
+
+/// Entries up to which a table is loaded into memory, a few hundred MB at most.
+pub const PRELOAD_LIMIT: u64 = 1_000_000;
+
+pub enum BaselineSnapshot{
+    Loaded(HashMap<String, FileMetadataExt>),
+    Live(ReadOnlyTable<String, FileMetadataExt>),
+}
+
+impl BaselineSnapshot {
+    /// Snapshot of `table`, loaded when it has at most `preload_limit` entries.
+    pub fn open(db: &Database, table: &str, preload_limit: u64) -> Result<Self, IntegrityWatcherError> {
+        let read_txn = db.begin_read().map_err(Box::new)?;
+        let table = read_txn.open_table(files_table(table))?;
+        if table.len()? > preload_limit{
+            return Ok(BaselineSnapshot::Live(table));
+        }
+        let mut entries = HashMap::new();
+        for k in table.range::<String>(..)?{
+            let k = k?;
+            entries.insert(k.0.value(), k.1.value());
+        }
+        Ok(BaselineSnapshot::Loaded(entries))
+    }
+
+    /// Stored entry of `path`, already folded like the DB keys.
+    pub fn get(&self, path: &str) -> Result<Option<FileMetadataExt>, IntegrityWatcherError> {
+        match self{
+            BaselineSnapshot::Loaded(entries) => Ok(entries.get(path).cloned()),
+            BaselineSnapshot::Live(table) => Ok(table.get(path.to_owned())?.map(|v| v.value())),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_loaded(&self) -> bool {
+        matches!(self, BaselineSnapshot::Loaded(_))
+    }
+}
+
+impl std::fmt::Debug for BaselineSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            BaselineSnapshot::Loaded(entries) => write!(f, "BaselineSnapshot::Loaded({} entries)", entries.len()),
+            BaselineSnapshot::Live(_) => write!(f, "BaselineSnapshot::Live"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::{AddFileInfo, WriteToDB};
+    use crate::types::FileMetadata;
+    use std::sync::Arc;
+
+    fn entry(i: u64) -> FileMetadataExt {
+        FileMetadataExt::File(FileMetadata{ hash: [i as u8; 32].into(), permissions: 0o100644, modified: i, size: i.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None })
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_lookups() {
+        let db_path = std::env::current_dir().unwrap().join("test_db_snapshot.redb");
+        let _ = std::fs::remove_file(&db_path);
+        let db = Database::create(&db_path).unwrap();
+        let entries: Vec<_> = (0..200).map(|i| (format!("/f/{i}"), entry(i))).collect();
+        WriteToDB::new(&db).add_file_info(&entries).unwrap();
+
+        for preload_limit in [PRELOAD_LIMIT, 10]{
+            let snapshot = Arc::new(BaselineSnapshot::open(&db, crate::fileops::DEFAULT_TABLE, preload_limit).unwrap());
+            assert_eq!(snapshot.is_loaded(), preload_limit == PRELOAD_LIMIT);
+            let mut tasks = tokio::task::JoinSet::new();
+            for t in 0..8u64{
+                let snapshot = snapshot.clone();
+                tasks.spawn(async move {
+                    for i in (t..200).step_by(8){
+                        assert_eq!(snapshot.get(&format!("/f/{i}")).unwrap(), Some(entry(i)));
+                    }
+                    assert!(snapshot.get("/f/missing").unwrap().is_none());
+                });
+            }
+            while let Some(r) = tasks.join_next().await{
+                r.unwrap();
+            }
+        }
+
+        drop(db);
+        std::fs::remove_file(db_path).unwrap();
+    }
+}
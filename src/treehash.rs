@@ -0,0 +1,191 @@
+//! `--parallel-file-hash`: a file is hashed as a tree of fixed-size stripes, so
+//! the stripes of a huge file can be hashed on every core. A stripe hashes to
+//! `SHA-256(0x00 ‖ stripe)`, the file to `SHA-256(0x01 ‖ stripe size as u64 BE
+//! ‖ stripe hashes)`; an empty file is one empty stripe. That's not the SHA-256
+//! of the file, DBs record the stripe size and only compare hashes made with it.
+
+use super::types;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::num::NonZero;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `--stripe-size` default in MiB.
+pub const DEFAULT_STRIPE_MIB: u32 = 64;
+
+/// Tree hash of data fed in order, e.g. a file read front to back.
+pub struct TreeHasher{
+    stripe: u64,
+    root: Sha256,
+    leaf: Sha256,
+    /// bytes in `leaf` so far
+    filled: u64,
+    leaves: u64,
+}
+
+impl TreeHasher {
+    pub fn new(stripe: u64) -> Self {
+        TreeHasher { stripe, root: root_hasher(stripe), leaf: leaf_hasher(), filled: 0, leaves: 0 }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty(){
+            let n = data.len().min(usize::try_from(self.stripe - self.filled).unwrap_or(usize::MAX));
+            self.leaf.update(&data[..n]);
+            self.filled += n as u64;
+            data = &data[n..];
+            if self.filled == self.stripe{
+                self.end_leaf();
+            }
+        }
+    }
+
+    fn end_leaf(&mut self) {
+        let leaf = std::mem::replace(&mut self.leaf, leaf_hasher()).finalize();
+        self.root.update(leaf);
+        self.filled = 0;
+        self.leaves += 1;
+    }
+
+    pub fn finish(mut self) -> types::Hash {
+        if self.filled > 0 || self.leaves == 0{
+            self.end_leaf();
+        }
+        let result: [u8; 32] = self.root.finalize().into();
+        result.into()
+    }
+}
+
+fn leaf_hasher() -> Sha256 {
+    Sha256::new_with_prefix([0])
+}
+
+fn root_hasher(stripe: u64) -> Sha256 {
+    let mut root = Sha256::new_with_prefix([1]);
+    root.update(stripe.to_be_bytes());
+    root
+}
+
+/// Helper threads all files being hashed share, so files hashed side by side
+/// don't start a thread per core each.
+static BUSY_HELPERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Up to `wanted` helper threads, as many as the cores left over by the other
+/// files allow; given back on drop.
+struct Helpers(usize);
+
+impl Helpers {
+    fn take(wanted: usize) -> Self {
+        // the thread asking hashes stripes itself
+        let limit = std::thread::available_parallelism().map_or(1, NonZero::get) - 1;
+        let mut taken = 0;
+        let _ = BUSY_HELPERS.fetch_update(Ordering::AcqRel, Ordering::Acquire, |busy| {
+            taken = wanted.min(limit.saturating_sub(busy));
+            Some(busy + taken)
+        });
+        Helpers(taken)
+    }
+}
+
+impl Drop for Helpers {
+    fn drop(&mut self) {
+        BUSY_HELPERS.fetch_sub(self.0, Ordering::AcqRel);
+    }
+}
+
+/// Tree hash of the file at `path`. Files of more than one stripe are read by
+/// the calling thread and the helper threads free, each with its own handle,
+/// hashing every n-th stripe.
+pub fn hash_file(path: &Path, stripe: u64) -> io::Result<types::Hash> {
+    let mut file = File::open(path)?;
+    let stripes = file.metadata()?.len().div_ceil(stripe).max(1);
+    if stripes == 1{
+        let mut hasher = TreeHasher::new(stripe);
+        read_into(&mut file, |data| hasher.update(data))?;
+        return Ok(hasher.finish());
+    }
+    let helpers = Helpers::take(usize::try_from(stripes - 1).unwrap_or(usize::MAX));
+    let jobs = helpers.0 + 1;
+    let hash_stripes = |first: u64| -> io::Result<Vec<(u64, [u8; 32])>> {
+        let mut file = File::open(path)?;
+        let mut leaves = Vec::new();
+        for index in (first..stripes).step_by(jobs){
+            file.seek(SeekFrom::Start(index * stripe))?;
+            let mut leaf = leaf_hasher();
+            read_into((&mut file).take(stripe), |data| leaf.update(data))?;
+            leaves.push((index, leaf.finalize().into()));
+        }
+        Ok(leaves)
+    };
+    let mut leaves = std::thread::scope(|scope| {
+        let workers: Vec<_> = (1..jobs as u64).map(|first| scope.spawn(move || hash_stripes(first))).collect();
+        let own = hash_stripes(0);
+        std::iter::once(own)
+            .chain(workers.into_iter().map(|w| w.join().unwrap_or_else(|_| Err(io::Error::other("stripe hashing thread panicked")))))
+            .collect::<io::Result<Vec<_>>>()
+    })?.concat();
+    drop(helpers);
+    leaves.sort_unstable_by_key(|(index, _)| *index);
+    let mut root = root_hasher(stripe);
+    for (_, leaf) in leaves{
+        root.update(leaf);
+    }
+    let result: [u8; 32] = root.finalize().into();
+    Ok(result.into())
+}
+
+fn read_into(mut input: impl Read, mut hash: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buffer = [0u8; 65536];
+    loop{
+        match input.read(&mut buffer){
+            Ok(0) => return Ok(()),
+            Ok(n) => hash(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_hash() {
+        let path = std::env::current_dir().unwrap().join("test_tree_treehash");
+        let _ = std::fs::remove_file(&path);
+        let stripe = 4096;
+        let content: Vec<u8> = (0..stripe * 10 + 123).map(|i| (i * 7 % 251) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        // stripes hashed in parallel, the same as fed in order in any pieces
+        let hash = hash_file(&path, stripe).unwrap();
+        assert_eq!(hash_file(&path, stripe).unwrap(), hash);
+        for piece in [1, 1000, 4096, 5000, content.len()]{
+            let mut hasher = TreeHasher::new(stripe);
+            for chunk in content.chunks(piece){
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), hash, "{piece}");
+        }
+        let sha256: [u8; 32] = Sha256::digest(&content).into();
+        assert_ne!(hash, sha256.into());
+        assert_ne!(hash_file(&path, stripe * 2).unwrap(), hash);
+
+        // one byte in a middle stripe
+        let mut changed = content.clone();
+        changed[stripe as usize * 5 + 17] ^= 1;
+        std::fs::write(&path, &changed).unwrap();
+        assert_ne!(hash_file(&path, stripe).unwrap(), hash);
+        // a whole stripe cut off
+        std::fs::write(&path, &content[..stripe as usize * 10]).unwrap();
+        assert_ne!(hash_file(&path, stripe).unwrap(), hash);
+
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(hash_file(&path, stripe).unwrap(), TreeHasher::new(stripe).finish());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
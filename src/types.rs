@@ -4,11 +4,267 @@ use std::time::UNIX_EPOCH;
 use chrono::DateTime;
 use redb::{Value,Key};
 use std::time::Duration;
+use std::sync::OnceLock;
 
 #[cfg(target_os = "linux")]
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 
 use super::error::IntegrityWatcherError;
+use super::winmeta;
+use super::inodeflags;
+
+/// Path wrapper for log output. Paths containing control characters (newlines,
+/// ANSI escapes, ...) or invisible format characters (bidi overrides, zero
+/// width spaces, ...) are quoted and escaped so a crafted filename can't forge
+/// or disguise log lines; ordinary paths are printed verbatim. A path that
+/// would read like an escaped one, starting with a quote or holding a
+/// backslash outside Windows, is escaped too.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapedPath<'a>(pub &'a str);
+
+impl std::fmt::Display for EscapedPath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ambiguous = self.0.starts_with('"') || (!cfg!(windows) && self.0.contains('\\'));
+        if !ambiguous && !self.0.chars().any(|c| c.is_control() || is_format(c)){
+            return f.write_str(self.0);
+        }
+        f.write_str("\"")?;
+        for c in self.0.chars(){
+            if is_format(c){
+                write!(f, "\\u{{{:x}}}", c as u32)?;
+            }
+            else{
+                write!(f, "{}", c.escape_debug())?;
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+/// Unicode format characters (category Cf), invisible but reordering or
+/// hiding the text around them.
+fn is_format(c: char) -> bool {
+    matches!(c,
+        '\u{ad}' | '\u{600}'..='\u{605}' | '\u{61c}' | '\u{6dd}' | '\u{70f}' | '\u{890}'..='\u{891}' | '\u{8e2}'
+        | '\u{180e}' | '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{206f}'
+        | '\u{feff}' | '\u{fff9}'..='\u{fffb}' | '\u{110bd}' | '\u{110cd}' | '\u{13430}'..='\u{1343f}'
+        | '\u{1bca0}'..='\u{1bca3}' | '\u{1d173}'..='\u{1d17a}' | '\u{e0001}' | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+/// Output rendering switches, set once from the command line. The defaults
+/// keep the historical format so existing log parsers aren't broken.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DisplayOptions{
+    pub local_time: bool,
+    pub human_sizes: bool,
+    pub symbolic_permissions: bool,
+    pub perms_only: bool,
+}
+
+static DISPLAY_OPTIONS: OnceLock<DisplayOptions> = OnceLock::new();
+
+impl DisplayOptions {
+    pub fn set_global(self){
+        let _ = DISPLAY_OPTIONS.set(self);
+    }
+
+    pub fn global() -> Self{
+        DISPLAY_OPTIONS.get().copied().unwrap_or_default()
+    }
+}
+
+/// Seconds since epoch rendered as UTC, or local time with `--local-time`.
+#[derive(Debug, Clone, Copy)]
+pub struct Timestamp{
+    secs: u64,
+    local: bool,
+}
+
+impl Timestamp {
+    pub fn new(secs: u64) -> Self{
+        Self::with_options(secs, &DisplayOptions::global())
+    }
+
+    pub fn with_options(secs: u64, opts: &DisplayOptions) -> Self{
+        Timestamp { secs, local: opts.local_time }
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match DateTime::from_timestamp(self.secs as i64, 0){
+            Some(t) if self.local => write!(f, "{}", t.with_timezone(&chrono::Local)),
+            Some(t) => write!(f, "{}", t),
+            None => f.write_str("#ERROR#"),
+        }
+    }
+}
+
+/// File type bits (`S_IFMT`) of a stored Linux mode.
+pub const MODE_TYPE_MASK: u32 = 0o170000;
+/// Permission bits including setuid, setgid and sticky.
+pub const MODE_PERMS_MASK: u32 = 0o7777;
+
+/// Mode bits in octal, followed by `ls -l` style notation with `--symbolic-permissions`.
+/// With `--perms-only` the file type bits are left out. On Windows the stored
+/// value holds attribute flags, which are shown by name.
+#[derive(Debug, Clone, Copy)]
+pub struct Permissions{
+    mode: u32,
+    symbolic: bool,
+    perms_only: bool,
+}
+
+impl Permissions {
+    pub fn new(mode: u32) -> Self{
+        Self::with_options(mode, &DisplayOptions::global())
+    }
+
+    pub fn with_options(mode: u32, opts: &DisplayOptions) -> Self{
+        Permissions { mode, symbolic: opts.symbolic_permissions, perms_only: opts.perms_only }
+    }
+
+    fn symbolic(&self) -> String{
+        let mode = self.mode;
+        let mut s = String::with_capacity(10);
+        s.push(match mode & MODE_TYPE_MASK{
+            0o140000 => 's',
+            0o120000 => 'l',
+            0o100000 => '-',
+            0o060000 => 'b',
+            0o040000 => 'd',
+            0o020000 => 'c',
+            0o010000 => 'p',
+            _ => '?',
+        });
+        let special = [(0o4000, 's', 'S'), (0o2000, 's', 'S'), (0o1000, 't', 'T')];
+        for (i, (bit, set, unset)) in special.into_iter().enumerate(){
+            let shift = 6 - 3 * i;
+            let rwx = (mode >> shift) & 0o7;
+            s.push(if rwx & 0o4 != 0 { 'r' } else { '-' });
+            s.push(if rwx & 0o2 != 0 { 'w' } else { '-' });
+            s.push(match (rwx & 0o1 != 0, mode & bit != 0){
+                (true, true) => set,
+                (false, true) => unset,
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+        s
+    }
+}
+
+impl std::fmt::Display for Permissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if cfg!(windows){
+            return f.write_str(&winmeta::attribute_names(self.mode));
+        }
+        let (mode, skip) = if self.perms_only { (self.mode & MODE_PERMS_MASK, 1) } else { (self.mode, 0) };
+        if self.symbolic{
+            write!(f, "{:o} ({})", mode, &self.symbolic()[skip..])
+        }
+        else{
+            write!(f, "{:o}", mode)
+        }
+    }
+}
+
+/// Directory size as stored by the filesystem, humanized with `--human-sizes`.
+#[derive(Debug, Clone, Copy)]
+pub struct DirSize{
+    size: u64,
+    human: bool,
+}
+
+impl DirSize {
+    pub fn new(size: u64) -> Self{
+        Self::with_options(size, &DisplayOptions::global())
+    }
+
+    pub fn with_options(size: u64, opts: &DisplayOptions) -> Self{
+        DirSize { size, human: opts.human_sizes }
+    }
+}
+
+impl std::fmt::Display for DirSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.human{
+            write!(f, "{}", ByteSize::new(self.size))
+        }
+        else{
+            write!(f, "{}", self.size)
+        }
+    }
+}
+
+/// Seconds in a `90s`, `30m`, `2h`, `7d` or `1w` style duration, never negative.
+fn parse_relative(s: &str) -> Option<i64> {
+    let unit = s.chars().last().filter(|c| "smhdw".contains(*c))?;
+    let n: i64 = s[..s.len() - 1].parse().ok().filter(|n| *n >= 0)?;
+    let mult = match unit{
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => 7 * 24 * 60 * 60,
+    };
+    n.checked_mul(mult)
+}
+
+/// Parses a duration argument given as plain seconds or with a unit (`30s`, `5m`, ...).
+pub fn parse_duration(s: &str, arg: &str) -> Result<Duration, IntegrityWatcherError> {
+    let s = s.trim();
+    let secs = s.parse::<u64>().ok()
+        .or_else(|| parse_relative(s).and_then(|secs| u64::try_from(secs).ok()))
+        .ok_or_else(|| IntegrityWatcherError::InvalidArgument(format!("{arg} {s}")))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parses a `--since` cutoff into seconds since epoch. Accepts a relative age
+/// (`90s`, `30m`, `2h`, `7d`, `1w`) counted back from `now`, an RFC 3339 time,
+/// also without seconds, `YYYY-MM-DD HH:MM:SS` / `YYYY-MM-DD` in UTC, or plain
+/// epoch seconds.
+pub fn parse_since(s: &str, now: i64) -> Result<u64, IntegrityWatcherError> {
+    let s = s.trim();
+    let invalid = || IntegrityWatcherError::InvalidArgument(format!("--since {s}"));
+    let secs = match s.ends_with(|c| "smhdw".contains(c)){
+        true => now - parse_relative(s).ok_or_else(invalid)?,
+        false => parse_absolute(s).ok_or_else(invalid)?,
+    };
+    u64::try_from(secs).map_err(|_| invalid())
+}
+
+/// Parses a `--maintenance-until` deadline like `parse_since`, with relative
+/// durations counted forward from `now`.
+pub fn parse_deadline(s: &str, now: i64) -> Result<u64, IntegrityWatcherError> {
+    let s = s.trim();
+    let invalid = || IntegrityWatcherError::InvalidArgument(format!("--maintenance-until {s}"));
+    let secs = match s.ends_with(|c| "smhdw".contains(c)){
+        true => now + parse_relative(s).ok_or_else(invalid)?,
+        false => parse_absolute(s).ok_or_else(invalid)?,
+    };
+    u64::try_from(secs).map_err(|_| invalid())
+}
+
+/// Epoch seconds of an absolute time in one of the `parse_since` formats.
+fn parse_absolute(s: &str) -> Option<i64> {
+    if let Ok(epoch) = s.parse::<i64>(){
+        Some(epoch)
+    }
+    else if let Ok(t) = DateTime::parse_from_rfc3339(s){
+        Some(t.timestamp())
+    }
+    else if let Ok(t) = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%MZ"){
+        Some(t.and_utc().timestamp())
+    }
+    else if let Ok(t) = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"){
+        Some(t.and_utc().timestamp())
+    }
+    else{
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0).map(|t| t.and_utc().timestamp())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Bandwidth{
@@ -104,20 +360,69 @@ impl std::fmt::Display for ByteSize {
     }
 }
 
+/// File digest. SHA-256 unless the DB was created with `--hash-command`,
+/// whose output can have any length, or `--prefilter` (XXH3-64).
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Hash{
-    hash: [u8;32],
+    hash: Vec<u8>,
 }
 
 impl From<[u8;32]> for Hash {
     fn from(value: [u8;32]) -> Self {
-        Hash { hash: value }
+        Hash { hash: value.to_vec() }
+    }
+}
+
+impl From<[u8;8]> for Hash {
+    fn from(value: [u8;8]) -> Self {
+        Hash { hash: value.to_vec() }
+    }
+}
+
+impl Hash {
+    /// No content hash, files of `--stat-only` databases.
+    pub fn none() -> Self {
+        Hash { hash: Vec::new() }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.hash.is_empty()
+    }
+
+    /// Digest length in bytes, 0 for none.
+    pub fn byte_len(&self) -> usize {
+        self.hash.len()
+    }
+
+    /// Parses an even number of hex digits (either case).
+    pub fn from_hex(s: &str) -> Result<Self, IntegrityWatcherError> {
+        let s = s.trim();
+        if s.is_empty() || !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()){
+            return Err(IntegrityWatcherError::InvalidHash(s.to_owned()));
+        }
+        let hash = (0..s.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i+2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| IntegrityWatcherError::InvalidHash(s.to_owned()))?;
+        Ok(Hash { hash })
+    }
+}
+
+impl std::str::FromStr for Hash {
+    type Err = IntegrityWatcherError;
+
+    /// Parses a SHA-256 as 64 hex digits (either case), as printed by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().len() != 64{
+            return Err(IntegrityWatcherError::InvalidHash(s.trim().to_owned()));
+        }
+        Hash::from_hex(s)
     }
 }
 
 impl std::fmt::Display for Hash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for i in self.hash{
+        for i in &self.hash{
             write!(f, "{:02x}", i)?;
         }
         Ok(())
@@ -126,15 +431,15 @@ impl std::fmt::Display for Hash {
 
 impl Value for Hash {
     type SelfType<'a> = Self;
-    type AsBytes<'a> = &'a[u8;32];
+    type AsBytes<'a> = &'a[u8];
 
     fn fixed_width() -> Option<usize> {
-        Some(32)
+        None
     }
 
     fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
         where Self: 'a{
-        from_bytes(data).unwrap()
+        Hash { hash: data.to_vec() }
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a> {
@@ -158,13 +463,55 @@ pub struct SymlinkMetadata{
     pub permissions: u32,
     pub modified: u64,
     pub size: ByteSize,
+    /// SHA-256 of the regular file the link resolves to, only recorded for
+    /// databases created with `--hash-link-content`
+    pub content: Option<Hash>,
+    /// the link resolved when scanned
+    pub target_exists: bool,
+    /// what the link resolved to, `Some` whenever `target_exists` except for
+    /// entries stored before it was recorded
+    pub target_type: Option<TargetType>,
+}
+
+/// Kind of entry a symlink resolves to.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum TargetType{
+    File,
+    Dir,
+    Other,
+}
+
+impl From<std::fs::FileType> for TargetType {
+    fn from(t: std::fs::FileType) -> Self {
+        if t.is_file(){
+            TargetType::File
+        }
+        else if t.is_dir(){
+            TargetType::Dir
+        }
+        else{
+            TargetType::Other
+        }
+    }
+}
+
+impl std::fmt::Display for TargetType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            TargetType::File => write!(f, "file"),
+            TargetType::Dir => write!(f, "dir"),
+            TargetType::Other => write!(f, "other"),
+        }
+    }
 }
 
 impl SymlinkMetadata {
     pub fn new(meta: &std::fs::Metadata, data: String) -> Result<Self, IntegrityWatcherError> {
         #[cfg(target_os = "linux")]
         let permissions = meta.permissions().mode();
-        #[cfg(not(target_os = "linux"))]
+        #[cfg(windows)]
+        let permissions = winmeta::attributes(meta);
+        #[cfg(not(any(target_os = "linux", windows)))]
         let permissions = meta.permissions().readonly() as u32;
         Ok(Self {
             data,
@@ -174,19 +521,80 @@ impl SymlinkMetadata {
                 Err(_) => 0,
             },
             size: meta.len().into(),
+            content: None,
+            target_exists: false,
+            target_type: None,
         })
     }
 }
 
 impl std::fmt::Display for SymlinkMetadata {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match DateTime::from_timestamp(self.modified as i64, 0){
-            Some(t) =>
-                write!(f, "-> {} perm: {:o} size: {} modified: {}", self.data, self.permissions, self.size, t),
-            None => {
-                write!(f, "-> {} perm: {:o} size: {} modified: #ERROR#", self.data, self.permissions, self.size)
-            }
+        write!(f, "-> {} perm: {} size: {} modified: {}", EscapedPath(&self.data), Permissions::new(self.permissions), self.size, Timestamp::new(self.modified))?;
+        if let Some(content) = &self.content{
+            write!(f, " content: {content}")?;
         }
+        match (self.target_exists, self.target_type){
+            (false, _) => write!(f, " dangling")?,
+            (true, Some(target)) => write!(f, " target: {target}")?,
+            (true, None) => {},
+        }
+        Ok(())
+    }
+}
+
+/// Owner and DACL of a Windows file or directory, see `winmeta::security`.
+#[derive(Debug,Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Security{
+    /// owner SID, e.g. `S-1-5-32-544`
+    pub owner: String,
+    /// SHA-256 of the owner and DACL in SDDL form
+    pub descriptor: Hash,
+}
+
+impl std::fmt::Display for Security {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "owner: {} acl: {}", self.owner, self.descriptor)
+    }
+}
+
+/// Owning user and group of a Unix file or directory, see `ownership`.
+#[derive(Debug,Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Owner{
+    pub uid: u32,
+    pub gid: u32,
+    /// name of `uid` on the scanned system, `None` when it has none
+    pub user: Option<String>,
+    /// name of `gid` on the scanned system, `None` when it has none
+    pub group: Option<String>,
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.user{
+            Some(user) => write!(f, "{}({})", EscapedPath(user), self.uid)?,
+            None => write!(f, "{}", self.uid)?,
+        }
+        match &self.group{
+            Some(group) => write!(f, ":{}({})", EscapedPath(group), self.gid),
+            None => write!(f, ":{}", self.gid),
+        }
+    }
+}
+
+/// Named NTFS data stream of a file (`file.txt:name`), see `winmeta::streams`.
+#[derive(Debug,Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct DataStream{
+    /// stream name without the leading `:` and the `:$DATA` type
+    pub name: String,
+    pub size: ByteSize,
+    /// SHA-256 of the stream content
+    pub hash: Hash,
+}
+
+impl std::fmt::Display for DataStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, ":{} size: {} hash: {}", EscapedPath(&self.name), self.size, self.hash)
     }
 }
 
@@ -196,35 +604,89 @@ pub struct FileMetadata{
     pub permissions: u32,
     pub modified: u64,
     pub size: ByteSize,
+    /// only recorded for databases created with `--track-inode`
+    pub ino: Option<u64>,
+    /// SHA-256 next to the XXH3 `hash` of `--prefilter` databases, stored with `--verify-matches`
+    pub strong: Option<Hash>,
+    /// owner and DACL, only recorded on Windows
+    pub security: Option<Security>,
+    /// alternate data streams, only recorded on Windows
+    pub streams: Option<Vec<DataStream>>,
+    /// MIME type told by the first bytes, see `magic::detect`, only recorded
+    /// for databases created with `--detect-type`
+    pub content_type: Option<String>,
+    /// birth time, `None` where the file system doesn't record one
+    pub created: Option<u64>,
+    /// immutable and append-only inode flags, see `inodeflags`, only recorded
+    /// for databases created with `--flags`
+    pub flags: Option<u32>,
+    /// only recorded for databases created with `--owner`
+    pub owner: Option<Owner>,
 }
 
 impl FileMetadata {
-    pub fn new(meta: &std::fs::Metadata, hash: [u8; 32]) -> Result<Self, IntegrityWatcherError> {
+    pub fn new(meta: &std::fs::Metadata, hash: Hash, track_inode: bool) -> Result<Self, IntegrityWatcherError> {
         #[cfg(target_os = "linux")]
-        let permissions = meta.permissions().mode();
+        let ino = track_inode.then(|| meta.ino());
         #[cfg(not(target_os = "linux"))]
+        let ino = { let _ = track_inode; None };
+        #[cfg(target_os = "linux")]
+        let permissions = meta.permissions().mode();
+        #[cfg(windows)]
+        let permissions = winmeta::attributes(meta);
+        #[cfg(not(any(target_os = "linux", windows)))]
         let permissions = meta.permissions().readonly() as u32;
         Ok(Self {
-            hash: hash.into(),
+            hash,
             permissions,
             modified: match meta.modified(){
                 Ok(t) => t.duration_since(UNIX_EPOCH)?.as_secs(),
                 Err(_) => 0,
             },
             size: meta.len().into(),
+            ino,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: meta.created().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+            flags: None,
+            owner: None,
         })
     }
 }
 
 impl std::fmt::Display for FileMetadata {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match DateTime::from_timestamp(self.modified as i64, 0){
-            Some(t) =>
-                write!(f, "hash: {} perm: {:o} size: {} modified: {}", self.hash, self.permissions, self.size, t),
-            None => {
-                write!(f, "hash: {} perm: {:o} size: {} modified: #ERROR#", self.hash, self.permissions, self.size)
-            }
+        if !self.hash.is_none(){
+            write!(f, "hash: {} ", self.hash)?;
+        }
+        write!(f, "perm: {} size: {} modified: {}", Permissions::new(self.permissions), self.size, Timestamp::new(self.modified))?;
+        if let Some(ino) = self.ino{
+            write!(f, " inode: {}", ino)?;
+        }
+        if let Some(strong) = &self.strong{
+            write!(f, " sha256: {}", strong)?;
         }
+        if let Some(security) = &self.security{
+            write!(f, " {security}")?;
+        }
+        for stream in self.streams.iter().flatten(){
+            write!(f, " stream {stream}")?;
+        }
+        if let Some(content_type) = &self.content_type{
+            write!(f, " type: {content_type}")?;
+        }
+        if let Some(created) = self.created{
+            write!(f, " created: {}", Timestamp::new(created))?;
+        }
+        if let Some(flags) = self.flags{
+            write!(f, " flags: {}", inodeflags::letters(flags))?;
+        }
+        if let Some(owner) = &self.owner{
+            write!(f, " owner: {owner}")?;
+        }
+        Ok(())
     }
 }
 
@@ -233,13 +695,25 @@ pub struct DirMetadata{
     pub permissions: u32,
     pub modified: u64,
     pub size: u64,
+    /// owner and DACL, only recorded on Windows
+    pub security: Option<Security>,
+    /// number of children, `None` when the directory wasn't listed, so new
+    /// files are noticed even where the size of a directory doesn't change
+    pub entries: Option<u32>,
+    /// immutable and append-only inode flags, see `inodeflags`, only recorded
+    /// for databases created with `--flags`
+    pub flags: Option<u32>,
+    /// only recorded for databases created with `--owner`
+    pub owner: Option<Owner>,
 }
 
 impl DirMetadata {
     pub fn new(meta: &std::fs::Metadata) -> Result<Self, IntegrityWatcherError> {
         #[cfg(target_os = "linux")]
         let permissions = meta.permissions().mode();
-        #[cfg(not(target_os = "linux"))]
+        #[cfg(windows)]
+        let permissions = winmeta::attributes(meta);
+        #[cfg(not(any(target_os = "linux", windows)))]
         let permissions = meta.permissions().readonly() as u32;
         Ok(Self {
             permissions,
@@ -248,19 +722,30 @@ impl DirMetadata {
                 Err(_) => 0,
             },
             size: meta.len(),
+            security: None,
+            entries: None,
+            flags: None,
+            owner: None,
         })
     }
 }
 
 impl std::fmt::Display for DirMetadata {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match DateTime::from_timestamp(self.modified as i64, 0){
-            Some(t) =>
-                write!(f, " perm: {:o} size: {} modified: {}", self.permissions, self.size, t),
-            None => {
-                write!(f, " perm: {:o} size: {} modified: #ERROR#", self.permissions, self.size)
-            }
+        write!(f, " perm: {} size: {} modified: {}", Permissions::new(self.permissions), DirSize::new(self.size), Timestamp::new(self.modified))?;
+        if let Some(entries) = self.entries{
+            write!(f, " entries: {entries}")?;
+        }
+        if let Some(security) = &self.security{
+            write!(f, " {security}")?;
+        }
+        if let Some(flags) = self.flags{
+            write!(f, " flags: {}", inodeflags::letters(flags))?;
+        }
+        if let Some(owner) = &self.owner{
+            write!(f, " owner: {owner}")?;
         }
+        Ok(())
     }
 }
 
@@ -269,6 +754,11 @@ pub enum FileMetadataExt {
     Symlink(SymlinkMetadata),
     File(FileMetadata),
     Dir(DirMetadata),
+    /// a path an overlay layer deletes, see `whiteout`
+    Whiteout,
+    /// the `.wh..wh..opq` marker of a directory an overlay layer made opaque,
+    /// hiding what lower layers have in it, see `whiteout`
+    Opaque,
 }
 
 impl std::fmt::Display for FileMetadataExt {
@@ -283,10 +773,43 @@ impl std::fmt::Display for FileMetadataExt {
             FileMetadataExt::Dir(dir) => {
                 write!(f, "Directory {}", dir)
             }
+            FileMetadataExt::Whiteout => {
+                write!(f, "Whiteout")
+            }
+            FileMetadataExt::Opaque => {
+                write!(f, "Opaque directory")
+            }
         }
     }
 }
 
+/// Entries are written as `FORMAT_MAGIC`, `FORMAT_VERSION`, postcard. Entries
+/// of the unversioned layout start directly with the postcard enum tag (0..=2),
+/// so they can't be mistaken for the versioned one.
+const FORMAT_MAGIC: u8 = 0xff;
+const FORMAT_VERSION: u8 = 1;
+
+impl FileMetadataExt {
+    /// Stands in for an entry that can't be decoded: an empty file nothing on
+    /// disk matches, so a check reports the path changed instead of panicking.
+    fn undecodable() -> Self {
+        FileMetadataExt::File(FileMetadata {
+            hash: [0u8; 32].into(),
+            permissions: 0,
+            modified: 0,
+            size: 0.into(),
+            ino: None,
+            strong: None,
+            security: None,
+            streams: None,
+            content_type: None,
+            created: None,
+            flags: None,
+            owner: None,
+        })
+    }
+}
+
 impl Value for FileMetadataExt {
     type SelfType<'a> = Self;
     type AsBytes<'a> = Vec<u8>;
@@ -297,11 +820,21 @@ impl Value for FileMetadataExt {
 
     fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
         where Self: 'a{
-        from_bytes(data).unwrap()
+        let decoded: Result<Self, String> = match data{
+            [FORMAT_MAGIC, FORMAT_VERSION, rest @ ..] => from_bytes(rest).map_err(|e| e.to_string()),
+            [FORMAT_MAGIC, version, ..] => Err(format!("format version {version}, written by a newer integrity-checker?")),
+            _ => from_bytes::<legacy::FileMetadataExtV1>(data).map(Into::into).map_err(|e| e.to_string()),
+        };
+        decoded.unwrap_or_else(|e| {
+            tracing::error!("Undecodable DB entry, comparing it as an empty file: {e}");
+            FileMetadataExt::undecodable()
+        })
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a> {
-        to_allocvec(value).unwrap()
+        let mut bytes = vec![FORMAT_MAGIC, FORMAT_VERSION];
+        bytes.extend(to_allocvec(value).unwrap());
+        bytes
     }
 
     fn type_name() -> redb::TypeName {
@@ -309,9 +842,84 @@ impl Value for FileMetadataExt {
     }
 }
 
+/// The unversioned entry layout of the first releases, with a fixed SHA-256.
+mod legacy {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct SymlinkMetadataV1{
+        data: String,
+        permissions: u32,
+        modified: u64,
+        size: ByteSize,
+    }
+
+    #[derive(Deserialize)]
+    pub struct DirMetadataV1{
+        permissions: u32,
+        modified: u64,
+        size: u64,
+    }
+
+    #[derive(Deserialize)]
+    pub struct FileMetadataV1{
+        hash: [u8; 32],
+        permissions: u32,
+        modified: u64,
+        size: ByteSize,
+    }
+
+    #[derive(Deserialize)]
+    pub enum FileMetadataExtV1 {
+        Symlink(SymlinkMetadataV1),
+        File(FileMetadataV1),
+        Dir(DirMetadataV1),
+    }
+
+    impl From<FileMetadataExtV1> for FileMetadataExt {
+        fn from(value: FileMetadataExtV1) -> Self {
+            match value{
+                FileMetadataExtV1::Symlink(s) => FileMetadataExt::Symlink(SymlinkMetadata {
+                    data: s.data,
+                    permissions: s.permissions,
+                    modified: s.modified,
+                    size: s.size,
+                    content: None,
+                    target_exists: true,
+                    target_type: None,
+                }),
+                FileMetadataExtV1::File(f) => FileMetadataExt::File(FileMetadata {
+                    hash: f.hash.into(),
+                    permissions: f.permissions,
+                    modified: f.modified,
+                    size: f.size,
+                    ino: None,
+                    strong: None,
+                    security: None,
+                    streams: None,
+                    content_type: None,
+                    created: None,
+                    flags: None,
+                    owner: None,
+                }),
+                FileMetadataExtV1::Dir(d) => FileMetadataExt::Dir(DirMetadata {
+                    permissions: d.permissions,
+                    modified: d.modified,
+                    size: d.size,
+                    security: None,
+                    entries: None,
+                    flags: None,
+                    owner: None,
+                }),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
 
     #[test]
     fn test_byte_sizes(){
@@ -352,4 +960,191 @@ mod tests {
         let d_half = Duration::from_millis(500);
         assert_eq!(format!("{}", ByteSize::new(512).bandwidth(d_half)), "1.00KiB/s");
     }
+
+    #[test]
+    fn test_display_options_defaults(){
+        let opts = DisplayOptions::default();
+        assert_eq!(format!("{}", Timestamp::with_options(1234567890, &opts)), "2009-02-13 23:31:30 UTC");
+        assert_eq!(format!("{}", Timestamp::with_options(i64::MAX as u64, &opts)), "#ERROR#");
+        assert_eq!(format!("{}", Permissions::with_options(0o100644, &opts)), "100644");
+        assert_eq!(format!("{}", DirSize::with_options(1468006, &opts)), "1468006");
+    }
+
+    #[test]
+    fn test_display_options_human(){
+        let opts = DisplayOptions{ local_time: true, human_sizes: true, symbolic_permissions: true, perms_only: false };
+        let local = chrono::Local.timestamp_opt(1234567890, 0).unwrap().to_string();
+        assert_eq!(format!("{}", Timestamp::with_options(1234567890, &opts)), local);
+        assert_eq!(format!("{}", DirSize::with_options(1468006, &opts)), "1.40MiB");
+        let cases = [
+            (0o100644, "100644 (-rw-r--r--)"),
+            (0o104755, "104755 (-rwsr-xr-x)"),
+            (0o102644, "102644 (-rw-r-Sr--)"),
+            (0o041777, "41777 (drwxrwxrwt)"),
+            (0o041776, "41776 (drwxrwxrwT)"),
+            (0o120777, "120777 (lrwxrwxrwx)"),
+            (0o000000, "0 (?---------)"),
+        ];
+        for (mode, expected) in cases {
+            assert_eq!(format!("{}", Permissions::with_options(mode, &opts)), expected, "Failed for mode {:o}", mode);
+        }
+    }
+
+    #[test]
+    fn test_decode_entries(){
+        #[derive(Serialize)]
+        struct FileV1{ hash: [u8; 32], permissions: u32, modified: u64, size: ByteSize }
+        #[derive(Serialize)]
+        struct SymlinkV1{ data: String, permissions: u32, modified: u64, size: ByteSize }
+        #[derive(Serialize)]
+        struct DirV1{ permissions: u32, modified: u64, size: u64 }
+        #[derive(Serialize)]
+        enum ExtV1{ Symlink(SymlinkV1), File(FileV1), Dir(DirV1) }
+
+        let file = FileMetadata{ hash: [7u8; 32].into(), permissions: 0o100644, modified: 1000, size: 10.into(), ino: None, strong: None, security: None, streams: None, content_type: None, created: None, flags: None, owner: None };
+        let v1 = to_allocvec(&ExtV1::File(FileV1{ hash: [7u8; 32], permissions: 0o100644, modified: 1000, size: 10.into() })).unwrap();
+        assert_eq!(<FileMetadataExt as Value>::from_bytes(&v1), FileMetadataExt::File(file.clone()));
+        let link = FileMetadataExt::Symlink(SymlinkMetadata{ data: "target".to_owned(), permissions: 0o120777, modified: 1000, size: 6.into(), content: None, target_exists: true, target_type: None });
+        let v1 = to_allocvec(&ExtV1::Symlink(SymlinkV1{ data: "target".to_owned(), permissions: 0o120777, modified: 1000, size: 6.into() })).unwrap();
+        assert_eq!(<FileMetadataExt as Value>::from_bytes(&v1), link);
+        let dir = FileMetadataExt::Dir(DirMetadata{ permissions: 0o40755, modified: 1000, size: 4096, security: None, entries: None, flags: None, owner: None });
+        let v1 = to_allocvec(&ExtV1::Dir(DirV1{ permissions: 0o40755, modified: 1000, size: 4096 })).unwrap();
+        assert_eq!(<FileMetadataExt as Value>::from_bytes(&v1), dir);
+
+        // corrupt or from a newer version, read as a file nothing matches
+        assert_eq!(<FileMetadataExt as Value>::from_bytes(&[FORMAT_MAGIC, FORMAT_VERSION, 9, 9]), FileMetadataExt::undecodable());
+        assert_eq!(<FileMetadataExt as Value>::from_bytes(&[FORMAT_MAGIC, FORMAT_VERSION + 1, 0]), FileMetadataExt::undecodable());
+        assert_eq!(<FileMetadataExt as Value>::from_bytes(&[]), FileMetadataExt::undecodable());
+
+        let owned = Some(Security{ owner: "S-1-5-18".to_owned(), descriptor: [3u8; 32].into() });
+        let entries = [
+            FileMetadataExt::File(FileMetadata{ hash: Hash::from_hex(&"ab".repeat(64)).unwrap(), ..file.clone() }),
+            FileMetadataExt::File(FileMetadata{ hash: [9u8; 8].into(), ino: Some(42), strong: Some([7u8; 32].into()), ..file.clone() }),
+            FileMetadataExt::File(FileMetadata{ permissions: 0x20, security: owned.clone(), streams: Some(vec![DataStream{ name: "evil.exe".to_owned(), size: 3.into(), hash: [4u8; 32].into() }]), ..file.clone() }),
+            FileMetadataExt::File(FileMetadata{ content_type: Some("application/x-executable".to_owned()), created: Some(900), flags: Some(inodeflags::IMMUTABLE), ..file.clone() }),
+            FileMetadataExt::Symlink(SymlinkMetadata{ data: "target".to_owned(), permissions: 0o120777, modified: 1000, size: 6.into(), content: Some([5u8; 32].into()), target_exists: true, target_type: None }),
+            FileMetadataExt::Symlink(SymlinkMetadata{ data: "gone".to_owned(), permissions: 0o120777, modified: 1000, size: 4.into(), content: None, target_exists: false, target_type: None }),
+            FileMetadataExt::Symlink(SymlinkMetadata{ data: "sub".to_owned(), permissions: 0o120777, modified: 1000, size: 3.into(), content: None, target_exists: true, target_type: Some(TargetType::Dir) }),
+            FileMetadataExt::Dir(DirMetadata{ permissions: 0x10, modified: 1000, size: 0, security: owned, entries: Some(51), flags: Some(inodeflags::APPEND_ONLY), owner: None }),
+            dir,
+            FileMetadataExt::Whiteout,
+            FileMetadataExt::Opaque,
+        ];
+        for entry in entries{
+            let bytes = <FileMetadataExt as Value>::as_bytes(&entry);
+            assert_eq!(&bytes[..2], &[FORMAT_MAGIC, FORMAT_VERSION]);
+            assert_eq!(<FileMetadataExt as Value>::from_bytes(&bytes), entry);
+        }
+    }
+
+    #[test]
+    fn test_permissions_perms_only(){
+        let opts = DisplayOptions{ perms_only: true, ..Default::default() };
+        assert_eq!(format!("{}", Permissions::with_options(0o100644, &opts)), "644");
+        assert_eq!(format!("{}", Permissions::with_options(0o104755, &opts)), "4755");
+        let opts = DisplayOptions{ perms_only: true, symbolic_permissions: true, ..Default::default() };
+        assert_eq!(format!("{}", Permissions::with_options(0o041777, &opts)), "1777 (rwxrwxrwt)");
+    }
+
+    #[test]
+    fn test_parse_since(){
+        let now = 1_700_000_000;
+        let cases = [
+            ("90s", now - 90),
+            ("30m", now - 30 * 60),
+            ("2h", now - 2 * 3600),
+            ("7d", now - 7 * 86400),
+            ("1w", now - 7 * 86400),
+            ("1234567890", 1234567890),
+            ("2009-02-13T23:31:30Z", 1234567890),
+            ("2009-02-13T23:31Z", 1234567860),
+            ("2009-02-14T00:31:30+01:00", 1234567890),
+            ("2009-02-13 23:31:30", 1234567890),
+            ("2009-02-13", 1234483200),
+        ];
+        for (arg, expected) in cases {
+            assert_eq!(parse_since(arg, now).unwrap(), expected as u64, "Failed for {}", arg);
+        }
+        for bad in ["", "h", "2x", "-5", "-5d", "-90s", "yesterday", "2009-13-01", "99999999999999999999d"]{
+            assert!(parse_since(bad, now).is_err(), "Parsed invalid since {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_deadline(){
+        let now = 1_700_000_000;
+        assert_eq!(parse_deadline("6h", now).unwrap(), now as u64 + 6 * 3600);
+        assert_eq!(parse_deadline("2024-06-01T06:00Z", now).unwrap(), 1717221600);
+        assert!(parse_deadline("tomorrow", now).is_err());
+        assert!(parse_deadline("-6h", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration(){
+        for (arg, expected) in [("30", 30), ("30s", 30), (" 5m", 300), ("2h", 7200), ("1d", 86400)]{
+            assert_eq!(parse_duration(arg, "--duration").unwrap(), Duration::from_secs(expected), "Failed for {}", arg);
+        }
+        for bad in ["", "s", "-5s", "1.5m", "2x"]{
+            assert!(parse_duration(bad, "--duration").is_err(), "Parsed invalid duration {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_hash_from_str(){
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let hash: Hash = hex.parse().unwrap();
+        assert_eq!(hash.to_string(), hex);
+        let upper: Hash = format!("  {}\n", hex.to_uppercase()).parse().unwrap();
+        assert_eq!(upper, hash);
+
+        for bad in ["", "e3b0", &hex[..63], &format!("{}00", hex), &hex.replace('e', "g"), &format!("+{}", &hex[1..]), &format!("ż{}", &hex[2..])]{
+            assert!(bad.parse::<Hash>().is_err(), "Parsed invalid hash {:?}", bad);
+        }
+
+        let md5 = Hash::from_hex("d41d8cd98f00b204e9800998ecf8427e").unwrap();
+        assert_eq!(md5.hash.len(), 16);
+        assert_eq!(md5.to_string(), "d41d8cd98f00b204e9800998ecf8427e");
+        for bad in ["", "abc", "zz"]{
+            assert!(Hash::from_hex(bad).is_err(), "Parsed invalid hex {:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_escaped_path(){
+        let cases = [
+            ("/etc/passwd", "/etc/passwd"),
+            ("/tmp/with space", "/tmp/with space"),
+            ("/tmp/zażółć", "/tmp/zażółć"),
+            ("foo\nFile removed /etc/shadow", "\"foo\\nFile removed /etc/shadow\""),
+            ("/tmp/\x1b[31mred\x1b[0m", "\"/tmp/\\u{1b}[31mred\\u{1b}[0m\""),
+            ("/tmp/tab\tcr\r", "\"/tmp/tab\\tcr\\r\""),
+            ("/tmp/\"quoted\"\n", "\"/tmp/\\\"quoted\\\"\\n\""),
+            // right-to-left override and zero width space, invisible in a terminal
+            ("/tmp/invoice\u{202e}fdp.exe", "\"/tmp/invoice\\u{202e}fdp.exe\""),
+            ("/usr/bin/s\u{200b}udo", "\"/usr/bin/s\\u{200b}udo\""),
+            // would pass for the escaped form of a path with a newline
+            ("\"foo\\nFile removed /etc/shadow\"", "\"\\\"foo\\\\nFile removed /etc/shadow\\\"\""),
+        ];
+        for (path, expected) in cases {
+            let escaped = format!("{}", EscapedPath(path));
+            assert_eq!(escaped, expected, "Failed for path {:?}", path);
+            assert!(!escaped.contains('\n') && !escaped.contains('\x1b'));
+        }
+    }
+
+    #[test]
+    fn test_symlink_display_escapes_target(){
+        let sym = SymlinkMetadata{
+            data: "target\nFile removed /etc/shadow".to_owned(),
+            permissions: 0o777,
+            modified: 0,
+            size: ByteSize::new(10),
+            content: None,
+            target_exists: true,
+            target_type: Some(TargetType::File),
+        };
+        let s = format!("{}", sym);
+        assert!(!s.contains('\n'));
+        assert!(s.starts_with("-> \"target\\nFile removed /etc/shadow\""));
+    }
 }
\ No newline at end of file
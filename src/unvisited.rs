@@ -0,0 +1,131 @@
+//! DB entries a check or update didn't come across. Run with a `--path` set
+//! missing one of the roots of the baseline, a check reports every entry under
+//! it as removed. When more entries than `--removed-threshold` and
+//! `--removed-threshold-percent` of the DB weren't visited, one warning names
+//! where they are instead, and `--confirm-removed` reports them anyway.
+//!
+//! An update only removes entries under its roots, and fails before removing
+//! more than `--max-removals` or `--max-removals-percent` of the DB, e.g. of a
+//! file system that wasn't mounted, unless given `--force-removals`.
+
+use super::types::EscapedPath;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Prefixes named in the warning.
+pub const TOP_PREFIXES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold{
+    /// unvisited entries allowed whatever the size of the DB
+    pub entries: u64,
+    /// unvisited entries allowed, in percent of the DB entries
+    pub percent: u64,
+}
+
+/// Counts of the entries of a baseline and the unvisited ones by prefix.
+#[derive(Debug, Default)]
+pub struct Unvisited{
+    roots: Vec<String>,
+    pub entries: u64,
+    pub unvisited: u64,
+    prefixes: BTreeMap<String, u64>,
+}
+
+impl Unvisited {
+    /// Counts for a walk of `roots`, folded like the DB keys.
+    pub fn new(roots: &[String]) -> Self {
+        Unvisited{ roots: roots.to_vec(), ..Default::default() }
+    }
+
+    pub fn add(&mut self, path: &str, visited: bool) {
+        self.entries += 1;
+        if !visited{
+            self.unvisited += 1;
+            *self.prefixes.entry(self.prefix(path)).or_default() += 1;
+        }
+    }
+
+    /// Whether more entries than both limits of `threshold` weren't visited.
+    pub fn exceeds(&self, threshold: Threshold) -> bool {
+        self.unvisited > threshold.entries && self.unvisited * 100 > threshold.percent * self.entries
+    }
+
+    /// Whether more entries than either limit of `threshold` weren't visited.
+    pub fn exceeds_any(&self, threshold: Threshold) -> bool {
+        self.unvisited > threshold.entries || self.unvisited * 100 > threshold.percent * self.entries
+    }
+
+    /// Prefixes with the most unvisited entries, most first.
+    pub fn top_prefixes(&self) -> Vec<(&str, u64)> {
+        let mut prefixes: Vec<_> = self.prefixes.iter().map(|(p, n)| (p.as_str(), *n)).collect();
+        prefixes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        prefixes.truncate(TOP_PREFIXES);
+        prefixes
+    }
+
+    /// The warning shown in place of the entries, `action` telling what
+    /// `--confirm-removed` does, e.g. "reports them as removed".
+    pub fn warning(&self, action: &str) -> String {
+        format!("{} of {} DB entries were not found under --path, is a root missing? Most are under {}; --confirm-removed {action}",
+            self.unvisited, self.entries, self.prefix_list())
+    }
+
+    /// Top prefixes with their counts, e.g. `/opt (4), /usr/sbin (2)`.
+    pub fn prefix_list(&self) -> String {
+        let prefixes: Vec<_> = self.top_prefixes().iter().map(|(p, n)| format!("{} ({n})", EscapedPath(p))).collect();
+        prefixes.join(", ")
+    }
+
+    /// First path component of `path` below what it shares with the walked
+    /// roots, e.g. `/opt` for `/opt/app/bin` when `/etc` and `/usr` were
+    /// walked, or `/usr/sbin` when `/usr/bin` was.
+    fn prefix(&self, path: &str) -> String {
+        let path = Path::new(path);
+        let mut child = path;
+        for ancestor in path.ancestors(){
+            if self.roots.iter().any(|root| Path::new(root).starts_with(ancestor)){
+                return child.to_string_lossy().into_owned();
+            }
+            child = ancestor;
+        }
+        child.to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unvisited() {
+        let threshold = Threshold{ entries: 3, percent: 20 };
+        let mut counts = Unvisited::new(&["/etc".to_owned(), "/usr/bin".to_owned()]);
+        for i in 0..20{
+            counts.add(&format!("/etc/{i}"), true);
+        }
+        counts.add("/etc/gone", false);
+        counts.add("/usr/sbin/a", false);
+        counts.add("/usr/sbin/b", false);
+        assert!(!counts.exceeds(threshold));
+        for i in 0..4{
+            counts.add(&format!("/opt/app/{i}"), false);
+        }
+        // 7 of 27 are past 3 entries and 20%
+        assert!(counts.exceeds(threshold));
+        assert!(!counts.exceeds(Threshold{ entries: 7, percent: 0 }));
+        assert!(!counts.exceeds(Threshold{ entries: 0, percent: 26 }));
+        assert!(counts.exceeds_any(Threshold{ entries: 7, percent: 25 }));
+        assert!(counts.exceeds_any(Threshold{ entries: 6, percent: 100 }));
+        assert!(!counts.exceeds_any(Threshold{ entries: 7, percent: 26 }));
+        assert_eq!(counts.top_prefixes(), [("/opt", 4), ("/usr/sbin", 2), ("/etc/gone", 1)]);
+        assert_eq!(counts.warning("reports them as removed"),
+            "7 of 27 DB entries were not found under --path, is a root missing? Most are under /opt (4), /usr/sbin (2), /etc/gone (1); --confirm-removed reports them as removed");
+
+        // relative roots
+        let mut counts = Unvisited::new(&["tree/a".to_owned()]);
+        counts.add("tree/b/c", false);
+        counts.add("other/d", false);
+        assert_eq!(counts.top_prefixes(), [("other", 1), ("tree/b", 1)]);
+    }
+}
@@ -0,0 +1,76 @@
+//! Version of the tool that wrote a baseline. `--create` and `--update` stamp
+//! it in the DB metadata. A check by another major or minor version warns and
+//! names the known behavior changes in between: entries stored by the old
+//! behavior can differ from a scan of the same, untouched files now.
+//!
+//! Baselines from before the stamp fall back to the version of their last
+//! recorded create or update run, if one is still kept.
+
+use super::dbmeta;
+use super::error::IntegrityWatcherError;
+use super::runs;
+use redb::Database;
+
+/// Version of this build.
+pub const CURRENT: &str = env!("CARGO_PKG_VERSION");
+
+/// Changes of what a scan stores or how entries compare, by the version that
+/// brought them, oldest first. None so far, every 0.1 baseline is read alike.
+pub const BEHAVIOR_CHANGES: &[(&str, &str)] = &[];
+
+/// `major.minor.patch`, pre-release and build suffixes ignored.
+fn parse(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+    Some((parts.next()??, parts.next()??, parts.next().flatten().unwrap_or(0)))
+}
+
+/// Version that wrote the baseline of `table`, `None` when neither the stamp
+/// nor a create or update run tells.
+pub fn baseline_version(db: &Database, table: &str) -> Result<Option<String>, IntegrityWatcherError> {
+    if let Some(version) = dbmeta::load_version(db, table)?{
+        return Ok(Some(version));
+    }
+    let runs = runs::load(db, table, runs::RUNS_KEPT as usize)?;
+    Ok(runs.into_iter().rev().find(|r| r.command == "create" || r.command == "update").map(|r| r.version))
+}
+
+/// The warning for a baseline written by `baseline` checked by `running`,
+/// none when they share major and minor version or either can't be parsed.
+/// `changes` is `BEHAVIOR_CHANGES` outside of tests.
+pub fn notice(baseline: &str, running: &str, changes: &[(&str, &str)]) -> Option<String> {
+    let (old, new) = (parse(baseline)?, parse(running)?);
+    if (old.0, old.1) == (new.0, new.1){
+        return None;
+    }
+    let (low, high) = if old < new { (old, new) } else { (new, old) };
+    let mut notice = format!("Baseline created by {baseline}, you are running {running}");
+    for (version, change) in changes{
+        if parse(version).is_some_and(|v| low < v && v <= high){
+            notice.push_str(&format!("; {change} in {version}"));
+        }
+    }
+    notice.push_str(", differences may be artifacts of the upgrade rather than file changes, --update takes them into the baseline");
+    Some(notice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notice() {
+        let changes = [("0.2.0", "symlink targets resolved"), ("0.4.0", "permission semantics changed"), ("0.6.0", "later")];
+        assert_eq!(parse("0.1.12"), Some((0, 1, 12)));
+        assert_eq!(parse("1.2"), Some((1, 2, 0)));
+        assert_eq!(parse("1.2.3-rc.1"), Some((1, 2, 3)));
+        assert_eq!(parse("dev"), None);
+        assert_eq!(notice("0.3.0", "0.3.7", &changes), None);
+        assert_eq!(notice("dev", "0.5.0", &changes), None);
+        assert_eq!(notice("0.3.0", "0.5.0", &changes).as_deref(), Some("Baseline created by 0.3.0, you are running 0.5.0; \
+            permission semantics changed in 0.4.0, differences may be artifacts of the upgrade rather than file changes, --update takes them into the baseline"));
+        // a baseline of a newer version checked by an older one
+        assert!(notice("0.5.1", "0.1.0", &changes).unwrap().contains("; symlink targets resolved in 0.2.0; permission semantics changed in 0.4.0,"));
+        assert!(notice("0.1.0", "1.0.0", &[]).unwrap().starts_with("Baseline created by 0.1.0, you are running 1.0.0, differences"));
+    }
+}
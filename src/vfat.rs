@@ -0,0 +1,98 @@
+//! `--vfat-friendly`: entries on FAT file systems, like an EFI system
+//! partition under `/boot/efi`, are compared by content and size. FAT has no
+//! permissions, the mode comes from the mount options, and keeps mtimes in 2
+//! second steps, so a check leaves out permission changes and allows
+//! [`MTIME_TOLERANCE`] for them.
+//!
+//! The file system is told by `statfs` of the directory of an entry, once per
+//! directory. Only Linux is detected, elsewhere nothing counts as FAT.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// mtime difference in seconds FAT entries may have without a change.
+pub const MTIME_TOLERANCE: u64 = 2;
+
+/// `statfs` magic of vfat and msdos.
+#[cfg(target_os = "linux")]
+const MSDOS_SUPER_MAGIC: u32 = 0x4d44;
+/// `statfs` magic of exfat, just as short of permissions.
+#[cfg(target_os = "linux")]
+const EXFAT_SUPER_MAGIC: u32 = 0x2011_bab0;
+
+/// Whether `dir` is on a FAT file system, false where that can't be told.
+pub fn is_fat(dir: &Path) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+            return false;
+        };
+        let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+        // SAFETY: the path is NUL terminated and `stat` is written by the call
+        if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } < 0{
+            tracing::debug!("File system of {} not told: {}", super::types::EscapedPath(dir.to_string_lossy().as_ref()), std::io::Error::last_os_error());
+            return false;
+        }
+        // SAFETY: statfs succeeded
+        let magic = unsafe { stat.assume_init() }.f_type as u32;
+        matches!(magic, MSDOS_SUPER_MAGIC | EXFAT_SUPER_MAGIC)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = dir;
+        false
+    }
+}
+
+/// Directories told FAT or not so far.
+#[derive(Debug, Default)]
+pub struct FatDirs(HashMap<PathBuf, bool>);
+
+impl FatDirs {
+    /// Whether the entry at `path` is on FAT: a directory by itself, as it can
+    /// be the mount point, anything else by the directory it's in.
+    pub fn contains(&mut self, path: &str, is_dir: bool) -> bool {
+        let path = Path::new(path);
+        let dir = match path.parent(){
+            _ if is_dir => path,
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if let Some(fat) = self.0.get(dir){
+            return *fat;
+        }
+        let fat = is_fat(dir);
+        self.0.insert(dir.to_owned(), fat);
+        fat
+    }
+
+    /// Takes `dir` as FAT or not without asking the file system.
+    #[cfg(test)]
+    pub(crate) fn assume(&mut self, dir: &Path, fat: bool) {
+        self.0.insert(dir.to_owned(), fat);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fat_dirs() {
+        let root = std::env::current_dir().unwrap();
+        let fat = is_fat(&root);
+        let mut dirs = FatDirs::default();
+        assert_eq!(dirs.contains(&root.join("Cargo.toml").to_string_lossy(), false), fat);
+        assert_eq!(dirs.contains(&root.to_string_lossy(), true), fat);
+        assert!(!is_fat(Path::new("/nonexistent/dir")));
+
+        // told once per directory
+        dirs.assume(&root, !fat);
+        assert_eq!(dirs.contains(&root.join("README.md").to_string_lossy(), false), !fat);
+        dirs.assume(Path::new("/boot/efi/EFI"), true);
+        assert!(dirs.contains("/boot/efi/EFI/grubx64.efi", false));
+        assert!(dirs.contains("/boot/efi/EFI", true));
+        assert_eq!(dirs.contains("relative", false), is_fat(Path::new(".")));
+    }
+}
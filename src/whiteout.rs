@@ -0,0 +1,130 @@
+//! Whiteouts of overlay layers, recognized with `--overlay-aware`. An upper
+//! layer deletes a file of a lower one with a whiteout: overlayfs makes it a
+//! character device 0:0 of the same name, aufs and OCI layer tarballs an empty
+//! `.wh.<name>` file next to it. Scanning a layer, the whiteout is stored as a
+//! [`FileMetadataExt::Whiteout`] entry of the path it deletes, so the baseline
+//! tells what the merged view no longer has instead of listing a device or a
+//! stray file.
+//!
+//! A layer makes a directory opaque to hide what lower layers have in it:
+//! aufs and OCI layers with a `.wh..wh..opq` marker file in it, overlayfs with
+//! the `trusted.overlay.opaque` xattr (`user.overlay.opaque` on `userxattr`
+//! mounts). Both are stored as a [`FileMetadataExt::Opaque`] entry of the
+//! marker path. The other `.wh..wh.` files aufs keeps for itself are skipped.
+//!
+//! [`FileMetadataExt::Whiteout`]: super::types::FileMetadataExt::Whiteout
+//! [`FileMetadataExt::Opaque`]: super::types::FileMetadataExt::Opaque
+
+use std::path::Path;
+
+/// Name prefix of aufs whiteouts.
+pub const PREFIX: &str = ".wh.";
+
+/// Name prefix of the files aufs keeps for itself.
+pub const META_PREFIX: &str = ".wh..wh.";
+
+/// Name of the marker in an opaque directory.
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+/// overlayfs xattrs marking a directory opaque. Reading `trusted.` ones takes
+/// CAP_SYS_ADMIN, without it they read as absent.
+#[cfg(target_os = "linux")]
+const OPAQUE_XATTRS: [&str; 2] = ["trusted.overlay.opaque", "user.overlay.opaque"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhiteoutFormat{
+    /// overlayfs: character devices 0:0
+    CharDevice,
+    /// aufs and OCI layers: `.wh.` prefixed files
+    Prefix,
+    /// both
+    Any,
+}
+
+impl std::str::FromStr for WhiteoutFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s{
+            "chardev" => Ok(WhiteoutFormat::CharDevice),
+            "prefix" => Ok(WhiteoutFormat::Prefix),
+            "any" => Ok(WhiteoutFormat::Any),
+            _ => Err(format!("unknown whiteout format {s}, expected chardev, prefix or any")),
+        }
+    }
+}
+
+/// What a walk makes of an entry of a layer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Layered{
+    /// an ordinary entry
+    Entry,
+    /// a whiteout deleting this path
+    Deletes(String),
+    /// the marker of an opaque directory, at this path
+    Opaque(String),
+    /// an aufs file to leave out
+    Meta,
+}
+
+impl WhiteoutFormat {
+    #[cfg(unix)]
+    fn char_device(self) -> bool {
+        matches!(self, WhiteoutFormat::CharDevice | WhiteoutFormat::Any)
+    }
+
+    fn prefix(self) -> bool {
+        matches!(self, WhiteoutFormat::Prefix | WhiteoutFormat::Any)
+    }
+
+    /// Whether the entry at `path` of type `file_type` is a whiteout. Only
+    /// character devices are looked at further, for their device number.
+    pub async fn classify(self, path: &Path, file_type: std::fs::FileType) -> Layered {
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+            return Layered::Entry;
+        };
+        if self.prefix() && file_type.is_file(){
+            if name == OPAQUE_MARKER{
+                return Layered::Opaque(path.to_string_lossy().into_owned());
+            }
+            if name.starts_with(META_PREFIX){
+                return Layered::Meta;
+            }
+            if let Some(deleted) = name.strip_prefix(PREFIX) && !deleted.is_empty(){
+                return Layered::Deletes(path.with_file_name(deleted).to_string_lossy().into_owned());
+            }
+        }
+        #[cfg(unix)]
+        if self.char_device(){
+            use std::os::unix::fs::{FileTypeExt, MetadataExt};
+            if file_type.is_char_device() && tokio::fs::symlink_metadata(path).await.is_ok_and(|m| m.rdev() == 0){
+                return Layered::Deletes(path.to_string_lossy().into_owned());
+            }
+        }
+        Layered::Entry
+    }
+
+    /// The marker path of directory `dir` when overlayfs made it opaque.
+    pub fn opaque_marker(self, dir: &Path) -> Option<String> {
+        #[cfg(target_os = "linux")]
+        if self.char_device() && OPAQUE_XATTRS.iter().any(|name| xattr_is_set(dir, name)){
+            return Some(dir.join(OPAQUE_MARKER).to_string_lossy().into_owned());
+        }
+        let _ = dir;
+        None
+    }
+}
+
+/// Whether xattr `name` of `path`, not following a symlink, is `y`.
+#[cfg(target_os = "linux")]
+fn xattr_is_set(path: &Path, name: &str) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let (Ok(path), Ok(name)) = (std::ffi::CString::new(path.as_os_str().as_bytes()), std::ffi::CString::new(name)) else {
+        return false;
+    };
+    let mut value = [0u8; 2];
+    // SAFETY: both strings are NUL terminated, the kernel writes at most value.len() bytes
+    let len = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+    len == 1 && value[0] == b'y'
+}
+
@@ -0,0 +1,218 @@
+//! Windows file metadata: attribute flags, stored in place of the Linux mode
+//! bits, the owner and DACL of files and directories and the alternate data
+//! streams of files.
+
+use super::types::Hash;
+#[cfg(windows)]
+use super::types::{DataStream, Security};
+
+/// `FILE_ATTRIBUTE_*` flags that are stored and compared. Flags the system
+/// changes on its own, like `NOT_CONTENT_INDEXED` or the cloud file ones, are left out.
+pub const ATTRIBUTE_NAMES: [(u32, &str); 9] = [
+    (0x1, "READONLY"),
+    (0x2, "HIDDEN"),
+    (0x4, "SYSTEM"),
+    (0x10, "DIRECTORY"),
+    (0x20, "ARCHIVE"),
+    (0x100, "TEMPORARY"),
+    (0x400, "REPARSE_POINT"),
+    (0x800, "COMPRESSED"),
+    (0x4000, "ENCRYPTED"),
+];
+
+pub const TRACKED_ATTRIBUTES: u32 = {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < ATTRIBUTE_NAMES.len(){
+        mask |= ATTRIBUTE_NAMES[i].0;
+        i += 1;
+    }
+    mask
+};
+
+/// `HIDDEN|SYSTEM` for the flags set in `attributes`, `-` when there are none.
+pub fn attribute_names(attributes: u32) -> String {
+    let names: Vec<_> = ATTRIBUTE_NAMES.iter().filter(|(bit, _)| attributes & bit != 0).map(|(_, name)| *name).collect();
+    let unknown = attributes & !TRACKED_ATTRIBUTES;
+    match (names.is_empty(), unknown){
+        (true, 0) => "-".to_owned(),
+        (false, 0) => names.join("|"),
+        (true, unknown) => format!("{unknown:#x}"),
+        (false, unknown) => format!("{}|{unknown:#x}", names.join("|")),
+    }
+}
+
+/// Digest of a security descriptor in SDDL form, so any ACE change shows up
+/// without storing the whole DACL.
+pub fn descriptor_hash(sddl: &str) -> Hash {
+    use sha2::{Digest, Sha256};
+    let digest: [u8; 32] = Sha256::digest(sddl.as_bytes()).into();
+    digest.into()
+}
+
+#[cfg(windows)]
+pub fn attributes(meta: &std::fs::Metadata) -> u32 {
+    use std::os::windows::fs::MetadataExt;
+    meta.file_attributes() & TRACKED_ATTRIBUTES
+}
+
+/// Owner SID and DACL of `path`. `None` when they can't be read, e.g. without
+/// `READ_CONTROL` access; the check then reports the descriptor as unreadable.
+#[cfg(windows)]
+pub fn security(path: &std::path::Path) -> Option<Security> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr::null_mut;
+    use windows_sys::core::PWSTR;
+    use windows_sys::Win32::Foundation::{LocalFree, ERROR_SUCCESS};
+    use windows_sys::Win32::Security::{DACL_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, PSID};
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertSecurityDescriptorToStringSecurityDescriptorW, ConvertSidToStringSidW, GetNamedSecurityInfoW, SDDL_REVISION_1, SE_FILE_OBJECT,
+    };
+
+    /// NUL terminated string allocated by the system.
+    unsafe fn take(s: PWSTR) -> String {
+        let mut len = 0;
+        // SAFETY: `s` is a NUL terminated string returned by the Convert* calls
+        unsafe {
+            while *s.add(len) != 0{
+                len += 1;
+            }
+            String::from_utf16_lossy(std::slice::from_raw_parts(s, len))
+        }
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let info = OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION;
+    let mut owner: PSID = null_mut();
+    let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+    // SAFETY: `wide` is NUL terminated and the out pointers are valid, `owner`
+    // points into `descriptor`, which is freed below
+    let err = unsafe { GetNamedSecurityInfoW(wide.as_ptr(), SE_FILE_OBJECT, info, &mut owner, null_mut(), null_mut(), null_mut(), &mut descriptor) };
+    if err != ERROR_SUCCESS{
+        tracing::debug!("Can't read security descriptor of {}: error {err}", path.display());
+        return None;
+    }
+    let mut sid: PWSTR = null_mut();
+    let mut sddl: PWSTR = null_mut();
+    // SAFETY: `owner` and `descriptor` come from GetNamedSecurityInfoW, the
+    // strings are only read when the conversion succeeded and all three are
+    // freed with LocalFree, which accepts null
+    unsafe {
+        let security = (ConvertSidToStringSidW(owner, &mut sid) != 0
+            && ConvertSecurityDescriptorToStringSecurityDescriptorW(descriptor, SDDL_REVISION_1, info, &mut sddl, null_mut()) != 0)
+            .then(|| Security{ owner: take(sid), descriptor: descriptor_hash(&take(sddl)) });
+        LocalFree(sid as _);
+        LocalFree(sddl as _);
+        LocalFree(descriptor);
+        security
+    }
+}
+
+/// Named data streams of the file at `path` with their size and SHA-256, the
+/// unnamed `::$DATA` stream is the file content itself. `None` when they can't
+/// be enumerated, e.g. on file systems without streams.
+#[cfg(windows)]
+pub fn streams(path: &std::path::Path) -> Option<Vec<DataStream>> {
+    use std::os::windows::ffi::OsStrExt;
+    use sha2::{Digest, Sha256};
+    use windows_sys::Win32::Foundation::{GetLastError, ERROR_HANDLE_EOF, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard, WIN32_FIND_STREAM_DATA};
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    // SAFETY: all zero is a valid WIN32_FIND_STREAM_DATA
+    let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+    // SAFETY: `wide` is NUL terminated and `data` matches FindStreamInfoStandard
+    let find = unsafe { FindFirstStreamW(wide.as_ptr(), FindStreamInfoStandard, &mut data as *mut _ as _, 0) };
+    if find == INVALID_HANDLE_VALUE{
+        // SAFETY: no other call in between
+        let err = unsafe { GetLastError() };
+        tracing::debug!("Can't enumerate streams of {}: error {err}", path.display());
+        return None;
+    }
+    let mut names = Vec::new();
+    loop {
+        let len = data.cStreamName.iter().position(|c| *c == 0).unwrap_or(data.cStreamName.len());
+        let name = String::from_utf16_lossy(&data.cStreamName[..len]);
+        if let Some(name) = name.strip_prefix(':').and_then(|n| n.strip_suffix(":$DATA"))
+        && !name.is_empty(){
+            names.push(name.to_owned());
+        }
+        // SAFETY: `find` is a valid stream search handle
+        if unsafe { FindNextStreamW(find, &mut data as *mut _ as _) } == 0{
+            break;
+        }
+    }
+    // SAFETY: read right after FindNextStreamW failed, `find` is closed once
+    let err = unsafe { GetLastError() };
+    unsafe { FindClose(find) };
+    if err != ERROR_HANDLE_EOF{
+        tracing::debug!("Can't enumerate streams of {}: error {err}", path.display());
+        return None;
+    }
+
+    let mut streams = Vec::with_capacity(names.len());
+    for name in names{
+        let mut stream_path = path.as_os_str().to_owned();
+        stream_path.push(format!(":{name}"));
+        let mut hasher = Sha256::new();
+        let size = match std::fs::File::open(&stream_path).and_then(|mut f| std::io::copy(&mut f, &mut hasher)){
+            Ok(size) => size,
+            Err(e) => {
+                tracing::debug!("Can't read stream {}:{name}: {e}", path.display());
+                return None;
+            }
+        };
+        let digest: [u8; 32] = hasher.finalize().into();
+        streams.push(DataStream{ name, size: size.into(), hash: digest.into() });
+    }
+    Some(streams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attribute_names() {
+        assert_eq!(attribute_names(0), "-");
+        assert_eq!(attribute_names(0x1), "READONLY");
+        assert_eq!(attribute_names(0x26), "HIDDEN|SYSTEM|ARCHIVE");
+        assert_eq!(attribute_names(0x10 | 0x80000), "DIRECTORY|0x80000");
+        assert_eq!(TRACKED_ATTRIBUTES, 0x4d37);
+        assert_ne!(descriptor_hash("O:BAD:(A;;FA;;;SY)"), descriptor_hash("O:BAD:(A;;FA;;;WD)"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_security_of_temp_dir() {
+        let security = security(&std::env::temp_dir()).unwrap();
+        assert!(security.owner.starts_with("S-1-"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_streams() {
+        let mut dir = std::env::current_dir().unwrap();
+        dir.push("test_tree_streams");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file.txt");
+        std::fs::write(&file, b"content").unwrap();
+        assert!(streams(&file).unwrap().is_empty());
+
+        std::fs::write(dir.join("file.txt:evil.exe"), b"MZ payload").unwrap();
+        let found = streams(&file).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "evil.exe");
+        assert_eq!(found[0].size, 10.into());
+        assert_eq!(std::fs::read(&file).unwrap(), b"content");
+
+        std::fs::write(dir.join("file.txt:evil.exe"), b"MZ other").unwrap();
+        let changed = streams(&file).unwrap();
+        assert_ne!(changed[0].hash, found[0].hash);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+//! `--create --stdout` piped into `--import -` through the built binary.
+
+use std::fs;
+use std::process::{Command, Stdio};
+
+const BIN: &str = env!("CARGO_BIN_EXE_integrity-checker");
+
+#[test]
+fn test_create_stdout_into_import() {
+    let mut dir = std::env::current_dir().unwrap();
+    dir.push("test_tree_create_stdout");
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    let tree = dir.join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("a"), b"first").unwrap();
+    fs::write(tree.join("sub/b"), b"second").unwrap();
+    let tree = tree.to_string_lossy().to_string();
+    let (created, imported) = (dir.join("created.redb"), dir.join("imported.redb"));
+
+    for compress in [&[][..], &["--compress", "zstd"]]{
+        let _ = fs::remove_file(&imported);
+        let mut create = Command::new(BIN).args(["--create", "--stdout", "--path", &tree]).args(compress)
+            .stdout(Stdio::piped()).stderr(Stdio::null()).spawn().unwrap();
+        let import = Command::new(BIN).args(["--import", "-", "--db"]).arg(&imported)
+            .stdin(create.stdout.take().unwrap()).output().unwrap();
+        assert!(create.wait().unwrap().success());
+        assert!(import.status.success(), "{}", String::from_utf8_lossy(&import.stdout));
+
+        let _ = fs::remove_file(&created);
+        assert!(Command::new(BIN).args(["--create", "--path", &tree, "--db"]).arg(&created).output().unwrap().status.success());
+        let equal = Command::new(BIN).args(["--db-equal", "--db"]).arg(&created).arg("--db2").arg(&imported).output().unwrap();
+        assert!(equal.status.success(), "{}", String::from_utf8_lossy(&equal.stdout));
+        assert!(String::from_utf8_lossy(&equal.stdout).contains("are identical"));
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}